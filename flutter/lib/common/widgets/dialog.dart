@@ -1927,6 +1927,245 @@ trackpadSpeedDialog(SessionID sessionId, FFI ffi) async {
       [btnClose]);
 }
 
+bandwidthLimitDialog(SessionID sessionId, FFI ffi) async {
+  final option = await bind.sessionGetOption(
+      sessionId: sessionId, arg: kOptionBandwidthLimit);
+  final controller = TextEditingController(text: option ?? '');
+  ffi.dialogManager.show((setState, close, context) {
+    submit() async {
+      final kbps = int.tryParse(controller.text.trim()) ?? 0;
+      await bind.sessionSetBandwidthLimit(sessionId: sessionId, kbps: kbps);
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Bandwidth limit')),
+      content: DialogTextField(
+        title: 'kbps',
+        helperText: translate('bandwidth-limit-tip'),
+        controller: controller,
+        keyboardType: TextInputType.number,
+        inputFormatters: [FilteringTextInputFormatter.digitsOnly],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+shareWindowDialog(SessionID sessionId, FFI ffi) async {
+  final ffiModel = ffi.ffiModel;
+  ffiModel.sharedWindows.clear();
+  await bind.sessionQuerySharedWindows(sessionId: sessionId);
+  ffi.dialogManager.show((setState, close, context) {
+    submit() async {
+      await bind.sessionShareWindow(
+          sessionId: sessionId, id: ffiModel.sharedWindow.value);
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Share a window')),
+      content: Obx(() {
+        final windows = [
+          {'id': 0, 'title': translate('No window')},
+          ...ffiModel.sharedWindows,
+        ];
+        return Column(
+          mainAxisSize: MainAxisSize.min,
+          crossAxisAlignment: CrossAxisAlignment.start,
+          children: [
+            Text(translate('share-window-tip')),
+            ...windows.map((w) => RadioListTile<int>(
+                  contentPadding: const EdgeInsets.all(0),
+                  dense: true,
+                  controlAffinity: ListTileControlAffinity.leading,
+                  title: Text(w['title'] as String),
+                  value: w['id'] as int,
+                  groupValue: ffiModel.sharedWindow.value,
+                  onChanged: (v) => ffiModel.sharedWindow.value = v ?? 0,
+                )),
+          ],
+        );
+      }),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+audioAppsDialog(SessionID sessionId, FFI ffi) async {
+  final ffiModel = ffi.ffiModel;
+  ffiModel.audioApps.clear();
+  await bind.sessionQueryAudioApps(sessionId: sessionId);
+  ffi.dialogManager.show((setState, close, context) {
+    submit() async {
+      await bind.sessionSetAudioApps(
+          sessionId: sessionId, selected: ffiModel.selectedAudioApps.toList());
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Audio of applications')),
+      content: Obx(() {
+        // The selected ones may have stopped playing for now
+        final apps = {...ffiModel.audioApps, ...ffiModel.selectedAudioApps};
+        return Column(
+          mainAxisSize: MainAxisSize.min,
+          crossAxisAlignment: CrossAxisAlignment.start,
+          children: [
+            Text(translate('audio-apps-tip')),
+            ...apps.map((app) => CheckboxListTile(
+                  contentPadding: const EdgeInsets.all(0),
+                  dense: true,
+                  controlAffinity: ListTileControlAffinity.leading,
+                  title: Text(app),
+                  value: ffiModel.selectedAudioApps.contains(app),
+                  onChanged: (v) {
+                    if (v == true) {
+                      ffiModel.selectedAudioApps.add(app);
+                    } else {
+                      ffiModel.selectedAudioApps.remove(app);
+                    }
+                  },
+                )),
+          ],
+        );
+      }),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+class _KeyMacroEditor {
+  final name = TextEditingController();
+  final hotkey = TextEditingController();
+  final text = TextEditingController();
+  bool global = false;
+
+  _KeyMacroEditor.fromJson(Map<String, dynamic> json) {
+    name.text = json['name'] ?? '';
+    hotkey.text = json['hotkey'] ?? '';
+    text.text = json['text'] ?? '';
+    global = json['global'] == true;
+  }
+
+  Map<String, dynamic> toJson() => {
+        'name': name.text.trim(),
+        'hotkey': hotkey.text.trim(),
+        'text': text.text,
+        'global': global,
+      };
+}
+
+keyboardMacrosDialog(SessionID sessionId, FFI ffi) async {
+  final List<dynamic> list =
+      jsonDecode(bind.sessionGetMacros(sessionId: sessionId));
+  final macros = list.map((e) => _KeyMacroEditor.fromJson(e)).toList();
+  var error = '';
+  ffi.dialogManager.show((setState, close, context) {
+    submit() {
+      error = bind.sessionSetMacros(
+          sessionId: sessionId,
+          macros: jsonEncode(macros.map((e) => e.toJson()).toList()));
+      if (error.isEmpty) {
+        close();
+      } else {
+        setState(() {});
+      }
+    }
+
+    Widget editor(_KeyMacroEditor m) {
+      return Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Row(
+            children: [
+              Expanded(
+                child: TextField(
+                  controller: m.name,
+                  decoration: InputDecoration(labelText: translate('Name')),
+                ),
+              ),
+              const SizedBox(width: 8),
+              Expanded(
+                child: TextField(
+                  controller: m.hotkey,
+                  decoration: InputDecoration(
+                      labelText: translate('Hotkey'), hintText: 'Ctrl+Alt+1'),
+                ),
+              ),
+              IconButton(
+                icon: Icon(Icons.delete_outline_rounded),
+                onPressed: () => setState(() => macros.remove(m)),
+              ),
+            ],
+          ),
+          TextField(
+            controller: m.text,
+            minLines: 1,
+            maxLines: 4,
+            decoration: InputDecoration(
+                hintText: '{+CTRL}c{-CTRL}{DELAY 100}{ENTER}'),
+          ),
+          CheckboxListTile(
+            contentPadding: const EdgeInsets.all(0),
+            dense: true,
+            controlAffinity: ListTileControlAffinity.leading,
+            title: Text(translate('For all peers')),
+            value: m.global,
+            onChanged: (v) => setState(() => m.global = v == true),
+          ),
+        ],
+      ).paddingOnly(bottom: 8);
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Keyboard macros')),
+      content: SizedBox(
+        width: 480,
+        child: SingleChildScrollView(
+          child: Column(
+            mainAxisSize: MainAxisSize.min,
+            crossAxisAlignment: CrossAxisAlignment.start,
+            children: [
+              Text(translate('keyboard-macros-tip')),
+              ...macros.map(editor),
+              TextButton.icon(
+                icon: Icon(Icons.add_rounded),
+                label: Text(translate('Add')),
+                onPressed: () => setState(() => macros.add(
+                    _KeyMacroEditor.fromJson(<String, dynamic>{}))),
+              ),
+              if (error.isNotEmpty)
+                Text(error, style: TextStyle(color: Colors.red)),
+            ],
+          ),
+        ),
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
 void deleteConfirmDialog(Function onSubmit, String title) async {
   gFFI.dialogManager.show(
     (setState, close, context) {
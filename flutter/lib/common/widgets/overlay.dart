@@ -606,6 +606,9 @@ class QualityMonitor extends StatelessWidget {
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
                       _row("Chroma", qualityMonitorModel.data.chroma ?? '-'),
+                      if (qualityMonitorModel.data.bandwidthLimit != null)
+                        _row("Bandwidth Limit",
+                            "${qualityMonitorModel.data.bandwidthLimit}kb"),
                     ],
                   ),
                 )
@@ -755,6 +755,33 @@ Future<List<TRadioMenu<String>>> toolbarCodec(
   ];
 }
 
+Future<List<TRadioMenu<String>>> toolbarAudioPreset(
+    BuildContext context, String id, FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  final groupValue = await bind.sessionGetOption(
+          sessionId: sessionId, arg: kOptionAudioPreset) ??
+      '';
+  onChanged(String? value) async {
+    if (value == null) return;
+    await bind.sessionSetAudioPreset(sessionId: sessionId, value: value);
+  }
+
+  TRadioMenu<String> radio(String label, String value) {
+    return TRadioMenu<String>(
+        child: Text(translate(label)),
+        value: value,
+        groupValue: groupValue,
+        onChanged: onChanged);
+  }
+
+  return [
+    radio('Auto', ''),
+    radio('Voice', 'voice'),
+    radio('Music', 'music'),
+    radio('Low bandwidth', 'low-bandwidth'),
+  ];
+}
+
 Future<List<TToggleMenu>> toolbarCursor(
     BuildContext context, String id, FFI ffi) async {
   List<TToggleMenu> v = [];
@@ -1012,6 +1039,21 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         child: Text(translate('True color (4:4:4)'))));
   }
 
+  if (codec_format == "AV1" &&
+      bind.sessionSupportCrispText(sessionId: sessionId)) {
+    final option = kOptionCrispText;
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionToggleOption(sessionId: sessionId, value: option);
+          bind.sessionChangePreferCodec(sessionId: sessionId);
+        },
+        child: Text(translate('Crisp text'))));
+  }
+
   if (isDefaultConn && isMobile) {
     v.addAll(toolbarKeyboardToggles(ffi));
   }
@@ -1181,6 +1223,26 @@ List<TToggleMenu> toolbarKeyboardToggles(FFI ffi) {
         child: Text(translate('Relative mouse mode'))));
   }
 
+  // forward gamepads, only linux exposes them as virtual ones
+  if (isDesktop &&
+      isDefaultConn &&
+      !isWeb &&
+      pi.platform == kPeerPlatformLinux) {
+    final enabled = !ffiModel.viewOnly;
+    final value = bind.sessionGetToggleOptionSync(
+        sessionId: sessionId, arg: kOptionForwardGamepads);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: enabled
+            ? (value) {
+                if (value == null) return;
+                bind.sessionToggleOption(
+                    sessionId: sessionId, value: kOptionForwardGamepads);
+              }
+            : null,
+        child: Text(translate('Forward gamepads'))));
+  }
+
   // reverse mouse wheel
   if (ffiModel.keyboard) {
     var optionValue =
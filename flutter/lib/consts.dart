@@ -115,6 +115,8 @@ const String kOptionEnableTunnel = "enable-tunnel";
 const String kOptionEnableRemoteRestart = "enable-remote-restart";
 const String kOptionEnableBlockInput = "enable-block-input";
 const String kOptionEnablePrivacyMode = "enable-privacy-mode";
+const String kOptionAllowMicRedirect = "allow-mic-redirect";
+const String kOptionAllowGamepad = "allow-gamepad";
 const String kOptionEnablePermChangeInAcceptWindow =
     "enable-perm-change-in-accept-window";
 const String kOptionAllowRemoteConfigModification =
@@ -138,6 +140,12 @@ const String kOptionLockAfterSessionEnd = "lock_after_session_end";
 const String kOptionPrivacyMode = "privacy_mode";
 const String kOptionTouchMode = "touch-mode";
 const String kOptionI444 = "i444";
+const String kOptionCrispText = "crisp-text";
+const String kOptionBandwidthLimit = "bandwidth-limit";
+const String kOptionAudioPreset = "audio-preset";
+const String kOptionMicRedirect = "mic-redirect";
+const String kOptionForwardGamepads = "forward-gamepads";
+const String kOptionVoiceProcessing = "voice-processing";
 const String kOptionSwapLeftRightMouse = "swap-left-right-mouse";
 const String kOptionCodecPreference = "codec-preference";
 const String kOptionRemoteMenubarDragLeft = "remote-menubar-drag-left";
@@ -220,6 +228,7 @@ const int kWindowMainId = 0;
 
 const String kPointerEventKindTouch = "touch";
 const String kPointerEventKindMouse = "mouse";
+const String kPointerEventKindPen = "pen";
 
 const String kMouseEventTypeDefault = "";
 const String kMouseEventTypePanStart = "pan_start";
@@ -227,6 +236,9 @@ const String kMouseEventTypePanUpdate = "pan_update";
 const String kMouseEventTypePanEnd = "pan_end";
 const String kMouseEventTypeDown = "down";
 const String kMouseEventTypeUp = "up";
+const String kPenEventTypeHover = "hover";
+const String kPenEventTypeMove = "move";
+const String kPenEventTypeLeave = "leave";
 
 const String kKeyFlutterKey = "flutter_key";
 
@@ -1076,6 +1076,13 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
               _OptionCheckBox(
                   context, 'Enable privacy mode', kOptionEnablePrivacyMode,
                   enabled: enabled, fakeValue: fakeValue),
+            if (isLinux)
+              _OptionCheckBox(context, 'Enable microphone redirection',
+                  kOptionAllowMicRedirect,
+                  enabled: enabled, fakeValue: fakeValue),
+            if (isLinux)
+              _OptionCheckBox(context, 'Enable gamepad', kOptionAllowGamepad,
+                  enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(context, 'Enable remote configuration modification',
                 kOptionAllowRemoteConfigModification,
                 enabled: enabled, fakeValue: fakeValue),
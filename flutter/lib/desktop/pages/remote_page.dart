@@ -560,7 +560,7 @@ class _RemotePageState extends State<RemotePage>
   void leaveView(PointerExitEvent evt) {
     _ffi.canvasModel.disableEdgeScroll();
 
-    if (_ffi.ffiModel.keyboard) {
+    if (_ffi.ffiModel.keyboard && !_ffi.inputModel.tryLeavePen(evt)) {
       _ffi.inputModel.tryMoveEdgeOnExit(evt.position);
     }
 
@@ -838,7 +838,41 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
                           },
                           translate('Enable privacy mode'),
                           canModify: canModifyPermission,
-                        )
+                        ),
+                      // only linux supports the virtual microphone
+                      if (isLinux)
+                        buildPermissionIcon(
+                          client.micRedirect,
+                          Icons.mic_rounded,
+                          (enabled) {
+                            bind.cmSwitchPermission(
+                                connId: client.id,
+                                name: "mic_redirect",
+                                enabled: enabled);
+                            setState(() {
+                              client.micRedirect = enabled;
+                            });
+                          },
+                          translate('Enable microphone redirection'),
+                          canModify: canModifyPermission,
+                        ),
+                      // only linux supports the virtual gamepads
+                      if (isLinux)
+                        buildPermissionIcon(
+                          client.gamepad,
+                          Icons.sports_esports_rounded,
+                          (enabled) {
+                            bind.cmSwitchPermission(
+                                connId: client.id,
+                                name: "gamepad",
+                                enabled: enabled);
+                            setState(() {
+                              client.gamepad = enabled;
+                            });
+                          },
+                          translate('Enable gamepad'),
+                          canModify: canModifyPermission,
+                        ),
                     ],
             ),
           ),
@@ -1442,6 +1442,16 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         scrollStyle(state, colorScheme),
         imageQuality(),
         codec(),
+        if (ffi.connType == ConnType.defaultConn) bandwidthLimit(),
+        if (ffi.connType == ConnType.defaultConn &&
+            pi.platform == kPeerPlatformLinux &&
+            perms['keyboard'] != false)
+          shareWindow(),
+        if (ffi.connType == ConnType.defaultConn) audioPreset(),
+        if (ffi.connType == ConnType.defaultConn &&
+            pi.platform == kPeerPlatformLinux &&
+            perms['audio'] != false)
+          audioApps(),
         if (ffi.connType == ConnType.defaultConn)
           _ResolutionsMenu(
             id: widget.id,
@@ -1666,6 +1676,49 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         });
   }
 
+  bandwidthLimit() {
+    return MenuButton(
+      child: Text(translate('Bandwidth limit')),
+      onPressed: () => bandwidthLimitDialog(ffi.sessionId, ffi),
+      ffi: ffi,
+    );
+  }
+
+  shareWindow() {
+    return MenuButton(
+      child: Text(translate('Share a window')),
+      onPressed: () => shareWindowDialog(ffi.sessionId, ffi),
+      ffi: ffi,
+    );
+  }
+
+  audioApps() {
+    return MenuButton(
+      child: Text(translate('Audio of applications')),
+      onPressed: () => audioAppsDialog(ffi.sessionId, ffi),
+      ffi: ffi,
+    );
+  }
+
+  audioPreset() {
+    return futureBuilder(
+        future: toolbarAudioPreset(context, id, ffi),
+        hasData: (data) {
+          final v = data as List<TRadioMenu<String>>;
+          return _SubmenuButton(
+              ffi: widget.ffi,
+              child: Text(translate('Audio preset')),
+              menuChildren: v
+                  .map((e) => RdoMenuButton(
+                      value: e.value,
+                      groupValue: e.groupValue,
+                      onChanged: e.onChanged,
+                      child: e.child,
+                      ffi: ffi))
+                  .toList());
+        });
+  }
+
   codec() {
     return futureBuilder(
         future: toolbarCodec(context, id, ffi),
@@ -2260,10 +2313,42 @@ class _KeyboardMenu extends StatelessWidget {
               Divider(),
               ...toolbarToggles(),
               ...mouseSpeed(),
+              ...keyboardMacros(),
               ...mobileActions(),
             ]);
   }
 
+  keyboardMacros() {
+    if (!isDesktop || ffi.ffiModel.viewOnly) return [];
+    final sessionId = ffi.sessionId;
+    final List<dynamic> macros =
+        jsonDecode(bind.sessionGetMacros(sessionId: sessionId));
+    return [
+      _SubmenuButton(
+        ffi: ffi,
+        child: Text(translate('Keyboard macros')).paddingOnly(left: 26.0),
+        menuChildren: [
+          ...macros.map((m) {
+            final String name = m['name'] ?? '';
+            final String hotkey = m['hotkey'] ?? '';
+            return MenuButton(
+              child: Text(hotkey.isEmpty ? name : '$name ($hotkey)'),
+              onPressed: () =>
+                  bind.sessionPlayMacro(sessionId: sessionId, name: name),
+              ffi: ffi,
+            );
+          }),
+          if (macros.isNotEmpty) Divider(),
+          MenuButton(
+            child: Text(translate('Edit')),
+            onPressed: () => keyboardMacrosDialog(sessionId, ffi),
+            ffi: ffi,
+          ),
+        ],
+      ),
+    ];
+  }
+
   mouseSpeed() {
     final speedWidgets = [];
     final sessionId = ffi.sessionId;
@@ -2598,6 +2683,31 @@ class _VoiceCallMenu extends StatelessWidget {
       return [
         audioInput,
         Divider(),
+        CkbMenuButton(
+          value: bind.sessionGetToggleOptionSync(
+              sessionId: ffi.sessionId, arg: kOptionVoiceProcessing),
+          onChanged: (value) async {
+            if (value == null) return;
+            await bind.sessionToggleOption(
+                sessionId: ffi.sessionId, value: kOptionVoiceProcessing);
+          },
+          child: Text(translate('Noise suppression and echo cancellation')),
+          ffi: ffi,
+        ),
+        if (ffi.ffiModel.pi.platform == kPeerPlatformLinux) ...[
+          CkbMenuButton(
+            value: bind.sessionGetToggleOptionSync(
+                sessionId: ffi.sessionId, arg: kOptionMicRedirect),
+            onChanged: (value) async {
+              if (value == null) return;
+              await bind.sessionToggleOption(
+                  sessionId: ffi.sessionId, value: kOptionMicRedirect);
+            },
+            child: Text(translate('Redirect microphone')),
+            ffi: ffi,
+          ),
+        ],
+        Divider(),
         MenuButton(
           child: Text(translate('End call')),
           onPressed: () => bind.sessionCloseVoiceCall(sessionId: ffi.sessionId),
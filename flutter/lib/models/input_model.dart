@@ -444,6 +444,8 @@ class InputModel {
 
   bool _pointerMovedAfterEnter = false;
   bool _pointerInsideImage = false;
+  // The pen is on the tablet of the peer, lifted when it leaves the remote image.
+  bool _penInRange = false;
 
   // mouse
   final isPhysicalMouse = false.obs;
@@ -735,6 +737,20 @@ class InputModel {
     }
   }
 
+  /// Play the keyboard macro of the key with the modifiers held, like `Ctrl+Alt+1`.
+  /// Only the combinations with Ctrl, Alt or Meta are macro hotkeys.
+  bool _playMacroHotkey(LogicalKeyboardKey key) {
+    if (!isDesktop || !(ctrl || alt || command)) return false;
+    final hotkey = [
+      if (ctrl) 'Ctrl',
+      if (alt) 'Alt',
+      if (shift) 'Shift',
+      if (command) 'Meta',
+      key.keyLabel,
+    ].join('+');
+    return bind.sessionPlayMacroHotkey(sessionId: sessionId, hotkey: hotkey);
+  }
+
   KeyEventResult handleRawKeyEvent(RawKeyEvent e) {
     if (isViewOnly) return KeyEventResult.handled;
     if (isViewCamera) return KeyEventResult.handled;
@@ -789,6 +805,10 @@ class InputModel {
       toReleaseRawKeys.updateKeyUp(key, e);
     }
 
+    if (e is RawKeyDownEvent && !e.repeat && _playMacroHotkey(key)) {
+      return KeyEventResult.handled;
+    }
+
     // On some mobile soft-keyboard paths, Flutter may leave cached Shift state
     // set even though the current raw key event is not shifted anymore.
     if (e is RawKeyDownEvent &&
@@ -861,6 +881,10 @@ class InputModel {
       handleKeyDownEventModifiers(e);
     }
 
+    if (e is KeyDownEvent && _playMacroHotkey(e.logicalKey)) {
+      return KeyEventResult.handled;
+    }
+
     bool isMobileAndMapMode = false;
     if (isMobile) {
       // Do not use map mode if mobile -> Android. Android does not support map mode for now.
@@ -1286,6 +1310,10 @@ class InputModel {
   void onPointHoverImage(PointerHoverEvent e) {
     _stopFling = true;
     if (isViewOnly && !showMyCursor) return;
+    if (_isPenToPeer(e)) {
+      _handlePen(e, kPenEventTypeHover, isMove: true);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
 
     // May fix https://github.com/rustdesk/rustdesk/issues/13009
@@ -1517,6 +1545,10 @@ class InputModel {
     _windowRect = null;
     if (isViewOnly && !showMyCursor) return;
     if (isViewCamera) return;
+    if (_isPenToPeer(e)) {
+      _handlePen(e, kMouseEventTypeDown);
+      return;
+    }
 
     // Track mouse down events for duplicate detection on iOS.
     final nowMs = DateTime.now().millisecondsSinceEpoch;
@@ -1557,6 +1589,10 @@ class InputModel {
     if (isDesktop) _queryOtherWindowCoords = false;
     if (isViewOnly && !showMyCursor) return;
     if (isViewCamera) return;
+    if (_isPenToPeer(e)) {
+      _handlePen(e, kMouseEventTypeUp);
+      return;
+    }
 
     if (_relativeMouse.enabled.value) {
       _relativeMouse.updatePointerRegionTopLeftGlobal(e);
@@ -1578,6 +1614,10 @@ class InputModel {
   void onPointMoveImage(PointerMoveEvent e) {
     if (isViewOnly && !showMyCursor) return;
     if (isViewCamera) return;
+    if (_isPenToPeer(e)) {
+      _handlePen(e, kPenEventTypeMove, isMove: true);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
 
     if (_relativeMouse.enabled.value) {
@@ -1755,6 +1795,56 @@ class InputModel {
         sessionId: sessionId, msg: json.encode(modify(evt)));
   }
 
+  // The pens are drawn with their pressure and tilt on a virtual tablet of the Linux peers.
+  bool _isPenToPeer(PointerEvent e) =>
+      (e.kind == ui.PointerDeviceKind.stylus ||
+          e.kind == ui.PointerDeviceKind.invertedStylus) &&
+      peerPlatform == kPeerPlatformLinux;
+
+  void _handlePen(PointerEvent e, String type, {bool isMove = false}) {
+    if (isViewOnly) return;
+    double x = e.position.dx;
+    double y = max(0.0, e.position.dy);
+    if (_checkPeerControlProtected(x, y)) {
+      return;
+    }
+    final pos = handlePointerDevicePos(
+      kPointerEventKindPen,
+      x,
+      y,
+      isMove,
+      type,
+      onExit: type == kPenEventTypeLeave,
+    );
+    if (pos == null) {
+      return;
+    }
+    final pressureRange = e.pressureMax - e.pressureMin;
+    final evt = PointerEventToRust(kPointerEventKindPen, type, {
+      'x': pos.x.toInt(),
+      'y': pos.y.toInt(),
+      'down': type != kMouseEventTypeUp && (e.buttons & kPrimaryButton) != 0,
+      'eraser': e.kind == ui.PointerDeviceKind.invertedStylus,
+      'buttons': ((e.buttons & kPrimaryStylusButton) != 0 ? 1 : 0) |
+          ((e.buttons & kSecondaryStylusButton) != 0 ? 2 : 0),
+      'pressure': pressureRange > 0
+          ? (e.pressure - e.pressureMin) / pressureRange
+          : e.pressure,
+      'tilt': e.tilt,
+      'orientation': e.orientation,
+    }).toJson();
+    _penInRange = type != kPenEventTypeLeave;
+    bind.sessionSendPointer(
+        sessionId: sessionId, msg: json.encode(modify(evt)));
+  }
+
+  /// Lift the pen off the tablet of the peer if it's the one leaving the remote image.
+  bool tryLeavePen(PointerExitEvent e) {
+    if (!_penInRange || !_isPenToPeer(e)) return false;
+    _handlePen(e, kPenEventTypeLeave);
+    return true;
+  }
+
   bool _checkPeerControlProtected(double x, double y) {
     final cursorModel = parent.target!.cursorModel;
     if (cursorModel.isPeerControlProtected) {
@@ -133,6 +133,14 @@ class FfiModel with ChangeNotifier {
 
   Timer? timerScreenshot;
 
+  // The windows of the peer to share as an extra display, and the one picked.
+  final sharedWindows = <Map<String, dynamic>>[].obs;
+  final sharedWindow = 0.obs;
+
+  // The applications playing audio on the peer, and the ones streamed alone.
+  final audioApps = <String>[].obs;
+  final selectedAudioApps = <String>[].obs;
+
   Rect? get rect => _rect;
   bool get isOriginalResolutionSet =>
       _pi.tryGetDisplayIfNotAllDisplay()?.isOriginalResolutionSet ?? false;
@@ -213,6 +221,17 @@ class FfiModel with ChangeNotifier {
     }
   }
 
+  updateSharedWindows(Map<String, dynamic> evt) {
+    sharedWindows.value =
+        List<Map<String, dynamic>>.from(evt['windows'] ?? []);
+    sharedWindow.value = evt['selected'] ?? 0;
+  }
+
+  updateAudioApps(Map<String, dynamic> evt) {
+    audioApps.value = List<String>.from(evt['apps'] ?? []);
+    selectedAudioApps.value = List<String>.from(evt['selected'] ?? []);
+  }
+
   updatePermission(Map<String, dynamic> evt, String id) {
     // Track previous keyboard permission to detect revocation.
     final hadKeyboardPerm = _permissions['keyboard'] != false;
@@ -463,6 +482,10 @@ class FfiModel with ChangeNotifier {
         if (isWeb) {
           parent.target?.fileModel.sendEmptyDirs(evt);
         }
+      } else if (name == "shared_windows") {
+        parent.target?.ffiModel.updateSharedWindows(evt);
+      } else if (name == "audio_apps") {
+        parent.target?.ffiModel.updateAudioApps(evt);
       } else if (name == "record_status") {
         if (desktopType == DesktopType.remote ||
             desktopType == DesktopType.viewCamera ||
@@ -1937,12 +1960,14 @@ class ImageModel with ChangeNotifier {
     final preValue = _useTextureRender;
     _useTextureRender = isDesktop && bind.mainGetUseTextureRender();
     if (preValue != _useTextureRender) {
+      parent.target?.canvasModel._updateViewports();
       notifyListeners();
     }
   }
 
   setUseTextureRender(bool value) {
     _useTextureRender = value;
+    parent.target?.canvasModel._updateViewports();
     notifyListeners();
   }
 
@@ -2162,6 +2187,8 @@ class CanvasModel with ChangeNotifier {
   // to avoid hammering a non-functional Bump Mouse
   bool _bumpMouseIsWorking = true;
   ViewStyle _lastViewStyle = ViewStyle.defaultViewStyle();
+  // the sizes in physical pixels the displays are shown at, told to the peer
+  final Map<int, Size> _viewports = {};
 
   Timer? _timerMobileFocusCanvasCursor;
   Timer? _timerMobileRestoreCanvasOffset;
@@ -2320,6 +2347,7 @@ class CanvasModel with ChangeNotifier {
       }
     }
     _resetCanvasOffset(displayWidth, displayHeight);
+    _updateViewports();
     final overflow = _x < 0 || y < 0;
     if (_imageOverflow.value != overflow) {
       _imageOverflow.value = overflow;
@@ -2333,6 +2361,37 @@ class CanvasModel with ChangeNotifier {
     tryUpdateScrollStyle(Duration.zero, style);
   }
 
+  // The peer may downscale the video to the size the displays are shown at, the textures
+  // are stretched to the displays. The other renders draw the image as is, they ask for the
+  // full size.
+  _updateViewports() {
+    final ffi = parent.target;
+    if (ffi == null || !isDesktop) return;
+    final pi = ffi.ffiModel.pi;
+    final textureRender =
+        ffi.imageModel.useTextureRender || pi.forceTextureRender;
+    final displays = pi.getCurDisplays();
+    for (var i = 0; i < displays.length; i++) {
+      final display =
+          pi.currentDisplay == kAllDisplayValue ? i : pi.currentDisplay;
+      final s = ffi.ffiModel.isPeerLinux ? _scale / displays[i].scale : _scale;
+      final viewport = textureRender
+          ? Size((displays[i].width * s * _devicePixelRatio).roundToDouble(),
+              (displays[i].height * s * _devicePixelRatio).roundToDouble())
+          : Size.zero;
+      if (_viewports[display] == viewport ||
+          (_viewports[display] == null && viewport == Size.zero)) {
+        continue;
+      }
+      _viewports[display] = viewport;
+      bind.sessionSetViewport(
+          sessionId: sessionId,
+          display: display,
+          width: viewport.width.toInt(),
+          height: viewport.height.toInt());
+    }
+  }
+
   _resetCanvasOffset(int displayWidth, int displayHeight) {
     _x = (size.width - displayWidth * _scale) / 2;
     _y = (size.height - displayHeight * _scale) / 2;
@@ -2653,6 +2712,7 @@ class CanvasModel with ChangeNotifier {
     _y = 0;
     _scale = 1.0;
     _lastViewStyle = ViewStyle.defaultViewStyle();
+    _viewports.clear();
     _timerMobileFocusCanvasCursor?.cancel();
     _timerMobileRestoreCanvasOffset?.cancel();
     _offsetBeforeMobileSoftKeyboard = null;
@@ -3477,6 +3537,7 @@ class QualityMonitorData {
   String? targetBitrate;
   String? codecFormat;
   String? chroma;
+  String? bandwidthLimit;
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -3539,6 +3600,11 @@ class QualityMonitorModel with ChangeNotifier {
       if (evt.containsKey('chroma') && (evt['chroma'] as String).isNotEmpty) {
         _data.chroma = evt['chroma'];
       }
+      if (evt.containsKey('bandwidth_limit') &&
+          (evt['bandwidth_limit'] as String).isNotEmpty) {
+        final limit = evt['bandwidth_limit'] as String;
+        _data.bandwidthLimit = limit == '0' ? null : limit;
+      }
       notifyListeners();
     } catch (e) {
       //
@@ -828,6 +828,8 @@ class Client {
   bool recording = false;
   bool blockInput = false;
   bool privacyMode = false;
+  bool micRedirect = false;
+  bool gamepad = false;
   bool disconnected = false;
   bool fromSwitch = false;
   bool inVoiceCall = false;
@@ -857,6 +859,8 @@ class Client {
     recording = json['recording'];
     blockInput = json['block_input'];
     privacyMode = json['privacy_mode'] ?? privacyMode;
+    micRedirect = json['mic_redirect'] ?? micRedirect;
+    gamepad = json['gamepad'] ?? gamepad;
     disconnected = json['disconnected'];
     fromSwitch = json['from_switch'];
     inVoiceCall = json['in_voice_call'];
@@ -882,6 +886,8 @@ class Client {
     data['recording'] = recording;
     data['block_input'] = blockInput;
     data['privacy_mode'] = privacyMode;
+    data['mic_redirect'] = micRedirect;
+    data['gamepad'] = gamepad;
     data['disconnected'] = disconnected;
     data['from_switch'] = fromSwitch;
     data['in_voice_call'] = inVoiceCall;
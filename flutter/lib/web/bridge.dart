@@ -269,6 +269,66 @@ class RustdeskImpl {
         ]));
   }
 
+  Future<void> sessionSetBandwidthLimit(
+      {required UuidValue sessionId, required int kbps, dynamic hint}) {
+    return Future.value();
+  }
+
+  Future<void> sessionQuerySharedWindows(
+      {required UuidValue sessionId, dynamic hint}) {
+    return Future.value();
+  }
+
+  Future<void> sessionShareWindow(
+      {required UuidValue sessionId, required int id, dynamic hint}) {
+    return Future.value();
+  }
+
+  Future<void> sessionSetAudioPreset(
+      {required UuidValue sessionId, required String value, dynamic hint}) {
+    return Future.value();
+  }
+
+  Future<void> sessionQueryAudioApps(
+      {required UuidValue sessionId, dynamic hint}) {
+    return Future.value();
+  }
+
+  Future<void> sessionSetAudioApps(
+      {required UuidValue sessionId,
+      required List<String> selected,
+      dynamic hint}) {
+    return Future.value();
+  }
+
+  String sessionGetMacros({required UuidValue sessionId, dynamic hint}) {
+    return '[]';
+  }
+
+  String sessionSetMacros(
+      {required UuidValue sessionId, required String macros, dynamic hint}) {
+    return '';
+  }
+
+  Future<void> sessionPlayMacro(
+      {required UuidValue sessionId, required String name, dynamic hint}) {
+    return Future.value();
+  }
+
+  bool sessionPlayMacroHotkey(
+      {required UuidValue sessionId, required String hotkey, dynamic hint}) {
+    return false;
+  }
+
+  Future<void> sessionSetViewport(
+      {required UuidValue sessionId,
+      required int display,
+      required int width,
+      required int height,
+      dynamic hint}) {
+    return Future.value();
+  }
+
   Future<int?> sessionGetTrackpadSpeed(
       {required UuidValue sessionId, dynamic hint}) {
     throw UnimplementedError("sessionGetTrackpadSpeed");
@@ -380,6 +440,10 @@ class RustdeskImpl {
     return false;
   }
 
+  bool sessionSupportCrispText({required UuidValue sessionId, dynamic hint}) {
+    return false;
+  }
+
   Future<void> sessionSetCustomImageQuality(
       {required UuidValue sessionId, required int value, dynamic hint}) {
     return Future(() => js.context.callMethod('setByName', [
@@ -1,6 +1,7 @@
-use crate::{Key, KeyboardControllable};
+use crate::{Key, KeyboardControllable, MouseButton};
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
 /// An error that can occur when parsing DSL
 #[derive(Debug, PartialEq, Eq)]
@@ -24,6 +25,16 @@ pub enum ParseError {
     /// Example: +SHIFT}Hello{-SHIFT}
     ///         ^
     UnmatchedClose,
+
+    /// When the argument of a tag is missing or not valid.
+    /// Example: {DELAY soon}
+    ///                 ^^^^
+    InvalidArgument(String),
+
+    /// When a {+REPEAT n} is not matched with a {-REPEAT}, or the opposite.
+    /// Example: {+REPEAT 3}Hello
+    ///          ^^^^^^^^^^^
+    UnmatchedRepeat,
 }
 impl Error for ParseError {
     fn description(&self) -> &str {
@@ -32,6 +43,8 @@ impl Error for ParseError {
             ParseError::UnexpectedOpen => "Unescaped open bracket ({) found inside tag name",
             ParseError::UnmatchedOpen => "Unmatched open bracket ({). No matching close (})",
             ParseError::UnmatchedClose => "Unmatched close bracket (}). No previous open ({)",
+            ParseError::InvalidArgument(_) => "Invalid argument of tag",
+            ParseError::UnmatchedRepeat => "Unmatched repeat. No matching {+REPEAT n} or {-REPEAT}",
         }
     }
 }
@@ -42,37 +55,124 @@ impl fmt::Display for ParseError {
 }
 
 /// Evaluate the DSL. This tokenizes the input and presses the keys.
+///
+/// The mouse tags are skipped, only the keyboard is controlled.
 pub fn eval<K>(enigo: &mut K, input: &str) -> Result<(), ParseError>
 where
     K: KeyboardControllable,
 {
-    for token in tokenize(input)? {
+    eval_tokens(enigo, &tokenize(input)?);
+    Ok(())
+}
+
+fn eval_tokens<K>(enigo: &mut K, tokens: &[Token])
+where
+    K: KeyboardControllable,
+{
+    for token in tokens {
         match token {
             Token::Sequence(buffer) => {
                 for key in buffer.chars() {
                     enigo.key_click(Key::Layout(key));
                 }
             }
-            Token::Unicode(buffer) => enigo.key_sequence(&buffer),
-            Token::KeyUp(key) => enigo.key_up(key),
-            Token::KeyDown(key) => enigo.key_down(key).unwrap_or(()),
+            Token::Unicode(buffer) => enigo.key_sequence(buffer),
+            Token::KeyUp(key) => enigo.key_up(*key),
+            Token::KeyDown(key) => enigo.key_down(*key).unwrap_or(()),
+            Token::KeyClick(key) => enigo.key_click(*key),
+            Token::Delay(ms) => std::thread::sleep(Duration::from_millis(*ms)),
+            Token::Repeat(n, tokens) => {
+                for _ in 0..*n {
+                    eval_tokens(enigo, tokens);
+                }
+            }
+            Token::Click(_) | Token::MoveTo(..) => {}
         }
     }
-    Ok(())
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Token {
+/// A step of the DSL.
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    /// Characters typed with the keys of the layout, `Hello`.
     Sequence(String),
+    /// Text typed as unicode, `{+UNICODE}❤️{-UNICODE}`.
     Unicode(String),
+    /// `{-SHIFT}`
     KeyUp(Key),
+    /// `{+SHIFT}`
     KeyDown(Key),
+    /// `{ENTER}`
+    KeyClick(Key),
+    /// Milliseconds to wait, `{DELAY 100}`.
+    Delay(u64),
+    /// `{CLICK LEFT}`, at the current position of the mouse.
+    Click(MouseButton),
+    /// `{MOVE 100 200}`
+    MoveTo(i32, i32),
+    /// The tokens between `{+REPEAT 3}` and `{-REPEAT}`, repeated.
+    Repeat(u32, Vec<Token>),
 }
 
-fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+/// The key of a tag, `SHIFT` of `{+SHIFT}`.
+fn named_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "SHIFT" => Key::Shift,
+        "CTRL" => Key::Control,
+        "META" => Key::Meta,
+        "ALT" => Key::Alt,
+        "ENTER" | "RETURN" => Key::Return,
+        "TAB" => Key::Tab,
+        "ESC" | "ESCAPE" => Key::Escape,
+        "BACKSPACE" => Key::Backspace,
+        "DELETE" | "DEL" => Key::Delete,
+        "INSERT" => Key::Insert,
+        "SPACE" => Key::Space,
+        "HOME" => Key::Home,
+        "END" => Key::End,
+        "PAGEUP" => Key::PageUp,
+        "PAGEDOWN" => Key::PageDown,
+        "UP" => Key::UpArrow,
+        "DOWN" => Key::DownArrow,
+        "LEFT" => Key::LeftArrow,
+        "RIGHT" => Key::RightArrow,
+        "CAPSLOCK" => Key::CapsLock,
+        "F1" => Key::F1,
+        "F2" => Key::F2,
+        "F3" => Key::F3,
+        "F4" => Key::F4,
+        "F5" => Key::F5,
+        "F6" => Key::F6,
+        "F7" => Key::F7,
+        "F8" => Key::F8,
+        "F9" => Key::F9,
+        "F10" => Key::F10,
+        "F11" => Key::F11,
+        "F12" => Key::F12,
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: &str) -> Result<T, ParseError> {
+    arg.parse()
+        .map_err(|_| ParseError::InvalidArgument(arg.to_owned()))
+}
+
+/// Tokenize the DSL, the tags are:
+///
+/// - `{+KEY}`, `{-KEY}` and `{KEY}` to press, release and click a key, like `SHIFT`, `CTRL`,
+///   `ENTER` or `F5`
+/// - `{+UNICODE}` and `{-UNICODE}` around text typed as unicode
+/// - `{DELAY ms}`, `{CLICK LEFT|RIGHT|MIDDLE}` and `{MOVE x y}`
+/// - `{+REPEAT n}` and `{-REPEAT}` around the tokens to repeat
+/// - `{{` and `}}` for the brackets
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
     let mut unicode = false;
 
     let mut tokens = Vec::new();
+    // The count of each repeat opened and the tokens before it
+    let mut repeats: Vec<(u32, Vec<Token>)> = Vec::new();
     let mut buffer = String::new();
     let mut iter = input.chars().peekable();
 
@@ -116,17 +216,43 @@ fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
                             None => return Err(ParseError::UnmatchedOpen),
                         }
                     }
-                    match &*tag {
-                        "+UNICODE" => unicode = true,
-                        "-UNICODE" => unicode = false,
-                        "+SHIFT" => tokens.push(Token::KeyDown(Key::Shift)),
-                        "-SHIFT" => tokens.push(Token::KeyUp(Key::Shift)),
-                        "+CTRL" => tokens.push(Token::KeyDown(Key::Control)),
-                        "-CTRL" => tokens.push(Token::KeyUp(Key::Control)),
-                        "+META" => tokens.push(Token::KeyDown(Key::Meta)),
-                        "-META" => tokens.push(Token::KeyUp(Key::Meta)),
-                        "+ALT" => tokens.push(Token::KeyDown(Key::Alt)),
-                        "-ALT" => tokens.push(Token::KeyUp(Key::Alt)),
+                    let mut words = tag.split_whitespace();
+                    let name = words.next().unwrap_or_default();
+                    match (name, &words.collect::<Vec<_>>()[..]) {
+                        ("+UNICODE", []) => unicode = true,
+                        ("-UNICODE", []) => unicode = false,
+                        ("DELAY", [ms]) => tokens.push(Token::Delay(parse_arg(ms)?)),
+                        ("CLICK", [button]) => tokens.push(Token::Click(match *button {
+                            "LEFT" => MouseButton::Left,
+                            "RIGHT" => MouseButton::Right,
+                            "MIDDLE" => MouseButton::Middle,
+                            _ => return Err(ParseError::InvalidArgument(button.to_string())),
+                        })),
+                        ("MOVE", [x, y]) => {
+                            tokens.push(Token::MoveTo(parse_arg(x)?, parse_arg(y)?))
+                        }
+                        ("+REPEAT", [n]) => {
+                            repeats.push((parse_arg(n)?, std::mem::take(&mut tokens)))
+                        }
+                        ("-REPEAT", []) => {
+                            let (n, outer) = repeats.pop().ok_or(ParseError::UnmatchedRepeat)?;
+                            let inner = std::mem::replace(&mut tokens, outer);
+                            tokens.push(Token::Repeat(n, inner));
+                        }
+                        (name, []) => {
+                            let token = if let Some(key) =
+                                name.strip_prefix('+').and_then(named_key)
+                            {
+                                Token::KeyDown(key)
+                            } else if let Some(key) = name.strip_prefix('-').and_then(named_key) {
+                                Token::KeyUp(key)
+                            } else if let Some(key) = named_key(name) {
+                                Token::KeyClick(key)
+                            } else {
+                                return Err(ParseError::UnknownTag(tag));
+                            };
+                            tokens.push(token);
+                        }
                         _ => return Err(ParseError::UnknownTag(tag)),
                     }
                 }
@@ -143,6 +269,9 @@ fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
     }
 
     flush(&mut tokens, buffer, unicode);
+    if !repeats.is_empty() {
+        return Err(ParseError::UnmatchedRepeat);
+    }
 
     Ok(tokens)
 }
@@ -164,6 +293,23 @@ mod tests {
         );
     }
     #[test]
+    fn macro_tags() {
+        assert_eq!(
+            tokenize("{+REPEAT 2}{CLICK LEFT}{DELAY 50}{-REPEAT}{+UNICODE}é{-UNICODE}{ENTER}"),
+            Ok(vec![
+                Token::Repeat(2, vec![Token::Click(MouseButton::Left), Token::Delay(50)]),
+                Token::Unicode("é".into()),
+                Token::KeyClick(Key::Return),
+            ])
+        );
+        assert_eq!(
+            tokenize("{DELAY soon}"),
+            Err(ParseError::InvalidArgument("soon".into()))
+        );
+        assert_eq!(tokenize("{+REPEAT 2}hi"), Err(ParseError::UnmatchedRepeat));
+        assert_eq!(tokenize("hi{-REPEAT}"), Err(ParseError::UnmatchedRepeat));
+    }
+    #[test]
     fn unexpected_open() {
         assert_eq!(tokenize("{hello{}world}"), Err(ParseError::UnexpectedOpen));
     }
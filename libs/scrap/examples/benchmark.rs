@@ -6,7 +6,8 @@ use hbb_common::{
 use scrap::{
     aom::{AomDecoder, AomEncoder, AomEncoderConfig},
     codec::{EncoderApi, EncoderCfg},
-    Capturer, Display, TraitCapturer, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig,
+    Capturer, Display, EncodeYuvFormat, Pixfmt, TraitCapturer, VpxDecoder, VpxDecoderConfig,
+    VpxEncoder, VpxEncoderConfig,
     VpxVideoCodecId::{self, *},
     STRIDE_ALIGN,
 };
@@ -62,6 +63,7 @@ fn main() {
         "benchmark {}x{} quality:{:?}, i444:{:?}",
         width, height, quality, args.flag_i444
     );
+    test_convert(&mut c, width, height, yuv_count);
     [VP8, VP9].map(|codec| {
         test_vpx(
             &mut c,
@@ -80,6 +82,59 @@ fn main() {
     }
 }
 
+fn test_convert(c: &mut Capturer, width: usize, height: usize, count: usize) {
+    let vp9_yuvfmt = |i444| {
+        let config = EncoderCfg::VPX(VpxEncoderConfig {
+            width: width as _,
+            height: height as _,
+            quality: 1.0,
+            codec: VP9,
+            keyframe_interval: None,
+        });
+        VpxEncoder::new(config, i444).unwrap().yuvfmt()
+    };
+    let align = |x: usize| (x + 63) / 64 * 64;
+    let nv12 = EncodeYuvFormat {
+        pixfmt: Pixfmt::NV12,
+        w: width,
+        h: height,
+        stride: vec![align(width), align(width)],
+        u: align(width) * align(height),
+        v: 0,
+    };
+    let yuvfmts = [vp9_yuvfmt(false), nv12, vp9_yuvfmt(true)];
+    loop {
+        match c.frame(std::time::Duration::from_millis(30)) {
+            Ok(frame) => {
+                let (mut yuv, mut mid_data) = (Vec::new(), Vec::new());
+                for yuvfmt in yuvfmts.iter() {
+                    for parallel in [false, true] {
+                        scrap::convert::set_parallel(parallel);
+                        let start = Instant::now();
+                        for _ in 0..count {
+                            frame.to(yuvfmt.clone(), &mut yuv, &mut mid_data).unwrap();
+                        }
+                        let elapsed = start.elapsed();
+                        let mpixels = (width * height * count) as f64 / elapsed.as_secs_f64() / 1e6;
+                        println!(
+                            "convert to {:?}, {}: {:?}, {:.0} Mpixel/s",
+                            yuvfmt.pixfmt,
+                            if parallel { "parallel" } else { "single" },
+                            elapsed / count as _,
+                            mpixels
+                        );
+                    }
+                }
+                scrap::convert::set_parallel(true);
+                break;
+            }
+            Err(e) => {
+                log::error!("{e:?}");
+            }
+        }
+    }
+}
+
 fn test_vpx(
     c: &mut Capturer,
     codec_id: VpxVideoCodecId,
@@ -173,6 +228,7 @@ fn test_av1(
         height: height as _,
         quality,
         keyframe_interval: None,
+        crisp_text: false,
     });
     let mut encoder = AomEncoder::new(config, i444).unwrap();
     let start = Instant::now();
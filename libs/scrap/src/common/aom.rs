@@ -6,8 +6,8 @@
 
 include!(concat!(env!("OUT_DIR"), "/aom_ffi.rs"));
 
-use crate::codec::{base_bitrate, codec_thread_num};
-use crate::{codec::EncoderApi, EncodeFrame, STRIDE_ALIGN};
+use crate::codec::{base_bitrate, codec_thread_num, BR_BALANCED};
+use crate::{codec::EncoderApi, damage::Damage, EncodeFrame, STRIDE_ALIGN};
 use crate::{common::GoogleImage, generate_call_macro, generate_call_ptr_macro, Error, Result};
 use crate::{EncodeInput, EncodeYuvFormat, Pixfmt};
 use hbb_common::{
@@ -47,6 +47,8 @@ pub struct AomEncoderConfig {
     pub height: u32,
     pub quality: f32,
     pub keyframe_interval: Option<usize>,
+    /// Screen content tools, and lossless refinement of static text, see `TextQuantizer`.
+    pub crisp_text: bool,
 }
 
 pub struct AomEncoder {
@@ -55,6 +57,39 @@ pub struct AomEncoder {
     height: usize,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    crisp_text: bool,
+    ratio: f32,
+    text_quantizer: TextQuantizer,
+    force_keyframe: bool,
+}
+
+// Changes above this share of the screen are motion, e.g. scrolling or video.
+const TEXT_MOTION_RATIO: f32 = 0.05;
+// Refining more than this share of the screen losslessly makes too large frames.
+const TEXT_LOSSLESS_RATIO: f32 = 0.25;
+const TEXT_NEAR_LOSSLESS_Q_MAX: u32 = 8;
+
+/// The quantizers of the crisp text mode. The tiles changed recently are refined lossless,
+/// or near lossless if they are many, and everything is lossy at the quality ratio under
+/// motion or below `BR_BALANCED`. The static tiles are skipped by the active map, so they
+/// keep their quality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextQuantizer {
+    Lossy,
+    NearLossless,
+    Lossless,
+}
+
+impl TextQuantizer {
+    fn new(damage: &Damage) -> Self {
+        if damage.changed_ratio() > TEXT_MOTION_RATIO {
+            Self::Lossy
+        } else if damage.active_ratio() > TEXT_LOSSLESS_RATIO {
+            Self::NearLossless
+        } else {
+            Self::Lossless
+        }
+    }
 }
 
 // https://webrtc.googlesource.com/src/+/refs/heads/main/modules/video_coding/codecs/av1/libaom_av1_encoder.cc
@@ -132,7 +167,11 @@ mod webrtc {
         Ok(c)
     }
 
-    pub fn set_controls(ctx: *mut aom_codec_ctx_t, cfg: &aom_codec_enc_cfg) -> ResultType<()> {
+    pub fn set_controls(
+        ctx: *mut aom_codec_ctx_t,
+        cfg: &aom_codec_enc_cfg,
+        crisp_text: bool,
+    ) -> ResultType<()> {
         use aom_tune_content::*;
         use aome_enc_control_id::*;
         macro_rules! call_ctl {
@@ -185,7 +224,8 @@ mod webrtc {
         call_ctl!(ctx, AV1E_SET_ENABLE_INTERINTRA_COMP, 0);
         call_ctl!(ctx, AV1E_SET_ENABLE_INTERINTRA_WEDGE, 0);
         call_ctl!(ctx, AV1E_SET_ENABLE_INTRA_EDGE_FILTER, 0);
-        call_ctl!(ctx, AV1E_SET_ENABLE_INTRABC, 0);
+        // intra block copy of repeated glyphs, keyframes only
+        call_ctl!(ctx, AV1E_SET_ENABLE_INTRABC, crisp_text as i32);
         call_ctl!(ctx, AV1E_SET_ENABLE_MASKED_COMP, 0);
         call_ctl!(ctx, AV1E_SET_ENABLE_PAETH_INTRA, 0);
         call_ctl!(ctx, AV1E_SET_ENABLE_QM, 0);
@@ -219,13 +259,17 @@ impl EncoderApi for AomEncoder {
                     flags,
                     AOM_ENCODER_ABI_VERSION as _
                 ));
-                webrtc::set_controls(&mut ctx, &c)?;
+                webrtc::set_controls(&mut ctx, &c, config.crisp_text)?;
                 Ok(Self {
                     ctx,
                     width: config.width as _,
                     height: config.height as _,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    crisp_text: config.crisp_text,
+                    ratio: config.quality,
+                    text_quantizer: TextQuantizer::Lossy,
+                    force_keyframe: false,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -257,8 +301,9 @@ impl EncoderApi for AomEncoder {
     }
 
     fn set_quality(&mut self, ratio: f32) -> ResultType<()> {
+        self.ratio = ratio;
         let mut c = unsafe { *self.ctx.config.enc.to_owned() };
-        let (q_min, q_max) = Self::calc_q_values(ratio);
+        let (q_min, q_max) = self.q_values();
         c.rc_min_quantizer = q_min;
         c.rc_max_quantizer = q_max;
         c.rc_target_bitrate = Self::bitrate(self.width as _, self.height as _, ratio);
@@ -284,6 +329,40 @@ impl EncoderApi for AomEncoder {
     }
 
     fn disable(&self) {}
+
+    fn request_keyframe(&mut self) -> bool {
+        self.force_keyframe = true;
+        true
+    }
+
+    fn support_damage(&self) -> bool {
+        true
+    }
+
+    fn set_damage(&mut self, damage: &Damage) {
+        // AV1 takes the active map in 16x16 blocks, a null map codes them all
+        let mut map = damage.active_map(self.width, self.height, 16);
+        let mut active_map = aom_active_map_t {
+            active_map: map.as_mut().map_or(ptr::null_mut(), |m| m.as_mut_ptr()),
+            rows: self.height.div_ceil(16) as _,
+            cols: self.width.div_ceil(16) as _,
+        };
+        let ret = unsafe {
+            aom_codec_control(
+                &mut self.ctx,
+                AOME_SET_ACTIVEMAP as _,
+                &mut active_map as *mut aom_active_map_t,
+            )
+        };
+        if ret != aom_codec_err_t::AOM_CODEC_OK {
+            log::debug!("Failed to set active map: {ret:?}");
+        }
+        if self.crisp_text {
+            if let Err(e) = self.set_text_quantizer(TextQuantizer::new(damage)) {
+                log::debug!("Failed to set text quantizer: {e}");
+            }
+        }
+    }
 }
 
 impl AomEncoder {
@@ -309,12 +388,17 @@ impl AomEncoder {
         ));
         let pts = webrtc::kTimeBaseDen / 1000 * ms;
         let duration = webrtc::kTimeBaseDen / 1000;
+        let flags = if std::mem::take(&mut self.force_keyframe) {
+            AOM_EFLAG_FORCE_KF
+        } else {
+            0
+        };
         call_aom!(aom_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             duration as _, // Duration
-            0,             // Flags
+            flags as _,
         ));
 
         Ok(EncodeFrames {
@@ -350,6 +434,32 @@ impl AomEncoder {
     }
 
     #[inline]
+    fn q_values(&self) -> (u32, u32) {
+        // Below the default quality the bandwidth is short, the refinement would take it all.
+        if self.ratio < BR_BALANCED {
+            return Self::calc_q_values(self.ratio);
+        }
+        match self.text_quantizer {
+            TextQuantizer::Lossy => Self::calc_q_values(self.ratio),
+            TextQuantizer::NearLossless => (0, TEXT_NEAR_LOSSLESS_Q_MAX),
+            // libaom codes lossless if both are 0
+            TextQuantizer::Lossless => (0, 0),
+        }
+    }
+
+    fn set_text_quantizer(&mut self, text_quantizer: TextQuantizer) -> ResultType<()> {
+        if text_quantizer == self.text_quantizer {
+            return Ok(());
+        }
+        self.text_quantizer = text_quantizer;
+        let mut c = unsafe { *self.ctx.config.enc.to_owned() };
+        let (q_min, q_max) = self.q_values();
+        c.rc_min_quantizer = q_min;
+        c.rc_max_quantizer = q_max;
+        call_aom!(aom_codec_enc_config_set(&mut self.ctx, &c));
+        Ok(())
+    }
+
     fn calc_q_values(ratio: f32) -> (u32, u32) {
         let b = (ratio * 100.0) as u32;
         let b = std::cmp::min(b, 200);
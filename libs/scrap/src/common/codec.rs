@@ -9,11 +9,14 @@ use std::{
 use crate::hwcodec::*;
 #[cfg(feature = "mediacodec")]
 use crate::mediacodec::{MediaCodecDecoder, H264_DECODER_SUPPORT, H265_DECODER_SUPPORT};
+#[cfg(feature = "openh264")]
+use crate::openh264::{SwH264Decoder, SwH264Encoder, SwH264EncoderConfig};
 #[cfg(feature = "vram")]
 use crate::vram::*;
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
+    damage::Damage,
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, EncodeInput, EncodeYuvFormat, ImageRgb, ImageTexture,
 };
@@ -46,6 +49,7 @@ lazy_static::lazy_static! {
 }
 
 pub const ENCODE_NEED_SWITCH: &'static str = "ENCODE_NEED_SWITCH";
+pub const OPTION_KEYFRAME_INTERVAL: &str = "keyframe-interval";
 
 #[derive(Debug, Clone)]
 pub enum EncoderCfg {
@@ -55,6 +59,8 @@ pub enum EncoderCfg {
     HWRAM(HwRamEncoderConfig),
     #[cfg(feature = "vram")]
     VRAM(VRamEncoderConfig),
+    #[cfg(feature = "openh264")]
+    SWH264(SwH264EncoderConfig),
 }
 
 pub trait EncoderApi {
@@ -80,6 +86,22 @@ pub trait EncoderApi {
     fn is_hardware(&self) -> bool;
 
     fn disable(&self);
+
+    /// Whether `set_damage` is used, the damage is not worth tracking otherwise.
+    fn support_damage(&self) -> bool {
+        false
+    }
+
+    /// Hint the encoder to code only the active tiles of the next frame.
+    fn set_damage(&mut self, _damage: &Damage) {}
+
+    /// Follow the capture rate, for the encoders whose rate control depends on it.
+    fn set_fps(&mut self, _fps: u32) {}
+
+    /// Make the next frame a keyframe, `false` if the encoder has to be recreated for it.
+    fn request_keyframe(&mut self) -> bool {
+        false
+    }
 }
 
 pub struct Encoder {
@@ -116,6 +138,8 @@ pub struct Decoder {
     h264_media_codec: MediaCodecDecoder,
     #[cfg(feature = "mediacodec")]
     h265_media_codec: MediaCodecDecoder,
+    #[cfg(feature = "openh264")]
+    h264_sw: Option<SwH264Decoder>,
     format: CodecFormat,
     valid: bool,
     #[cfg(feature = "hwcodec")]
@@ -165,6 +189,10 @@ impl Encoder {
                     Err(e)
                 }
             },
+            #[cfg(feature = "openh264")]
+            EncoderCfg::SWH264(_) => Ok(Encoder {
+                codec: Box::new(SwH264Encoder::new(config, i444)?),
+            }),
         }
     }
 
@@ -230,8 +258,11 @@ impl Encoder {
                     HwRamEncoder::try_get(CodecFormat::H265).map_or(None, |c| Some(c.name));
             }
         }
-        let h264_useable =
+        // Software H.264 is used only if asked for, it is slower than VP9 with the same quality
+        let h264sw_encoding = cfg!(feature = "openh264") && _all_support_h264_decoding;
+        let h264hw_useable =
             _all_support_h264_decoding && (h264vram_encoding || h264hw_encoding.is_some());
+        let h264_useable = h264hw_useable || h264sw_encoding;
         let h265_useable =
             _all_support_h265_decoding && (h265vram_encoding || h265hw_encoding.is_some());
         let mut format = ENCODE_CODEC_FORMAT.lock().unwrap();
@@ -275,7 +306,7 @@ impl Encoder {
         } else {
             CodecFormat::VP9
         };
-        if h264_useable {
+        if h264hw_useable {
             auto_codec = CodecFormat::H264;
         }
         if h265_useable {
@@ -295,7 +326,7 @@ impl Encoder {
             PreferCodec::VP9 => CodecFormat::VP9,
             PreferCodec::AV1 => CodecFormat::AV1,
             PreferCodec::H264 => {
-                if h264vram_encoding || h264hw_encoding.is_some() {
+                if h264_useable {
                     CodecFormat::H264
                 } else {
                     auto_codec
@@ -341,6 +372,9 @@ impl Encoder {
             .into(),
             ..Default::default()
         };
+        // AV1 only
+        encoding.crisp_text = encoding.av1;
+        encoding.keyframe_request = true;
         #[cfg(feature = "hwcodec")]
         if enable_hwcodec_option() {
             encoding.h264 |= HwRamEncoder::try_get(CodecFormat::H264).is_some();
@@ -351,6 +385,10 @@ impl Encoder {
             encoding.h264 |= VRamEncoder::available(CodecFormat::H264).len() > 0;
             encoding.h265 |= VRamEncoder::available(CodecFormat::H265).len() > 0;
         }
+        #[cfg(feature = "openh264")]
+        {
+            encoding.h264 = true;
+        }
         encoding
     }
 
@@ -392,6 +430,8 @@ impl Encoder {
                     return;
                 }
             },
+            #[cfg(feature = "openh264")]
+            EncoderCfg::SWH264(_) => CodecFormat::H264,
         };
         let current = ENCODE_CODEC_FORMAT.lock().unwrap().clone();
         if current != format {
@@ -415,9 +455,17 @@ impl Encoder {
             EncoderCfg::HWRAM(_) => false,
             #[cfg(feature = "vram")]
             EncoderCfg::VRAM(_) => false,
+            #[cfg(feature = "openh264")]
+            EncoderCfg::SWH264(_) => false,
         };
         prefer_i444 && i444_useable && !decodings.is_empty()
     }
+
+    /// Whether all the peers asked for the crisp text mode, which is used if AV1 is negotiated.
+    pub fn use_crisp_text() -> bool {
+        let decodings = PEER_DECODINGS.lock().unwrap();
+        !decodings.is_empty() && decodings.iter().all(|d| d.1.crisp_text)
+    }
 }
 
 impl Decoder {
@@ -427,7 +475,7 @@ impl Decoder {
         _luid: Option<i64>,
         mark_unsupported: &Vec<CodecFormat>,
     ) -> SupportedDecoding {
-        let (prefer, prefer_chroma) = Self::preference(id_for_perfer);
+        let (prefer, prefer_chroma, prefer_crisp_text) = Self::preference(id_for_perfer);
 
         #[allow(unused_mut)]
         let mut decoding = SupportedDecoding {
@@ -485,6 +533,11 @@ impl Decoder {
                     0
                 };
         }
+        #[cfg(feature = "openh264")]
+        {
+            decoding.ability_h264 = 1;
+        }
+        decoding.crisp_text = prefer_crisp_text;
         for unsupported in mark_unsupported {
             match unsupported {
                 CodecFormat::VP8 => decoding.ability_vp8 = 0,
@@ -507,6 +560,8 @@ impl Decoder {
         let (mut h264_vram, mut h265_vram) = (None, None);
         #[cfg(feature = "mediacodec")]
         let (mut h264_media_codec, mut h265_media_codec) = (None, None);
+        #[cfg(feature = "openh264")]
+        let mut h264_sw = None;
         let mut valid = false;
 
         match format {
@@ -560,6 +615,14 @@ impl Decoder {
                     }
                     valid = h264_media_codec.is_some();
                 }
+                #[cfg(feature = "openh264")]
+                if !valid {
+                    match SwH264Decoder::new() {
+                        Ok(v) => h264_sw = Some(v),
+                        Err(e) => log::error!("create H264 software decoder failed: {}", e),
+                    }
+                    valid = h264_sw.is_some();
+                }
             }
             CodecFormat::H265 => {
                 #[cfg(feature = "vram")]
@@ -612,6 +675,8 @@ impl Decoder {
             h264_media_codec,
             #[cfg(feature = "mediacodec")]
             h265_media_codec,
+            #[cfg(feature = "openh264")]
+            h264_sw,
             format,
             valid,
             #[cfg(feature = "hwcodec")]
@@ -658,7 +723,7 @@ impl Decoder {
                     bail!("av1 decoder not available");
                 }
             }
-            #[cfg(any(feature = "hwcodec", feature = "vram"))]
+            #[cfg(any(feature = "hwcodec", feature = "vram", feature = "openh264"))]
             video_frame::Union::H264s(h264s) => {
                 *chroma = Some(Chroma::I420);
                 #[cfg(feature = "vram")]
@@ -670,6 +735,10 @@ impl Decoder {
                 if let Some(decoder) = &mut self.h264_ram {
                     return Decoder::handle_hwram_video_frame(decoder, h264s, rgb, &mut self.i420);
                 }
+                #[cfg(feature = "openh264")]
+                if let Some(decoder) = &mut self.h264_sw {
+                    return decoder.decode(h264s, rgb);
+                }
                 Err(anyhow!("don't support h264!"))
             }
             #[cfg(any(feature = "hwcodec", feature = "vram"))]
@@ -816,10 +885,10 @@ impl Decoder {
         return Ok(false);
     }
 
-    fn preference(id: Option<&str>) -> (PreferCodec, Chroma) {
+    fn preference(id: Option<&str>) -> (PreferCodec, Chroma, bool) {
         let id = id.unwrap_or_default();
         if id.is_empty() {
-            return (PreferCodec::Auto, Chroma::I420);
+            return (PreferCodec::Auto, Chroma::I420, false);
         }
         let options = PeerConfig::load(id).options;
         let codec = options
@@ -843,10 +912,26 @@ impl Decoder {
         } else {
             Chroma::I420
         };
-        (codec, chroma)
+        let crisp_text = options.get("crisp-text") == Some(&"Y".to_string());
+        (codec, chroma, crisp_text)
     }
 }
 
+/// The max keyframe interval in frames of the codec, from the option `keyframe-interval-<codec>`
+/// or `keyframe-interval` for all of them. `None` makes keyframes only on demand.
+pub fn keyframe_interval(format: CodecFormat) -> Option<usize> {
+    let parse = |key: &str| {
+        Config::get_option(key)
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|v| *v > 0)
+    };
+    let codec = format.to_string().to_lowercase();
+    parse(&format!("{OPTION_KEYFRAME_INTERVAL}-{codec}"))
+        .or_else(|| parse(OPTION_KEYFRAME_INTERVAL))
+}
+
 #[cfg(any(feature = "hwcodec", feature = "mediacodec"))]
 pub fn enable_hwcodec_option() -> bool {
     use hbb_common::config::keys::OPTION_ENABLE_HWCODEC;
@@ -1107,6 +1192,7 @@ pub fn test_av1() {
                     height,
                     quality,
                     keyframe_interval,
+                    crisp_text: false,
                 }),
                 i444,
             ) else {
@@ -10,9 +10,56 @@ include!(concat!(env!("OUT_DIR"), "/yuv_ffi.rs"));
 use crate::PixelBuffer;
 use crate::{generate_call_macro, EncodeYuvFormat, TraitPixelBuffer};
 use hbb_common::{bail, log, ResultType};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 generate_call_macro!(call_yuv, false);
 
+// The conversions run on bands of rows in parallel, with the SIMD kernels of libyuv in each
+// band. Smaller frames are converted on the calling thread, the hand-off costs more.
+const PARALLEL_MIN_PIXELS: usize = 1280 * 720;
+const MIN_BAND_ROWS: usize = 64;
+const MAX_THREADS: usize = 8;
+
+static PARALLEL: AtomicBool = AtomicBool::new(true);
+
+lazy_static::lazy_static! {
+    static ref POOL: Option<rayon::ThreadPool> = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_cpus::get().min(MAX_THREADS))
+        .thread_name(|i| format!("convert-{i}"))
+        .build()
+        .map_err(|e| log::error!("Failed to create the conversion threads: {e}"))
+        .ok();
+}
+
+/// Convert on the calling thread only, e.g. to compare in benchmarks.
+pub fn set_parallel(parallel: bool) {
+    PARALLEL.store(parallel, Ordering::Relaxed);
+}
+
+// Run `f(first_row, rows)` on bands covering the rows, each band starts on an even row so the
+// 2x2 subsampled chroma of I420 and NV12 is not split.
+fn for_each_band<F>(width: usize, height: usize, f: F) -> ResultType<()>
+where
+    F: Fn(usize, usize) -> ResultType<()> + Sync,
+{
+    let pool = POOL
+        .as_ref()
+        .filter(|_| PARALLEL.load(Ordering::Relaxed) && width * height >= PARALLEL_MIN_PIXELS);
+    let bands = pool.map_or(1, |p| p.current_num_threads().min(height / MIN_BAND_ROWS));
+    let Some(pool) = pool.filter(|_| bands > 1) else {
+        return f(0, height);
+    };
+    let band_rows = (height.div_ceil(bands) + 1) & !1;
+    pool.install(|| {
+        (0..height)
+            .step_by(band_rows)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .try_for_each(|first| f(first, band_rows.min(height - first)))
+    })
+}
+
 #[cfg(not(target_os = "ios"))]
 pub fn convert_to_yuv(
     captured: &PixelBuffer,
@@ -22,10 +69,12 @@ pub fn convert_to_yuv(
 ) -> ResultType<()> {
     let src = captured.data();
     let src_stride = captured.stride();
-    let src_pixfmt = captured.pixfmt();
-    let src_width = captured.width();
-    let src_height = captured.height();
-    if src_width > dst_fmt.w || src_height > dst_fmt.h {
+    let mut src_pixfmt = captured.pixfmt();
+    let mut src_width = captured.width();
+    let mut src_height = captured.height();
+    // A larger capture is downscaled to the encoder size
+    let scale = src_width > dst_fmt.w || src_height > dst_fmt.h;
+    if scale && (src_width < dst_fmt.w || src_height < dst_fmt.h) {
         bail!(
             "src rect > dst rect: ({src_width}, {src_height}) > ({},{})",
             dst_fmt.w,
@@ -58,6 +107,62 @@ pub fn convert_to_yuv(
         "unsupported pixfmt conversion: {src_pixfmt:?} -> {:?}",
         dst_fmt.pixfmt
     );
+    // The buffers are shared by the bands as addresses, each band writes its own rows.
+    let mut src_stride = src_stride[0];
+    let mut src_addr = src.as_ptr() as usize;
+    let mut src_len = src.len();
+    // The scaled frame is kept at the head of `mid_data`, the intermediate rows follow it.
+    let mut mid_offset = 0;
+    if scale {
+        match src_pixfmt {
+            crate::Pixfmt::BGRA | crate::Pixfmt::RGBA => {}
+            crate::Pixfmt::RGB565LE => {
+                // Expanded to ARGB in `dst` first, it's written only after the scaling
+                let argb_stride = src_width * 4;
+                dst.resize(argb_stride * src_height, 0);
+                let argb_addr = dst.as_mut_ptr() as usize;
+                for_each_band(src_width, src_height, |first, rows| {
+                    call_yuv!(RGB565ToARGB(
+                        (src_addr + first * src_stride) as _,
+                        src_stride as _,
+                        (argb_addr + first * argb_stride) as _,
+                        argb_stride as _,
+                        src_width as _,
+                        rows as _,
+                    ));
+                    Ok(())
+                })?;
+                (src_addr, src_stride, src_pixfmt) = (argb_addr, argb_stride, crate::Pixfmt::BGRA);
+            }
+            _ => bail!(unsupported),
+        }
+        let (width, height) = (dst_fmt.w, dst_fmt.h);
+        let stride = width * 4;
+        mid_offset = stride * height;
+        // Room for the intermediate rows as well, resizing later never moves the scaled frame
+        mid_data.resize(mid_offset * 2, 0);
+        let scaled_addr = mid_data.as_mut_ptr() as usize;
+        for_each_band(width, height, |first, rows| {
+            call_yuv!(ARGBScaleClip(
+                src_addr as _,
+                src_stride as _,
+                src_width as _,
+                src_height as _,
+                scaled_addr as _,
+                stride as _,
+                width as _,
+                height as _,
+                0,
+                first as _,
+                width as _,
+                rows as _,
+                FilterMode::kFilterBox,
+            ));
+            Ok(())
+        })?;
+        (src_addr, src_stride, src_len) = (scaled_addr, stride, mid_offset);
+        (src_width, src_height) = (width, height);
+    }
 
     match (src_pixfmt, dst_fmt.pixfmt) {
         (crate::Pixfmt::BGRA, crate::Pixfmt::I420)
@@ -66,27 +171,30 @@ pub fn convert_to_yuv(
             let dst_stride_y = dst_fmt.stride[0];
             let dst_stride_uv = dst_fmt.stride[1];
             dst.resize(dst_fmt.h * dst_stride_y * 2, 0); // waste some memory to ensure memory safety
-            let dst_y = dst.as_mut_ptr();
-            let dst_u = dst[dst_fmt.u..].as_mut_ptr();
-            let dst_v = dst[dst_fmt.v..].as_mut_ptr();
+            let dst_y = dst.as_mut_ptr() as usize;
+            let dst_u = dst[dst_fmt.u..].as_mut_ptr() as usize;
+            let dst_v = dst[dst_fmt.v..].as_mut_ptr() as usize;
             let f = match src_pixfmt {
                 crate::Pixfmt::BGRA => ARGBToI420,
                 crate::Pixfmt::RGBA => ABGRToI420,
                 crate::Pixfmt::RGB565LE => RGB565ToI420,
                 _ => bail!(unsupported),
             };
-            call_yuv!(f(
-                src.as_ptr(),
-                src_stride[0] as _,
-                dst_y,
-                dst_stride_y as _,
-                dst_u,
-                dst_stride_uv as _,
-                dst_v,
-                dst_stride_uv as _,
-                src_width as _,
-                src_height as _,
-            ));
+            for_each_band(src_width, src_height, |first, rows| {
+                call_yuv!(f(
+                    (src_addr + first * src_stride) as _,
+                    src_stride as _,
+                    (dst_y + first * dst_stride_y) as _,
+                    dst_stride_y as _,
+                    (dst_u + first / 2 * dst_stride_uv) as _,
+                    dst_stride_uv as _,
+                    (dst_v + first / 2 * dst_stride_uv) as _,
+                    dst_stride_uv as _,
+                    src_width as _,
+                    rows as _,
+                ));
+                Ok(())
+            })?;
         }
         (crate::Pixfmt::BGRA, crate::Pixfmt::NV12)
         | (crate::Pixfmt::RGBA, crate::Pixfmt::NV12)
@@ -97,42 +205,47 @@ pub fn convert_to_yuv(
                 align(dst_fmt.h) * (align(dst_stride_y) + align(dst_stride_uv / 2)),
                 0,
             );
-            let dst_y = dst.as_mut_ptr();
-            let dst_uv = dst[dst_fmt.u..].as_mut_ptr();
-            let (input, input_stride) = match src_pixfmt {
-                crate::Pixfmt::BGRA => (src.as_ptr(), src_stride[0]),
-                crate::Pixfmt::RGBA => (src.as_ptr(), src_stride[0]),
-                crate::Pixfmt::RGB565LE => {
-                    let mid_stride = src_width * 4;
-                    mid_data.resize(mid_stride * src_height, 0);
-                    call_yuv!(RGB565ToARGB(
-                        src.as_ptr(),
-                        src_stride[0] as _,
-                        mid_data.as_mut_ptr(),
-                        mid_stride as _,
-                        src_width as _,
-                        src_height as _,
-                    ));
-                    (mid_data.as_ptr(), mid_stride)
-                }
-                _ => bail!(unsupported),
-            };
+            let dst_y = dst.as_mut_ptr() as usize;
+            let dst_uv = dst[dst_fmt.u..].as_mut_ptr() as usize;
+            // RGB565 is expanded to ARGB in the rows of the band first
+            let mid_stride = src_width * 4;
+            if src_pixfmt == crate::Pixfmt::RGB565LE {
+                mid_data.resize(mid_offset + mid_stride * src_height, 0);
+            }
+            let mid_addr = mid_data[mid_offset..].as_mut_ptr() as usize;
             let f = match src_pixfmt {
                 crate::Pixfmt::BGRA => ARGBToNV12,
                 crate::Pixfmt::RGBA => ABGRToNV12,
                 crate::Pixfmt::RGB565LE => ARGBToNV12,
                 _ => bail!(unsupported),
             };
-            call_yuv!(f(
-                input,
-                input_stride as _,
-                dst_y,
-                dst_stride_y as _,
-                dst_uv,
-                dst_stride_uv as _,
-                src_width as _,
-                src_height as _,
-            ));
+            for_each_band(src_width, src_height, |first, rows| {
+                let (input, input_stride) = if src_pixfmt == crate::Pixfmt::RGB565LE {
+                    let mid = mid_addr + first * mid_stride;
+                    call_yuv!(RGB565ToARGB(
+                        (src_addr + first * src_stride) as _,
+                        src_stride as _,
+                        mid as _,
+                        mid_stride as _,
+                        src_width as _,
+                        rows as _,
+                    ));
+                    (mid, mid_stride)
+                } else {
+                    (src_addr + first * src_stride, src_stride)
+                };
+                call_yuv!(f(
+                    input as _,
+                    input_stride as _,
+                    (dst_y + first * dst_stride_y) as _,
+                    dst_stride_y as _,
+                    (dst_uv + first / 2 * dst_stride_uv) as _,
+                    dst_stride_uv as _,
+                    src_width as _,
+                    rows as _,
+                ));
+                Ok(())
+            })?;
         }
         (crate::Pixfmt::BGRA, crate::Pixfmt::I444)
         | (crate::Pixfmt::RGBA, crate::Pixfmt::I444)
@@ -145,51 +258,65 @@ pub fn convert_to_yuv(
                     * (align(dst_stride_y) + align(dst_stride_u) + align(dst_stride_v)),
                 0,
             );
-            let dst_y = dst.as_mut_ptr();
-            let dst_u = dst[dst_fmt.u..].as_mut_ptr();
-            let dst_v = dst[dst_fmt.v..].as_mut_ptr();
-            let (input, input_stride) = match src_pixfmt {
-                crate::Pixfmt::BGRA => (src.as_ptr(), src_stride[0]),
+            let dst_y = dst.as_mut_ptr() as usize;
+            let dst_u = dst[dst_fmt.u..].as_mut_ptr() as usize;
+            let dst_v = dst[dst_fmt.v..].as_mut_ptr() as usize;
+            // RGBA and RGB565 are turned into ARGB in the rows of the band first
+            let mid_stride = match src_pixfmt {
+                crate::Pixfmt::BGRA => 0,
                 crate::Pixfmt::RGBA => {
-                    mid_data.resize(src.len(), 0);
-                    call_yuv!(ABGRToARGB(
-                        src.as_ptr(),
-                        src_stride[0] as _,
-                        mid_data.as_mut_ptr(),
-                        src_stride[0] as _,
-                        src_width as _,
-                        src_height as _,
-                    ));
-                    (mid_data.as_ptr(), src_stride[0])
+                    mid_data.resize(mid_offset + src_len, 0);
+                    src_stride
                 }
                 crate::Pixfmt::RGB565LE => {
-                    let mid_stride = src_width * 4;
-                    mid_data.resize(mid_stride * src_height, 0);
-                    call_yuv!(RGB565ToARGB(
-                        src.as_ptr(),
-                        src_stride[0] as _,
-                        mid_data.as_mut_ptr(),
-                        mid_stride as _,
-                        src_width as _,
-                        src_height as _,
-                    ));
-                    (mid_data.as_ptr(), mid_stride)
+                    mid_data.resize(mid_offset + src_width * 4 * src_height, 0);
+                    src_width * 4
                 }
                 _ => bail!(unsupported),
             };
-
-            call_yuv!(ARGBToI444(
-                input,
-                input_stride as _,
-                dst_y,
-                dst_stride_y as _,
-                dst_u,
-                dst_stride_u as _,
-                dst_v,
-                dst_stride_v as _,
-                src_width as _,
-                src_height as _,
-            ));
+            let mid_addr = mid_data[mid_offset..].as_mut_ptr() as usize;
+            for_each_band(src_width, src_height, |first, rows| {
+                let src_band = src_addr + first * src_stride;
+                let mid = mid_addr + first * mid_stride;
+                let (input, input_stride) = match src_pixfmt {
+                    crate::Pixfmt::RGBA => {
+                        call_yuv!(ABGRToARGB(
+                            src_band as _,
+                            src_stride as _,
+                            mid as _,
+                            mid_stride as _,
+                            src_width as _,
+                            rows as _,
+                        ));
+                        (mid, mid_stride)
+                    }
+                    crate::Pixfmt::RGB565LE => {
+                        call_yuv!(RGB565ToARGB(
+                            src_band as _,
+                            src_stride as _,
+                            mid as _,
+                            mid_stride as _,
+                            src_width as _,
+                            rows as _,
+                        ));
+                        (mid, mid_stride)
+                    }
+                    _ => (src_band, src_stride),
+                };
+                call_yuv!(ARGBToI444(
+                    input as _,
+                    input_stride as _,
+                    (dst_y + first * dst_stride_y) as _,
+                    dst_stride_y as _,
+                    (dst_u + first * dst_stride_u) as _,
+                    dst_stride_u as _,
+                    (dst_v + first * dst_stride_v) as _,
+                    dst_stride_v as _,
+                    src_width as _,
+                    rows as _,
+                ));
+                Ok(())
+            })?;
         }
         _ => {
             bail!(unsupported);
@@ -198,18 +325,29 @@ pub fn convert_to_yuv(
     Ok(())
 }
 
+/// Convert to packed rows of `pixfmt`, without the padding of the captured stride.
 #[cfg(not(target_os = "ios"))]
 pub fn convert(captured: &PixelBuffer, pixfmt: crate::Pixfmt, dst: &mut Vec<u8>) -> ResultType<()> {
-    if captured.pixfmt() == pixfmt {
-        dst.extend_from_slice(captured.data());
-        return Ok(());
-    }
-
     let src = captured.data();
-    let src_stride = captured.stride();
+    let src_stride = captured.stride()[0];
     let src_pixfmt = captured.pixfmt();
     let src_width = captured.width();
     let src_height = captured.height();
+    let dst_stride = src_width * src_pixfmt.bytes_per_pixel();
+    if src_stride < dst_stride || src.len() < src_stride * src_height {
+        bail!("wrong src stride or len: {src_stride}, {}", src.len());
+    }
+
+    if src_pixfmt == pixfmt {
+        if src_stride == dst_stride {
+            dst.extend_from_slice(&src[..dst_stride * src_height]);
+        } else {
+            for row in src.chunks(src_stride).take(src_height) {
+                dst.extend_from_slice(&row[..dst_stride]);
+            }
+        }
+        return Ok(());
+    }
 
     let unsupported = format!(
         "unsupported pixfmt conversion: {src_pixfmt:?} -> {:?}",
@@ -218,15 +356,20 @@ pub fn convert(captured: &PixelBuffer, pixfmt: crate::Pixfmt, dst: &mut Vec<u8>)
 
     match (src_pixfmt, pixfmt) {
         (crate::Pixfmt::BGRA, crate::Pixfmt::RGBA) | (crate::Pixfmt::RGBA, crate::Pixfmt::BGRA) => {
-            dst.resize(src.len(), 0);
-            call_yuv!(ABGRToARGB(
-                src.as_ptr(),
-                src_stride[0] as _,
-                dst.as_mut_ptr(),
-                src_stride[0] as _,
-                src_width as _,
-                src_height as _,
-            ));
+            dst.resize(dst_stride * src_height, 0);
+            let src_addr = src.as_ptr() as usize;
+            let dst_addr = dst.as_mut_ptr() as usize;
+            for_each_band(src_width, src_height, |first, rows| {
+                call_yuv!(ABGRToARGB(
+                    (src_addr + first * src_stride) as _,
+                    src_stride as _,
+                    (dst_addr + first * dst_stride) as _,
+                    dst_stride as _,
+                    src_width as _,
+                    rows as _,
+                ));
+                Ok(())
+            })?;
         }
         _ => {
             bail!(unsupported);
@@ -234,3 +377,85 @@ pub fn convert(captured: &PixelBuffer, pixfmt: crate::Pixfmt, dst: &mut Vec<u8>)
     }
     Ok(())
 }
+
+#[cfg(all(test, x11))]
+mod tests {
+    use super::*;
+    use crate::Pixfmt;
+
+    // planes laid out as the encoders do, with the rows aligned
+    fn yuvfmt(pixfmt: Pixfmt, w: usize, h: usize) -> EncodeYuvFormat {
+        let stride_y = (w + 63) / 64 * 64;
+        let (stride, u, v) = match pixfmt {
+            Pixfmt::I420 => {
+                let stride_uv = stride_y / 2;
+                let u = stride_y * h;
+                (vec![stride_y, stride_uv], u, u + stride_uv * h.div_ceil(2))
+            }
+            Pixfmt::NV12 => (vec![stride_y, stride_y], stride_y * h, stride_y * h),
+            _ => (vec![stride_y; 3], stride_y * h, stride_y * h * 2),
+        };
+        EncodeYuvFormat {
+            pixfmt,
+            w,
+            h,
+            stride,
+            u,
+            v,
+        }
+    }
+
+    // a frame with padded rows, the padding is not part of the image
+    fn frame(pixfmt: Pixfmt, w: usize, h: usize) -> Vec<u8> {
+        let stride = w * pixfmt.bytes_per_pixel() + 64;
+        (0..stride * h)
+            .map(|i| ((i % stride) * 7 + (i / stride) * 13) as u8)
+            .collect()
+    }
+
+    fn convert_both(captured: &PixelBuffer, dst_fmt: &EncodeYuvFormat) -> (Vec<u8>, Vec<u8>) {
+        let mut outputs = [vec![], vec![]];
+        for (parallel, dst) in [true, false].into_iter().zip(outputs.iter_mut()) {
+            set_parallel(parallel);
+            let mut mid_data = vec![];
+            convert_to_yuv(captured, dst_fmt.clone(), dst, &mut mid_data).unwrap();
+        }
+        set_parallel(true);
+        let [a, b] = outputs;
+        (a, b)
+    }
+
+    // one test as the switch to the calling thread is global
+    #[test]
+    fn test_bands() {
+        let sources = [Pixfmt::BGRA, Pixfmt::RGBA, Pixfmt::RGB565LE];
+        let targets = [Pixfmt::I420, Pixfmt::NV12, Pixfmt::I444];
+        // an odd height leaves a last band with an odd number of rows
+        for (w, h) in [(1280, 720), (1280, 721)] {
+            for src_pixfmt in sources {
+                let data = frame(src_pixfmt, w, h);
+                let captured = PixelBuffer::new(&data, src_pixfmt, w, h);
+                for dst_pixfmt in targets {
+                    let (a, b) = convert_both(&captured, &yuvfmt(dst_pixfmt, w, h));
+                    assert!(
+                        a == b,
+                        "{src_pixfmt:?} -> {dst_pixfmt:?} at {w}x{h} differs in bands"
+                    );
+                }
+            }
+        }
+        // downscaled to the encoder size before the conversion
+        let (w, h) = (1920, 1081);
+        for src_pixfmt in sources {
+            let data = frame(src_pixfmt, w, h);
+            let captured = PixelBuffer::new(&data, src_pixfmt, w, h);
+            for dst_pixfmt in targets {
+                let (a, b) = convert_both(&captured, &yuvfmt(dst_pixfmt, 1280, 721));
+                assert!(
+                    a == b,
+                    "{src_pixfmt:?} -> {dst_pixfmt:?} scaled from {w}x{h} differs in bands"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Tile based damage detection of captured frames.
+//!
+//! Each frame is compared with the previous one in tiles of `TILE_SIZE` pixels. The
+//! encoders only code the tiles changed recently and skip the rest, see
+//! `EncoderApi::set_damage`. A tile stays active for `REFINE_FRAMES` frames after its
+//! last change, so the encoder can bring it from the first rough version to full quality.
+
+use crate::{Pixfmt, TraitPixelBuffer};
+
+pub const TILE_SIZE: usize = 64;
+const REFINE_FRAMES: u8 = 10;
+
+#[derive(Debug, Clone, Default)]
+pub struct Damage {
+    pub width: usize,
+    pub height: usize,
+    cols: usize,
+    /// Tiles changed in this frame.
+    changed: Vec<bool>,
+    /// Tiles changed in this frame or recently.
+    active: Vec<bool>,
+}
+
+impl Damage {
+    fn ratio(tiles: &[bool]) -> f32 {
+        if tiles.is_empty() {
+            return 1.0;
+        }
+        tiles.iter().filter(|t| **t).count() as f32 / tiles.len() as f32
+    }
+
+    /// Share of the screen changed in this frame, 0.0 ~ 1.0.
+    #[inline]
+    pub fn changed_ratio(&self) -> f32 {
+        Self::ratio(&self.changed)
+    }
+
+    /// Share of the screen the encoder still has to code, 0.0 ~ 1.0.
+    #[inline]
+    pub fn active_ratio(&self) -> f32 {
+        Self::ratio(&self.active)
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.active.iter().all(|t| *t)
+    }
+
+    /// The active map on a grid of `unit` pixel blocks of a `width` x `height` image,
+    /// row major, 1 for the blocks to code and 0 for the blocks to skip.
+    ///
+    /// `None` if every block has to be coded, or the image is not the one tracked.
+    pub fn active_map(&self, width: usize, height: usize, unit: usize) -> Option<Vec<u8>> {
+        if self.is_full() || width != self.width || height != self.height {
+            return None;
+        }
+        let cols = width.div_ceil(unit);
+        let rows = height.div_ceil(unit);
+        let mut map = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            let tile_row = row * unit / TILE_SIZE;
+            for col in 0..cols {
+                let tile_col = col * unit / TILE_SIZE;
+                map.push(self.active[tile_row * self.cols + tile_col] as u8);
+            }
+        }
+        Some(map)
+    }
+}
+
+#[derive(Default)]
+pub struct DamageTracker {
+    last: Vec<u8>,
+    stride: usize,
+    /// Frames since the last change of each tile.
+    ages: Vec<u8>,
+    damage: Damage,
+}
+
+impl DamageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Code the whole screen on the next frame, e.g. after a keyframe was requested.
+    pub fn reset(&mut self) {
+        self.last.clear();
+    }
+
+    /// Compare the frame with the previous one, `None` for the formats not tracked.
+    pub fn update(&mut self, frame: &dyn TraitPixelBuffer) -> Option<&Damage> {
+        if !matches!(frame.pixfmt(), Pixfmt::BGRA | Pixfmt::RGBA) {
+            return None;
+        }
+        let stride = *frame.stride().first()?;
+        self.update_data(frame.data(), frame.width(), frame.height(), stride)
+    }
+
+    fn update_data(
+        &mut self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) -> Option<&Damage> {
+        const BPP: usize = 4;
+        if width == 0 || height == 0 || stride < width * BPP || data.len() < stride * height {
+            return None;
+        }
+        let data = &data[..stride * height];
+        let cols = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+        let d = &mut self.damage;
+        let resized = d.width != width || d.height != height || self.stride != stride;
+        if resized || self.last.len() != data.len() {
+            *d = Damage {
+                width,
+                height,
+                cols,
+                changed: vec![true; cols * rows],
+                active: vec![true; cols * rows],
+            };
+            self.ages = vec![0; cols * rows];
+            self.stride = stride;
+            self.last.clear();
+            self.last.extend_from_slice(data);
+            return Some(&self.damage);
+        }
+        d.changed.iter_mut().for_each(|t| *t = false);
+        for y in 0..height {
+            let tile_row = y / TILE_SIZE;
+            let line = y * stride;
+            for tile_col in 0..cols {
+                let tile = tile_row * cols + tile_col;
+                if d.changed[tile] {
+                    continue;
+                }
+                let start = line + tile_col * TILE_SIZE * BPP;
+                let end = line + (width.min((tile_col + 1) * TILE_SIZE)) * BPP;
+                if data[start..end] != self.last[start..end] {
+                    d.changed[tile] = true;
+                }
+            }
+        }
+        for (tile, changed) in d.changed.iter().enumerate() {
+            let age = &mut self.ages[tile];
+            *age = if *changed { 0 } else { age.saturating_add(1) };
+            d.active[tile] = *age < REFINE_FRAMES;
+        }
+        if d.changed.iter().any(|t| *t) {
+            self.last.copy_from_slice(data);
+        }
+        Some(&self.damage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_damage_tiles() {
+        let (w, h) = (200, 100);
+        let stride = w * 4;
+        let mut data = vec![0u8; stride * h];
+        let mut tracker = DamageTracker::new();
+        let damage = tracker.update_data(&data, w, h, stride).unwrap();
+        assert!(damage.is_full(), "the first frame is coded as a whole");
+
+        for _ in 0..REFINE_FRAMES {
+            tracker.update_data(&data, w, h, stride);
+        }
+        let damage = tracker.update_data(&data, w, h, stride).unwrap();
+        assert_eq!(damage.changed_ratio(), 0.0);
+        let cols = w.div_ceil(16);
+        let map = damage.active_map(w, h, 16).unwrap();
+        assert_eq!(
+            map,
+            vec![0; cols * h.div_ceil(16)],
+            "nothing left to refine"
+        );
+
+        // a cursor blinking in the tile at column 3, row 1
+        data[70 * stride + 195 * 4] = 0xff;
+        let damage = tracker.update_data(&data, w, h, stride).unwrap();
+        assert_eq!(damage.changed_ratio(), 1.0 / 8.0);
+        let map = damage.active_map(w, h, 16).unwrap();
+        let active: Vec<usize> = (0..map.len()).filter(|i| map[*i] == 1).collect();
+        // the tile is clipped to macroblock rows 4 ~ 6 of column 12 by the frame
+        assert_eq!(active, vec![4 * cols + 12, 5 * cols + 12, 6 * cols + 12]);
+        assert!(damage.active_map(w / 2, h, 16).is_none());
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "synthetic")]
+use crate::common::synthetic;
 use crate::{
     common::{
         wayland,
@@ -11,6 +13,8 @@ use std::{io, time::Duration};
 pub enum Capturer {
     X11(x11::Capturer),
     WAYLAND(wayland::Capturer),
+    #[cfg(feature = "synthetic")]
+    SYNTHETIC(synthetic::Capturer),
 }
 
 impl Capturer {
@@ -18,6 +22,8 @@ impl Capturer {
         Ok(match display {
             Display::X11(d) => Capturer::X11(x11::Capturer::new(d)?),
             Display::WAYLAND(d) => Capturer::WAYLAND(wayland::Capturer::new(d)?),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => Capturer::SYNTHETIC(synthetic::Capturer::new(d)?),
         })
     }
 
@@ -25,6 +31,8 @@ impl Capturer {
         match self {
             Capturer::X11(d) => d.width(),
             Capturer::WAYLAND(d) => d.width(),
+            #[cfg(feature = "synthetic")]
+            Capturer::SYNTHETIC(d) => d.width(),
         }
     }
 
@@ -32,6 +40,8 @@ impl Capturer {
         match self {
             Capturer::X11(d) => d.height(),
             Capturer::WAYLAND(d) => d.height(),
+            #[cfg(feature = "synthetic")]
+            Capturer::SYNTHETIC(d) => d.height(),
         }
     }
 }
@@ -41,6 +51,8 @@ impl TraitCapturer for Capturer {
         match self {
             Capturer::X11(d) => d.frame(timeout),
             Capturer::WAYLAND(d) => d.frame(timeout),
+            #[cfg(feature = "synthetic")]
+            Capturer::SYNTHETIC(d) => d.frame(timeout),
         }
     }
 }
@@ -48,6 +60,8 @@ impl TraitCapturer for Capturer {
 pub enum Display {
     X11(x11::Display),
     WAYLAND(wayland::Display),
+    #[cfg(feature = "synthetic")]
+    SYNTHETIC(synthetic::Display),
 }
 
 impl Display {
@@ -74,10 +88,28 @@ impl Display {
         })
     }
 
+    /// The region of the screens as a display, X11 only.
+    pub fn region(x: i32, y: i32, width: i32, height: i32) -> io::Result<Display> {
+        if !super::is_x11() {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        Ok(Display::X11(x11::Display::region(x, y, width, height)?))
+    }
+
+    /// The top-level window as a display, X11 only.
+    pub fn window(id: u32) -> io::Result<Display> {
+        if !super::is_x11() {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        Ok(Display::X11(x11::Display::window(id)?))
+    }
+
     pub fn width(&self) -> usize {
         match self {
             Display::X11(d) => d.width(),
             Display::WAYLAND(d) => d.width(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.width(),
         }
     }
 
@@ -85,6 +117,8 @@ impl Display {
         match self {
             Display::X11(d) => d.height(),
             Display::WAYLAND(d) => d.height(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.height(),
         }
     }
 
@@ -92,6 +126,8 @@ impl Display {
         match self {
             Display::X11(_d) => 1.0,
             Display::WAYLAND(d) => d.scale(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(_d) => 1.0,
         }
     }
 
@@ -99,6 +135,8 @@ impl Display {
         match self {
             Display::X11(d) => d.width(),
             Display::WAYLAND(d) => d.logical_width(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.width(),
         }
     }
 
@@ -106,6 +144,8 @@ impl Display {
         match self {
             Display::X11(d) => d.height(),
             Display::WAYLAND(d) => d.logical_height(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.height(),
         }
     }
 
@@ -113,6 +153,8 @@ impl Display {
         match self {
             Display::X11(d) => d.origin(),
             Display::WAYLAND(d) => d.origin(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.origin(),
         }
     }
 
@@ -120,6 +162,8 @@ impl Display {
         match self {
             Display::X11(d) => d.is_online(),
             Display::WAYLAND(d) => d.is_online(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.is_online(),
         }
     }
 
@@ -127,6 +171,8 @@ impl Display {
         match self {
             Display::X11(d) => d.is_primary(),
             Display::WAYLAND(d) => d.is_primary(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.is_primary(),
         }
     }
 
@@ -134,6 +180,8 @@ impl Display {
         match self {
             Display::X11(d) => d.name(),
             Display::WAYLAND(d) => d.name(),
+            #[cfg(feature = "synthetic")]
+            Display::SYNTHETIC(d) => d.name(),
         }
     }
 }
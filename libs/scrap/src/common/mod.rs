@@ -14,6 +14,8 @@ cfg_if! {
         cfg_if! {
             if #[cfg(feature="wayland")] {
                 mod linux;
+                #[cfg(feature = "synthetic")]
+                pub mod synthetic;
                 mod wayland;
                 mod x11;
                 pub use self::linux::*;
@@ -37,10 +39,13 @@ cfg_if! {
 
 pub mod codec;
 pub mod convert;
+pub mod damage;
 #[cfg(feature = "hwcodec")]
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
 pub mod mediacodec;
+#[cfg(feature = "openh264")]
+pub mod openh264;
 pub mod vpxcodec;
 #[cfg(feature = "vram")]
 pub mod vram;
@@ -0,0 +1,363 @@
+//! Software H.264 with OpenH264, for the machines without any usable hardware codec.
+
+use crate::{
+    codec::{base_bitrate, EncoderApi, EncoderCfg},
+    common::GoogleImage,
+    EncodeInput, EncodeYuvFormat, ImageRgb, Pixfmt, STRIDE_ALIGN,
+};
+use hbb_common::{
+    anyhow::{anyhow, Context},
+    bytes::Bytes,
+    log,
+    message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, VideoFrame},
+    ResultType,
+};
+use openh264::{
+    decoder::{DecodedYUV, Decoder},
+    encoder::{BitRate, Encoder, EncoderConfig, FrameRate, FrameType, RateControlMode, UsageType},
+    formats::{YUVSlices, YUVSource},
+    Timestamp,
+};
+use openh264_sys2::{
+    SBitrateInfo, ENCODER_OPTION_BITRATE, ENCODER_OPTION_FRAME_RATE, SPATIAL_LAYER_ALL,
+};
+
+// OpenH264 needs about twice the bits of VP9 for the same quality of screen content.
+const BITRATE_FACTOR: f32 = 2.0;
+
+#[derive(Debug, Clone)]
+pub struct SwH264EncoderConfig {
+    pub width: usize,
+    pub height: usize,
+    pub quality: f32,
+    pub keyframe_interval: Option<usize>,
+    /// The capture rate, which the rate control spreads the bitrate over.
+    pub fps: u32,
+}
+
+pub struct SwH264Encoder {
+    encoder: Encoder,
+    yuvfmt: EncodeYuvFormat,
+    bitrate: u32, // kbps
+    fps: u32,
+    keyframe_interval: Option<usize>,
+    frames_since_keyframe: usize,
+}
+
+impl EncoderApi for SwH264Encoder {
+    fn new(cfg: EncoderCfg, _i444: bool) -> ResultType<Self>
+    where
+        Self: Sized,
+    {
+        match cfg {
+            EncoderCfg::SWH264(config) => {
+                let bitrate = Self::bitrate(config.width, config.height, config.quality);
+                let encoder_config = EncoderConfig::new()
+                    .usage_type(UsageType::ScreenContentRealTime)
+                    .rate_control_mode(RateControlMode::Bitrate)
+                    .bitrate(BitRate::from_bps(bitrate * 1000))
+                    .max_frame_rate(FrameRate::from_hz(config.fps as _))
+                    .skip_frames(false);
+                let encoder =
+                    Encoder::with_api_config(openh264::OpenH264API::from_source(), encoder_config)
+                        .map_err(|e| anyhow!("Failed to create openh264 encoder: {e}"))?;
+                Ok(Self {
+                    encoder,
+                    yuvfmt: Self::get_yuvfmt(config.width, config.height),
+                    bitrate,
+                    fps: config.fps,
+                    keyframe_interval: config.keyframe_interval,
+                    frames_since_keyframe: 0,
+                })
+            }
+            _ => Err(anyhow!("encoder type mismatch")),
+        }
+    }
+
+    fn encode_to_message(&mut self, input: EncodeInput, ms: i64) -> ResultType<VideoFrame> {
+        let frame = self
+            .encode(input.yuv()?, ms)
+            .with_context(|| "Failed to encode")?;
+        let Some(frame) = frame else {
+            return Err(anyhow!("no valid frame"));
+        };
+        let mut vf = VideoFrame::new();
+        vf.set_h264s(EncodedVideoFrames {
+            frames: vec![frame].into(),
+            ..Default::default()
+        });
+        Ok(vf)
+    }
+
+    fn yuvfmt(&self) -> EncodeYuvFormat {
+        self.yuvfmt.clone()
+    }
+
+    #[cfg(feature = "vram")]
+    fn input_texture(&self) -> bool {
+        false
+    }
+
+    fn set_quality(&mut self, ratio: f32) -> ResultType<()> {
+        let bitrate = Self::bitrate(self.yuvfmt.w, self.yuvfmt.h, ratio);
+        if bitrate == 0 || bitrate == self.bitrate {
+            return Ok(());
+        }
+        let mut info = SBitrateInfo {
+            iLayer: SPATIAL_LAYER_ALL,
+            iBitrate: (bitrate * 1000) as _,
+        };
+        let ret = unsafe {
+            self.encoder
+                .raw_api()
+                .set_option(ENCODER_OPTION_BITRATE, &mut info as *mut _ as _)
+        };
+        if ret != 0 {
+            log::error!("Failed to set openh264 bitrate to {bitrate}kbps: {ret}");
+        } else {
+            self.bitrate = bitrate;
+        }
+        Ok(())
+    }
+
+    fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    fn support_changing_quality(&self) -> bool {
+        true
+    }
+
+    fn latency_free(&self) -> bool {
+        true
+    }
+
+    fn is_hardware(&self) -> bool {
+        false
+    }
+
+    fn disable(&self) {}
+
+    fn request_keyframe(&mut self) -> bool {
+        self.encoder.force_intra_frame();
+        true
+    }
+
+    fn set_fps(&mut self, fps: u32) {
+        if fps == 0 || fps == self.fps {
+            return;
+        }
+        let mut rate = fps as f32;
+        let ret = unsafe {
+            self.encoder
+                .raw_api()
+                .set_option(ENCODER_OPTION_FRAME_RATE, &mut rate as *mut _ as _)
+        };
+        if ret != 0 {
+            log::error!("Failed to set openh264 frame rate to {fps}: {ret}");
+        } else {
+            self.fps = fps;
+        }
+    }
+}
+
+impl SwH264Encoder {
+    fn encode(&mut self, yuv: &[u8], ms: i64) -> ResultType<Option<EncodedVideoFrame>> {
+        let fmt = &self.yuvfmt;
+        // H.264 codes whole macroblock pairs of chroma, an odd last column or row is dropped
+        let (w, h) = (fmt.w & !1, fmt.h & !1);
+        let (stride_y, stride_uv) = (fmt.stride[0], fmt.stride[1]);
+        let len_y = stride_y * h;
+        let len_uv = stride_uv * (h / 2);
+        if yuv.len() < fmt.v + len_uv {
+            return Err(anyhow!("wrong yuv len: {}", yuv.len()));
+        }
+        let source = YUVSlices::new(
+            (
+                &yuv[..len_y],
+                &yuv[fmt.u..fmt.u + len_uv],
+                &yuv[fmt.v..fmt.v + len_uv],
+            ),
+            (w, h),
+            (stride_y, stride_uv, stride_uv),
+        );
+        if let Some(interval) = self.keyframe_interval {
+            if self.frames_since_keyframe >= interval {
+                self.encoder.force_intra_frame();
+            }
+        }
+        let stream = self
+            .encoder
+            .encode_at(&source, Timestamp::from_millis(ms.max(0) as _))
+            .map_err(|e| anyhow!("{e}"))?;
+        let key = match stream.frame_type() {
+            FrameType::IDR | FrameType::I => true,
+            FrameType::P => false,
+            FrameType::Skip | FrameType::Invalid => return Ok(None),
+        };
+        let data = stream.to_vec();
+        if data.is_empty() {
+            return Ok(None);
+        }
+        self.frames_since_keyframe = if key {
+            0
+        } else {
+            self.frames_since_keyframe + 1
+        };
+        Ok(Some(EncodedVideoFrame {
+            data: Bytes::from(data),
+            key,
+            pts: ms,
+            ..Default::default()
+        }))
+    }
+
+    pub fn bitrate(width: usize, height: usize, ratio: f32) -> u32 {
+        (base_bitrate(width as _, height as _) as f32 * ratio * BITRATE_FACTOR) as u32
+    }
+
+    fn get_yuvfmt(width: usize, height: usize) -> EncodeYuvFormat {
+        let align = |x: usize| (x + STRIDE_ALIGN - 1) / STRIDE_ALIGN * STRIDE_ALIGN;
+        let stride_y = align(width);
+        let stride_uv = align((width + 1) / 2);
+        let u = stride_y * height;
+        let v = u + stride_uv * ((height + 1) / 2);
+        EncodeYuvFormat {
+            pixfmt: Pixfmt::I420,
+            w: width,
+            h: height,
+            stride: vec![stride_y, stride_uv, stride_uv],
+            u,
+            v,
+        }
+    }
+}
+
+pub struct SwH264Decoder {
+    decoder: Decoder,
+}
+
+impl SwH264Decoder {
+    pub fn new() -> ResultType<Self> {
+        let decoder =
+            Decoder::new().map_err(|e| anyhow!("Failed to create openh264 decoder: {e}"))?;
+        Ok(Self { decoder })
+    }
+
+    /// Decode the frames and convert the last picture to `rgb`, return whether there is one.
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    pub fn decode(&mut self, frames: &EncodedVideoFrames, rgb: &mut ImageRgb) -> ResultType<bool> {
+        let mut ret = false;
+        for h264 in frames.frames.iter() {
+            let image = self
+                .decoder
+                .decode(&h264.data)
+                .map_err(|e| anyhow!("{e}"))?;
+            if let Some(image) = image {
+                SwH264Image(image).to(rgb);
+                ret = true;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct SwH264Image<'a>(DecodedYUV<'a>);
+
+impl GoogleImage for SwH264Image<'_> {
+    fn width(&self) -> usize {
+        self.0.dimensions().0
+    }
+
+    fn height(&self) -> usize {
+        self.0.dimensions().1
+    }
+
+    fn stride(&self) -> Vec<i32> {
+        let (y, u, v) = self.0.strides();
+        vec![y as _, u as _, v as _]
+    }
+
+    fn planes(&self) -> Vec<*mut u8> {
+        // read only by the conversion
+        vec![
+            self.0.y().as_ptr() as _,
+            self.0.u().as_ptr() as _,
+            self.0.v().as_ptr() as _,
+        ]
+    }
+
+    fn chroma(&self) -> Chroma {
+        Chroma::I420
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageFormat;
+
+    #[test]
+    fn test_yuvfmt() {
+        let fmt = SwH264Encoder::get_yuvfmt(1366, 767);
+        assert_eq!(fmt.stride, vec![1408, 704, 704]);
+        assert_eq!(fmt.u, 1408 * 767);
+        assert_eq!(fmt.v, fmt.u + 704 * 384);
+        // the layout converted from the captured frames
+        assert!(fmt.u >= fmt.stride[0] * fmt.h && fmt.v < fmt.h * fmt.stride[0] * 2);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let (w, h) = (64, 48);
+        let mut encoder = SwH264Encoder::new(
+            EncoderCfg::SWH264(SwH264EncoderConfig {
+                width: w,
+                height: h,
+                quality: 1.0,
+                keyframe_interval: None,
+                fps: 30,
+            }),
+            false,
+        )
+        .unwrap();
+        // a horizontal gray ramp over the video range
+        let fmt = encoder.yuvfmt();
+        let mut yuv = vec![128u8; fmt.v + fmt.stride[2] * ((h + 1) / 2)];
+        for y in 0..h {
+            for x in 0..w {
+                yuv[y * fmt.stride[0] + x] = (16 + x * 219 / (w - 1)) as u8;
+            }
+        }
+        let mut frames = vec![];
+        for i in 0..3 {
+            encoder.set_fps(15 + i as u32);
+            if let Some(frame) = encoder.encode(&yuv, i * 33).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert!(frames.first().map_or(false, |f| f.key));
+
+        let mut decoder = SwH264Decoder::new().unwrap();
+        let mut rgb = ImageRgb::new(ImageFormat::ARGB, 1);
+        let frames = EncodedVideoFrames {
+            frames: frames.into(),
+            ..Default::default()
+        };
+        assert!(decoder.decode(&frames, &mut rgb).unwrap());
+        assert_eq!((rgb.w, rgb.h), (w, h));
+        let stride = rgb.raw.len() / h;
+        let mut error = 0;
+        for y in 0..h {
+            for x in 0..w {
+                let g = rgb.raw[y * stride + x * 4 + 1] as i32;
+                error += (g - (x * 255 / (w - 1)) as i32).abs();
+            }
+        }
+        assert!(
+            error / ((w * h) as i32) < 8,
+            "mean error {}",
+            error / (w * h) as i32
+        );
+    }
+}
@@ -3,7 +3,8 @@ use crate::CodecFormat;
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono, log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{message, video_frame, AudioFrame, EncodedVideoFrame, Message},
+    sodiumoxide::crypto::box_,
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
@@ -16,9 +17,28 @@ use std::{
     sync::mpsc::Sender,
     time::Instant,
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
+
+mod crypto;
+mod ebml;
+mod index;
+mod retention;
+mod timeline;
+pub use crypto::{decrypt_file, is_encrypted_file, RecordFile, ENCRYPTED_EXT};
+use index::Index;
+pub use index::{
+    index_filename, is_plain_matroska, recording_info, repair, repair_dir_once, RecordingInfo,
+};
+pub use retention::{prune, RetentionPolicy, SegmentConfig};
+use timeline::Timeline;
+pub use timeline::{timeline_filename, RecordEvent, TimelineConfig};
 
 const MIN_SECS: u64 = 1;
+// Opus is always decoded at 48kHz, and stereo output plays mono packets as well,
+// so one track layout fits every audio format the peers negotiate.
+const OPUS_SAMPLE_RATE: u32 = 48000;
+const OPUS_CHANNELS: u8 = 2;
+pub const AUDIO_EXT: &str = ".audio.webm";
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -28,6 +48,16 @@ pub struct RecorderContext {
     pub display_idx: usize,
     pub camera: bool,
     pub tx: Option<Sender<RecordState>>,
+    /// Encrypt recordings to this public key when set.
+    pub public_key: Option<box_::PublicKey>,
+    /// Write an event timeline next to each recording when set.
+    pub timeline: Option<TimelineConfig>,
+    /// Record the Opus frames of `Recorder::write_audio`, in an audio track, or in an
+    /// audio only WebM next to the recordings of the hardware muxer, see `audio_filename`.
+    pub audio: bool,
+    pub segment: SegmentConfig,
+    /// Applied to `dir` whenever a new file is started.
+    pub retention: RetentionPolicy,
 }
 
 #[derive(Debug, Clone)]
@@ -53,13 +83,16 @@ impl RecorderContext2 {
                 ctx.display_idx
             )
             + &self.format.to_string().to_lowercase()
-            + if self.format == CodecFormat::VP9
-                || self.format == CodecFormat::VP8
-                || self.format == CodecFormat::AV1
-            {
-                ".webm"
+            + match self.format {
+                CodecFormat::VP9 | CodecFormat::VP8 | CodecFormat::AV1 => ".webm",
+                #[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+                CodecFormat::H264 => ".mkv",
+                _ => ".mp4",
+            }
+            + if ctx.public_key.is_some() {
+                ENCRYPTED_EXT
             } else {
-                ".mp4"
+                ""
             };
         self.filename = PathBuf::from(&ctx.dir)
             .join(file)
@@ -67,6 +100,14 @@ impl RecorderContext2 {
             .to_string();
         Ok(())
     }
+
+    // The filename without extensions, shared by the sidecars.
+    fn stem(&self) -> String {
+        PathBuf::from(self.filename.trim_end_matches(ENCRYPTED_EXT))
+            .with_extension("")
+            .to_string_lossy()
+            .to_string()
+    }
 }
 
 unsafe impl Send for Recorder {}
@@ -77,14 +118,20 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool;
 }
 
 #[derive(Debug)]
 pub enum RecordState {
     NewFile(String),
+    /// A new file continuing the previous one, which reached the segment limit.
+    NewSegment(String),
+    /// A finished file written next to the current one, like the audio of the hardware muxer.
+    Sidecar(String),
     NewFrame,
-    WriteTail,
-    RemoveFile,
+    /// The file is complete, which may come after the next file started.
+    WriteTail(String),
+    RemoveFile(String),
 }
 
 pub struct Recorder {
@@ -93,6 +140,15 @@ pub struct Recorder {
     ctx2: Option<RecorderContext2>,
     pts: Option<i64>,
     check_failed: bool,
+    timeline: Option<Timeline>,
+    index: Option<Index>,
+    // pts of the last written frame and when it was written, to place events between frames
+    last_frame: Option<(i64, Instant)>,
+    last_audio_pts: Option<i64>,
+    segment_start: Instant,
+    segment_size: u64,
+    // the next file is a new segment of the current one
+    next_segment: bool,
 }
 
 impl Deref for Recorder {
@@ -111,15 +167,76 @@ impl DerefMut for Recorder {
 
 impl Recorder {
     pub fn new(ctx: RecorderContext) -> ResultType<Self> {
+        index::repair_dir_once(&ctx.dir);
+        crypto::secure_dir_once(&ctx.dir, ctx.public_key);
         Ok(Self {
             inner: None,
             ctx,
             ctx2: None,
             pts: None,
             check_failed: false,
+            timeline: None,
+            index: None,
+            last_frame: None,
+            last_audio_pts: None,
+            segment_start: Instant::now(),
+            segment_size: 0,
+            next_segment: false,
         })
     }
 
+    fn close(&mut self) {
+        // drop the video first, so the sidecars know whether it was kept
+        let closed = self.inner.take().is_some();
+        self.last_frame = None;
+        self.last_audio_pts = None;
+        if let Some(timeline) = self.timeline.take() {
+            timeline.close();
+        }
+        let index = self.index.take();
+        let finished = self.ctx2.as_ref().filter(|_| closed).map(|ctx2| {
+            let kept = PathBuf::from(&ctx2.filename).exists();
+            let webm = matches!(
+                ctx2.format,
+                CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1
+            );
+            (
+                ctx2.filename.clone(),
+                kept,
+                webm && self.ctx.public_key.is_none(),
+            )
+        });
+        match finished {
+            Some((filename, true, true)) => {
+                // The muxer writes no cues, add them before the tail is uploaded. It reads the
+                // whole file, so not on the capture thread. The index ends after, a crash in
+                // between leaves the recording to the repair.
+                let tx = self.ctx.tx.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = ebml::finalize(&filename) {
+                        log::error!("Failed to index recording {filename}: {e}");
+                    }
+                    if let Some(index) = index {
+                        index.close();
+                    }
+                    tx.map(|tx| tx.send(RecordState::WriteTail(filename)));
+                });
+            }
+            _ => {
+                if let Some(index) = index {
+                    index.close();
+                }
+                match finished {
+                    Some((filename, true, _)) => self.send_state(RecordState::WriteTail(filename)),
+                    Some((filename, false, _)) => {
+                        self.send_state(RecordState::RemoveFile(filename))
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
     fn check(&mut self, w: usize, h: usize, format: CodecFormat) -> ResultType<()> {
         match self.ctx2 {
             Some(ref ctx2) => {
@@ -131,8 +248,8 @@ impl Recorder {
                         filename: Default::default(),
                     };
                     ctx2.set_filename(&self.ctx)?;
+                    self.close();
                     self.ctx2 = Some(ctx2);
-                    self.inner = None;
                 }
             }
             None => {
@@ -143,8 +260,8 @@ impl Recorder {
                     filename: Default::default(),
                 };
                 ctx2.set_filename(&self.ctx)?;
+                self.close();
                 self.ctx2 = Some(ctx2);
-                self.inner = None;
             }
         }
         let Some(ctx2) = &self.ctx2 else {
@@ -160,12 +277,35 @@ impl Recorder {
                     self.ctx.clone(),
                     (*ctx2).clone(),
                 )?)),
+                #[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+                CodecFormat::H264 => Some(Box::new(MkvRecorder::new(
+                    self.ctx.clone(),
+                    (*ctx2).clone(),
+                )?)),
                 #[cfg(not(feature = "hwcodec"))]
                 _ => bail!("unsupported codec type"),
             };
             // pts is None when new inner is created
             self.pts = None;
-            self.send_state(RecordState::NewFile(ctx2.filename.clone()));
+            if let Some(cfg) = &self.ctx.timeline {
+                self.timeline = Timeline::new(&self.ctx, &ctx2.filename, cfg)
+                    .map_err(|e| log::error!("Failed to create timeline: {e}"))
+                    .ok();
+            }
+            // the keyframe times would tell about an encrypted recording
+            if self.ctx.public_key.is_none() {
+                self.index = Index::new(ctx2)
+                    .map_err(|e| log::error!("Failed to create index: {e}"))
+                    .ok();
+            }
+            self.segment_start = Instant::now();
+            self.segment_size = 0;
+            if std::mem::take(&mut self.next_segment) {
+                self.send_state(RecordState::NewSegment(ctx2.filename.clone()));
+            } else {
+                self.send_state(RecordState::NewFile(ctx2.filename.clone()));
+            }
+            retention::prune(&self.ctx.dir, &self.ctx.retention, &ctx2.stem());
         }
         Ok(())
     }
@@ -200,34 +340,29 @@ impl Recorder {
         match frame {
             video_frame::Union::Vp8s(vp8s) => {
                 for f in vp8s.frames.iter() {
-                    self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f, w, h, format)?;
                 }
             }
             video_frame::Union::Vp9s(vp9s) => {
                 for f in vp9s.frames.iter() {
-                    self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f, w, h, format)?;
                 }
             }
             video_frame::Union::Av1s(av1s) => {
                 for f in av1s.frames.iter() {
-                    self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f, w, h, format)?;
                 }
             }
-            #[cfg(feature = "hwcodec")]
+            #[cfg(any(feature = "hwcodec", feature = "openh264"))]
             video_frame::Union::H264s(h264s) => {
                 for f in h264s.frames.iter() {
-                    self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f, w, h, format)?;
                 }
             }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H265s(h265s) => {
                 for f in h265s.frames.iter() {
-                    self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f, w, h, format)?;
                 }
             }
             _ => bail!("unsupported frame type"),
@@ -236,6 +371,44 @@ impl Recorder {
         Ok(())
     }
 
+    fn write_video(
+        &mut self,
+        frame: &EncodedVideoFrame,
+        w: usize,
+        h: usize,
+        format: CodecFormat,
+    ) -> ResultType<()> {
+        if frame.key
+            && self.inner.is_some()
+            && self
+                .ctx
+                .segment
+                .is_exceeded(self.segment_start.elapsed(), self.segment_size)
+        {
+            log::info!("segment limit reached, start a new record file");
+            self.close();
+            self.ctx2 = None;
+            self.next_segment = true;
+            let res = self.check(w, h, format);
+            if res.is_err() {
+                self.check_failed = true;
+                log::error!("check failed: {:?}", res);
+                res?;
+            }
+        }
+        self.check_pts(frame.pts, frame.key, w, h, format)?;
+        if self.as_mut().map_or(false, |x| x.write_video(frame)) {
+            self.segment_size += frame.data.len() as u64;
+            if let Some(index) = self.index.as_mut() {
+                if let Err(e) = index.frame(frame.pts, frame.key) {
+                    log::error!("Failed to write index: {e}");
+                    self.index = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn check_pts(
         &mut self,
         pts: i64,
@@ -252,7 +425,7 @@ impl Recorder {
         self.pts = Some(pts);
         if old_pts.clone().unwrap_or_default() > pts {
             log::info!("pts {:?} -> {}, change record filename", old_pts, pts);
-            self.inner = None;
+            self.close();
             self.ctx2 = None;
             let res = self.check(w, h, format);
             if res.is_err() {
@@ -262,17 +435,100 @@ impl Recorder {
             }
             self.pts = Some(pts);
         }
+        self.last_frame = Some((pts, Instant::now()));
         Ok(())
     }
 
+    // Audio frames and events carry no pts, place them after the last video frame.
+    #[inline]
+    fn current_pts(&self) -> Option<i64> {
+        self.last_frame
+            .map(|(pts, time)| pts + time.elapsed().as_millis() as i64)
+    }
+
+    /// Append an Opus frame to the audio track, aligned to the pts of the video being written.
+    pub fn write_audio(&mut self, frame: &AudioFrame) {
+        if !self.ctx.audio {
+            return;
+        }
+        let Some(mut pts) = self.current_pts() else {
+            return;
+        };
+        // keep audio monotonic if the video pts jitters behind the wall clock
+        if let Some(last) = self.last_audio_pts {
+            pts = pts.max(last + 1);
+        }
+        self.last_audio_pts = Some(pts);
+        self.as_mut().map(|x| x.write_audio(&frame.data, pts));
+    }
+
+    /// Append an event of the connection `conn` to the timeline, keyed to the pts of the video
+    /// being written.
+    pub fn write_event(&mut self, conn: i32, event: &RecordEvent) {
+        let Some(pts) = self.current_pts() else {
+            return;
+        };
+        let Some(timeline) = self.timeline.as_mut() else {
+            return;
+        };
+        if let Err(e) = timeline.write(pts, conn, event) {
+            log::error!("Failed to write timeline: {e}");
+            self.timeline = None;
+        }
+    }
+
     fn send_state(&self, state: RecordState) {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
 
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// `incoming_x_display0_h264.mp4[.enc]` -> `incoming_x_display0_h264.audio.webm[.enc]`, where
+/// the recordings without an audio track keep it.
+pub fn audio_filename(video: &str) -> String {
+    let encrypted = video.ends_with(ENCRYPTED_EXT);
+    let path = PathBuf::from(video.trim_end_matches(ENCRYPTED_EXT));
+    let mut filename = path.with_extension("").to_string_lossy().to_string() + AUDIO_EXT;
+    if encrypted {
+        filename += ENCRYPTED_EXT;
+    }
+    filename
+}
+
+fn opus_head() -> Vec<u8> {
+    // https://datatracker.ietf.org/doc/html/rfc7845#section-5.1
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(OPUS_CHANNELS);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&OPUS_SAMPLE_RATE.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
+}
+
+fn add_opus_track(webm: &mut Segment<Writer<RecordFile>>) -> ResultType<AudioTrack> {
+    let at = webm.add_audio_track(
+        OPUS_SAMPLE_RATE as _,
+        OPUS_CHANNELS as _,
+        None,
+        mux::AudioCodecId::Opus,
+    );
+    if !webm.set_codec_private(at.track_number(), &opus_head()) {
+        bail!("Failed to set opus codec private");
+    }
+    Ok(at)
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
-    webm: Option<Segment<Writer<File>>>,
+    at: Option<AudioTrack>,
+    webm: Option<Segment<Writer<RecordFile>>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     key: bool,
@@ -292,6 +548,7 @@ impl RecorderApi for WebmRecorder {
             Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(&ctx2.filename)?,
             Err(e) => return Err(e.into()),
         };
+        let out = RecordFile::create(out, ctx.public_key.as_ref())?;
         let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
             Some(v) => v,
             None => bail!("Failed to create webm mux"),
@@ -315,8 +572,15 @@ impl RecorderApi for WebmRecorder {
                 bail!("Failed to set codec private");
             }
         }
+        // tracks can not be added once frames are written
+        let at = if ctx.audio {
+            Some(add_opus_track(&mut webm)?)
+        } else {
+            None
+        };
         Ok(WebmRecorder {
             vt,
+            at,
             webm: Some(webm),
             ctx,
             ctx2,
@@ -342,23 +606,104 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        if !self.written {
+            return false;
+        }
+        self.at
+            .as_mut()
+            .map_or(false, |at| at.add_frame(data, pts as u64 * 1_000_000, true))
+    }
 }
 
 impl Drop for WebmRecorder {
     fn drop(&mut self) {
         let _ = std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
-        let mut state = RecordState::WriteTail;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
             std::fs::remove_file(&self.ctx2.filename).ok();
-            state = RecordState::RemoveFile;
         }
-        self.ctx.tx.as_ref().map(|tx| tx.send(state));
+    }
+}
+
+// H.264 without the hardware muxer
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+struct MkvRecorder {
+    writer: Option<ebml::AvcWriter<RecordFile>>,
+    ctx: RecorderContext,
+    ctx2: RecorderContext2,
+    written: bool,
+    start: Instant,
+}
+
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+impl RecorderApi for MkvRecorder {
+    fn new(ctx: RecorderContext, ctx2: RecorderContext2) -> ResultType<Self> {
+        let out = RecordFile::create(File::create(&ctx2.filename)?, ctx.public_key.as_ref())?;
+        Ok(MkvRecorder {
+            writer: Some(ebml::AvcWriter::new(
+                out,
+                ctx2.width,
+                ctx2.height,
+                ctx.audio,
+            )),
+            ctx,
+            ctx2,
+            written: false,
+            start: Instant::now(),
+        })
+    }
+
+    fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool {
+        let Some(writer) = self.writer.as_mut() else {
+            return false;
+        };
+        match writer.write(&frame.data, frame.pts, frame.key) {
+            Ok(ok) => {
+                self.written |= ok;
+                ok
+            }
+            Err(e) => {
+                log::error!("Failed to write recording {}: {e}", self.ctx2.filename);
+                false
+            }
+        }
+    }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        let Some(writer) = self.writer.as_mut() else {
+            return false;
+        };
+        writer.write_audio(data, pts).unwrap_or_else(|e| {
+            log::error!("Failed to write audio of {}: {e}", self.ctx2.filename);
+            false
+        })
+    }
+}
+
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+impl Drop for MkvRecorder {
+    fn drop(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(e) = writer.finish() {
+                log::error!("Failed to finish recording {}: {e}", self.ctx2.filename);
+            }
+        }
+        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
+            std::fs::remove_file(&self.ctx2.filename).ok();
+        }
     }
 }
 
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Option<Muxer>,
+    // The muxer writes by path, so an encrypted recording is muxed here first
+    // and encrypted into `ctx2.filename` when the tail is written, see `crypto`.
+    plain_filename: String,
+    // The muxer has no audio support, it goes to an audio only WebM next to the recording.
+    audio: Option<(Segment<Writer<RecordFile>>, AudioTrack)>,
+    audio_failed: bool,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     written: bool,
@@ -369,8 +714,15 @@ struct HwRecorder {
 #[cfg(feature = "hwcodec")]
 impl RecorderApi for HwRecorder {
     fn new(ctx: RecorderContext, ctx2: RecorderContext2) -> ResultType<Self> {
+        let plain_filename = if ctx.public_key.is_some() {
+            let plain_filename = crypto::plain_filename(&ctx2.filename);
+            crypto::create_plain_file(&plain_filename)?;
+            plain_filename
+        } else {
+            ctx2.filename.clone()
+        };
         let muxer = Muxer::new(MuxContext {
-            filename: ctx2.filename.clone(),
+            filename: plain_filename.clone(),
             width: ctx2.width,
             height: ctx2.height,
             is265: ctx2.format == CodecFormat::H265,
@@ -379,6 +731,9 @@ impl RecorderApi for HwRecorder {
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
         Ok(HwRecorder {
             muxer: Some(muxer),
+            plain_filename,
+            audio: None,
+            audio_failed: false,
             ctx,
             ctx2,
             written: false,
@@ -405,19 +760,75 @@ impl RecorderApi for HwRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        if !self.written || self.audio_failed {
+            return false;
+        }
+        if self.audio.is_none() {
+            match self.create_audio() {
+                Ok(audio) => self.audio = Some(audio),
+                Err(e) => {
+                    log::error!("Failed to create audio recording: {e}");
+                    self.audio_failed = true;
+                    return false;
+                }
+            }
+        }
+        self.audio.as_mut().map_or(false, |(_, at)| {
+            at.add_frame(data, pts as u64 * 1_000_000, true)
+        })
+    }
+}
+
+#[cfg(feature = "hwcodec")]
+impl HwRecorder {
+    fn create_audio(&self) -> ResultType<(Segment<Writer<RecordFile>>, AudioTrack)> {
+        let out = RecordFile::create(
+            File::create(audio_filename(&self.ctx2.filename))?,
+            self.ctx.public_key.as_ref(),
+        )?;
+        let Some(mut webm) = mux::Segment::new(mux::Writer::new(out)) else {
+            bail!("Failed to create webm mux");
+        };
+        let at = add_opus_track(&mut webm)?;
+        Ok((webm, at))
+    }
 }
 
 #[cfg(feature = "hwcodec")]
 impl Drop for HwRecorder {
     fn drop(&mut self) {
         self.muxer.as_mut().map(|m| m.write_tail().ok());
-        let mut state = RecordState::WriteTail;
+        let audio = self.audio.take().map(|(webm, _)| {
+            webm.finalize(None);
+            audio_filename(&self.ctx2.filename)
+        });
+        let mut removed = false;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
             // The process cannot access the file because it is being used by another process
             self.muxer = None;
+            std::fs::remove_file(&self.plain_filename).ok();
             std::fs::remove_file(&self.ctx2.filename).ok();
-            state = RecordState::RemoveFile;
+            removed = true;
+        } else if let Some(pk) = &self.ctx.public_key {
+            self.muxer = None;
+            if let Err(e) = crypto::encrypt_file(&self.plain_filename, &self.ctx2.filename, pk) {
+                log::error!("Failed to encrypt recording {}: {e}", self.ctx2.filename);
+                std::fs::remove_file(&self.ctx2.filename).ok();
+                removed = true;
+            }
+            std::fs::remove_file(&self.plain_filename).ok();
+        }
+        if let Some(audio) = audio {
+            if removed {
+                std::fs::remove_file(&audio).ok();
+            } else {
+                self.ctx
+                    .tx
+                    .as_ref()
+                    .map(|tx| tx.send(RecordState::Sidecar(audio)));
+            }
         }
-        self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
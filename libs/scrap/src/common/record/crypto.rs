@@ -0,0 +1,462 @@
+//! Encryption at rest for recordings.
+//!
+//! An encrypted recording starts with a fixed header followed by a sequence of records,
+//! each the length of its box followed by a secretbox of the container offset and the
+//! bytes written there. The random key is sealed to the recipient public key, so only
+//! the holder of the matching secret key can decrypt the file.
+//!
+//! The nonce of a record is the header nonce with the record counter mixed in, so records
+//! can not be reordered, and the last record is an empty one sealed with a distinct nonce,
+//! so a truncated file is told apart from a finished one.
+//!
+//! Records are only ever appended. When the muxers seek back to rewrite the segment header
+//! on finalization, the new bytes go in a record of their own which overrides the older
+//! ones on decryption. Nothing written is touched again, so `record_upload` can ship the
+//! file as it grows without knowing about the encryption.
+//!
+//! The hardware muxer writes by path and can only be encrypted once finished. Its plain
+//! file is readable by the owner only, and one left behind by a crash is encrypted the
+//! next time a recorder starts in the same directory.
+
+use super::retention::ACTIVE_THRESHOLD;
+use hbb_common::{
+    bail, log,
+    sodiumoxide::crypto::{
+        box_, sealedbox,
+        secretbox::{self, Key, Nonce},
+    },
+    ResultType,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+pub const ENCRYPTED_MAGIC: &[u8; 8] = b"RDREC\0\x02\0";
+pub const ENCRYPTED_EXT: &str = ".enc";
+const SEALED_KEY_LEN: usize = secretbox::KEYBYTES + sealedbox::SEALBYTES;
+pub const ENCRYPTED_HEADER_LEN: usize =
+    ENCRYPTED_MAGIC.len() + secretbox::NONCEBYTES + SEALED_KEY_LEN;
+const OFFSET_LEN: usize = 8;
+// Data is buffered up to this before it's sealed in a record
+const CHUNK_LEN: usize = 64 * 1024;
+const PLAIN_EXT: &str = ".plain";
+
+static SECURED_DIRS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Cipher {
+    key: Key,
+    nonce: Nonce,
+    counter: u64,
+}
+
+impl Cipher {
+    fn nonce(&self, counter: u64, last: bool) -> Nonce {
+        let mut nonce = self.nonce;
+        for (n, c) in nonce.0.iter_mut().zip(counter.to_le_bytes()) {
+            *n ^= c;
+        }
+        if last {
+            nonce.0[OFFSET_LEN] ^= 1;
+        }
+        nonce
+    }
+
+    // The length of the box, then the box of the offset and the data.
+    fn seal(&mut self, offset: u64, data: &[u8], last: bool) -> Vec<u8> {
+        let mut plain = Vec::with_capacity(OFFSET_LEN + data.len());
+        plain.extend_from_slice(&offset.to_le_bytes());
+        plain.extend_from_slice(data);
+        let sealed = secretbox::seal(&plain, &self.nonce(self.counter, last), &self.key);
+        self.counter += 1;
+        let mut record = (sealed.len() as u32).to_le_bytes().to_vec();
+        record.extend(sealed);
+        record
+    }
+
+    // The offset and the data of the record `counter`, and whether it's the last one.
+    fn open(&self, counter: u64, sealed: &[u8]) -> Option<(Vec<u8>, bool)> {
+        [false, true].into_iter().find_map(|last| {
+            secretbox::open(sealed, &self.nonce(counter, last), &self.key)
+                .ok()
+                .map(|plain| (plain, last))
+        })
+    }
+}
+
+/// The file a recorder writes to, optionally encrypted.
+///
+/// Positions reported by `Seek` are those of the container, so the muxer sees the same
+/// offsets as for a plain file.
+pub struct RecordFile {
+    file: File,
+    cipher: Option<Cipher>,
+    pos: u64,
+    len: u64,
+    // not sealed yet, contiguous from `pending_pos`
+    pending: Vec<u8>,
+    pending_pos: u64,
+    finished: bool,
+}
+
+impl RecordFile {
+    pub fn create(file: File, public_key: Option<&box_::PublicKey>) -> ResultType<Self> {
+        let mut file = file;
+        let cipher = match public_key {
+            Some(pk) => {
+                let cipher = Cipher {
+                    key: secretbox::gen_key(),
+                    nonce: secretbox::gen_nonce(),
+                    counter: 0,
+                };
+                let sealed = sealedbox::seal(&cipher.key.0, pk);
+                file.write_all(ENCRYPTED_MAGIC)?;
+                file.write_all(&cipher.nonce.0)?;
+                file.write_all(&sealed)?;
+                Some(cipher)
+            }
+            None => None,
+        };
+        Ok(Self {
+            file,
+            cipher,
+            pos: 0,
+            len: 0,
+            pending: vec![],
+            pending_pos: 0,
+            finished: false,
+        })
+    }
+
+    fn seal_pending(&mut self) -> io::Result<()> {
+        let Some(cipher) = self.cipher.as_mut() else {
+            return Ok(());
+        };
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let record = cipher.seal(self.pending_pos, &self.pending, false);
+        self.pending.clear();
+        self.file.write_all(&record)
+    }
+
+    /// Seal what's pending and the last record, done on drop as well.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.seal_pending()?;
+        if let Some(cipher) = self.cipher.as_mut() {
+            let record = cipher.seal(self.len, &[], true);
+            self.file.write_all(&record)?;
+        }
+        self.finished = true;
+        self.file.flush()
+    }
+}
+
+impl Write for RecordFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.cipher.is_none() {
+            let n = self.file.write(buf)?;
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        if self.finished {
+            return Err(io::Error::other("recording finished"));
+        }
+        let pending_end = self.pending_pos + self.pending.len() as u64;
+        if !self.pending.is_empty() && (self.pos < self.pending_pos || self.pos > pending_end) {
+            self.seal_pending()?;
+        }
+        if self.pending.is_empty() {
+            self.pending_pos = self.pos;
+        }
+        // records are at most a chunk long, `write_all` takes care of the rest
+        let start = (self.pos - self.pending_pos) as usize;
+        let n = buf.len().min(CHUNK_LEN - start);
+        if start + n > self.pending.len() {
+            self.pending.resize(start + n, 0);
+        }
+        self.pending[start..start + n].copy_from_slice(&buf[..n]);
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        if self.pending.len() >= CHUNK_LEN {
+            self.seal_pending()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.seal_pending()?;
+        self.file.flush()
+    }
+}
+
+impl Seek for RecordFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if self.cipher.is_none() {
+            self.pos = self.file.seek(pos)?;
+            return Ok(self.pos);
+        }
+        // the file itself is only appended to, see the module docs
+        let n = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+        };
+        let Some(n) = n else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start",
+            ));
+        };
+        self.pos = n;
+        Ok(n)
+    }
+}
+
+impl Drop for RecordFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Failed to finish recording file: {e}");
+        }
+    }
+}
+
+pub fn is_encrypted_file<P: AsRef<Path>>(path: P) -> bool {
+    let mut magic = [0u8; ENCRYPTED_MAGIC.len()];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map_or(false, |_| &magic == ENCRYPTED_MAGIC)
+}
+
+/// Encrypt a finished plain recording, used for recorders that write by path.
+pub fn encrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    public_key: &box_::PublicKey,
+) -> ResultType<()> {
+    let mut reader = File::open(input)?;
+    let mut writer = RecordFile::create(File::create(output)?, Some(public_key))?;
+    let mut buf = vec![0u8; CHUNK_LEN];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// The extension with its dot, if any.
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+/// The plain file muxed by path before it's encrypted into `filename`, like `x.mp4.enc` to
+/// `x.plain.mp4`, the muxer guesses the container from the extension.
+pub(super) fn plain_filename(filename: &str) -> String {
+    let path = PathBuf::from(filename.trim_end_matches(ENCRYPTED_EXT));
+    path.with_extension("").to_string_lossy().to_string() + PLAIN_EXT + &extension(&path)
+}
+
+// The reverse of `plain_filename`.
+fn encrypted_filename(plain: &Path) -> String {
+    let stem = plain.with_extension("").to_string_lossy().to_string();
+    stem.trim_end_matches(PLAIN_EXT).to_owned() + &extension(plain) + ENCRYPTED_EXT
+}
+
+/// Create the plain file readable by the owner only, before the muxer opens it.
+pub(super) fn create_plain_file(filename: &str) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(filename).map(|_| ())
+}
+
+fn is_leftover_plain_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    let is_plain = PathBuf::from(name)
+        .file_stem()
+        .map_or(false, |s| s.to_string_lossy().ends_with(PLAIN_EXT));
+    // a recorder still writing it, in this process or another one
+    let active = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_or(true, |t| {
+            SystemTime::now().duration_since(t).unwrap_or_default() < ACTIVE_THRESHOLD
+        });
+    is_plain && !active
+}
+
+/// Encrypt the plain files in `dir` left behind by a crash to `public_key`, once per directory.
+/// They are removed if recordings are not encrypted anymore.
+pub(super) fn secure_dir_once(dir: &str, public_key: Option<box_::PublicKey>) {
+    {
+        let mut dirs = SECURED_DIRS.lock().unwrap();
+        if dirs.iter().any(|d| d == dir) {
+            return;
+        }
+        dirs.push(dir.to_owned());
+    }
+    let dir = dir.to_owned();
+    std::thread::spawn(move || {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return;
+        };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if !is_leftover_plain_file(&path) {
+                continue;
+            }
+            let plain = path.to_string_lossy().to_string();
+            if let Some(pk) = &public_key {
+                let encrypted = encrypted_filename(&path);
+                match encrypt_file(&plain, &encrypted, pk) {
+                    Ok(()) => log::info!("Encrypted leftover recording {encrypted}"),
+                    Err(e) => {
+                        log::error!("Failed to encrypt leftover recording {plain}: {e}");
+                        std::fs::remove_file(&encrypted).ok();
+                    }
+                }
+            } else {
+                log::warn!("Removing leftover plain recording {plain}, no key to encrypt it to");
+            }
+            std::fs::remove_file(&plain).ok();
+        }
+    });
+}
+
+/// Decrypt an encrypted recording into a playable file.
+///
+/// Returns false if the recording has no last record, because the recorder crashed or the
+/// file was truncated, what was written before is decrypted all the same.
+pub fn decrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    public_key: &box_::PublicKey,
+    secret_key: &box_::SecretKey,
+) -> ResultType<bool> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut magic = [0u8; ENCRYPTED_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != ENCRYPTED_MAGIC {
+        bail!("not an encrypted recording");
+    }
+    let mut nonce = [0u8; secretbox::NONCEBYTES];
+    reader.read_exact(&mut nonce)?;
+    let mut sealed = [0u8; SEALED_KEY_LEN];
+    reader.read_exact(&mut sealed)?;
+    let Ok(key) = sealedbox::open(&sealed, public_key, secret_key) else {
+        bail!("failed to open the recording key, wrong secret key?");
+    };
+    let Some(key) = Key::from_slice(&key) else {
+        bail!("invalid recording key");
+    };
+    let cipher = Cipher {
+        key,
+        nonce: Nonce(nonce),
+        counter: 0,
+    };
+    let mut writer = File::create(output)?;
+    let mut counter = 0u64;
+    loop {
+        let mut len = [0u8; 4];
+        if reader.read_exact(&mut len).is_err() {
+            return Ok(false);
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        let overhead = secretbox::MACBYTES + OFFSET_LEN;
+        if len < overhead || len > overhead + CHUNK_LEN {
+            bail!("invalid record {counter}");
+        }
+        let mut sealed = vec![0u8; len];
+        if reader.read_exact(&mut sealed).is_err() {
+            return Ok(false);
+        }
+        let Some((plain, last)) = cipher.open(counter, &sealed) else {
+            bail!("record {counter} is corrupted or out of order");
+        };
+        let (offset, data) = plain.split_at(OFFSET_LEN);
+        let offset = u64::from_le_bytes(offset.try_into()?);
+        if last {
+            if !data.is_empty() || reader.read(&mut [0u8; 1])? != 0 {
+                bail!("unexpected data after the last record");
+            }
+            writer.set_len(offset)?;
+            writer.flush()?;
+            return Ok(true);
+        }
+        writer.seek(SeekFrom::Start(offset))?;
+        writer.write_all(data)?;
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_seek_and_decrypt() {
+        let dir = std::env::temp_dir();
+        let enc = dir.join(format!("record_crypto_{}.webm.enc", std::process::id()));
+        let dec = dir.join(format!("record_crypto_{}.webm", std::process::id()));
+        let (pk, sk) = box_::gen_keypair();
+
+        let mut expected: Vec<u8> = (0..200_000u32).map(|x| (x % 251) as u8).collect();
+        let mut f = RecordFile::create(File::create(&enc).unwrap(), Some(&pk)).unwrap();
+        f.write_all(&expected).unwrap();
+        f.flush().unwrap();
+        let written = std::fs::read(&enc).unwrap();
+        // rewrite a sealed chunk, like a muxer fixing up its header
+        assert_eq!(f.seek(SeekFrom::Start(70)).unwrap(), 70);
+        f.write_all(&[0xAA; 10]).unwrap();
+        expected[70..80].copy_from_slice(&[0xAA; 10]);
+        assert_eq!(f.seek(SeekFrom::End(0)).unwrap(), 200_000);
+        f.write_all(&[0xBB; 5]).unwrap();
+        expected.extend_from_slice(&[0xBB; 5]);
+        assert!(f.seek(SeekFrom::Current(-200_006)).is_err());
+        drop(f);
+
+        let data = std::fs::read(&enc).unwrap();
+        assert!(data.starts_with(&written), "only appended to");
+        assert!(is_encrypted_file(&enc));
+        assert!(decrypt_file(&enc, &dec, &pk, &sk).unwrap());
+        assert_eq!(std::fs::read(&dec).unwrap(), expected);
+
+        let (other_pk, other_sk) = box_::gen_keypair();
+        assert!(decrypt_file(&enc, &dec, &other_pk, &other_sk).is_err());
+
+        // a flipped bit
+        let mut tampered = data.clone();
+        tampered[ENCRYPTED_HEADER_LEN + 100] ^= 1;
+        std::fs::write(&enc, &tampered).unwrap();
+        assert!(decrypt_file(&enc, &dec, &pk, &sk).is_err());
+
+        // the last record dropped
+        let last = 4 + secretbox::MACBYTES + OFFSET_LEN;
+        std::fs::write(&enc, &data[..data.len() - last]).unwrap();
+        assert!(!decrypt_file(&enc, &dec, &pk, &sk).unwrap());
+        std::fs::remove_file(&enc).ok();
+        std::fs::remove_file(&dec).ok();
+    }
+
+    #[test]
+    fn test_plain_filename() {
+        let plain = plain_filename("/tmp/x_h264.mp4.enc");
+        assert_eq!(plain, "/tmp/x_h264.plain.mp4");
+        assert_eq!(encrypted_filename(Path::new(&plain)), "/tmp/x_h264.mp4.enc");
+        assert!(!is_leftover_plain_file(Path::new("/tmp/x_h264.mp4")));
+        assert!(!is_leftover_plain_file(Path::new("/none/x.plain.mp4")));
+    }
+}
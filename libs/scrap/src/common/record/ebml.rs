@@ -0,0 +1,1133 @@
+//! Minimal EBML scanning and patching of the WebM files written by the recorder.
+//!
+//! The muxer writes no cues, and only fixes up the cluster and segment sizes when it
+//! is finalized, so a crashed recording is hard to seek in or even to play. `finalize`
+//! walks the clusters, drops a truncated tail, and patches the sizes, duration, cues
+//! and seek head in place.
+//!
+//! `AvcWriter` writes H.264, which the WebM muxer does not take, and the Opus audio into
+//! Matroska the same way, leaving the rest to `finalize`.
+
+use hbb_common::{bail, ResultType};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    sync::Mutex,
+};
+
+const ID_EBML: u32 = 0x1A45DFA3;
+const ID_DOC_TYPE: u32 = 0x4282;
+const ID_DOC_TYPE_VERSION: u32 = 0x4287;
+const ID_DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_SEEK_HEAD: u32 = 0x114D9B74;
+const ID_SEEK: u32 = 0x4DBB;
+const ID_SEEK_ID: u32 = 0x53AB;
+const ID_SEEK_POSITION: u32 = 0x53AC;
+const ID_INFO: u32 = 0x1549A966;
+const ID_TIMECODE_SCALE: u32 = 0x2AD7B1;
+const ID_DURATION: u32 = 0x4489;
+const ID_TRACKS: u32 = 0x1654AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_TRACK_UID: u32 = 0x73C5;
+const ID_TRACK_TYPE: u32 = 0x83;
+const ID_CODEC_ID: u32 = 0x86;
+const ID_CODEC_PRIVATE: u32 = 0x63A2;
+const ID_VIDEO: u32 = 0xE0;
+const ID_PIXEL_WIDTH: u32 = 0xB0;
+const ID_PIXEL_HEIGHT: u32 = 0xBA;
+const ID_AUDIO: u32 = 0xE1;
+const ID_SAMPLING_FREQUENCY: u32 = 0xB5;
+const ID_CHANNELS: u32 = 0x9F;
+const ID_CLUSTER: u32 = 0x1F43B675;
+const ID_TIMECODE: u32 = 0xE7;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+const ID_REFERENCE_BLOCK: u32 = 0xFB;
+const ID_CUES: u32 = 0x1C53BB6B;
+const ID_CUE_POINT: u32 = 0xBB;
+const ID_CUE_TIME: u32 = 0xB3;
+const ID_CUE_TRACK_POSITIONS: u32 = 0xB7;
+const ID_CUE_TRACK: u32 = 0xF7;
+const ID_CUE_CLUSTER_POSITION: u32 = 0xF1;
+const ID_TAGS: u32 = 0x1254C367;
+const ID_VOID: u32 = 0xEC;
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+pub(super) const CODEC_AVC: &str = "V_MPEG4/ISO/AVC";
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+const CODEC_OPUS: &str = "A_OPUS";
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+const VIDEO_TRACK: u64 = 1;
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+const AUDIO_TRACK: u64 = 2;
+// Room for the seek head written by `finalize`
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+const SEEK_HEAD_SPACE: usize = 100;
+
+static FINALIZE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone)]
+struct Element {
+    id: u32,
+    pos: u64,
+    size_pos: u64,
+    size_len: usize,
+    data: u64,
+    // None if unknown
+    size: Option<u64>,
+}
+
+impl Element {
+    #[inline]
+    fn end(&self) -> Option<u64> {
+        self.size.map(|s| self.data + s)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Track {
+    pub number: u64,
+    pub kind: u64,
+    pub codec: String,
+    pub width: u64,
+    pub height: u64,
+}
+
+impl Track {
+    #[inline]
+    pub fn is_video(&self) -> bool {
+        self.kind == TRACK_TYPE_VIDEO
+    }
+
+    #[inline]
+    pub fn is_audio(&self) -> bool {
+        self.kind == TRACK_TYPE_AUDIO
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    track: u64,
+    // in timecode scale units
+    time: i64,
+    key: bool,
+    cluster: u64,
+}
+
+pub(super) struct WebmScan {
+    segment: Element,
+    // the seek head and the voids around it at the start of the segment
+    seek_head_space: Option<(u64, u64)>,
+    info_pos: Option<u64>,
+    tracks_pos: Option<u64>,
+    // position and size of the Duration float
+    duration_pos: Option<(u64, u64)>,
+    timecode_scale: u64,
+    pub tracks: Vec<Track>,
+    pub has_cues: bool,
+    blocks: Vec<Block>,
+    // clusters whose size is unknown, and where they really end
+    open_clusters: Vec<(Element, u64)>,
+    // end of the last complete element
+    end: u64,
+    truncated: bool,
+}
+
+impl WebmScan {
+    pub fn video_track(&self) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.is_video())
+    }
+
+    #[inline]
+    fn to_ms(&self, time: i64) -> i64 {
+        (time as i128 * self.timecode_scale as i128 / 1_000_000) as i64
+    }
+
+    fn duration_units(&self) -> i64 {
+        self.blocks.iter().map(|b| b.time).max().unwrap_or_default()
+    }
+
+    /// From the first block to the last, recordings do not necessarily start at 0.
+    pub fn duration_ms(&self) -> i64 {
+        let start = self.blocks.iter().map(|b| b.time).min().unwrap_or_default();
+        self.to_ms(self.duration_units() - start)
+    }
+
+    // The first video keyframe of each cluster, which is where a player can seek to.
+    fn cue_blocks(&self) -> Vec<Block> {
+        let Some(video) = self.video_track() else {
+            return vec![];
+        };
+        let mut cues: Vec<Block> = vec![];
+        for b in self.blocks.iter() {
+            if b.track == video.number && b.key && cues.last().map(|c| c.cluster) != Some(b.cluster)
+            {
+                cues.push(*b);
+            }
+        }
+        cues
+    }
+
+    pub fn keyframes_ms(&self) -> Vec<i64> {
+        let Some(video) = self.video_track() else {
+            return vec![];
+        };
+        self.blocks
+            .iter()
+            .filter(|b| b.track == video.number && b.key)
+            .map(|b| self.to_ms(b.time))
+            .collect()
+    }
+
+    /// Whether the muxer was finalized and the cues are in place.
+    pub fn is_complete(&self) -> bool {
+        !self.truncated
+            && self.has_cues
+            && self.open_clusters.is_empty()
+            && self.segment.end() == Some(self.end)
+    }
+}
+
+struct Reader<'a> {
+    inner: BufReader<&'a mut File>,
+    pos: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn new(file: &'a mut File) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            inner: BufReader::new(file),
+            pos: 0,
+        })
+    }
+
+    fn seek(&mut self, pos: u64) -> io::Result<()> {
+        if pos != self.pos {
+            self.inner.seek_relative(pos as i64 - self.pos as i64)?;
+            self.pos = pos;
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; n];
+        self.inner.read_exact(&mut buf)?;
+        self.pos += n as u64;
+        Ok(buf)
+    }
+
+    // Returns the value, with the length marker if `keep_marker`, and the length.
+    fn read_vint(&mut self, keep_marker: bool) -> io::Result<(u64, usize)> {
+        let first = self.read_bytes(1)?[0];
+        if first == 0 {
+            return Err(invalid("invalid variable size integer"));
+        }
+        let len = first.leading_zeros() as usize + 1;
+        let mut value = if keep_marker {
+            first as u64
+        } else {
+            first as u64 & (0xFF >> len)
+        };
+        for b in self.read_bytes(len - 1)? {
+            value = (value << 8) | b as u64;
+        }
+        Ok((value, len))
+    }
+
+    fn read_element(&mut self) -> io::Result<Element> {
+        let pos = self.pos;
+        let (id, id_len) = self.read_vint(true)?;
+        if id_len > 4 {
+            return Err(invalid("invalid element id"));
+        }
+        let size_pos = self.pos;
+        let (size, size_len) = self.read_vint(false)?;
+        Ok(Element {
+            id: id as _,
+            pos,
+            size_pos,
+            size_len,
+            data: self.pos,
+            size: if size == unknown_size(size_len) {
+                None
+            } else {
+                Some(size)
+            },
+        })
+    }
+
+    fn read_uint(&mut self, size: u64) -> io::Result<u64> {
+        if size > 8 {
+            return Err(invalid("integer too long"));
+        }
+        Ok(self
+            .read_bytes(size as _)?
+            .iter()
+            .fold(0, |v, b| (v << 8) | *b as u64))
+    }
+
+    // Children of a master element with a known size.
+    fn children(&mut self, parent: &Element) -> io::Result<Vec<Element>> {
+        let Some(end) = parent.end() else {
+            return Err(invalid("unknown size"));
+        };
+        let mut children = vec![];
+        let mut pos = parent.data;
+        while pos < end {
+            self.seek(pos)?;
+            let e = self.read_element()?;
+            let Some(e_end) = e.end().filter(|x| *x <= end) else {
+                return Err(invalid("element exceeds its parent"));
+            };
+            pos = e_end;
+            children.push(e);
+        }
+        Ok(children)
+    }
+}
+
+#[inline]
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[inline]
+fn unknown_size(len: usize) -> u64 {
+    (1u64 << (7 * len)) - 1
+}
+
+#[inline]
+fn is_top_level(id: u32) -> bool {
+    [
+        ID_CLUSTER,
+        ID_CUES,
+        ID_TAGS,
+        ID_INFO,
+        ID_TRACKS,
+        ID_SEEK_HEAD,
+    ]
+    .contains(&id)
+}
+
+pub(super) fn scan(file: &mut File) -> ResultType<WebmScan> {
+    let len = file.metadata()?.len();
+    let mut r = Reader::new(file)?;
+    let ebml = r.read_element()?;
+    let Some(ebml_end) = ebml.end().filter(|_| ebml.id == ID_EBML) else {
+        bail!("not a WebM file");
+    };
+    r.seek(ebml_end)?;
+    let segment = r.read_element()?;
+    if segment.id != ID_SEGMENT {
+        bail!("no segment found");
+    }
+    let segment_end = segment.end().filter(|e| *e <= len).unwrap_or(len);
+    let mut scan = WebmScan {
+        segment: segment.clone(),
+        seek_head_space: None,
+        info_pos: None,
+        tracks_pos: None,
+        duration_pos: None,
+        timecode_scale: DEFAULT_TIMECODE_SCALE,
+        tracks: vec![],
+        has_cues: false,
+        blocks: vec![],
+        open_clusters: vec![],
+        end: segment.data,
+        truncated: false,
+    };
+    let mut pos = segment.data;
+    while pos < segment_end {
+        r.seek(pos)?;
+        let Ok(e) = r.read_element() else {
+            scan.truncated = true;
+            break;
+        };
+        if e.id == ID_CLUSTER {
+            let (end, complete) = scan_cluster(&mut r, &e, segment_end, &mut scan)?;
+            scan.end = end;
+            if e.end() != Some(end) {
+                scan.open_clusters.push((e, end));
+            }
+            if !complete {
+                scan.truncated = true;
+                break;
+            }
+            pos = end;
+            continue;
+        }
+        let Some(end) = e.end().filter(|x| *x <= segment_end) else {
+            scan.truncated = true;
+            break;
+        };
+        match e.id {
+            ID_SEEK_HEAD | ID_VOID => {
+                let contiguous = match scan.seek_head_space {
+                    Some((_, space_end)) => space_end == e.pos,
+                    None => e.pos == segment.data,
+                };
+                if contiguous {
+                    let start = scan.seek_head_space.map_or(e.pos, |(s, _)| s);
+                    scan.seek_head_space = Some((start, end));
+                }
+            }
+            ID_INFO => {
+                scan.info_pos = Some(e.pos);
+                for c in r.children(&e)? {
+                    match (c.id, c.size) {
+                        (ID_TIMECODE_SCALE, Some(size)) => {
+                            r.seek(c.data)?;
+                            scan.timecode_scale = r.read_uint(size)?.max(1);
+                        }
+                        (ID_DURATION, Some(size)) => scan.duration_pos = Some((c.data, size)),
+                        _ => {}
+                    }
+                }
+            }
+            ID_TRACKS => {
+                scan.tracks_pos = Some(e.pos);
+                for entry in r.children(&e)? {
+                    if entry.id == ID_TRACK_ENTRY {
+                        scan.tracks.push(read_track(&mut r, &entry)?);
+                    }
+                }
+            }
+            ID_CUES => scan.has_cues = true,
+            _ => {}
+        }
+        scan.end = end;
+        pos = end;
+    }
+    Ok(scan)
+}
+
+fn read_track(r: &mut Reader, entry: &Element) -> io::Result<Track> {
+    let mut track = Track::default();
+    for c in r.children(entry)? {
+        let size = c.size.unwrap_or_default();
+        r.seek(c.data)?;
+        match c.id {
+            ID_TRACK_NUMBER => track.number = r.read_uint(size)?,
+            ID_TRACK_TYPE => track.kind = r.read_uint(size)?,
+            ID_CODEC_ID => {
+                track.codec = String::from_utf8_lossy(&r.read_bytes(size as _)?)
+                    .trim_end_matches('\0')
+                    .to_owned()
+            }
+            ID_VIDEO => {
+                for v in r.children(&c)? {
+                    r.seek(v.data)?;
+                    match v.id {
+                        ID_PIXEL_WIDTH => track.width = r.read_uint(v.size.unwrap_or_default())?,
+                        ID_PIXEL_HEIGHT => {
+                            track.height = r.read_uint(v.size.unwrap_or_default())?
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(track)
+}
+
+// Returns where the cluster really ends, and false if it was cut off.
+fn scan_cluster(
+    r: &mut Reader,
+    cluster: &Element,
+    segment_end: u64,
+    scan: &mut WebmScan,
+) -> ResultType<(u64, bool)> {
+    let declared_end = cluster.end().filter(|e| *e <= segment_end);
+    let limit = declared_end.unwrap_or(segment_end);
+    let mut timecode = 0i64;
+    let mut pos = cluster.data;
+    while pos < limit {
+        r.seek(pos)?;
+        let Ok(e) = r.read_element() else {
+            return Ok((pos, false));
+        };
+        // a cluster of unknown size ends at the next top level element
+        if cluster.size.is_none() && is_top_level(e.id) {
+            return Ok((pos, true));
+        }
+        let Some(end) = e.end().filter(|x| *x <= limit) else {
+            return Ok((pos, false));
+        };
+        let size = end - e.data;
+        match e.id {
+            ID_TIMECODE => timecode = r.read_uint(size)? as i64,
+            ID_SIMPLE_BLOCK => {
+                let (track, time, flags) = read_block_header(r)?;
+                scan.blocks.push(Block {
+                    track,
+                    time: timecode + time,
+                    key: flags & 0x80 != 0,
+                    cluster: cluster.pos,
+                });
+            }
+            ID_BLOCK_GROUP => {
+                let mut block = None;
+                let mut key = true;
+                for c in r.children(&e)? {
+                    match c.id {
+                        ID_BLOCK => {
+                            r.seek(c.data)?;
+                            block = Some(read_block_header(r)?);
+                        }
+                        ID_REFERENCE_BLOCK => key = false,
+                        _ => {}
+                    }
+                }
+                if let Some((track, time, _)) = block {
+                    scan.blocks.push(Block {
+                        track,
+                        time: timecode + time,
+                        key,
+                        cluster: cluster.pos,
+                    });
+                }
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+    Ok((pos, declared_end.is_some() || pos == segment_end))
+}
+
+// Whether the element at `pos` runs past the end of the file, as the one being written
+// when the recorder crashed does.
+fn is_cut_off(file: &mut File, pos: u64, len: u64) -> io::Result<bool> {
+    let mut r = Reader::new(file)?;
+    r.seek(pos)?;
+    match r.read_element() {
+        Ok(e) => Ok(e.end().map_or(false, |end| end > len)),
+        Err(e) => Ok(e.kind() == io::ErrorKind::UnexpectedEof),
+    }
+}
+
+fn read_block_header(r: &mut Reader) -> io::Result<(u64, i64, u8)> {
+    let (track, _) = r.read_vint(false)?;
+    let header = r.read_bytes(3)?;
+    let time = i16::from_be_bytes([header[0], header[1]]) as i64;
+    Ok((track, time, header[2]))
+}
+
+fn put_id(buf: &mut Vec<u8>, id: u32) {
+    let skip = (id.leading_zeros() / 8) as usize;
+    buf.extend_from_slice(&id.to_be_bytes()[skip..]);
+}
+
+fn put_size(buf: &mut Vec<u8>, size: u64, len: usize) {
+    let v = size | (1u64 << (7 * len));
+    buf.extend_from_slice(&v.to_be_bytes()[8 - len..]);
+}
+
+fn size_len(size: u64) -> usize {
+    (1..=8).find(|l| size < unknown_size(*l)).unwrap_or(8)
+}
+
+fn put_uint(buf: &mut Vec<u8>, id: u32, v: u64) {
+    let len = (64 - v.leading_zeros() as usize).div_ceil(8).max(1);
+    put_id(buf, id);
+    put_size(buf, len as _, 1);
+    buf.extend_from_slice(&v.to_be_bytes()[8 - len..]);
+}
+
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+fn put_float(buf: &mut Vec<u8>, id: u32, v: f64) {
+    put_id(buf, id);
+    put_size(buf, 8, 1);
+    buf.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_master(buf: &mut Vec<u8>, id: u32, data: &[u8]) {
+    put_id(buf, id);
+    put_size(buf, data.len() as _, size_len(data.len() as _));
+    buf.extend_from_slice(data);
+}
+
+// A void element of exactly `n` bytes, `n` can not be 1.
+fn put_void(buf: &mut Vec<u8>, n: usize) {
+    if n == 0 {
+        return;
+    }
+    let size_len = if n < 10 { 1 } else { 8 };
+    put_id(buf, ID_VOID);
+    put_size(buf, (n - 1 - size_len) as _, size_len);
+    buf.resize(buf.len() + n - 1 - size_len, 0);
+}
+
+fn write_at<W: Write + Seek>(file: &mut W, pos: u64, data: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(pos))?;
+    file.write_all(data)
+}
+
+fn patch_size<W: Write + Seek>(file: &mut W, e: &Element, size: u64) -> io::Result<()> {
+    let mut buf = vec![];
+    if size < unknown_size(e.size_len) {
+        put_size(&mut buf, size, e.size_len);
+    } else {
+        put_size(&mut buf, unknown_size(e.size_len), e.size_len);
+    }
+    write_at(file, e.size_pos, &buf)
+}
+
+// The cues of the video `track` from the `(time, cluster position in the segment)` to seek to.
+fn cues(track: u64, points: impl Iterator<Item = (i64, u64)>) -> Vec<u8> {
+    let mut data = vec![];
+    for (time, cluster) in points {
+        let mut positions = vec![];
+        put_uint(&mut positions, ID_CUE_TRACK, track);
+        put_uint(&mut positions, ID_CUE_CLUSTER_POSITION, cluster);
+        let mut point = vec![];
+        put_uint(&mut point, ID_CUE_TIME, time.max(0) as _);
+        put_master(&mut point, ID_CUE_TRACK_POSITIONS, &positions);
+        put_master(&mut data, ID_CUE_POINT, &point);
+    }
+    let mut cues = vec![];
+    put_master(&mut cues, ID_CUES, &data);
+    cues
+}
+
+// The seek head to the `(id, position in the segment)` of the top level elements, padded to
+// fill `space` exactly, `None` if it does not fit.
+fn seek_head(entries: &[(u32, u64)], space: usize) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    for (id, pos) in entries {
+        let mut seek = vec![];
+        let mut seek_id = vec![];
+        put_id(&mut seek_id, *id);
+        put_master(&mut seek, ID_SEEK_ID, &seek_id);
+        put_uint(&mut seek, ID_SEEK_POSITION, *pos);
+        put_master(&mut data, ID_SEEK, &seek);
+    }
+    let mut seek_head = vec![];
+    put_master(&mut seek_head, ID_SEEK_HEAD, &data);
+    if seek_head.len() == space || seek_head.len() + 2 <= space {
+        let padding = space - seek_head.len();
+        put_void(&mut seek_head, padding);
+        Some(seek_head)
+    } else {
+        None
+    }
+}
+
+/// Make a plain WebM recording seekable: drop a tail cut off by a crash, fix up the
+/// sizes and the duration, and add cues if there are none.
+///
+/// Returns the scan of the file before patching, `None` if it was complete already.
+pub(super) fn finalize(path: &str) -> ResultType<Option<WebmScan>> {
+    // the recorder, the repair of its directory and the upload resume may race
+    let _lock = FINALIZE_LOCK.lock().unwrap();
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let scan = scan(&mut file)?;
+    if scan.is_complete() {
+        return Ok(None);
+    }
+    if scan.video_track().is_none() {
+        bail!("no video track");
+    }
+    let len = file.metadata()?.len();
+    if scan.end < len {
+        // only what a crash leaves behind is dropped, anything else is not ours to touch
+        if !is_cut_off(&mut file, scan.end, len)? {
+            bail!("unexpected data at {} of {len} bytes", scan.end);
+        }
+        file.set_len(scan.end)?;
+    }
+    for (cluster, end) in scan.open_clusters.iter() {
+        patch_size(&mut file, cluster, end - cluster.data)?;
+    }
+    if let Some((pos, size)) = scan.duration_pos {
+        let duration = scan.duration_units() as f64;
+        match size {
+            4 => write_at(&mut file, pos, &(duration as f32).to_be_bytes())?,
+            8 => write_at(&mut file, pos, &duration.to_be_bytes())?,
+            _ => {}
+        }
+    }
+    let mut end = scan.end;
+    if !scan.has_cues {
+        let segment_data = scan.segment.data;
+        let video = scan.video_track().map(|t| t.number).unwrap_or(1);
+        let points = scan.cue_blocks().into_iter();
+        let cues = cues(video, points.map(|b| (b.time, b.cluster - segment_data)));
+        write_at(&mut file, end, &cues)?;
+        let cues_pos = end;
+        end += cues.len() as u64;
+
+        // players look the cues up in the seek head
+        if let Some((start, space_end)) = scan.seek_head_space {
+            let entries: Vec<_> = [
+                (ID_INFO, scan.info_pos),
+                (ID_TRACKS, scan.tracks_pos),
+                (ID_CUES, Some(cues_pos)),
+            ]
+            .into_iter()
+            .filter_map(|(id, pos)| pos.map(|pos| (id, pos - segment_data)))
+            .collect();
+            if let Some(seek_head) = seek_head(&entries, (space_end - start) as _) {
+                write_at(&mut file, start, &seek_head)?;
+            }
+        }
+    }
+    patch_size(&mut file, &scan.segment, end - scan.segment.data)?;
+    file.flush()?;
+    Ok(Some(scan))
+}
+
+/// Write H.264 access units, and Opus packets if `audio`, into a Matroska file as they come.
+///
+/// Like the WebM muxer, the segment and the cluster being written are of unknown size, so a
+/// crashed recording is left to `finalize`. The size of a cluster is patched in when the next
+/// one starts, and the cues are kept as they go and written by `finish`, there is nothing to
+/// scan the file for.
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+pub(super) struct AvcWriter<W: Write + Seek> {
+    out: W,
+    width: u64,
+    height: u64,
+    audio: bool,
+    // the end of what's written
+    pos: u64,
+    // set once the header is written
+    layout: Option<Layout>,
+    // time and position of the cluster being written
+    cluster: Option<(i64, u64)>,
+    // time and position of the clusters starting at a keyframe
+    cue_points: Vec<(i64, u64)>,
+    duration: i64,
+    finished: bool,
+}
+
+// Positions in the header to patch on `finish`
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+struct Layout {
+    segment_size: u64,
+    segment_data: u64,
+    info: u64,
+    duration: u64,
+    tracks: u64,
+}
+
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+impl<W: Write + Seek> AvcWriter<W> {
+    pub fn new(out: W, width: usize, height: usize, audio: bool) -> Self {
+        Self {
+            out,
+            width: width as _,
+            height: height as _,
+            audio,
+            pos: 0,
+            layout: None,
+            cluster: None,
+            cue_points: vec![],
+            duration: 0,
+            finished: false,
+        }
+    }
+
+    /// Append an Annex B access unit at `pts` in milliseconds. Returns false for the frames
+    /// before the first keyframe, which carries the parameter sets.
+    pub fn write(&mut self, data: &[u8], pts: i64, key: bool) -> io::Result<bool> {
+        let nals = nal_units(data);
+        if nals.is_empty() || (self.layout.is_none() && !key) {
+            return Ok(false);
+        }
+        if self.layout.is_none() {
+            let Some(config) = avc_config(&nals) else {
+                return Err(invalid("no parameter sets in the keyframe"));
+            };
+            let header = self.header(&config);
+            self.append(&header)?;
+        }
+        let pts = pts.max(0);
+        // every keyframe starts a cluster for players to seek to
+        let time = self.cluster(pts, key)?;
+        let mut frame = vec![];
+        for nal in nals {
+            frame.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            frame.extend_from_slice(nal);
+        }
+        self.block(VIDEO_TRACK, pts, pts - time, key, &frame)?;
+        Ok(true)
+    }
+
+    /// Append an Opus packet at `pts` in milliseconds. Returns false without an audio track
+    /// and before the first video keyframe.
+    pub fn write_audio(&mut self, data: &[u8], pts: i64) -> io::Result<bool> {
+        if !self.audio || self.layout.is_none() {
+            return Ok(false);
+        }
+        let pts = pts.max(0);
+        let time = self.cluster(pts, false)?;
+        self.block(AUDIO_TRACK, pts, pts - time, true, data)?;
+        Ok(true)
+    }
+
+    /// Write the cues and the seek head, and patch the sizes and the duration.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let Some(layout) = self.layout.as_ref().filter(|_| !self.finished) else {
+            return Ok(());
+        };
+        let segment_data = layout.segment_data;
+        let (info, tracks) = (layout.info, layout.tracks);
+        let (segment_size, duration) = (layout.segment_size, layout.duration);
+        self.close_cluster()?;
+        let points = self.cue_points.iter().map(|(t, p)| (*t, p - segment_data));
+        let cues = cues(VIDEO_TRACK, points);
+        let entries = [
+            (ID_INFO, info - segment_data),
+            (ID_TRACKS, tracks - segment_data),
+            (ID_CUES, self.pos - segment_data),
+        ];
+        self.append(&cues)?;
+        if let Some(seek_head) = seek_head(&entries, SEEK_HEAD_SPACE) {
+            self.patch(segment_data, &seek_head)?;
+        }
+        self.patch(duration, &(self.duration as f64).to_be_bytes())?;
+        let mut size = vec![];
+        put_size(&mut size, self.pos - segment_data, 8);
+        self.patch(segment_size, &size)?;
+        self.finished = true;
+        self.out.flush()
+    }
+
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+
+    fn patch(&mut self, pos: u64, data: &[u8]) -> io::Result<()> {
+        write_at(&mut self.out, pos, data)?;
+        self.out.seek(SeekFrom::Start(self.pos))?;
+        Ok(())
+    }
+
+    // The time of the cluster the block at `pts` goes in, starting a new one if needed.
+    fn cluster(&mut self, pts: i64, new: bool) -> io::Result<i64> {
+        match self.cluster {
+            Some((t, _)) if !new && pts >= t && pts - t <= i16::MAX as i64 => Ok(t),
+            _ => {
+                self.close_cluster()?;
+                let pos = self.pos;
+                let mut cluster = vec![];
+                put_id(&mut cluster, ID_CLUSTER);
+                put_size(&mut cluster, unknown_size(8), 8);
+                put_uint(&mut cluster, ID_TIMECODE, pts as _);
+                self.append(&cluster)?;
+                self.cluster = Some((pts, pos));
+                if new {
+                    self.cue_points.push((pts, pos));
+                }
+                Ok(pts)
+            }
+        }
+    }
+
+    fn close_cluster(&mut self) -> io::Result<()> {
+        if let Some((_, pos)) = self.cluster.take() {
+            // after the 4 bytes of the id
+            let data = pos + 4 + 8;
+            let mut size = vec![];
+            put_size(&mut size, self.pos - data, 8);
+            self.patch(pos + 4, &size)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, track: u64, pts: i64, time: i64, key: bool, data: &[u8]) -> io::Result<()> {
+        let mut block = vec![0x80 | track as u8];
+        block.extend_from_slice(&(time as i16).to_be_bytes());
+        block.push(if key { 0x80 } else { 0 });
+        block.extend_from_slice(data);
+        let mut buf = vec![];
+        put_master(&mut buf, ID_SIMPLE_BLOCK, &block);
+        self.append(&buf)?;
+        self.duration = self.duration.max(pts);
+        Ok(())
+    }
+
+    fn header(&mut self, config: &[u8]) -> Vec<u8> {
+        let mut ebml = vec![];
+        put_master(&mut ebml, ID_DOC_TYPE, b"matroska");
+        put_uint(&mut ebml, ID_DOC_TYPE_VERSION, 4);
+        put_uint(&mut ebml, ID_DOC_TYPE_READ_VERSION, 2);
+        let mut info = vec![];
+        put_uint(&mut info, ID_TIMECODE_SCALE, DEFAULT_TIMECODE_SCALE);
+        put_id(&mut info, ID_DURATION);
+        put_size(&mut info, 8, 1);
+        info.extend_from_slice(&0f64.to_be_bytes());
+        let mut video = vec![];
+        put_uint(&mut video, ID_PIXEL_WIDTH, self.width);
+        put_uint(&mut video, ID_PIXEL_HEIGHT, self.height);
+        let mut entry = vec![];
+        put_uint(&mut entry, ID_TRACK_NUMBER, VIDEO_TRACK);
+        put_uint(&mut entry, ID_TRACK_UID, VIDEO_TRACK);
+        put_uint(&mut entry, ID_TRACK_TYPE, TRACK_TYPE_VIDEO);
+        put_master(&mut entry, ID_CODEC_ID, CODEC_AVC.as_bytes());
+        put_master(&mut entry, ID_CODEC_PRIVATE, config);
+        put_master(&mut entry, ID_VIDEO, &video);
+        let mut tracks = vec![];
+        put_master(&mut tracks, ID_TRACK_ENTRY, &entry);
+        if self.audio {
+            use super::{opus_head, OPUS_CHANNELS, OPUS_SAMPLE_RATE};
+
+            let mut audio = vec![];
+            put_float(&mut audio, ID_SAMPLING_FREQUENCY, OPUS_SAMPLE_RATE as _);
+            put_uint(&mut audio, ID_CHANNELS, OPUS_CHANNELS as _);
+            let mut entry = vec![];
+            put_uint(&mut entry, ID_TRACK_NUMBER, AUDIO_TRACK);
+            put_uint(&mut entry, ID_TRACK_UID, AUDIO_TRACK);
+            put_uint(&mut entry, ID_TRACK_TYPE, TRACK_TYPE_AUDIO);
+            put_master(&mut entry, ID_CODEC_ID, CODEC_OPUS.as_bytes());
+            put_master(&mut entry, ID_CODEC_PRIVATE, &opus_head());
+            put_master(&mut entry, ID_AUDIO, &audio);
+            put_master(&mut tracks, ID_TRACK_ENTRY, &entry);
+        }
+
+        let mut buf = vec![];
+        put_master(&mut buf, ID_EBML, &ebml);
+        put_id(&mut buf, ID_SEGMENT);
+        let segment_size = buf.len() as u64;
+        put_size(&mut buf, unknown_size(8), 8);
+        let segment_data = buf.len() as u64;
+        put_void(&mut buf, SEEK_HEAD_SPACE);
+        let info_pos = buf.len() as u64;
+        put_master(&mut buf, ID_INFO, &info);
+        let tracks_pos = buf.len() as u64;
+        put_master(&mut buf, ID_TRACKS, &tracks);
+        self.layout = Some(Layout {
+            segment_size,
+            segment_data,
+            info: info_pos,
+            // the last of the info
+            duration: tracks_pos - 8,
+            tracks: tracks_pos,
+        });
+        buf
+    }
+}
+
+// The NAL units of an Annex B access unit, without their start codes.
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let mut nals = vec![];
+    for (k, start) in starts.iter().enumerate() {
+        let end = starts.get(k + 1).map_or(data.len(), |next| next - 3);
+        let mut nal = &data[*start..end];
+        // the first byte of a 4 byte start code, or trailing zeros
+        while let [rest @ .., 0] = nal {
+            nal = rest;
+        }
+        if !nal.is_empty() {
+            nals.push(nal);
+        }
+    }
+    nals
+}
+
+// The AVCDecoderConfigurationRecord of ISO/IEC 14496-15 with the first SPS and PPS.
+#[cfg(any(test, all(feature = "openh264", not(feature = "hwcodec"))))]
+fn avc_config(nals: &[&[u8]]) -> Option<Vec<u8>> {
+    let sps = nals.iter().find(|n| n[0] & 0x1F == 7 && n.len() >= 4)?;
+    let pps = nals.iter().find(|n| n[0] & 0x1F == 8)?;
+    // 4 byte lengths, one SPS
+    let mut config = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+    config.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    config.extend_from_slice(sps);
+    config.push(1);
+    config.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    config.extend_from_slice(pps);
+    Some(config)
+}
+
+/// Scan a plain WebM recording.
+pub(super) fn scan_file(path: &str) -> ResultType<WebmScan> {
+    scan(&mut File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(time: u64, blocks: &[(i16, bool)], size: Option<u64>) -> Vec<u8> {
+        let mut data = vec![];
+        put_uint(&mut data, ID_TIMECODE, time);
+        for (t, key) in blocks {
+            let mut block = vec![0x81];
+            block.extend_from_slice(&t.to_be_bytes());
+            block.push(if *key { 0x80 } else { 0 });
+            block.extend_from_slice(&[0xAB; 16]);
+            put_master(&mut data, ID_SIMPLE_BLOCK, &block);
+        }
+        let mut buf = vec![];
+        put_id(&mut buf, ID_CLUSTER);
+        put_size(&mut buf, size.unwrap_or(data.len() as _), 8);
+        buf.extend_from_slice(&data);
+        buf
+    }
+
+    // What the muxer leaves behind on a crash: reserved seek head space, unknown
+    // segment and last cluster sizes, no cues, and a block cut in half.
+    fn crashed_webm() -> Vec<u8> {
+        let mut ebml = vec![];
+        put_master(
+            &mut ebml,
+            ID_EBML,
+            &[0x42, 0x82, 0x84, b'w', b'e', b'b', b'm'],
+        );
+        let mut info = vec![];
+        put_uint(&mut info, ID_TIMECODE_SCALE, DEFAULT_TIMECODE_SCALE);
+        put_id(&mut info, ID_DURATION);
+        put_size(&mut info, 4, 1);
+        info.extend_from_slice(&0f32.to_be_bytes());
+        let mut video = vec![];
+        put_uint(&mut video, ID_PIXEL_WIDTH, 1920);
+        put_uint(&mut video, ID_PIXEL_HEIGHT, 1080);
+        let mut entry = vec![];
+        put_uint(&mut entry, ID_TRACK_NUMBER, 1);
+        put_uint(&mut entry, ID_TRACK_TYPE, TRACK_TYPE_VIDEO);
+        put_master(&mut entry, ID_CODEC_ID, b"V_VP9");
+        put_master(&mut entry, ID_VIDEO, &video);
+        let mut tracks = vec![];
+        put_master(&mut tracks, ID_TRACK_ENTRY, &entry);
+
+        let mut segment = vec![];
+        put_void(&mut segment, 100);
+        put_master(&mut segment, ID_INFO, &info);
+        put_master(&mut segment, ID_TRACKS, &tracks);
+        segment.extend(cluster(0, &[(0, true), (33, false)], None));
+        segment.extend(cluster(
+            1000,
+            &[(0, true), (33, false)],
+            Some(unknown_size(8)),
+        ));
+        segment.truncate(segment.len() - 5);
+
+        let mut buf = ebml;
+        put_id(&mut buf, ID_SEGMENT);
+        put_size(&mut buf, unknown_size(8), 8);
+        buf.extend(segment);
+        buf
+    }
+
+    #[test]
+    fn test_finalize_crashed_webm() {
+        let path = std::env::temp_dir().join(format!("record_ebml_{}.webm", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(&path, crashed_webm()).unwrap();
+
+        let before = scan_file(&path).unwrap();
+        assert!(!before.is_complete());
+        assert_eq!(before.keyframes_ms(), vec![0, 1000]);
+        let track = before.video_track().unwrap();
+        assert_eq!(
+            (track.codec.as_str(), track.width, track.height),
+            ("V_VP9", 1920, 1080)
+        );
+
+        assert!(finalize(&path).unwrap().is_some());
+        let after = scan_file(&path).unwrap();
+        assert!(after.is_complete(), "sizes patched and cues added");
+        // the half written block is gone
+        assert_eq!(after.duration_ms(), 1000);
+        assert_eq!(after.cue_blocks().len(), 2);
+        assert!(finalize(&path).unwrap().is_none());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_finalize_keeps_unknown_data() {
+        let path = std::env::temp_dir().join(format!("record_ebml_{}_x.webm", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        // the cut block completed, followed by bytes that are no element
+        let mut data = crashed_webm();
+        data.extend_from_slice(&[0; 8]);
+        std::fs::write(&path, &data).unwrap();
+
+        assert!(finalize(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        std::fs::remove_file(&path).ok();
+    }
+    #[test]
+    fn test_avc_writer() {
+        let path = std::env::temp_dir().join(format!("record_ebml_{}.mkv", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let sps = [0, 0, 0, 1, 0x67, 0x42, 0xC0, 0x1F, 0xAB];
+        let pps = [0, 0, 0, 1, 0x68, 0xCE, 0x3C, 0x80];
+        let idr = [0, 0, 1, 0x65, 0x88, 0x84, 0x00];
+        let p = [0, 0, 0, 1, 0x41, 0x9A, 0x02];
+        let key = [&sps[..], &pps, &idr].concat();
+
+        let opus = [0xFC, 0xFF, 0xFE];
+
+        let mut writer = AvcWriter::new(File::create(&path).unwrap(), 1920, 1080, true);
+        assert!(!writer.write(&p, 0, false).unwrap(), "waits for a keyframe");
+        assert!(!writer.write_audio(&opus, 5).unwrap());
+        assert!(writer.write(&key, 10, true).unwrap());
+        assert!(writer.write_audio(&opus, 20).unwrap());
+        assert!(writer.write(&p, 43, false).unwrap());
+        assert!(writer.write_audio(&opus, 40).unwrap());
+        assert!(writer.write(&key, 1010, true).unwrap());
+        assert!(
+            writer.write(&p, 40000, false).unwrap(),
+            "past the range of a cluster"
+        );
+        writer.finish().unwrap();
+        drop(writer);
+        assert!(finalize(&path).unwrap().is_none(), "nothing to finalize");
+
+        let scan = scan_file(&path).unwrap();
+        assert!(scan.is_complete());
+        let track = scan.video_track().unwrap();
+        assert_eq!(
+            (track.codec.as_str(), track.width, track.height),
+            (CODEC_AVC, 1920, 1080)
+        );
+        let audio = scan.tracks.iter().find(|t| t.is_audio()).unwrap();
+        assert_eq!(
+            (audio.number, audio.codec.as_str()),
+            (AUDIO_TRACK, CODEC_OPUS)
+        );
+        assert_eq!(
+            scan.blocks
+                .iter()
+                .filter(|b| b.track == AUDIO_TRACK)
+                .count(),
+            2
+        );
+        assert_eq!(scan.keyframes_ms(), vec![10, 1010]);
+        assert_eq!(scan.duration_ms(), 39990);
+        assert_eq!(scan.cue_blocks().len(), 2);
+        assert_eq!(
+            avc_config(&nal_units(&key)).unwrap(),
+            [
+                &[1, 0x42, 0xC0, 0x1F, 0xFF, 0xE1, 0, 5][..],
+                &sps[4..],
+                &[1, 0, 4],
+                &pps[4..],
+            ]
+            .concat()
+        );
+        std::fs::remove_file(&path).ok();
+    }
+}
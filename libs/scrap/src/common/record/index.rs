@@ -0,0 +1,308 @@
+//! Keyframe index written next to a recording, and recovery of unfinished recordings.
+//!
+//! The index is flushed on every keyframe, so the keyframe positions survive a crash
+//! whatever the container, which lets `recording_info` describe hardware muxed recordings
+//! as well. Encrypted recordings have none, the keyframe times are left to the file.
+//! A recording whose index has no `end` entry was not finalized, it is repaired the
+//! next time a recorder starts in the same directory.
+
+use super::{
+    audio_filename, ebml, is_encrypted_file, retention::ACTIVE_THRESHOLD, RecorderContext2,
+    ENCRYPTED_EXT,
+};
+use hbb_common::{bail, log, serde_json, ResultType};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+pub const INDEX_EXT: &str = ".index.jsonl";
+
+static REPAIRED_DIRS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum IndexEntry {
+    Start {
+        codec: String,
+        width: usize,
+        height: usize,
+    },
+    Keyframe {
+        pts: i64,
+        /// Size of the recording on disk when the keyframe was written.
+        offset: u64,
+    },
+    End {
+        duration: i64,
+        /// Written by the repair after a crash rather than by the recorder.
+        #[serde(default)]
+        recovered: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RecordingInfo {
+    pub codec: String,
+    /// In milliseconds.
+    pub duration: i64,
+    /// `(pts, width, height)` from where each resolution applies.
+    pub resolutions: Vec<(i64, usize, usize)>,
+    /// pts of the keyframes.
+    pub keyframes: Vec<i64>,
+    pub audio: bool,
+    /// The audio recorded next to the video, see `audio_filename`.
+    pub audio_file: Option<String>,
+    /// Whether the file has cues, `None` if it could not be inspected.
+    pub cues: Option<bool>,
+    pub finalized: bool,
+    pub recovered: bool,
+}
+
+/// `incoming_x_display0_vp9.webm[.enc]` -> `incoming_x_display0_vp9.index.jsonl`
+pub fn index_filename(video: &str) -> String {
+    PathBuf::from(video.trim_end_matches(ENCRYPTED_EXT))
+        .with_extension("")
+        .to_string_lossy()
+        .to_string()
+        + INDEX_EXT
+}
+
+pub(super) struct Index {
+    file: File,
+    filename: String,
+    video: String,
+    // (first, last)
+    pts: Option<(i64, i64)>,
+}
+
+impl Index {
+    pub(super) fn new(ctx2: &RecorderContext2) -> ResultType<Self> {
+        let filename = index_filename(&ctx2.filename);
+        let mut index = Self {
+            file: File::create(&filename)?,
+            filename,
+            video: ctx2.filename.clone(),
+            pts: None,
+        };
+        index.write(&IndexEntry::Start {
+            codec: ctx2.format.to_string(),
+            width: ctx2.width,
+            height: ctx2.height,
+        })?;
+        Ok(index)
+    }
+
+    fn write(&mut self, entry: &IndexEntry) -> ResultType<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    pub(super) fn frame(&mut self, pts: i64, key: bool) -> ResultType<()> {
+        self.pts = Some((self.pts.map_or(pts, |(first, _)| first), pts));
+        if key {
+            let offset = std::fs::metadata(&self.video).map_or(0, |m| m.len());
+            self.write(&IndexEntry::Keyframe { pts, offset })?;
+        }
+        Ok(())
+    }
+
+    /// Remove the index as well if the video was discarded.
+    pub(super) fn close(mut self) {
+        if Path::new(&self.video).exists() {
+            let duration = self.pts.map_or(0, |(first, last)| last - first);
+            if let Err(e) = self.write(&IndexEntry::End {
+                duration,
+                recovered: false,
+            }) {
+                log::error!("Failed to finish index {}: {e}", self.filename);
+            }
+        } else {
+            drop(self.file);
+            if let Err(e) = std::fs::remove_file(&self.filename) {
+                log::error!("Failed to remove index {}: {e}", self.filename);
+            }
+        }
+    }
+}
+
+fn read_index(filename: &str) -> ResultType<Vec<IndexEntry>> {
+    let reader = BufReader::new(File::open(filename)?);
+    // the last line may be cut off by a crash
+    Ok(reader
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+/// Whether the recording is WebM or Matroska, and not encrypted, which `repair` takes.
+pub fn is_plain_matroska(path: &str) -> bool {
+    (path.ends_with(".webm") || path.ends_with(".mkv")) && !is_encrypted_file(path)
+}
+
+/// Make a plain WebM or Matroska recording seekable, see `ebml::finalize`.
+///
+/// Returns false if it was complete already.
+pub fn repair(path: &str) -> ResultType<bool> {
+    if !is_plain_matroska(path) {
+        bail!("Only plain WebM and Matroska recordings can be repaired, decrypt first");
+    }
+    Ok(ebml::finalize(path)?.is_some())
+}
+
+/// Describe a recording from its index, and from the container for plain WebM and Matroska
+/// files.
+pub fn recording_info(path: &str) -> ResultType<RecordingInfo> {
+    let mut info = RecordingInfo::default();
+    let index = read_index(&index_filename(path)).ok();
+    if let Some(entries) = &index {
+        let mut pending = None;
+        let mut first = None;
+        for entry in entries {
+            match entry {
+                IndexEntry::Start {
+                    codec,
+                    width,
+                    height,
+                } => {
+                    info.codec = codec.clone();
+                    pending = Some((*width, *height));
+                }
+                IndexEntry::Keyframe { pts, .. } => {
+                    if let Some((w, h)) = pending.take() {
+                        info.resolutions.push((*pts, w, h));
+                    }
+                    info.keyframes.push(*pts);
+                    info.duration = pts - *first.get_or_insert(*pts);
+                }
+                IndexEntry::End {
+                    duration,
+                    recovered,
+                } => {
+                    info.duration = *duration;
+                    info.finalized = !recovered;
+                    info.recovered = *recovered;
+                }
+            }
+        }
+    }
+    if is_plain_matroska(path) {
+        let scan = ebml::scan_file(path)?;
+        let Some(video) = scan.video_track() else {
+            bail!("No video track in {path}");
+        };
+        info.codec = match video.codec.as_str() {
+            ebml::CODEC_AVC => "H264".to_owned(),
+            codec => codec.trim_start_matches("V_").to_owned(),
+        };
+        if info.resolutions.is_empty() {
+            info.resolutions = vec![(0, video.width as _, video.height as _)];
+        }
+        info.keyframes = scan.keyframes_ms();
+        info.duration = scan.duration_ms();
+        info.audio = scan.tracks.iter().any(|t| t.is_audio());
+        info.cues = Some(scan.has_cues);
+        info.finalized = scan.is_complete() && !info.recovered;
+    } else if index.is_none() {
+        bail!("No index found for {path}, only plain WebM and Matroska recordings can be inspected without one");
+    }
+    let audio = audio_filename(path);
+    if Path::new(&audio).exists() {
+        info.audio = true;
+        info.audio_file = Some(audio);
+    }
+    Ok(info)
+}
+
+fn find_video(index: &str) -> Option<String> {
+    let stem = index.trim_end_matches(INDEX_EXT);
+    [".webm", ".mkv", ".mp4"]
+        .iter()
+        .flat_map(|ext| {
+            [
+                format!("{stem}{ext}"),
+                format!("{stem}{ext}{ENCRYPTED_EXT}"),
+            ]
+        })
+        .find(|f| Path::new(f).exists())
+}
+
+fn modified_recently(path: &str) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_or(false, |t| {
+            SystemTime::now().duration_since(t).unwrap_or_default() < ACTIVE_THRESHOLD
+        })
+}
+
+fn repair_index(index: &str) -> ResultType<()> {
+    let entries = read_index(index)?;
+    if entries.iter().any(|e| matches!(e, IndexEntry::End { .. })) {
+        return Ok(());
+    }
+    let Some(video) = find_video(index) else {
+        // the recording was discarded, but the index outlived it
+        std::fs::remove_file(index)?;
+        return Ok(());
+    };
+    if modified_recently(index) || modified_recently(&video) {
+        return Ok(());
+    }
+    let keyframes = entries.iter().filter_map(|e| match e {
+        IndexEntry::Keyframe { pts, .. } => Some(*pts),
+        _ => None,
+    });
+    let mut duration =
+        keyframes.clone().max().unwrap_or_default() - keyframes.min().unwrap_or_default();
+    if is_plain_matroska(&video) {
+        if let Some(scan) = ebml::finalize(&video)? {
+            duration = scan.duration_ms();
+        }
+        log::info!("Repaired unfinished recording {video}");
+    } else {
+        // encrypted WebM and Matroska can be repaired once decrypted, mp4 can not
+        log::warn!("Unfinished recording {video} can not be repaired in place");
+    }
+    let mut file = OpenOptions::new().append(true).open(index)?;
+    let mut line = serde_json::to_vec(&IndexEntry::End {
+        duration,
+        recovered: true,
+    })?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+/// Repair the recordings in `dir` left unfinished by a crash, once per directory.
+pub fn repair_dir_once(dir: &str) {
+    {
+        let mut dirs = REPAIRED_DIRS.lock().unwrap();
+        if dirs.iter().any(|d| d == dir) {
+            return;
+        }
+        dirs.push(dir.to_owned());
+    }
+    let dir = dir.to_owned();
+    std::thread::spawn(move || {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path().to_string_lossy().to_string();
+            if !path.ends_with(INDEX_EXT) {
+                continue;
+            }
+            if let Err(e) = repair_index(&path) {
+                log::error!("Failed to repair recording of {path}: {e}");
+            }
+        }
+    });
+}
@@ -0,0 +1,146 @@
+//! Segmentation limits and the retention policy of the recording directory.
+
+use hbb_common::log;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+pub(super) const ACTIVE_THRESHOLD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default)]
+pub struct SegmentConfig {
+    /// Start a new file at the first keyframe after this duration.
+    pub max_duration: Option<Duration>,
+    /// Start a new file at the first keyframe after this many bytes.
+    pub max_size: Option<u64>,
+}
+
+impl SegmentConfig {
+    #[inline]
+    pub fn is_exceeded(&self, duration: Duration, size: u64) -> bool {
+        self.max_duration.map_or(false, |d| duration >= d)
+            || self.max_size.map_or(false, |s| size >= s)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Remove recordings older than this.
+    pub max_age: Option<Duration>,
+    /// Remove the oldest recordings until the directory is below this size.
+    pub max_total_size: Option<u64>,
+}
+
+impl RetentionPolicy {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.max_age.is_none() && self.max_total_size.is_none()
+    }
+}
+
+// Files written by the recorder, including sidecars and encrypted variants.
+fn is_record_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|x| x.to_str()) else {
+        return false;
+    };
+    (name.starts_with("incoming_") || name.starts_with("outgoing_"))
+        && [".webm", ".mkv", ".mp4", ".jsonl", ".enc"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+/// Remove old recordings from `dir` according to `policy`, never touching the files
+/// starting with `keep_prefix`, which are the recording being written and its sidecars.
+///
+/// Returns the removed files.
+pub fn prune(dir: &str, policy: &RetentionPolicy, keep_prefix: &str) -> Vec<PathBuf> {
+    let mut removed = vec![];
+    if policy.is_empty() {
+        return removed;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return removed;
+    };
+    let mut files: Vec<(PathBuf, SystemTime, u64)> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| is_record_file(&e.path()))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            if !meta.is_file() {
+                return None;
+            }
+            Some((e.path(), meta.modified().ok()?, meta.len()))
+        })
+        .collect();
+    // oldest first
+    files.sort_by_key(|f| f.1);
+    let now = SystemTime::now();
+    let mut total: u64 = files.iter().map(|f| f.2).sum();
+    for (path, modified, len) in files {
+        // the files being written still count to the total size
+        if path.to_string_lossy().starts_with(keep_prefix) {
+            continue;
+        }
+        // recordings of other displays or connections still being written
+        if now.duration_since(modified).unwrap_or_default() < ACTIVE_THRESHOLD {
+            continue;
+        }
+        let too_old = policy.max_age.map_or(false, |max_age| {
+            now.duration_since(modified).unwrap_or_default() > max_age
+        });
+        let too_big = policy.max_total_size.map_or(false, |max| total > max);
+        if !too_old && !too_big {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                log::info!("Remove recording {path:?} by retention policy");
+                total -= len;
+                removed.push(path);
+            }
+            Err(e) => log::error!("Failed to remove recording {path:?}: {e}"),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_total_size() {
+        let dir = std::env::temp_dir().join(format!("record_retention_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "incoming_1_a_display0_vp9.webm",
+            "incoming_1_b_display0_vp9.webm",
+            "incoming_1_c_display0_vp9.webm",
+            "notes.txt",
+        ];
+        for (i, name) in names.iter().enumerate() {
+            std::fs::write(dir.join(name), vec![0u8; 100]).unwrap();
+            let f = std::fs::File::options()
+                .write(true)
+                .open(dir.join(name))
+                .unwrap();
+            f.set_modified(SystemTime::now() - Duration::from_secs(100 - i as u64))
+                .unwrap();
+        }
+        let keep = dir.join("incoming_1_c_").to_string_lossy().to_string();
+        let policy = RetentionPolicy {
+            max_age: None,
+            max_total_size: Some(150),
+        };
+        let removed = prune(&dir.to_string_lossy(), &policy, &keep);
+        assert_eq!(
+            removed,
+            vec![dir.join(names[0]), dir.join(names[1])],
+            "the oldest recordings go first, the kept and foreign files stay"
+        );
+        assert!(dir.join(names[2]).exists());
+        assert!(dir.join(names[3]).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,184 @@
+//! Sidecar event timeline written next to a recording.
+//!
+//! Each line is a JSON object with the `pts` of the video at the time of the event and
+//! the connection it came from, so a player can show what was typed, copied or
+//! transferred alongside the video. Chat messages are only written as their SHA-256,
+//! which tells whether a given text was sent without keeping it in the recording.
+
+use super::{RecordFile, RecorderContext, ENCRYPTED_EXT};
+use hbb_common::{
+    log, serde_json,
+    sha2::{Digest, Sha256},
+    ResultType,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub const TIMELINE_EXT: &str = ".timeline.jsonl";
+const REDACTED: &str = "*";
+
+#[derive(Debug, Clone, Default)]
+pub struct TimelineConfig {
+    /// Replace printable keys with `*`, control keys are still recorded.
+    pub redact_text: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordEvent {
+    Key {
+        down: bool,
+        key: String,
+        #[serde(skip)]
+        printable: bool,
+    },
+    Mouse {
+        x: i32,
+        y: i32,
+        mask: i32,
+    },
+    Clipboard {
+        incoming: bool,
+        format: String,
+        len: usize,
+    },
+    FileTransfer {
+        action: String,
+        path: String,
+        files: Vec<(String, i64)>,
+    },
+    Chat {
+        incoming: bool,
+        #[serde(rename = "sha256", serialize_with = "serialize_sha256")]
+        text: String,
+    },
+    Permission {
+        name: String,
+        enabled: bool,
+    },
+    /// A keyboard macro played by the controller.
+    Macro {
+        name: String,
+    },
+}
+
+fn serialize_sha256<S: Serializer>(text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let hash = Sha256::digest(text.as_bytes());
+    serializer.serialize_str(&hash.iter().map(|b| format!("{b:02x}")).collect::<String>())
+}
+
+#[derive(Serialize)]
+struct TimelineEntry<'a> {
+    pts: i64,
+    conn: i32,
+    #[serde(flatten)]
+    event: &'a RecordEvent,
+}
+
+/// `incoming_x_display0_vp9.webm[.enc]` -> `incoming_x_display0_vp9.timeline.jsonl[.enc]`
+pub fn timeline_filename(video: &str) -> String {
+    let encrypted = video.ends_with(ENCRYPTED_EXT);
+    let path = PathBuf::from(video.trim_end_matches(ENCRYPTED_EXT));
+    let mut filename = path.with_extension("").to_string_lossy().to_string() + TIMELINE_EXT;
+    if encrypted {
+        filename += ENCRYPTED_EXT;
+    }
+    filename
+}
+
+pub(super) struct Timeline {
+    file: RecordFile,
+    filename: String,
+    video: String,
+    redact_text: bool,
+}
+
+impl Timeline {
+    pub(super) fn new(
+        ctx: &RecorderContext,
+        video: &str,
+        cfg: &TimelineConfig,
+    ) -> ResultType<Self> {
+        let filename = timeline_filename(video);
+        let file = RecordFile::create(File::create(&filename)?, ctx.public_key.as_ref())?;
+        Ok(Self {
+            file,
+            filename,
+            video: video.to_owned(),
+            redact_text: cfg.redact_text,
+        })
+    }
+
+    pub(super) fn write(&mut self, pts: i64, conn: i32, event: &RecordEvent) -> ResultType<()> {
+        let redacted;
+        let event = match event {
+            RecordEvent::Key {
+                down,
+                printable: true,
+                ..
+            } if self.redact_text => {
+                redacted = RecordEvent::Key {
+                    down: *down,
+                    key: REDACTED.to_owned(),
+                    printable: true,
+                };
+                &redacted
+            }
+            _ => event,
+        };
+        let mut line = serde_json::to_vec(&TimelineEntry { pts, conn, event })?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Remove the timeline as well if the video was discarded.
+    pub(super) fn close(mut self) {
+        self.file.flush().ok();
+        if !Path::new(&self.video).exists() {
+            drop(self.file);
+            if let Err(e) = std::fs::remove_file(&self.filename) {
+                log::error!("Failed to remove timeline {}: {e}", self.filename);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline_filename() {
+        assert_eq!(
+            timeline_filename("/tmp/incoming_1_display0_vp9.webm"),
+            "/tmp/incoming_1_display0_vp9.timeline.jsonl"
+        );
+        assert_eq!(
+            timeline_filename("/tmp/incoming_1_display0_h264.mp4.enc"),
+            "/tmp/incoming_1_display0_h264.timeline.jsonl.enc"
+        );
+    }
+
+    #[test]
+    fn test_chat_hashed() {
+        let event = RecordEvent::Chat {
+            incoming: true,
+            text: "abc".to_owned(),
+        };
+        let line = serde_json::to_string(&TimelineEntry {
+            pts: 10,
+            conn: 2,
+            event: &event,
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            r#"{"pts":10,"conn":2,"type":"chat","incoming":true,"sha256":"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"}"#
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! A synthetic capture source, for running the video pipeline without any screen, e.g. in CI.
+//!
+//! It replaces the screens of X11 or headless sessions if the env `RUSTDESK_SYNTHETIC_CAPTURE`
+//! or the option `synthetic-capture` is set:
+//!
+//! - `pattern[:<width>x<height>@<fps>]`, deterministic animated test patterns.
+//! - `png:<dir>[:<width>x<height>@<fps>]`, the PNG files of a directory in name order, in a loop.
+//! - `raw:<file>:<width>x<height>[@<fps>]`, a file of concatenated BGRA frames, in a loop.
+//!
+//! Linux only, with the `wayland` feature the builds there have. The Windows and macOS services
+//! enumerate the displays of their capturers, which have no synthetic one.
+
+use crate::{Frame, PixelBuffer, Pixfmt, TraitCapturer};
+use hbb_common::{bail, config::Config, log, ResultType};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+pub const ENV_SYNTHETIC_CAPTURE: &str = "RUSTDESK_SYNTHETIC_CAPTURE";
+pub const OPTION_SYNTHETIC_CAPTURE: &str = "synthetic-capture";
+
+const DEFAULT_SIZE: (usize, usize) = (1920, 1080);
+const DEFAULT_FPS: u32 = 30;
+const MAX_FPS: u32 = 120;
+// The bars of the pattern, 0xAARRGGBB to be BGRA in memory.
+const BARS: [u32; 8] = [
+    0xFFFFFFFF, 0xFFFFFF00, 0xFF00FFFF, 0xFF00FF00, 0xFFFF00FF, 0xFFFF0000, 0xFF0000FF, 0xFF000000,
+];
+// The frame index is drawn as bits in the bottom row of the pattern, to check the order of frames.
+const COUNTER_BITS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntheticSource {
+    Pattern,
+    Png(PathBuf),
+    Raw(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticConfig {
+    pub source: SyntheticSource,
+    pub width: usize,
+    pub height: usize,
+    pub fps: u32,
+}
+
+/// The synthetic source configured, if any, the env takes precedence over the option.
+pub fn config() -> Option<SyntheticConfig> {
+    let value = std::env::var(ENV_SYNTHETIC_CAPTURE)
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| Config::get_option(OPTION_SYNTHETIC_CAPTURE));
+    if value.trim().is_empty() {
+        return None;
+    }
+    match SyntheticConfig::parse(value.trim()) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            log::error!("Invalid synthetic capture '{value}': {e}");
+            None
+        }
+    }
+}
+
+impl SyntheticConfig {
+    pub fn parse(value: &str) -> ResultType<Self> {
+        let (kind, rest) = value.split_once(':').unwrap_or((value, ""));
+        // The last part is the size and fps if it can be parsed as them, else a part of the path.
+        let (path, mode) = match rest
+            .rsplit_once(':')
+            .and_then(|(path, mode)| Some((path, parse_mode(mode)?)))
+        {
+            Some((path, mode)) => (path, Some(mode)),
+            None if kind == "pattern" => ("", parse_mode(rest)),
+            None => (rest, None),
+        };
+        let (size, fps) = mode.unwrap_or((None, None));
+        let fps = fps.unwrap_or(DEFAULT_FPS).clamp(1, MAX_FPS);
+        let source = match kind {
+            "pattern" => {
+                if !rest.is_empty() && mode.is_none() {
+                    bail!("wrong size or fps: {rest}");
+                }
+                SyntheticSource::Pattern
+            }
+            "png" | "raw" if path.is_empty() => bail!("no path"),
+            "png" => SyntheticSource::Png(PathBuf::from(path)),
+            "raw" => SyntheticSource::Raw(PathBuf::from(path)),
+            _ => bail!("unknown source: {kind}"),
+        };
+        let (width, height) = match (&source, size) {
+            (_, Some(size)) => size,
+            (SyntheticSource::Png(dir), None) => png_size(&first_png(dir)?)?,
+            (SyntheticSource::Raw(_), None) => bail!("the size of raw frames is required"),
+            (SyntheticSource::Pattern, None) => DEFAULT_SIZE,
+        };
+        if width < 2 || height < 2 {
+            bail!("too small size: {width}x{height}");
+        }
+        Ok(Self {
+            source,
+            width,
+            height,
+            fps,
+        })
+    }
+}
+
+// `<width>x<height>`, `@<fps>` or both.
+fn parse_mode(mode: &str) -> Option<(Option<(usize, usize)>, Option<u32>)> {
+    let (size, fps) = match mode.split_once('@') {
+        Some((size, fps)) => (size, Some(fps.parse::<u32>().ok()?)),
+        None => (mode, None),
+    };
+    let size = if size.is_empty() {
+        None
+    } else {
+        let (w, h) = size.split_once('x')?;
+        Some((w.parse().ok()?, h.parse().ok()?))
+    };
+    if size.is_none() && fps.is_none() {
+        return None;
+    }
+    Some((size, fps))
+}
+
+fn png_files(dir: &PathBuf) -> ResultType<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .map_or(false, |e| e.eq_ignore_ascii_case("png"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn first_png(dir: &PathBuf) -> ResultType<PathBuf> {
+    match png_files(dir)?.into_iter().next() {
+        Some(path) => Ok(path),
+        None => bail!("no png in {}", dir.display()),
+    }
+}
+
+fn png_size(path: &PathBuf) -> ResultType<(usize, usize)> {
+    let reader = png::Decoder::new(File::open(path)?).read_info()?;
+    let info = reader.info();
+    Ok((info.width as _, info.height as _))
+}
+
+// Decode the png to the top left of the BGRA frame, the rest is black.
+fn read_png(path: &PathBuf, width: usize, height: usize, dst: &mut [u8]) -> ResultType<()> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let bpp = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => bail!("unexpected indexed color of {}", path.display()),
+    };
+    dst.fill(0);
+    let (w, h) = (
+        (info.width as usize).min(width),
+        (info.height as usize).min(height),
+    );
+    for y in 0..h {
+        let src_row = &buf[y * info.line_size..];
+        let dst_row = &mut dst[y * width * 4..];
+        for x in 0..w {
+            let s = &src_row[x * bpp..x * bpp + bpp];
+            let (r, g, b) = if bpp < 3 {
+                (s[0], s[0], s[0])
+            } else {
+                (s[0], s[1], s[2])
+            };
+            dst_row[x * 4..x * 4 + 4].copy_from_slice(&[b, g, r, 0xFF]);
+        }
+    }
+    Ok(())
+}
+
+/// Draw the frame `index` of the test pattern: color bars, a moving box, and the index in bits.
+pub fn draw_pattern(index: u64, width: usize, height: usize, dst: &mut [u8]) {
+    let mut pixels = vec![0u32; width * height];
+    for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+        for (x, p) in row.iter_mut().enumerate() {
+            // a gradient in the lower half, for the chroma subsampling
+            *p = if y < height / 2 {
+                BARS[x * BARS.len() / width]
+            } else {
+                let v = (x * 255 / width) as u32;
+                0xFF000000 | v << 16 | v << 8 | v
+            };
+        }
+    }
+    let side = (height / 8).max(1);
+    let step = (width / 120).max(1) as u64;
+    let x0 = (index * step % (width - side.min(width - 1)) as u64) as usize;
+    let y0 = height / 2 - side / 2;
+    for row in pixels.chunks_exact_mut(width).skip(y0).take(side) {
+        for p in row.iter_mut().skip(x0).take(side) {
+            *p = !*p | 0xFF000000;
+        }
+    }
+    let cell = (width / COUNTER_BITS).max(1);
+    let cell_rows = cell.min(height / 8).max(1);
+    for row in pixels.chunks_exact_mut(width).skip(height - cell_rows) {
+        for (x, p) in row.iter_mut().enumerate().take(cell * COUNTER_BITS) {
+            let bit = (index >> (COUNTER_BITS - 1 - x / cell)) & 1;
+            *p = if bit == 1 { 0xFFFFFFFF } else { 0xFF000000 };
+        }
+    }
+    for (d, p) in dst.chunks_exact_mut(4).zip(pixels.iter()) {
+        d.copy_from_slice(&p.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Display {
+    config: SyntheticConfig,
+}
+
+impl Display {
+    pub fn new(config: SyntheticConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn width(&self) -> usize {
+        self.config.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.config.height
+    }
+
+    pub fn origin(&self) -> (i32, i32) {
+        (0, 0)
+    }
+
+    pub fn is_online(&self) -> bool {
+        true
+    }
+
+    pub fn is_primary(&self) -> bool {
+        true
+    }
+
+    pub fn name(&self) -> String {
+        "Synthetic".to_owned()
+    }
+}
+
+enum Reader {
+    Pattern,
+    Png(Vec<PathBuf>),
+    Raw(BufReader<File>),
+}
+
+pub struct Capturer {
+    config: SyntheticConfig,
+    reader: Reader,
+    data: Vec<u8>,
+    index: u64,
+    next: Option<Instant>,
+}
+
+impl Capturer {
+    pub fn new(display: Display) -> io::Result<Self> {
+        let config = display.config;
+        let reader = match &config.source {
+            SyntheticSource::Pattern => Reader::Pattern,
+            SyntheticSource::Png(dir) => {
+                let files = png_files(dir)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                if files.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no png in {}", dir.display()),
+                    ));
+                }
+                Reader::Png(files)
+            }
+            SyntheticSource::Raw(path) => Reader::Raw(BufReader::new(File::open(path)?)),
+        };
+        log::info!("Synthetic capturer: {config:?}");
+        Ok(Self {
+            data: vec![0; config.width * config.height * 4],
+            config,
+            reader,
+            index: 0,
+            next: None,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.config.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.config.height
+    }
+
+    fn read(&mut self) -> io::Result<()> {
+        let (width, height) = (self.config.width, self.config.height);
+        match &mut self.reader {
+            Reader::Pattern => draw_pattern(self.index, width, height, &mut self.data),
+            Reader::Png(files) => {
+                let path = &files[(self.index % files.len() as u64) as usize];
+                read_png(path, width, height, &mut self.data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            }
+            Reader::Raw(file) => {
+                if let Err(e) = file.read_exact(&mut self.data) {
+                    if e.kind() != io::ErrorKind::UnexpectedEof || self.index == 0 {
+                        return Err(e);
+                    }
+                    // loop, a partial frame at the end is skipped
+                    file.seek(SeekFrom::Start(0))?;
+                    file.read_exact(&mut self.data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TraitCapturer for Capturer {
+    fn frame<'a>(&'a mut self, timeout: Duration) -> io::Result<Frame<'a>> {
+        let interval = Duration::from_secs(1) / self.config.fps;
+        let now = Instant::now();
+        if let Some(next) = self.next {
+            if next > now {
+                std::thread::sleep((next - now).min(timeout));
+                if next > Instant::now() {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+        }
+        // Late frames are not made up, the frames are still the same ones by index.
+        let now = Instant::now();
+        self.next = Some(match self.next {
+            Some(next) if next + interval > now => next + interval,
+            _ => now + interval,
+        });
+        self.read()?;
+        self.index += 1;
+        Ok(Frame::PixelBuffer(PixelBuffer::new(
+            &self.data,
+            Pixfmt::BGRA,
+            self.config.width,
+            self.config.height,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = SyntheticConfig::parse("pattern").unwrap();
+        assert_eq!(config.source, SyntheticSource::Pattern);
+        assert_eq!((config.width, config.height, config.fps), (1920, 1080, 30));
+        let config = SyntheticConfig::parse("pattern:1280x720@60").unwrap();
+        assert_eq!((config.width, config.height, config.fps), (1280, 720, 60));
+        let config = SyntheticConfig::parse("pattern:@15").unwrap();
+        assert_eq!((config.width, config.height, config.fps), (1920, 1080, 15));
+        let config = SyntheticConfig::parse("raw:/tmp/a:b.bgra:640x480").unwrap();
+        assert_eq!(config.source, SyntheticSource::Raw("/tmp/a:b.bgra".into()));
+        assert_eq!((config.width, config.height, config.fps), (640, 480, 30));
+        assert!(SyntheticConfig::parse("raw:/tmp/a.bgra").is_err());
+        assert!(SyntheticConfig::parse("pattern:big").is_err());
+        assert!(SyntheticConfig::parse("camera").is_err());
+    }
+
+    #[test]
+    fn test_pattern() {
+        let (width, height) = (320, 240);
+        let mut a = vec![0; width * height * 4];
+        let mut b = a.clone();
+        draw_pattern(5, width, height, &mut a);
+        draw_pattern(5, width, height, &mut b);
+        assert_eq!(a, b);
+        draw_pattern(6, width, height, &mut b);
+        assert_ne!(a, b);
+        // the last bit of the counter in the bottom row
+        let last = ((height - 1) * width + width / COUNTER_BITS * COUNTER_BITS - 1) * 4;
+        assert_eq!(a[last], 0xFF);
+        assert_eq!(b[last], 0x00);
+    }
+
+    // capture -> convert -> encode -> decode, as the video service and the controller do
+    #[test]
+    fn test_pipeline() {
+        use crate::{
+            codec::{EncoderApi, EncoderCfg},
+            vpxcodec::{
+                VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId,
+            },
+            GoogleImage, STRIDE_ALIGN,
+        };
+
+        let (width, height) = (320, 240);
+        let config = SyntheticConfig::parse("pattern:320x240@120").unwrap();
+        let mut capturer = Capturer::new(Display::new(config)).unwrap();
+        let mut encoder = VpxEncoder::new(
+            EncoderCfg::VPX(VpxEncoderConfig {
+                width: width as _,
+                height: height as _,
+                quality: 1.0,
+                codec: VpxVideoCodecId::VP9,
+                keyframe_interval: None,
+            }),
+            false,
+        )
+        .unwrap();
+        let mut decoder = VpxDecoder::new(VpxDecoderConfig {
+            codec: VpxVideoCodecId::VP9,
+        })
+        .unwrap();
+        let yuvfmt = encoder.yuvfmt();
+        let (mut yuv, mut mid_data) = (Vec::new(), Vec::new());
+        let mut decoded = 0;
+        for pts in 0..5 {
+            let frame = capturer.frame(Duration::from_secs(1)).unwrap();
+            let input = frame.to(yuvfmt.clone(), &mut yuv, &mut mid_data).unwrap();
+            let encoded: Vec<_> = encoder
+                .encode(pts, input.yuv().unwrap(), STRIDE_ALIGN)
+                .unwrap()
+                .map(|f| (f.data.to_vec(), f.key))
+                .collect();
+            for (data, key) in encoded {
+                assert!(key || decoded > 0);
+                for image in decoder.decode(&data).unwrap() {
+                    assert_eq!((image.width(), image.height()), (width, height));
+                    decoded += 1;
+                }
+            }
+        }
+        assert!(decoded > 0);
+    }
+
+    // The video is downscaled to the viewport of the controller, which stretches it to the
+    // display, the display info is kept at the capture size. A point clicked on the video is
+    // sent at the stretched position, and has to be on what's at that point of the screen.
+    #[test]
+    fn test_downscaled_input() {
+        use crate::{
+            codec::{EncoderApi, EncoderCfg},
+            vpxcodec::{
+                VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId,
+            },
+            GoogleImage, ImageFormat, ImageRgb, STRIDE_ALIGN,
+        };
+
+        let (width, height) = (640, 480);
+        let (video_width, video_height) = (320, 240);
+        let mut captured = vec![0; width * height * 4];
+        draw_pattern(0, width, height, &mut captured);
+        let frame = Frame::PixelBuffer(PixelBuffer::new(&captured, Pixfmt::BGRA, width, height));
+        let mut encoder = VpxEncoder::new(
+            EncoderCfg::VPX(VpxEncoderConfig {
+                width: video_width as _,
+                height: video_height as _,
+                quality: 1.0,
+                codec: VpxVideoCodecId::VP9,
+                keyframe_interval: None,
+            }),
+            false,
+        )
+        .unwrap();
+        let mut decoder = VpxDecoder::new(VpxDecoderConfig {
+            codec: VpxVideoCodecId::VP9,
+        })
+        .unwrap();
+        let (mut yuv, mut mid_data) = (Vec::new(), Vec::new());
+        let input = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data).unwrap();
+        let mut rgb = ImageRgb::new(ImageFormat::ARGB, 1);
+        for f in encoder
+            .encode(0, input.yuv().unwrap(), STRIDE_ALIGN)
+            .unwrap()
+        {
+            for image in decoder.decode(f.data).unwrap() {
+                image.to(&mut rgb);
+            }
+        }
+        assert_eq!((rgb.w, rgb.h), (video_width, video_height));
+
+        let stride = rgb.raw.len() / rgb.h;
+        // the middles of the bars, the moving block on the white bar and the gradient
+        let mut points: Vec<_> = (0..BARS.len()).map(|i| (i * 40 + 20, 30)).collect();
+        points.extend([(15, 112), (100, 180), (260, 180)]);
+        for (x, y) in points {
+            let (display_x, display_y) = (x * width / video_width, y * height / video_height);
+            let shown = &rgb.raw[y * stride + x * 4..][..3];
+            let at = &captured[(display_y * width + display_x) * 4..][..3];
+            assert!(
+                shown.iter().zip(at).all(|(a, b)| a.abs_diff(*b) < 32),
+                "({x}, {y}) of the video shows {shown:?}, ({display_x}, {display_y}) is {at:?}"
+            );
+        }
+    }
+}
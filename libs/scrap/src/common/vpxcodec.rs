@@ -9,6 +9,7 @@ use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, V
 use hbb_common::ResultType;
 
 use crate::codec::{base_bitrate, codec_thread_num, EncoderApi};
+use crate::damage::Damage;
 use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
@@ -39,6 +40,7 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    force_keyframe: bool,
 }
 
 pub struct VpxDecoder {
@@ -165,6 +167,7 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    force_keyframe: false,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -228,6 +231,35 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn disable(&self) {}
+
+    fn request_keyframe(&mut self) -> bool {
+        self.force_keyframe = true;
+        true
+    }
+
+    fn support_damage(&self) -> bool {
+        true
+    }
+
+    fn set_damage(&mut self, damage: &Damage) {
+        // VP8 and VP9 take the active map in 16x16 macroblocks, a null map codes them all
+        let mut map = damage.active_map(self.width, self.height, 16);
+        let mut active_map = vpx_active_map_t {
+            active_map: map.as_mut().map_or(ptr::null_mut(), |m| m.as_mut_ptr()),
+            rows: self.height.div_ceil(16) as _,
+            cols: self.width.div_ceil(16) as _,
+        };
+        let ret = unsafe {
+            vpx_codec_control_(
+                &mut self.ctx,
+                VP8E_SET_ACTIVEMAP as _,
+                &mut active_map as *mut vpx_active_map_t,
+            )
+        };
+        if ret != VPX_CODEC_OK {
+            log::debug!("Failed to set active map: {ret:?}");
+        }
+    }
 }
 
 impl VpxEncoder {
@@ -252,12 +284,17 @@ impl VpxEncoder {
             data.as_ptr() as _,
         ));
 
+        let flags = if std::mem::take(&mut self.force_keyframe) {
+            VPX_EFLAG_FORCE_KF
+        } else {
+            0
+        };
         call_vpx!(vpx_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags as _,
             VPX_DL_REALTIME as _,
         ));
 
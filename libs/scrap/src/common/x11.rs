@@ -136,4 +136,25 @@ impl Display {
     pub fn get_shm_status(&self) -> Result<(), x11::Error> {
         self.0.server().get_shm_status()
     }
+
+    /// The region of the screens as a display.
+    pub fn region(x: i32, y: i32, width: i32, height: i32) -> io::Result<Display> {
+        let screen = Self::primary()?.0;
+        let Some(rect) = screen
+            .server()
+            .region_rect(screen.root(), x, y, width, height)
+        else {
+            return Err(io::ErrorKind::NotFound.into());
+        };
+        Ok(Display(screen.area(rect, "Region".to_owned(), None)))
+    }
+
+    /// The top-level window as a display, which moves with the window.
+    pub fn window(id: u32) -> io::Result<Display> {
+        let screen = Self::primary()?.0;
+        let Some(window) = screen.server().windows().into_iter().find(|w| w.id == id) else {
+            return Err(io::ErrorKind::NotFound.into());
+        };
+        Ok(Display(screen.area(window.rect, window.title, Some(id))))
+    }
 }
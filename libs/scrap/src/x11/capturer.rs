@@ -1,7 +1,16 @@
+use super::damage::XDamage;
 use super::ffi::*;
-use super::Display;
+use super::{Display, Rect};
 use hbb_common::libc;
-use std::{io, ptr, slice};
+use std::{
+    io, ptr, slice,
+    time::{Duration, Instant},
+};
+
+// Some drivers draw without reporting damage, e.g. direct rendering without a compositor.
+const FULL_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+// More rectangles cost more round trips than reading their bounding box.
+const MAX_DAMAGE_RECTS: usize = 16;
 
 pub struct Capturer {
     display: Display,
@@ -11,10 +20,27 @@ pub struct Capturer {
 
     size: usize,
     saved_raw_data: Vec<u8>, // for faster compare and copy
+
+    damage: Option<XDamage>,
+    last_full: Instant,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Update {
+    Nothing,
+    Full,
+    // Rectangles relative to the display.
+    Rects(Vec<Rect>),
 }
 
 impl Capturer {
     pub fn new(display: Display) -> io::Result<Capturer> {
+        Self::with_damage(display, true)
+    }
+
+    /// Without `use_damage`, or if XDamage is not available, the whole screen is read
+    /// and compared on every frame.
+    pub fn with_damage(display: Display, use_damage: bool) -> io::Result<Capturer> {
         // Calculate dimensions.
 
         let pixel_width = display.pixfmt().bytes_per_pixel();
@@ -57,6 +83,12 @@ impl Capturer {
             );
         }
 
+        let damage = if use_damage {
+            unsafe { XDamage::new(server, display.root()) }
+        } else {
+            None
+        };
+
         let c = Capturer {
             display,
             shmid,
@@ -64,6 +96,8 @@ impl Capturer {
             buffer,
             size,
             saved_raw_data: Vec::new(),
+            damage,
+            last_full: Instant::now(),
         };
         Ok(c)
     }
@@ -94,16 +128,184 @@ impl Capturer {
     }
 
     pub fn frame<'b>(&'b mut self) -> std::io::Result<&'b [u8]> {
+        if let Some(window) = self.display.window() {
+            self.follow_window(window)?;
+        }
+        if self.damage.is_some() {
+            return self.damaged_frame();
+        }
         self.get_image();
         let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
         crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
         Ok(result)
     }
+
+    // The display of a window moves with it, the size is fixed for the shared memory and the
+    // encoder, a resized window needs a new capturer.
+    fn follow_window(&mut self, window: xcb_window_t) -> io::Result<()> {
+        let server = self.display.server();
+        let Some(rect) = server.window_rect(self.display.root(), window) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the shared window is closed or off-screen",
+            ));
+        };
+        let old = self.display.rect();
+        if (rect.w, rect.h) != (old.w, old.h) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the shared window is resized",
+            ));
+        }
+        if (rect.x, rect.y) != (old.x, old.y) {
+            self.display.set_rect(rect);
+            // read the whole window at the new position
+            self.saved_raw_data.clear();
+        }
+        Ok(())
+    }
+
+    // `saved_raw_data` is the screen, kept up to date from the damaged rectangles.
+    fn damaged_frame(&mut self) -> std::io::Result<&[u8]> {
+        let areas = match &self.damage {
+            Some(damage) => unsafe { damage.take(self.display.server().raw()) },
+            None => vec![],
+        };
+        let first = self.saved_raw_data.len() != self.size;
+        let update = if first || self.last_full.elapsed() >= FULL_REFRESH_INTERVAL {
+            Update::Full
+        } else {
+            damaged_update(&areas, self.display.rect())
+        };
+        match update {
+            Update::Nothing => return Err(io::ErrorKind::WouldBlock.into()),
+            Update::Rects(rects) if self.get_rects(&rects) => {}
+            _ => {
+                self.last_full = Instant::now();
+                self.get_image();
+                let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
+                // Also the check of the periodic refresh, whether the damage was reported.
+                crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
+            }
+        }
+        Ok(&self.saved_raw_data)
+    }
+
+    // Read the rectangles into the shared memory one after another, and copy them into
+    // `saved_raw_data`. Returns false if any of them failed.
+    fn get_rects(&mut self, rects: &[Rect]) -> bool {
+        let bpp = self.display.pixfmt().bytes_per_pixel();
+        let stride = self.display.w() * bpp;
+        // the rows of the image are padded to 32 bits
+        let row_len = |r: &Rect| (r.w as usize * bpp).div_ceil(4) * 4;
+        let mut offsets = Vec::with_capacity(rects.len());
+        let mut offset = 0;
+        for r in rects {
+            offsets.push(offset);
+            offset += row_len(r) * r.h as usize;
+        }
+        if offset > self.size {
+            return false;
+        }
+        let server = self.display.server().raw();
+        let root = self.display.rect();
+        let requests: Vec<_> = rects
+            .iter()
+            .zip(offsets.iter())
+            .map(|(r, offset)| unsafe {
+                xcb_shm_get_image_unchecked(
+                    server,
+                    self.display.root(),
+                    root.x + r.x,
+                    root.y + r.y,
+                    r.w,
+                    r.h,
+                    !0,
+                    XCB_IMAGE_FORMAT_Z_PIXMAP,
+                    self.xcbid,
+                    *offset as _,
+                )
+            })
+            .collect();
+        let mut ok = true;
+        for request in requests {
+            let response = unsafe { xcb_shm_get_image_reply(server, request, ptr::null_mut()) };
+            ok &= !response.is_null();
+            unsafe { libc::free(response as *mut _) };
+        }
+        if !ok {
+            return false;
+        }
+        for (r, offset) in rects.iter().zip(offsets) {
+            let len = r.w as usize * bpp;
+            for row in 0..r.h as usize {
+                let src = offset + row * row_len(r);
+                let dst = (r.y as usize + row) * stride + r.x as usize * bpp;
+                let src = unsafe { slice::from_raw_parts(self.buffer.add(src), len) };
+                self.saved_raw_data[dst..dst + len].copy_from_slice(src);
+            }
+        }
+        true
+    }
+}
+
+// Clip the damaged areas of the root window to the display. Many or large areas are read
+// as a whole.
+fn damaged_update(areas: &[xcb_rectangle_t], display: Rect) -> Update {
+    let (dx, dy) = (display.x as i32, display.y as i32);
+    let (dw, dh) = (display.w as i32, display.h as i32);
+    let mut rects: Vec<Rect> = areas
+        .iter()
+        .filter_map(|a| {
+            let x0 = (a.x as i32 - dx).max(0);
+            let y0 = (a.y as i32 - dy).max(0);
+            let x1 = (a.x as i32 + a.width as i32 - dx).min(dw);
+            let y1 = (a.y as i32 + a.height as i32 - dy).min(dh);
+            (x0 < x1 && y0 < y1).then(|| Rect {
+                x: x0 as _,
+                y: y0 as _,
+                w: (x1 - x0) as _,
+                h: (y1 - y0) as _,
+            })
+        })
+        .collect();
+    if rects.is_empty() {
+        return Update::Nothing;
+    }
+    let area = |r: &Rect| r.w as usize * r.h as usize;
+    if rects.len() > MAX_DAMAGE_RECTS {
+        let x0 = rects.iter().map(|r| r.x).min().unwrap_or_default();
+        let y0 = rects.iter().map(|r| r.y).min().unwrap_or_default();
+        let x1 = rects
+            .iter()
+            .map(|r| r.x + r.w as i16)
+            .max()
+            .unwrap_or_default();
+        let y1 = rects
+            .iter()
+            .map(|r| r.y + r.h as i16)
+            .max()
+            .unwrap_or_default();
+        rects = vec![Rect {
+            x: x0,
+            y: y0,
+            w: (x1 - x0) as _,
+            h: (y1 - y0) as _,
+        }];
+    }
+    // overlapping areas are counted twice, it only decides when to read the whole screen
+    if rects.iter().map(area).sum::<usize>() * 2 > dw as usize * dh as usize {
+        return Update::Full;
+    }
+    Update::Rects(rects)
 }
 
 impl Drop for Capturer {
     fn drop(&mut self) {
         unsafe {
+            if let Some(damage) = self.damage.take() {
+                damage.destroy(self.display.server().raw());
+            }
             // Detach segment from XCB.
             xcb_shm_detach(self.display.server().raw(), self.xcbid);
             // Detach segment from our space.
@@ -113,3 +315,126 @@ impl Drop for Capturer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x11::Server;
+
+    #[test]
+    fn test_damaged_update() {
+        let display = Rect {
+            x: 100,
+            y: 0,
+            w: 200,
+            h: 100,
+        };
+        let area = |x, y, width, height| xcb_rectangle_t {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(damaged_update(&[], display), Update::Nothing);
+        assert_eq!(
+            damaged_update(&[area(0, 0, 50, 50)], display),
+            Update::Nothing,
+            "damage of another display"
+        );
+        assert_eq!(
+            damaged_update(&[area(90, 10, 20, 20)], display),
+            Update::Rects(vec![Rect {
+                x: 0,
+                y: 10,
+                w: 10,
+                h: 20
+            }])
+        );
+        assert_eq!(
+            damaged_update(&[area(100, 0, 200, 60)], display),
+            Update::Full
+        );
+        let cursor: Vec<_> = (0..20).map(|i| area(110 + i, 10, 1, 1)).collect();
+        assert_eq!(
+            damaged_update(&cursor, display),
+            Update::Rects(vec![Rect {
+                x: 10,
+                y: 10,
+                w: 20,
+                h: 1
+            }])
+        );
+    }
+
+    #[link(name = "xcb")]
+    extern "C" {
+        fn xcb_create_gc(
+            c: *mut xcb_connection_t,
+            cid: u32,
+            drawable: xcb_drawable_t,
+            value_mask: u32,
+            value_list: *const u32,
+        ) -> xcb_void_cookie_t;
+
+        fn xcb_poly_fill_rectangle(
+            c: *mut xcb_connection_t,
+            drawable: xcb_drawable_t,
+            gc: u32,
+            rectangles_len: u32,
+            rectangles: *const xcb_rectangle_t,
+        ) -> xcb_void_cookie_t;
+
+        fn xcb_free_gc(c: *mut xcb_connection_t, gc: u32) -> xcb_void_cookie_t;
+    }
+
+    const XCB_GC_FOREGROUND: u32 = 4;
+
+    // Needs an X server with the DAMAGE extension, e.g.
+    // xvfb-run -s "-screen 0 640x480x24" cargo test test_xdamage_capture
+    #[test]
+    fn test_xdamage_capture() {
+        if std::env::var("DISPLAY").is_err() {
+            return;
+        }
+        let display = || Server::displays(Server::default().unwrap()).next().unwrap();
+        let mut capturer = Capturer::new(display()).unwrap();
+        if capturer.damage.is_none() {
+            return;
+        }
+        capturer.frame().unwrap();
+        assert_eq!(
+            capturer.frame().unwrap_err().kind(),
+            io::ErrorKind::WouldBlock,
+            "nothing changed"
+        );
+
+        // another client draws a green square
+        let painter = display();
+        let rect = painter.rect();
+        unsafe {
+            let c = painter.server().raw();
+            let gc = xcb_generate_id(c);
+            xcb_create_gc(c, gc, painter.root(), XCB_GC_FOREGROUND, &0x00ff00u32);
+            let square = xcb_rectangle_t {
+                x: rect.x + 10,
+                y: rect.y + 10,
+                width: 20,
+                height: 20,
+            };
+            xcb_poly_fill_rectangle(c, painter.root(), gc, 1, &square);
+            xcb_free_gc(c, gc);
+            xcb_flush(c);
+        }
+        std::thread::sleep(Duration::from_millis(200));
+
+        let frame = capturer.frame().unwrap().to_vec();
+        let bpp = capturer.display().pixfmt().bytes_per_pixel();
+        let pixel = (15 * capturer.display().w() + 15) * bpp;
+        assert_eq!(&frame[pixel..pixel + 3], &[0x00, 0xff, 0x00]);
+        let mut full = Capturer::with_damage(display(), false).unwrap();
+        assert!(
+            frame == full.frame().unwrap(),
+            "the damaged rectangles are merged into the screen"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Damage reports of the X server, so that only the changed areas of the screen are read.
+//!
+//! libxcb-damage is loaded at runtime, the capturer falls back to reading the whole
+//! screen if the library or the DAMAGE extension is missing.
+
+use super::ffi::*;
+use hbb_common::{dlopen::symbor::Library, lazy_static, libc, log};
+use std::ptr;
+
+const LIB_NAME: &str = "libxcb-damage.so.0";
+
+type QueryVersion =
+    unsafe extern "C" fn(*mut xcb_connection_t, u32, u32) -> xcb_damage_query_version_cookie_t;
+type QueryVersionReply = unsafe extern "C" fn(
+    *mut xcb_connection_t,
+    xcb_damage_query_version_cookie_t,
+    *mut *mut xcb_generic_error_t,
+) -> *mut xcb_damage_query_version_reply_t;
+type Create = unsafe extern "C" fn(
+    *mut xcb_connection_t,
+    xcb_damage_damage_t,
+    xcb_drawable_t,
+    u8,
+) -> xcb_void_cookie_t;
+type Destroy =
+    unsafe extern "C" fn(*mut xcb_connection_t, xcb_damage_damage_t) -> xcb_void_cookie_t;
+type Subtract = unsafe extern "C" fn(
+    *mut xcb_connection_t,
+    xcb_damage_damage_t,
+    xcb_xfixes_region_t,
+    xcb_xfixes_region_t,
+) -> xcb_void_cookie_t;
+
+struct Lib {
+    _lib: Library,
+    id: *mut xcb_extension_t,
+    query_version: QueryVersion,
+    query_version_reply: QueryVersionReply,
+    create: Create,
+    destroy: Destroy,
+    subtract: Subtract,
+}
+
+// `id` points to static data of the library, which is never unloaded.
+unsafe impl Send for Lib {}
+unsafe impl Sync for Lib {}
+
+lazy_static::lazy_static! {
+    static ref LIB: Option<Lib> = Lib::load();
+}
+
+impl Lib {
+    fn load() -> Option<Self> {
+        let lib = match Library::open(LIB_NAME) {
+            Ok(lib) => lib,
+            Err(e) => {
+                log::info!("Failed to load {LIB_NAME}, capture the whole screen: {e}");
+                return None;
+            }
+        };
+        unsafe {
+            Some(Self {
+                id: *lib.symbol::<*mut xcb_extension_t>("xcb_damage_id").ok()?,
+                query_version: *lib.symbol("xcb_damage_query_version").ok()?,
+                query_version_reply: *lib.symbol("xcb_damage_query_version_reply").ok()?,
+                create: *lib.symbol("xcb_damage_create").ok()?,
+                destroy: *lib.symbol("xcb_damage_destroy").ok()?,
+                subtract: *lib.symbol("xcb_damage_subtract").ok()?,
+                _lib: lib,
+            })
+        }
+    }
+}
+
+pub(super) struct XDamage {
+    lib: &'static Lib,
+    damage: xcb_damage_damage_t,
+    notify_event: u8,
+}
+
+impl XDamage {
+    /// Report the damage of `drawable` from now on, `None` if the server does not support it.
+    pub(super) unsafe fn new(c: *mut xcb_connection_t, drawable: xcb_drawable_t) -> Option<Self> {
+        let lib = LIB.as_ref()?;
+        let ext = xcb_get_extension_data(c, lib.id);
+        if ext.is_null() || (*ext).present == 0 {
+            log::info!("No DAMAGE extension, capture the whole screen");
+            return None;
+        }
+        // The version must be negotiated before any other request of the extension.
+        let cookie = (lib.query_version)(c, 1, 1);
+        let mut e: *mut xcb_generic_error_t = ptr::null_mut();
+        let reply = (lib.query_version_reply)(c, cookie, &mut e as _);
+        if !e.is_null() {
+            libc::free(e as *mut _);
+        }
+        if reply.is_null() {
+            log::info!("Failed to query the DAMAGE version, capture the whole screen");
+            return None;
+        }
+        libc::free(reply as *mut _);
+        let damage = xcb_generate_id(c);
+        (lib.create)(
+            c,
+            damage,
+            drawable,
+            XCB_DAMAGE_REPORT_LEVEL_DELTA_RECTANGLES,
+        );
+        xcb_flush(c);
+        Some(Self {
+            lib,
+            damage,
+            notify_event: (*ext).first_event + XCB_DAMAGE_NOTIFY,
+        })
+    }
+
+    /// The areas damaged since the last call, which count as repaired afterwards.
+    pub(super) unsafe fn take(&self, c: *mut xcb_connection_t) -> Vec<xcb_rectangle_t> {
+        let mut areas = vec![];
+        loop {
+            let event = xcb_poll_for_event(c);
+            if event.is_null() {
+                break;
+            }
+            // The top bit is set for the events from SendEvent.
+            if (*event).response_type & 0x7f == self.notify_event {
+                let notify = event as *const xcb_damage_notify_event_t;
+                if (*notify).damage == self.damage {
+                    areas.push((*notify).area);
+                }
+            }
+            libc::free(event as *mut _);
+        }
+        if !areas.is_empty() {
+            // Damage from now on is reported again, even where it was reported already.
+            (self.lib.subtract)(c, self.damage, XCB_NONE, XCB_NONE);
+        }
+        areas
+    }
+
+    pub(super) unsafe fn destroy(self, c: *mut xcb_connection_t) {
+        (self.lib.destroy)(c, self.damage);
+        xcb_flush(c);
+    }
+}
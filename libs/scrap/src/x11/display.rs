@@ -12,6 +12,8 @@ pub struct Display {
     root: xcb_window_t,
     name: String,
     pixfmt: Pixfmt,
+    // The shared window, the rect follows it.
+    window: Option<xcb_window_t>,
 }
 
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
@@ -38,6 +40,20 @@ impl Display {
             root,
             name,
             pixfmt,
+            window: None,
+        }
+    }
+
+    /// A part of the root window as a display, e.g. a region or a window.
+    pub fn area(&self, rect: Rect, name: String, window: Option<xcb_window_t>) -> Display {
+        Display {
+            server: self.server.clone(),
+            default: true,
+            rect,
+            root: self.root,
+            name,
+            pixfmt: self.pixfmt,
+            window,
         }
     }
 
@@ -50,6 +66,12 @@ impl Display {
     pub fn rect(&self) -> Rect {
         self.rect
     }
+    pub(super) fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+    pub fn window(&self) -> Option<xcb_window_t> {
+        self.window
+    }
     pub fn w(&self) -> usize {
         self.rect.w as _
     }
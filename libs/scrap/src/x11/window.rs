@@ -0,0 +1,172 @@
+use std::ptr;
+
+use hbb_common::libc;
+
+use super::ffi::*;
+use super::{Rect, Server};
+
+// Enough for the managed windows of any desktop, in 32-bit units.
+const MAX_PROPERTY_LEN: u32 = 4096;
+
+/// A top-level window managed by the window manager.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: xcb_window_t,
+    pub title: String,
+    /// The visible part on the root window.
+    pub rect: Rect,
+}
+
+impl Server {
+    pub fn root(&self) -> Option<xcb_window_t> {
+        unsafe {
+            let mut iter = xcb_setup_roots_iterator(self.setup());
+            for _ in 0..self.screenp() {
+                if iter.rem == 0 {
+                    break;
+                }
+                xcb_screen_next(&mut iter);
+            }
+            (iter.rem != 0).then(|| (*iter.data).root)
+        }
+    }
+
+    /// The top-level windows in stacking order, from `_NET_CLIENT_LIST_STACKING` or
+    /// `_NET_CLIENT_LIST` of the window manager. The off-screen ones are skipped.
+    pub fn windows(&self) -> Vec<Window> {
+        let Some(root) = self.root() else {
+            return vec![];
+        };
+        let ids = ["_NET_CLIENT_LIST_STACKING", "_NET_CLIENT_LIST"]
+            .iter()
+            .filter_map(|name| self.atom(name))
+            .map(|atom| self.property(root, atom, XCB_ATOM_WINDOW))
+            .find(|v| !v.is_empty())
+            .unwrap_or_default();
+        ids.chunks_exact(4)
+            .filter_map(|id| {
+                let id = u32::from_ne_bytes([id[0], id[1], id[2], id[3]]);
+                let rect = self.window_rect(root, id)?;
+                Some(Window {
+                    id,
+                    title: self.window_title(id),
+                    rect,
+                })
+            })
+            .collect()
+    }
+
+    /// The visible part of the window on the root window, `None` if it's closed or off-screen.
+    pub fn window_rect(&self, root: xcb_window_t, window: xcb_window_t) -> Option<Rect> {
+        unsafe {
+            let c = self.raw();
+            let root_cookie = xcb_get_geometry_unchecked(c, root);
+            let window_cookie = xcb_get_geometry_unchecked(c, window);
+            let origin_cookie = xcb_translate_coordinates(c, window, root, 0, 0);
+            let root_geo = xcb_get_geometry_reply(c, root_cookie, ptr::null_mut());
+            let window_geo = xcb_get_geometry_reply(c, window_cookie, ptr::null_mut());
+            let origin = xcb_translate_coordinates_reply(c, origin_cookie, ptr::null_mut());
+            let rect = if root_geo.is_null() || window_geo.is_null() || origin.is_null() {
+                None
+            } else {
+                clip(
+                    (*origin).dst_x as i32,
+                    (*origin).dst_y as i32,
+                    (*window_geo).width as i32,
+                    (*window_geo).height as i32,
+                    (*root_geo).width as i32,
+                    (*root_geo).height as i32,
+                )
+            };
+            libc::free(root_geo as _);
+            libc::free(window_geo as _);
+            libc::free(origin as _);
+            rect
+        }
+    }
+
+    /// The region of the root window clipped to it, `None` if nothing is left.
+    pub fn region_rect(&self, root: xcb_window_t, x: i32, y: i32, w: i32, h: i32) -> Option<Rect> {
+        let screen = self.window_rect(root, root)?;
+        clip(x, y, w, h, screen.w as _, screen.h as _)
+    }
+
+    fn window_title(&self, window: xcb_window_t) -> String {
+        let utf8 = self
+            .atom("_NET_WM_NAME")
+            .zip(self.atom("UTF8_STRING"))
+            .map(|(name, utf8)| self.property(window, name, utf8))
+            .unwrap_or_default();
+        let title = if utf8.is_empty() {
+            self.property(window, XCB_ATOM_WM_NAME, XCB_ATOM_ANY)
+        } else {
+            utf8
+        };
+        String::from_utf8_lossy(&title).to_string()
+    }
+
+    fn atom(&self, name: &str) -> Option<xcb_atom_t> {
+        unsafe {
+            let cookie = xcb_intern_atom(self.raw(), 1, name.len() as _, name.as_ptr());
+            let reply = xcb_intern_atom_reply(self.raw(), cookie, ptr::null_mut());
+            if reply.is_null() {
+                return None;
+            }
+            let atom = (*reply).atom;
+            libc::free(reply as _);
+            (atom != XCB_NONE).then_some(atom)
+        }
+    }
+
+    fn property(&self, window: xcb_window_t, property: xcb_atom_t, type_: xcb_atom_t) -> Vec<u8> {
+        unsafe {
+            let cookie =
+                xcb_get_property(self.raw(), 0, window, property, type_, 0, MAX_PROPERTY_LEN);
+            let reply = xcb_get_property_reply(self.raw(), cookie, ptr::null_mut());
+            if reply.is_null() {
+                return vec![];
+            }
+            let len = xcb_get_property_value_length(reply).max(0) as usize;
+            let value = xcb_get_property_value(reply) as *const u8;
+            let v = if value.is_null() {
+                vec![]
+            } else {
+                std::slice::from_raw_parts(value, len).to_vec()
+            };
+            libc::free(reply as _);
+            v
+        }
+    }
+}
+
+/// Clip the rectangle to the root window of `root_w` x `root_h`, `None` if nothing is left.
+fn clip(x: i32, y: i32, w: i32, h: i32, root_w: i32, root_h: i32) -> Option<Rect> {
+    let (x0, y0) = (x.max(0), y.max(0));
+    let (x1, y1) = ((x + w).min(root_w), (y + h).min(root_h));
+    // even sizes for the 2x2 subsampled chroma
+    let (w, h) = ((x1 - x0) & !1, (y1 - y0) & !1);
+    (w > 0 && h > 0).then(|| Rect {
+        x: x0 as _,
+        y: y0 as _,
+        w: w as _,
+        h: h as _,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip() {
+        let rect = |x, y, w, h| Some(Rect { x, y, w, h });
+        assert_eq!(clip(10, 20, 300, 200, 1920, 1080), rect(10, 20, 300, 200));
+        assert_eq!(clip(-10, -20, 301, 200, 1920, 1080), rect(0, 0, 290, 180));
+        assert_eq!(
+            clip(1800, 1000, 300, 200, 1920, 1080),
+            rect(1800, 1000, 120, 80)
+        );
+        assert_eq!(clip(2000, 0, 300, 200, 1920, 1080), None);
+        assert_eq!(clip(0, 0, 1, 1, 1920, 1080), None);
+    }
+}
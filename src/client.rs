@@ -21,6 +21,7 @@ use std::{
     ops::Deref,
     str::FromStr,
     sync::{
+        atomic::{AtomicU32, Ordering},
         mpsc::{self, RecvTimeoutError},
         Arc, Mutex, RwLock,
     },
@@ -90,8 +91,12 @@ use crate::ui_session_interface::SessionPermissionConfig;
 pub use super::lang::*;
 
 pub mod file_trait;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod gamepad;
 pub mod helper;
 pub mod io_loop;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod macros;
 pub mod screenshot;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
@@ -1180,6 +1185,8 @@ pub struct AudioHandler {
     audio_decoder: Option<(AudioDecoder, Vec<f32>)>,
     #[cfg(target_os = "linux")]
     simple: Option<psimple::Simple>,
+    #[cfg(target_os = "linux")]
+    device: Option<String>,
     #[cfg(not(target_os = "linux"))]
     audio_buffer: AudioBuffer,
     sample_rate: (u32, u32),
@@ -1190,6 +1197,35 @@ pub struct AudioHandler {
     device_channel: u16,
     #[cfg(not(target_os = "linux"))]
     ready: Arc<std::sync::Mutex<bool>>,
+    loss: Option<Arc<AudioLoss>>,
+    last_seq: Option<u64>,
+    // The samples per channel of the last frame, the size of a frame lost before the next one
+    frame_size: usize,
+}
+
+/// The audio frames received and lost since the last report to the peer.
+#[derive(Default)]
+pub struct AudioLoss {
+    received: AtomicU32,
+    lost: AtomicU32,
+}
+
+impl AudioLoss {
+    fn on_frame(&self, lost: u32) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.lost.fetch_add(lost, Ordering::Relaxed);
+    }
+
+    /// The percentage of the frames lost since the last call, `None` without any frame.
+    pub fn take_percent(&self) -> Option<u32> {
+        let received = self.received.swap(0, Ordering::Relaxed);
+        let lost = self.lost.swap(0, Ordering::Relaxed);
+        let total = received + lost;
+        if total == 0 {
+            return None;
+        }
+        Some(lost * 100 / total)
+    }
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -1334,7 +1370,7 @@ impl AudioHandler {
             None,                   // Use the default server
             &crate::get_app_name(), // Our application’s name
             Direction::Playback,    // We want a playback stream
-            None,                   // Use the default device
+            self.device.as_deref(), // Use the default device if not given
             "playback",             // Description of our stream
             &spec,                  // Our sample format
             None,                   // Use default channel map
@@ -1399,6 +1435,10 @@ impl AudioHandler {
     pub fn handle_format(&mut self, f: AudioFormat) {
         match AudioDecoder::new(f.sample_rate, if f.channels > 1 { Stereo } else { Mono }) {
             Ok(d) => {
+                if f.audio_preset != 0 {
+                    let preset = crate::AudioPreset::from_u32(f.audio_preset);
+                    log::info!("audio preset of the peer: {preset:?}");
+                }
                 let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
                 self.audio_decoder = Some((d, buffer));
                 self.channels = f.channels as _;
@@ -1422,42 +1462,71 @@ impl AudioHandler {
             log::debug!("PulseAudio simple binding does not exists");
             return;
         }
-        self.audio_decoder.as_mut().map(|(d, buffer)| {
-            if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
-                let channels = self.channels;
-                let n = n * (channels as usize);
-                #[cfg(not(target_os = "linux"))]
-                {
-                    let sample_rate0 = self.sample_rate.0;
-                    let sample_rate = self.sample_rate.1;
-                    let mut buffer = buffer[0..n].to_owned();
-                    if sample_rate != sample_rate0 {
-                        buffer = crate::audio_resample(
-                            &buffer[0..n],
-                            sample_rate0,
-                            sample_rate,
-                            channels,
-                        );
-                    }
-                    if self.channels != self.device_channel {
-                        buffer = crate::audio_rechannel(
-                            buffer,
-                            sample_rate,
-                            sample_rate,
-                            self.channels,
-                            self.device_channel,
-                        );
-                    }
-                    self.audio_buffer.append_pcm(&buffer);
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    let data_u8 =
-                        unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
-                    self.simple.as_mut().map(|x| x.write(data_u8));
-                }
+        let seq = frame.seq;
+        // a lower seq is a restarted audio service, not a loss
+        let lost = match (self.last_seq, seq) {
+            (Some(last), Some(seq)) if seq > last => seq - last - 1,
+            _ => 0,
+        };
+        if seq.is_some() {
+            self.last_seq = seq;
+        }
+        if let Some(loss) = self.loss.as_ref() {
+            loss.on_frame(lost.min(u32::MAX as u64) as _);
+        }
+        let Some((mut d, mut buffer)) = self.audio_decoder.take() else {
+            return;
+        };
+        let channels = self.channels as usize;
+        if lost == 1 && self.frame_size > 0 {
+            // Recover the lost frame from the in-band FEC data of this one
+            let len = (self.frame_size * channels).min(buffer.len());
+            if let Ok(n) = d.decode_float(&frame.data, &mut buffer[..len], true) {
+                self.play(&buffer[0..n * channels]);
             }
-        });
+        }
+        if let Ok(n) = d.decode_float(&frame.data, &mut buffer, false) {
+            self.frame_size = n;
+            self.play(&buffer[0..n * channels]);
+        }
+        self.audio_decoder = Some((d, buffer));
+    }
+
+    fn play(&mut self, buffer: &[f32]) {
+        #[cfg(not(target_os = "linux"))]
+        {
+            let channels = self.channels;
+            let sample_rate0 = self.sample_rate.0;
+            let sample_rate = self.sample_rate.1;
+            let mut buffer = buffer.to_owned();
+            if sample_rate != sample_rate0 {
+                buffer = crate::audio_resample(&buffer, sample_rate0, sample_rate, channels);
+            }
+            if self.channels != self.device_channel {
+                buffer = crate::audio_rechannel(
+                    buffer,
+                    sample_rate,
+                    sample_rate,
+                    self.channels,
+                    self.device_channel,
+                );
+            }
+            self.audio_buffer.append_pcm(&buffer);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            // Played after the audio written before
+            let delay = self
+                .simple
+                .as_ref()
+                .and_then(|x| x.get_latency().ok())
+                .map(|x| Duration::from_micros(x.0))
+                .unwrap_or_default();
+            crate::push_echo_reference(buffer, self.sample_rate.0, self.channels, delay);
+            let data_u8 =
+                unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, buffer.len() * 4) };
+            self.simple.as_mut().map(|x| x.write(data_u8));
+        }
     }
 
     /// Build audio output stream for current device.
@@ -1476,6 +1545,7 @@ impl AudioHandler {
             .resize(config.sample_rate.0 as _, config.channels as _);
         let audio_buffer = self.audio_buffer.0.clone();
         let ready = self.ready.clone();
+        let (sample_rate, channels) = (config.sample_rate.0, config.channels);
         let timeout = None;
         let stream = device.build_output_stream(
             config,
@@ -1518,6 +1588,12 @@ impl AudioHandler {
                     lock.pop_slice(&mut elems);
                 }
                 drop(lock);
+                let tms = info.timestamp();
+                let delay = tms
+                    .playback
+                    .duration_since(&tms.callback)
+                    .unwrap_or_default();
+                crate::push_echo_reference(&elems, sample_rate, channels, delay);
 
                 let mut input = elems.into_iter();
                 for sample in data.iter_mut() {
@@ -1654,6 +1730,14 @@ impl VideoHandler {
     pub fn record_screen(&mut self, start: bool, id: String, display_idx: usize, camera: bool) {
         self.record = false;
         if start {
+            let public_key = match crate::get_record_public_key() {
+                Ok(public_key) => public_key,
+                Err(e) => {
+                    log::error!("Recording disabled: {e}");
+                    self.recorder = Default::default();
+                    return;
+                }
+            };
             self.recorder = Recorder::new(RecorderContext {
                 server: false,
                 id,
@@ -1661,6 +1745,12 @@ impl VideoHandler {
                 display_idx,
                 camera,
                 tx: None,
+                public_key,
+                // The events of the timeline are recorded by the controlled side only
+                timeline: None,
+                audio: !camera,
+                segment: crate::get_record_segment_config(),
+                retention: crate::get_record_retention_policy(),
             })
             .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
         } else {
@@ -1669,6 +1759,17 @@ impl VideoHandler {
 
         self.record = start;
     }
+
+    /// Write an audio frame to the screen record.
+    pub fn record_audio(&mut self, frame: &AudioFrame) {
+        if self.record {
+            self.recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.write_audio(frame));
+        }
+    }
 }
 
 // The source of sent password
@@ -2176,6 +2277,19 @@ impl LoginConfigHandler {
                     option.show_my_cursor = BoolOption::No.into();
                 }
             }
+        } else if name == crate::OPTION_MIC_REDIRECT || name == crate::OPTION_VOICE_PROCESSING {
+            let on = !self.get_toggle_option(&name);
+            if on {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            let q = if on { BoolOption::Yes } else { BoolOption::No };
+            if name == crate::OPTION_MIC_REDIRECT {
+                option.mic_redirect = q.into();
+            } else {
+                option.voice_processing = q.into();
+            }
         } else if name == "show-my-cursor" {
             config.show_my_cursor.v = !config.show_my_cursor.v;
             option.show_my_cursor = if config.show_my_cursor.v {
@@ -2298,6 +2412,17 @@ impl LoginConfigHandler {
             msg.disable_clipboard = BoolOption::Yes.into();
         }
         msg.supported_decoding = MessageField::some(self.get_supported_decoding());
+        msg.bandwidth_limit = self.get_bandwidth_limit();
+        let preset = crate::AudioPreset::from_option(&self.get_option(crate::OPTION_AUDIO_PRESET));
+        if preset != crate::AudioPreset::Auto {
+            msg.audio_preset = Some(preset as _);
+        }
+        if self.get_toggle_option(crate::OPTION_MIC_REDIRECT) {
+            msg.mic_redirect = BoolOption::Yes.into();
+        }
+        if self.get_toggle_option(crate::OPTION_VOICE_PROCESSING) {
+            msg.voice_processing = BoolOption::Yes.into();
+        }
         Some(msg)
     }
 
@@ -2400,6 +2525,29 @@ impl LoginConfigHandler {
         msg_out
     }
 
+    /// Create a [`Message`] for asking a keyframe of the display.
+    pub fn request_keyframe(display: usize) -> Message {
+        let mut misc = Misc::new();
+        misc.set_keyframe_request(display as _);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    /// Create a [`Message`] for telling the size the display is shown at.
+    pub fn viewport(display: usize, width: usize, height: usize) -> Message {
+        let mut misc = Misc::new();
+        misc.set_viewport(Viewport {
+            display: display as _,
+            width: width as _,
+            height: height as _,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
     /// Create a [`Message`] for saving custom image quality.
     ///
     /// # Arguments
@@ -2444,6 +2592,58 @@ impl LoginConfigHandler {
         res
     }
 
+    /// The bandwidth limit of the session in kbps, `None` for no limit.
+    pub fn get_bandwidth_limit(&self) -> Option<u32> {
+        self.get_option(crate::OPTION_BANDWIDTH_LIMIT)
+            .parse()
+            .ok()
+            .filter(|v| *v > 0)
+    }
+
+    /// Create a [`Message`] for setting the bandwidth limit, and save it to the config.
+    ///
+    /// # Arguments
+    ///
+    /// * `kbps` - The limit in kbps, 0 for no limit.
+    pub fn set_bandwidth_limit(&mut self, kbps: u32) -> Message {
+        let option = OptionMessage {
+            bandwidth_limit: Some(kbps),
+            ..Default::default()
+        };
+        let mut misc = Misc::new();
+        misc.set_option(option);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        let value = if kbps > 0 {
+            kbps.to_string()
+        } else {
+            "".to_owned()
+        };
+        self.set_option(crate::OPTION_BANDWIDTH_LIMIT.to_owned(), value);
+        msg_out
+    }
+
+    /// Create a [`Message`] for setting the audio preset, and save it to the config.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The preset option, "" for auto.
+    pub fn set_audio_preset(&mut self, value: &str) -> Message {
+        let preset = crate::AudioPreset::from_option(value);
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            audio_preset: Some(preset as _),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.set_option(
+            crate::OPTION_AUDIO_PRESET.to_owned(),
+            preset.to_option().to_owned(),
+        );
+        msg_out
+    }
+
     pub fn save_trackpad_speed(&mut self, speed: i32) {
         let mut config = self.load_config();
         config.trackpad_speed = speed;
@@ -2925,7 +3125,7 @@ pub fn start_video_thread<F, T>(
                                     //
                                     // to-do: fix the error
                                     log::error!("handle video frame error, {}", e);
-                                    session.refresh_video(display as _);
+                                    session.request_keyframe(display as _);
                                 }
                                 _ => {}
                             }
@@ -2963,6 +3163,11 @@ pub fn start_video_thread<F, T>(
                             handler.record_screen(start, id, display, is_view_camera);
                         }
                     }
+                    MediaData::AudioFrame(af) => {
+                        if let Some(handler) = video_handler.as_mut() {
+                            handler.record_audio(&af);
+                        }
+                    }
                     _ => {}
                 }
             } else {
@@ -2975,10 +3180,22 @@ pub fn start_video_thread<F, T>(
 
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
-pub fn start_audio_thread() -> MediaSender {
+///
+/// # Arguments
+///
+/// * `loss` - Counts the frames lost in the network, to report to the peer.
+/// * `device` - The sink to play to on Linux, the default output if `None`.
+pub fn start_audio_thread(loss: Option<Arc<AudioLoss>>, device: Option<String>) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
+    #[cfg(not(target_os = "linux"))]
+    let _ = device;
     std::thread::spawn(move || {
-        let mut audio_handler = AudioHandler::default();
+        let mut audio_handler = AudioHandler {
+            loss,
+            #[cfg(target_os = "linux")]
+            device,
+            ..Default::default()
+        };
         loop {
             if let Ok(data) = audio_receiver.recv() {
                 match data {
@@ -3766,6 +3983,8 @@ pub enum Data {
     AddPortForward((i32, String, i32)),
     #[cfg(all(target_os = "windows", not(feature = "flutter")))]
     ToggleClipboardFile,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    ToggleGamepad,
     NewRDP,
     SetConfirmOverrideFile((i32, i32, bool, bool, bool)),
     AddJob((i32, JobType, String, String, i32, bool, bool)),
@@ -0,0 +1,142 @@
+//! Forward the state of the local gamepads to the peer, which exposes them as virtual ones.
+
+use super::Data;
+use crate::{GamepadButton, GamepadState, GAMEPAD_MAX};
+use gilrs::{Axis, Button, EventType, Gamepad, GamepadId, Gilrs};
+use hbb_common::{log, message_proto::*, tokio::sync::mpsc};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{channel, Sender, TryRecvError},
+    time::Duration,
+};
+
+// The gamepads are polled at this interval, below a frame of 60 fps.
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
+/// Start forwarding the gamepads, until the returned sender is used or dropped.
+pub fn start(sender: mpsc::UnboundedSender<Data>) -> Sender<()> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(err) => {
+                log::error!("Failed to open the gamepads: {}", err);
+                return;
+            }
+        };
+        log::info!("Start forwarding gamepads");
+        // The state last sent of the gamepads forwarded, with their index on the peer
+        let mut forwarded: HashMap<GamepadId, GamepadState> = HashMap::new();
+        loop {
+            match rx.try_recv() {
+                Ok(_) | Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
+            }
+            while let Some(event) = gilrs.next_event() {
+                let id = event.id;
+                if matches!(event.event, EventType::Disconnected) {
+                    if let Some(state) = forwarded.remove(&id) {
+                        send(&sender, &unplugged(state.index));
+                    }
+                    continue;
+                }
+                let index = match forwarded.get(&id) {
+                    Some(state) => state.index,
+                    None => {
+                        let Some(index) =
+                            (0..GAMEPAD_MAX).find(|i| !forwarded.values().any(|s| s.index == *i))
+                        else {
+                            continue;
+                        };
+                        log::info!("Forward gamepad {}", index);
+                        index
+                    }
+                };
+                let state = read_state(&gilrs.gamepad(id), index);
+                if forwarded.get(&id) != Some(&state) {
+                    send(&sender, &state);
+                    forwarded.insert(id, state);
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        for state in forwarded.values() {
+            send(&sender, &unplugged(state.index));
+        }
+        log::info!("Stop forwarding gamepads");
+    });
+    tx
+}
+
+fn send(sender: &mpsc::UnboundedSender<Data>, state: &GamepadState) {
+    let mut misc = Misc::new();
+    misc.set_gamepad_state(state.to_message());
+    let mut msg = Message::new();
+    msg.set_misc(misc);
+    sender.send(Data::Message(msg)).ok();
+}
+
+fn unplugged(index: u8) -> GamepadState {
+    GamepadState {
+        index,
+        ..Default::default()
+    }
+}
+
+fn read_state(gamepad: &Gamepad, index: u8) -> GamepadState {
+    let mut state = GamepadState {
+        index,
+        connected: true,
+        // gilrs has the y axes up positive
+        axes: [
+            stick(gamepad.value(Axis::LeftStickX)),
+            stick(-gamepad.value(Axis::LeftStickY)),
+            stick(gamepad.value(Axis::RightStickX)),
+            stick(-gamepad.value(Axis::RightStickY)),
+        ],
+        triggers: [
+            trigger(gamepad, Button::LeftTrigger2),
+            trigger(gamepad, Button::RightTrigger2),
+        ],
+        ..Default::default()
+    };
+    for button in GamepadButton::ALL {
+        let pressed = match button {
+            GamepadButton::South => gamepad.is_pressed(Button::South),
+            GamepadButton::East => gamepad.is_pressed(Button::East),
+            GamepadButton::West => gamepad.is_pressed(Button::West),
+            GamepadButton::North => gamepad.is_pressed(Button::North),
+            GamepadButton::LeftShoulder => gamepad.is_pressed(Button::LeftTrigger),
+            GamepadButton::RightShoulder => gamepad.is_pressed(Button::RightTrigger),
+            GamepadButton::Select => gamepad.is_pressed(Button::Select),
+            GamepadButton::Start => gamepad.is_pressed(Button::Start),
+            GamepadButton::Mode => gamepad.is_pressed(Button::Mode),
+            GamepadButton::LeftThumb => gamepad.is_pressed(Button::LeftThumb),
+            GamepadButton::RightThumb => gamepad.is_pressed(Button::RightThumb),
+            // Some gamepads report the d-pad as axes
+            GamepadButton::DPadUp => {
+                gamepad.is_pressed(Button::DPadUp) || gamepad.value(Axis::DPadY) > 0.5
+            }
+            GamepadButton::DPadDown => {
+                gamepad.is_pressed(Button::DPadDown) || gamepad.value(Axis::DPadY) < -0.5
+            }
+            GamepadButton::DPadLeft => {
+                gamepad.is_pressed(Button::DPadLeft) || gamepad.value(Axis::DPadX) < -0.5
+            }
+            GamepadButton::DPadRight => {
+                gamepad.is_pressed(Button::DPadRight) || gamepad.value(Axis::DPadX) > 0.5
+            }
+        };
+        state.set_pressed(button, pressed);
+    }
+    state
+}
+
+fn stick(v: f32) -> i16 {
+    (v.clamp(-1., 1.) * i16::MAX as f32) as i16
+}
+
+fn trigger(gamepad: &Gamepad, button: Button) -> u8 {
+    let v = gamepad.button_data(button).map_or(0., |d| d.value());
+    (v.clamp(0., 1.) * u8::MAX as f32) as u8
+}
@@ -4,7 +4,7 @@ use crate::clipboard::{update_clipboard, ClipboardSide};
 use crate::{audio_service, clipboard::CLIPBOARD_INTERVAL, ConnInner, CLIENT_SERVER};
 use crate::{
     client::{
-        self, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
+        self, new_voice_call_request, AudioLoss, Client, Data, Interface, MediaData, MediaSender,
         QualityStatus, MILLI1, SEC30,
     },
     common::get_default_sound_input,
@@ -56,10 +56,16 @@ use std::{
 pub struct Remote<T: InvokeUiSession> {
     handler: Session<T>,
     audio_sender: MediaSender,
+    audio_loss: Arc<AudioLoss>,
     receiver: mpsc::UnboundedReceiver<Data>,
     sender: mpsc::UnboundedSender<Data>,
     // Stop sending local audio to remote client.
     stop_voice_call_sender: Option<std::sync::mpsc::Sender<()>>,
+    // The connection of the voice call to the local audio service
+    voice_call_conn_id: Option<i32>,
+    // Stop forwarding the local gamepads.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    stop_gamepad_sender: Option<std::sync::mpsc::Sender<()>>,
     voice_call_request_timestamp: Option<NonZeroI64>,
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
@@ -103,9 +109,11 @@ impl<T: InvokeUiSession> Remote<T> {
         receiver: mpsc::UnboundedReceiver<Data>,
         sender: mpsc::UnboundedSender<Data>,
     ) -> Self {
+        let audio_loss = Arc::new(AudioLoss::default());
         Self {
             handler,
-            audio_sender: crate::client::start_audio_thread(),
+            audio_sender: crate::client::start_audio_thread(Some(audio_loss.clone()), None),
+            audio_loss,
             receiver,
             sender,
             read_jobs: Vec::new(),
@@ -120,6 +128,9 @@ impl<T: InvokeUiSession> Remote<T> {
             data_count: Arc::new(AtomicUsize::new(0)),
             video_format: CodecFormat::Unknown,
             stop_voice_call_sender: None,
+            voice_call_conn_id: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            stop_gamepad_sender: None,
             voice_call_request_timestamp: None,
             elevation_requested: false,
             peer_info: Default::default(),
@@ -323,6 +334,10 @@ impl<T: InvokeUiSession> Remote<T> {
                 if let Some(s) = self.stop_voice_call_sender.take() {
                     s.send(()).ok();
                 }
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                if let Some(s) = self.stop_gamepad_sender.take() {
+                    s.send(()).ok();
+                }
                 if kcp.is_some() {
                     // Send the close reason if it hasn't been sent yet, as KCP cannot detect the socket close event.
                     self.send_close_reason(&mut peer, "kcp").await;
@@ -441,6 +456,23 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Forward the local gamepads if asked and not view only.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn check_gamepad(&mut self) {
+        let on = {
+            let lc = self.handler.lc.read().unwrap();
+            lc.get_toggle_option(crate::OPTION_FORWARD_GAMEPADS) && !lc.view_only.v
+        };
+        if on == self.stop_gamepad_sender.is_some() {
+            return;
+        }
+        if on {
+            self.stop_gamepad_sender = Some(super::gamepad::start(self.sender.clone()));
+        } else if let Some(s) = self.stop_gamepad_sender.take() {
+            s.send(()).ok();
+        }
+    }
+
     // Start a voice call recorder, records audio and send to remote
     fn start_voice_call(&mut self) -> Option<std::sync::mpsc::Sender<()>> {
         if self.handler.is_file_transfer()
@@ -465,6 +497,15 @@ impl<T: InvokeUiSession> Remote<T> {
             // Create a stand-alone inner, add subscribe to audio service
             let conn_id = CLIENT_SERVER.write().unwrap().get_new_id();
             let client_conn_inner = ConnInner::new(conn_id.clone(), Some(tx_audio_data), None);
+            self.voice_call_conn_id = Some(conn_id);
+            crate::audio_service::set_voice_processing(
+                conn_id,
+                self.handler
+                    .lc
+                    .read()
+                    .unwrap()
+                    .get_toggle_option(crate::OPTION_VOICE_PROCESSING),
+            );
             // now we subscribe
             CLIENT_SERVER.write().unwrap().subscribe(
                 audio_service::NAME,
@@ -484,6 +525,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 client_conn_inner,
                                 false,
                             );
+                            crate::audio_service::set_voice_processing(conn_id, false);
                             crate::audio_service::set_voice_call_input_device(None, true);
                             break;
                         }
@@ -548,6 +590,10 @@ impl<T: InvokeUiSession> Remote<T> {
             Data::ToggleClipboardFile => {
                 self.check_clipboard_file_context();
             }
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            Data::ToggleGamepad => {
+                self.check_gamepad();
+            }
             Data::Message(msg) => {
                 match &msg.union {
                     Some(message::Union::Misc(misc)) => match misc.union {
@@ -561,6 +607,28 @@ impl<T: InvokeUiSession> Remote<T> {
                                 *v.discard_queue.write().unwrap() = true;
                             }
                         }
+                        // Applied to the microphone of the voice call too
+                        #[cfg(not(target_os = "ios"))]
+                        Some(misc::Union::Option(ref o)) => {
+                            if let (Ok(q), Some(conn_id), true) = (
+                                o.voice_processing.enum_value(),
+                                self.voice_call_conn_id,
+                                self.stop_voice_call_sender.is_some(),
+                            ) {
+                                if q != BoolOption::NotSet {
+                                    crate::audio_service::set_voice_processing(
+                                        conn_id,
+                                        q == BoolOption::Yes,
+                                    );
+                                }
+                            }
+                        }
+                        // The frames queued refer to the lost ones, skip them until the keyframe
+                        Some(misc::Union::KeyframeRequest(display)) => {
+                            if let Some(v) = self.video_threads.get_mut(&(display as usize)) {
+                                *v.discard_queue.write().unwrap() = true;
+                            }
+                        }
                         _ => {}
                     },
                     _ => {}
@@ -1302,16 +1370,26 @@ impl<T: InvokeUiSession> Remote<T> {
                     let Some(thread) = self.video_threads.get_mut(&display) else {
                         return true;
                     };
+                    // 0 from the peers not numbering the frames, and a lower one from a restarted
+                    // video service, only a gap is a loss
+                    let lost = thread.last_seq > 0 && vf.seq > thread.last_seq + 1;
+                    thread.last_seq = vf.seq;
                     if Self::contains_key_frame(&vf) {
                         thread
                             .video_sender
                             .send(MediaData::VideoFrame(Box::new(vf)))
                             .ok();
+                    } else if lost {
+                        log::info!(
+                            "frames of display {display} lost before {}",
+                            thread.last_seq
+                        );
+                        self.handler.request_keyframe(display as _);
                     } else {
                         let video_queue = thread.video_queue.read().unwrap();
                         if video_queue.force_push(vf).is_some() {
                             drop(video_queue);
-                            self.handler.refresh_video(display as _);
+                            self.handler.request_keyframe(display as _);
                         } else {
                             thread.video_sender.send(MediaData::VideoQueue).ok();
                         }
@@ -1406,6 +1484,9 @@ impl<T: InvokeUiSession> Remote<T> {
                                 crate::plugin::EVENT_ON_CONN_CLIENT.to_owned(),
                                 self.handler.get_id(),
                             );
+
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            self.check_gamepad();
                         }
 
                         if self.handler.is_file_transfer() {
@@ -1976,13 +2057,29 @@ impl<T: InvokeUiSession> Remote<T> {
                     Some(misc::Union::FollowCurrentDisplay(d_idx)) => {
                         self.handler.set_current_display(d_idx);
                     }
+                    Some(misc::Union::SharedWindows(windows)) => {
+                        self.handler.update_shared_windows(&windows);
+                    }
+                    Some(misc::Union::AudioApps(apps)) => {
+                        self.handler.update_audio_apps(&apps);
+                    }
                     _ => {}
                 },
-                Some(message::Union::TestDelay(t)) => {
+                Some(message::Union::TestDelay(mut t)) => {
+                    if !t.from_client {
+                        t.audio_loss = self.audio_loss.take_percent();
+                    }
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
+                        if self.last_record_state {
+                            for (_, v) in self.video_threads.iter() {
+                                v.video_sender
+                                    .send(MediaData::AudioFrame(Box::new(frame.clone())))
+                                    .ok();
+                            }
+                        }
                         self.audio_sender
                             .send(MediaData::AudioFrame(Box::new(frame)))
                             .ok();
@@ -2400,6 +2497,7 @@ impl<T: InvokeUiSession> Remote<T> {
             frame_count: frame_count.clone(),
             fps_control: Default::default(),
             discard_queue: discard_queue.clone(),
+            last_seq: 0,
         };
         let handler = self.handler.ui_handler.clone();
         crate::client::start_video_thread(
@@ -2504,6 +2602,8 @@ struct VideoThread {
     frame_count: Arc<RwLock<usize>>,
     discard_queue: Arc<RwLock<bool>>,
     fps_control: FpsControl,
+    // The sequence number of the last frame received.
+    last_seq: u64,
 }
 
 impl Drop for VideoThread {
@@ -0,0 +1,357 @@
+//! Keyboard macros written in the DSL of enigo, like `{+CTRL}c{-CTRL}{DELAY 100}{ENTER}`,
+//! played into the session as key and mouse events.
+//!
+//! The macros of a peer are saved in its `PeerConfig`, the global ones in `LocalConfig`, so they
+//! are not synced with the address book. The peer is told the name of the macro played for its
+//! audit, which is advisory only: the events themselves are ordinary input, a modified client can
+//! send them without telling.
+
+use crate::input::{
+    MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT, MOUSE_BUTTON_WHEEL, MOUSE_TYPE_DOWN, MOUSE_TYPE_MOVE,
+    MOUSE_TYPE_UP,
+};
+use enigo::{
+    dsl::{self, Token},
+    Key, MouseButton,
+};
+use hbb_common::{bail, config::LocalConfig, message_proto::*, serde_json, ResultType};
+use serde_derive::{Deserialize, Serialize};
+
+/// The macros of a peer in the options of its `PeerConfig`, the ones of all peers in the options
+/// of `LocalConfig`, as JSON.
+pub const OPTION_KEYBOARD_MACROS: &str = "keyboard-macros";
+// A macro is at most this many events, once the repeats are expanded, and its nested repeats
+// run at most this many times in total.
+const MAX_STEPS: usize = 10_000;
+// The delays of a macro add up to at most this many milliseconds, once the repeats are expanded.
+const MAX_DELAY_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyMacro {
+    pub name: String,
+    /// The DSL of the macro.
+    pub text: String,
+    /// Played when pressed in the session, like `Ctrl+Alt+1`.
+    #[serde(default)]
+    pub hotkey: String,
+    /// For all peers.
+    #[serde(default)]
+    pub global: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroStep {
+    Key(KeyEvent),
+    Mouse {
+        mask: i32,
+        x: i32,
+        y: i32,
+        alt: bool,
+        ctrl: bool,
+        shift: bool,
+        command: bool,
+    },
+    /// Milliseconds to wait.
+    Delay(u64),
+}
+
+fn parse_macros(json: &str, global: bool) -> Vec<KeyMacro> {
+    serde_json::from_str::<Vec<KeyMacro>>(json)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|mut m| {
+            m.global = global;
+            m
+        })
+        .collect()
+}
+
+fn macros_to_option(macros: &[KeyMacro]) -> String {
+    if macros.is_empty() {
+        return "".to_owned();
+    }
+    serde_json::to_string(macros).unwrap_or_default()
+}
+
+/// The macros of all peers, then the ones of the peer of `peer_option`, which override the former
+/// of the same name.
+pub fn get_macros(peer_option: &str) -> Vec<KeyMacro> {
+    let peer = parse_macros(peer_option, false);
+    let mut macros: Vec<_> = parse_macros(&LocalConfig::get_option(OPTION_KEYBOARD_MACROS), true)
+        .into_iter()
+        .filter(|m| !peer.iter().any(|p| p.name == m.name))
+        .collect();
+    macros.extend(peer);
+    macros
+}
+
+/// Save the global macros and return the option of the peer with the others.
+pub fn set_macros(macros: Vec<KeyMacro>) -> ResultType<String> {
+    for m in macros.iter() {
+        if m.name.is_empty() {
+            bail!("The name of a macro is empty");
+        }
+        if let Err(err) = compile(&m.text) {
+            bail!("{}: {}", m.name, err);
+        }
+    }
+    let (global, peer): (Vec<_>, Vec<_>) = macros.into_iter().partition(|m| m.global);
+    LocalConfig::set_option(OPTION_KEYBOARD_MACROS.to_owned(), macros_to_option(&global));
+    Ok(macros_to_option(&peer))
+}
+
+fn normalize_hotkey(hotkey: &str) -> Vec<String> {
+    let mut keys: Vec<String> = hotkey
+        .split('+')
+        .map(|k| match k.trim().to_lowercase().as_str() {
+            "control" => "ctrl".to_owned(),
+            "option" => "alt".to_owned(),
+            "cmd" | "command" | "super" | "win" => "meta".to_owned(),
+            k => k.to_owned(),
+        })
+        .filter(|k| !k.is_empty())
+        .collect();
+    // The modifiers in any order, the key last
+    let key = keys.pop();
+    keys.sort();
+    keys.extend(key);
+    keys
+}
+
+/// Whether the hotkeys are the same, like `Ctrl+Alt+1` and `alt+control+1`.
+pub fn is_same_hotkey(a: &str, b: &str) -> bool {
+    let a = normalize_hotkey(a);
+    !a.is_empty() && a == normalize_hotkey(b)
+}
+
+/// Translate the DSL into the events sent to the peer.
+///
+/// The modifiers held when the macro starts, like the ones of its hotkey, are released first,
+/// and the ones pressed by the macro are released at the end.
+pub fn compile(text: &str) -> ResultType<Vec<MacroStep>> {
+    let tokens = match dsl::tokenize(text) {
+        Ok(tokens) => tokens,
+        Err(err) => bail!("{:?}", err),
+    };
+    let mut compiler = Compiler::default();
+    for key in [Key::Control, Key::Alt, Key::Shift, Key::Meta] {
+        compiler.push_control_key(key, Some(false))?;
+    }
+    compiler.compile(&tokens, 1)?;
+    for (key, held) in [
+        (Key::Control, compiler.ctrl),
+        (Key::Alt, compiler.alt),
+        (Key::Shift, compiler.shift),
+        (Key::Meta, compiler.command),
+    ] {
+        if held {
+            compiler.push_control_key(key, Some(false))?;
+        }
+    }
+    Ok(compiler.steps)
+}
+
+#[derive(Default)]
+struct Compiler {
+    steps: Vec<MacroStep>,
+    delay: u64,
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+    command: bool,
+}
+
+impl Compiler {
+    // `repeats` - The product of the counts of the repeats the tokens are in.
+    fn compile(&mut self, tokens: &[Token], repeats: usize) -> ResultType<()> {
+        for token in tokens {
+            if self.steps.len() > MAX_STEPS {
+                bail!("More than {} steps", MAX_STEPS);
+            }
+            match token {
+                Token::Sequence(text) => {
+                    for c in text.chars() {
+                        match c {
+                            '\n' => self.push_control_key(Key::Return, None)?,
+                            '\t' => self.push_control_key(Key::Tab, None)?,
+                            c => {
+                                let mut evt = KeyEvent::new();
+                                evt.set_chr(c as _);
+                                evt.press = true;
+                                self.push_key(evt);
+                            }
+                        }
+                    }
+                }
+                Token::Unicode(text) => {
+                    let mut evt = KeyEvent::new();
+                    evt.set_seq(text.clone());
+                    self.push_key(evt);
+                }
+                Token::KeyDown(key) => self.push_control_key(*key, Some(true))?,
+                Token::KeyUp(key) => self.push_control_key(*key, Some(false))?,
+                Token::KeyClick(key) => self.push_control_key(*key, None)?,
+                Token::Delay(ms) => {
+                    self.delay = self.delay.saturating_add(*ms);
+                    if self.delay > MAX_DELAY_MS {
+                        bail!("Delays longer than {}ms", MAX_DELAY_MS);
+                    }
+                    self.steps.push(MacroStep::Delay(*ms));
+                }
+                Token::Click(button) => {
+                    let button = match button {
+                        MouseButton::Left => MOUSE_BUTTON_LEFT,
+                        MouseButton::Right => MOUSE_BUTTON_RIGHT,
+                        MouseButton::Middle => MOUSE_BUTTON_WHEEL,
+                        b => bail!("Unsupported mouse button {:?}", b),
+                    };
+                    self.push_mouse(button << 3 | MOUSE_TYPE_DOWN, 0, 0);
+                    self.push_mouse(button << 3 | MOUSE_TYPE_UP, 0, 0);
+                }
+                Token::MoveTo(x, y) => self.push_mouse(MOUSE_TYPE_MOVE, *x, *y),
+                Token::Repeat(n, tokens) => {
+                    let repeats = repeats.saturating_mul(*n as usize);
+                    if repeats > MAX_STEPS {
+                        bail!("More than {} repeats", MAX_STEPS);
+                    }
+                    if !tokens.is_empty() {
+                        for _ in 0..*n {
+                            self.compile(tokens, repeats)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push_key(&mut self, mut evt: KeyEvent) {
+        crate::keyboard::client::legacy_modifiers(
+            &mut evt,
+            self.alt,
+            self.ctrl,
+            self.shift,
+            self.command,
+        );
+        evt.mode = KeyboardMode::Legacy.into();
+        self.steps.push(MacroStep::Key(evt));
+    }
+
+    // Press and release the key if `down` is `None`.
+    fn push_control_key(&mut self, key: Key, down: Option<bool>) -> ResultType<()> {
+        let Some(control_key) = control_key(key) else {
+            bail!("Unsupported key {:?}", key);
+        };
+        if let Some(down) = down {
+            match key {
+                Key::Alt => self.alt = down,
+                Key::Control => self.ctrl = down,
+                Key::Shift => self.shift = down,
+                Key::Meta => self.command = down,
+                _ => {}
+            }
+        }
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(control_key);
+        match down {
+            Some(down) => evt.down = down,
+            None => evt.press = true,
+        }
+        self.push_key(evt);
+        Ok(())
+    }
+
+    fn push_mouse(&mut self, mask: i32, x: i32, y: i32) {
+        self.steps.push(MacroStep::Mouse {
+            mask,
+            x,
+            y,
+            alt: self.alt,
+            ctrl: self.ctrl,
+            shift: self.shift,
+            command: self.command,
+        });
+    }
+}
+
+// The keys of the tags of the DSL.
+fn control_key(key: Key) -> Option<ControlKey> {
+    let key = match key {
+        Key::Alt => ControlKey::Alt,
+        Key::Control => ControlKey::Control,
+        Key::Shift => ControlKey::Shift,
+        Key::Meta => ControlKey::Meta,
+        Key::Return => ControlKey::Return,
+        Key::Tab => ControlKey::Tab,
+        Key::Escape => ControlKey::Escape,
+        Key::Backspace => ControlKey::Backspace,
+        Key::Delete => ControlKey::Delete,
+        Key::Insert => ControlKey::Insert,
+        Key::Space => ControlKey::Space,
+        Key::Home => ControlKey::Home,
+        Key::End => ControlKey::End,
+        Key::PageUp => ControlKey::PageUp,
+        Key::PageDown => ControlKey::PageDown,
+        Key::UpArrow => ControlKey::UpArrow,
+        Key::DownArrow => ControlKey::DownArrow,
+        Key::LeftArrow => ControlKey::LeftArrow,
+        Key::RightArrow => ControlKey::RightArrow,
+        Key::CapsLock => ControlKey::CapsLock,
+        Key::F1 => ControlKey::F1,
+        Key::F2 => ControlKey::F2,
+        Key::F3 => ControlKey::F3,
+        Key::F4 => ControlKey::F4,
+        Key::F5 => ControlKey::F5,
+        Key::F6 => ControlKey::F6,
+        Key::F7 => ControlKey::F7,
+        Key::F8 => ControlKey::F8,
+        Key::F9 => ControlKey::F9,
+        Key::F10 => ControlKey::F10,
+        Key::F11 => ControlKey::F11,
+        Key::F12 => ControlKey::F12,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let steps = compile("{+CTRL}c{-CTRL}{+REPEAT 2}{CLICK LEFT}{-REPEAT}").unwrap();
+        // The modifiers released first
+        assert_eq!(steps.len(), 4 + 3 + 4);
+        let MacroStep::Key(copy) = &steps[5] else {
+            panic!("not a key");
+        };
+        assert_eq!(copy.chr(), 'c' as u32);
+        assert!(copy.press);
+        assert_eq!(copy.modifiers, vec![ControlKey::Control.into()]);
+        assert!(matches!(
+            steps[7],
+            MacroStep::Mouse { mask, ctrl: false, .. } if mask == MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_DOWN
+        ));
+        // The modifiers left pressed are released
+        let steps = compile("{+SHIFT}").unwrap();
+        let MacroStep::Key(release) = steps.last().unwrap() else {
+            panic!("not a key");
+        };
+        assert!(crate::is_control_key(release, &ControlKey::Shift) && !release.down);
+        assert!(compile("{+REPEAT 100000}a{-REPEAT}").is_err());
+        // Nested repeats of nothing are bounded too
+        assert!(compile("{+REPEAT 1000}{+REPEAT 1000}{-REPEAT}{-REPEAT}").is_err());
+        let steps = compile("{+REPEAT 10}{+REPEAT 10}a{-REPEAT}{-REPEAT}").unwrap();
+        assert_eq!(steps.len(), 4 + 100);
+        assert!(compile("{+REPEAT 10}{DELAY 10000}{-REPEAT}").is_err());
+        assert!(compile("{FOO}").is_err());
+    }
+
+    #[test]
+    fn test_hotkey() {
+        assert!(is_same_hotkey("Ctrl+Alt+1", "alt+control+1"));
+        assert!(!is_same_hotkey("Ctrl+Alt+1", "Ctrl+1"));
+        assert!(!is_same_hotkey("", ""));
+    }
+}
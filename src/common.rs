@@ -2,7 +2,10 @@ use std::{
     collections::HashMap,
     future::Future,
     net::{SocketAddr, ToSocketAddrs},
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
     task::Poll,
 };
 
@@ -559,6 +562,192 @@ audio_rechannel!(audio_rechannel_8_5, 8, 5);
 audio_rechannel!(audio_rechannel_8_6, 8, 6);
 audio_rechannel!(audio_rechannel_8_7, 8, 7);
 
+// The voice processing runs on frames of 10ms at 48kHz, the frame size of the noise suppression.
+const VOICE_SAMPLE_RATE: u32 = 48000;
+const VOICE_FRAME_SIZE: usize = nnnoiseless::DenoiseState::FRAME_SIZE;
+// The audio played and not yet used as the reference of the echo canceller, 200ms at most after
+// the delay of the playback.
+const ECHO_REFERENCE_MAX: usize = VOICE_FRAME_SIZE * 20;
+// The delay of the playback compensated, 1s at most.
+const ECHO_DELAY_MAX: usize = VOICE_SAMPLE_RATE as usize;
+// The echo canceller adapts at 16kHz, on the average of every 3 samples.
+const AEC_DECIMATION: usize = 3;
+// 128ms at 16kHz, the echo path covered once the delay of the playback is compensated.
+const AEC_TAPS: usize = 2048;
+const AEC_STEP: f32 = 0.3;
+const AEC_REGULARIZATION: f32 = 1e-3;
+// Geigel detector, the near end is talking if it is louder than half of the far end.
+const AEC_DOUBLE_TALK: f32 = 0.5;
+const AEC_PEAK_DECAY: f32 = 0.999;
+
+lazy_static::lazy_static! {
+    // The audio played, mono at VOICE_SAMPLE_RATE
+    static ref ECHO_REFERENCE: Mutex<std::collections::VecDeque<f32>> = Default::default();
+}
+static ECHO_REFERENCE_ON: AtomicBool = AtomicBool::new(false);
+
+// Mix the interleaved samples down to mono at VOICE_SAMPLE_RATE.
+fn voice_mono(data: &[f32], sample_rate: u32, channels: usize) -> Vec<f32> {
+    let mono: Vec<f32> = data
+        .chunks_exact(channels)
+        .map(|x| x.iter().sum::<f32>() / channels as f32)
+        .collect();
+    if sample_rate == VOICE_SAMPLE_RATE {
+        return mono;
+    }
+    audio_resample(&mono, sample_rate, VOICE_SAMPLE_RATE, 1)
+}
+
+/// Record the audio played from now on, while a voice call is processed by [`VoiceProcessor`].
+/// The audio recorded is dropped when turned off, it's not the echo of the next call.
+pub fn set_echo_reference(on: bool) {
+    if ECHO_REFERENCE_ON.swap(on, Ordering::Relaxed) != on {
+        log::info!("echo reference: {on}");
+        if !on {
+            ECHO_REFERENCE.lock().unwrap().clear();
+        }
+    }
+}
+
+/// Record the audio being played, its echo in the microphone is removed by [`VoiceProcessor`].
+///
+/// * `delay` - How long before `data` comes out of the speakers.
+pub fn push_echo_reference(data: &[f32], sample_rate: u32, channels: u16, delay: Duration) {
+    if sample_rate == 0 || channels == 0 || !ECHO_REFERENCE_ON.load(Ordering::Relaxed) {
+        return;
+    }
+    let data = voice_mono(data, sample_rate, channels as _);
+    let delay =
+        ((delay.as_secs_f64() * VOICE_SAMPLE_RATE as f64).round() as usize).min(ECHO_DELAY_MAX);
+    let mut lock = ECHO_REFERENCE.lock().unwrap();
+    // The microphone reads the samples queued before these ones, they make the delay.
+    let n = lock.len();
+    if n < delay {
+        lock.extend(std::iter::repeat(0.).take(delay - n));
+    } else if n > delay + ECHO_REFERENCE_MAX {
+        lock.drain(..n - delay - ECHO_REFERENCE_MAX);
+    }
+    lock.extend(data);
+}
+
+/// Noise suppression and acoustic echo cancellation of the microphone in voice calls.
+///
+/// The audio is processed as mono at 48kHz, resampled from and back to the sample rate of the
+/// microphone, and delayed by one frame.
+pub struct VoiceProcessor {
+    sample_rate: u32,
+    channels: usize,
+    denoise: Box<nnnoiseless::DenoiseState<'static>>,
+    echo: EchoCanceller,
+    input: std::collections::VecDeque<f32>,
+    output: std::collections::VecDeque<f32>,
+}
+
+impl VoiceProcessor {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let delay = VOICE_FRAME_SIZE * sample_rate as usize / VOICE_SAMPLE_RATE as usize;
+        Self {
+            sample_rate,
+            channels: channels.max(1) as _,
+            denoise: nnnoiseless::DenoiseState::new(),
+            echo: EchoCanceller::new(),
+            input: Default::default(),
+            output: std::iter::repeat(0.).take(delay).collect(),
+        }
+    }
+
+    /// Process the interleaved samples in place.
+    pub fn process(&mut self, data: &mut [f32]) {
+        if self.sample_rate == 0 {
+            return;
+        }
+        let channels = self.channels;
+        self.input
+            .extend(voice_mono(data, self.sample_rate, channels));
+        let mut reference = [0.; VOICE_FRAME_SIZE];
+        let mut denoised = [0.; VOICE_FRAME_SIZE];
+        let mut processed = Vec::with_capacity(self.input.len());
+        while self.input.len() >= VOICE_FRAME_SIZE {
+            let mut frame: Vec<f32> = self.input.drain(..VOICE_FRAME_SIZE).collect();
+            reference.fill(0.);
+            let mut lock = ECHO_REFERENCE.lock().unwrap();
+            let n = lock.len().min(VOICE_FRAME_SIZE);
+            reference
+                .iter_mut()
+                .zip(lock.drain(..n))
+                .for_each(|(r, x)| *r = x);
+            drop(lock);
+            self.echo.process(&mut frame, &reference);
+            // nnnoiseless works on the range of i16
+            frame.iter_mut().for_each(|x| *x *= i16::MAX as f32);
+            self.denoise.process_frame(&mut denoised, &frame);
+            processed.extend(denoised.iter().map(|x| x / i16::MAX as f32));
+        }
+        if self.sample_rate != VOICE_SAMPLE_RATE && !processed.is_empty() {
+            processed = audio_resample(&processed, VOICE_SAMPLE_RATE, self.sample_rate, 1);
+        }
+        self.output.extend(processed);
+        for x in data.chunks_exact_mut(channels) {
+            x.fill(self.output.pop_front().unwrap_or_default());
+        }
+    }
+}
+
+// NLMS adaptive filter estimating the echo of the far end in the near end.
+struct EchoCanceller {
+    // The far end, newest first, mirrored to read the window without wrapping
+    far: Vec<f32>,
+    pos: usize,
+    energy: f32,
+    peak: f32,
+    weights: Vec<f32>,
+    last_echo: f32,
+}
+
+impl EchoCanceller {
+    fn new() -> Self {
+        Self {
+            far: vec![0.; AEC_TAPS * 2],
+            pos: 0,
+            energy: 0.,
+            peak: 0.,
+            weights: vec![0.; AEC_TAPS],
+            last_echo: 0.,
+        }
+    }
+
+    fn process(&mut self, near: &mut [f32], far: &[f32]) {
+        for (near, far) in near
+            .chunks_exact_mut(AEC_DECIMATION)
+            .zip(far.chunks_exact(AEC_DECIMATION))
+        {
+            let x = far.iter().sum::<f32>() / AEC_DECIMATION as f32;
+            let d = near.iter().sum::<f32>() / AEC_DECIMATION as f32;
+            self.pos = (self.pos + AEC_TAPS - 1) % AEC_TAPS;
+            let oldest = self.far[self.pos];
+            self.energy = (self.energy + x * x - oldest * oldest).max(0.);
+            self.far[self.pos] = x;
+            self.far[self.pos + AEC_TAPS] = x;
+            self.peak = (self.peak * AEC_PEAK_DECAY).max(x.abs());
+            let window = &self.far[self.pos..self.pos + AEC_TAPS];
+            let echo: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+            if self.energy > 0. && d.abs() < AEC_DOUBLE_TALK * self.peak {
+                let step = AEC_STEP * (d - echo) / (self.energy + AEC_REGULARIZATION);
+                self.weights
+                    .iter_mut()
+                    .zip(window)
+                    .for_each(|(w, x)| *w += step * x);
+            }
+            // Back to the sample rate of the near end
+            for (i, s) in near.iter_mut().enumerate() {
+                let t = (i + 1) as f32 / AEC_DECIMATION as f32;
+                *s -= self.last_echo + (echo - self.last_echo) * t;
+            }
+            self.last_echo = echo;
+        }
+    }
+}
+
 pub struct CheckTestNatType {
     is_direct: bool,
 }
@@ -582,7 +771,6 @@ impl Drop for CheckTestNatType {
 
 pub fn test_nat_type() {
     test_ipv6_sync();
-    use std::sync::atomic::{AtomicBool, Ordering};
     std::thread::spawn(move || {
         static IS_RUNNING: AtomicBool = AtomicBool::new(false);
         if IS_RUNNING.load(Ordering::SeqCst) {
@@ -2010,6 +2198,354 @@ pub fn get_rs_pk(str_base64: &str) -> Option<sign::PublicKey> {
     }
 }
 
+pub const OPTION_RECORD_PUBLIC_KEY: &str = "record-public-key";
+
+/// The public key recordings are encrypted to, `None` if recordings are kept in plain.
+///
+/// An invalid key is an error rather than `None`, so that a typo does not silently
+/// fall back to plain recordings.
+pub fn get_record_public_key() -> ResultType<Option<box_::PublicKey>> {
+    let key = Config::get_option(OPTION_RECORD_PUBLIC_KEY);
+    if key.trim().is_empty() {
+        return Ok(None);
+    }
+    match decode64(key.trim()).ok().and_then(|x| get_pk(&x)) {
+        Some(pk) => Ok(Some(box_::PublicKey(pk))),
+        None => bail!("Invalid {}", OPTION_RECORD_PUBLIC_KEY),
+    }
+}
+
+pub const OPTION_RECORD_TIMELINE: &str = "record-timeline";
+pub const OPTION_RECORD_TIMELINE_REDACT_TEXT: &str = "record-timeline-redact-text";
+
+/// The timeline written next to incoming recordings, `None` if disabled.
+pub fn get_record_timeline_config() -> Option<scrap::record::TimelineConfig> {
+    if !config::option2bool(
+        OPTION_RECORD_TIMELINE,
+        &Config::get_option(OPTION_RECORD_TIMELINE),
+    ) {
+        return None;
+    }
+    Some(scrap::record::TimelineConfig {
+        redact_text: config::option2bool(
+            OPTION_RECORD_TIMELINE_REDACT_TEXT,
+            &Config::get_option(OPTION_RECORD_TIMELINE_REDACT_TEXT),
+        ),
+    })
+}
+
+pub const OPTION_RECORD_SEGMENT_SECS: &str = "record-segment-secs";
+pub const OPTION_RECORD_SEGMENT_SIZE: &str = "record-segment-size";
+pub const OPTION_RECORD_RETENTION_DAYS: &str = "record-retention-days";
+pub const OPTION_RECORD_RETENTION_SIZE: &str = "record-retention-size";
+
+// A positive number option, empty or invalid values mean no limit.
+fn get_positive_option(key: &str) -> Option<u64> {
+    Config::get_option(key)
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|v| *v > 0)
+}
+
+/// Split recordings by duration in seconds and size in MB.
+pub fn get_record_segment_config() -> scrap::record::SegmentConfig {
+    scrap::record::SegmentConfig {
+        max_duration: get_positive_option(OPTION_RECORD_SEGMENT_SECS)
+            .map(std::time::Duration::from_secs),
+        max_size: get_positive_option(OPTION_RECORD_SEGMENT_SIZE).map(|mb| mb * 1024 * 1024),
+    }
+}
+
+/// Prune the recording directory by age in days and total size in MB.
+pub fn get_record_retention_policy() -> scrap::record::RetentionPolicy {
+    scrap::record::RetentionPolicy {
+        max_age: get_positive_option(OPTION_RECORD_RETENTION_DAYS)
+            .map(|days| std::time::Duration::from_secs(days * 24 * 3600)),
+        max_total_size: get_positive_option(OPTION_RECORD_RETENTION_SIZE)
+            .map(|mb| mb * 1024 * 1024),
+    }
+}
+
+pub const OPTION_BANDWIDTH_LIMIT: &str = "bandwidth-limit";
+
+/// The bandwidth limit in kbps of all the connections together, set on the controlled side.
+pub fn get_bandwidth_limit_option() -> Option<u32> {
+    get_positive_option(OPTION_BANDWIDTH_LIMIT).map(|v| v.min(u32::MAX as u64) as u32)
+}
+
+pub const OPTION_AUDIO_PRESET: &str = "audio-preset";
+
+/// The tuning of the Opus encoder asked by the controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, PartialOrd, Ord)]
+pub enum AudioPreset {
+    #[default]
+    Auto,
+    Voice,
+    Music,
+    LowBandwidth,
+}
+
+impl AudioPreset {
+    pub fn from_option(v: &str) -> Self {
+        match v {
+            "voice" => Self::Voice,
+            "music" => Self::Music,
+            "low-bandwidth" => Self::LowBandwidth,
+            _ => Self::Auto,
+        }
+    }
+
+    /// The preset in `OptionMessage` and `AudioFormat`, the unknown ones are auto.
+    pub fn from_u32(v: u32) -> Self {
+        match v {
+            1 => Self::Voice,
+            2 => Self::Music,
+            3 => Self::LowBandwidth,
+            _ => Self::Auto,
+        }
+    }
+
+    pub fn to_option(&self) -> &'static str {
+        match self {
+            Self::Auto => "",
+            Self::Voice => "voice",
+            Self::Music => "music",
+            Self::LowBandwidth => "low-bandwidth",
+        }
+    }
+}
+
+/// The peer option of the controller to redirect its microphone in voice calls.
+pub const OPTION_MIC_REDIRECT: &str = "mic-redirect";
+/// The permission of the controlled side to feed the microphone of the controller into a virtual
+/// input device, off by default as its `allow-` prefix.
+pub const OPTION_ALLOW_MIC_REDIRECT: &str = "allow-mic-redirect";
+
+/// The peer option of noise suppression and echo cancellation of the microphone in voice calls,
+/// applied by both sides.
+pub const OPTION_VOICE_PROCESSING: &str = "voice-processing";
+
+/// The peer option of the controller to forward its gamepads.
+pub const OPTION_FORWARD_GAMEPADS: &str = "forward-gamepads";
+/// The permission of the controlled side to expose the gamepads of the controller as virtual ones,
+/// off by default as its `allow-` prefix.
+pub const OPTION_ALLOW_GAMEPAD: &str = "allow-gamepad";
+/// The gamepads of a controller exposed at most.
+pub const GAMEPAD_MAX: u8 = 4;
+
+/// The buttons of `GamepadState`, bit `1 << button` of its `buttons`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: [Self; 15] = [
+        Self::South,
+        Self::East,
+        Self::West,
+        Self::North,
+        Self::LeftShoulder,
+        Self::RightShoulder,
+        Self::Select,
+        Self::Start,
+        Self::Mode,
+        Self::LeftThumb,
+        Self::RightThumb,
+        Self::DPadUp,
+        Self::DPadDown,
+        Self::DPadLeft,
+        Self::DPadRight,
+    ];
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub struct GamepadState {
+    /// The gamepad of the controller, below `GAMEPAD_MAX`.
+    pub index: u8,
+    /// The virtual gamepad is removed once unplugged.
+    pub connected: bool,
+    pub buttons: u32,
+    /// The x and y of the left stick, then of the right one, right and down positive.
+    pub axes: [i16; 4],
+    /// The left and right triggers, released at 0.
+    pub triggers: [u8; 2],
+}
+
+impl GamepadState {
+    pub fn is_pressed(&self, button: GamepadButton) -> bool {
+        self.buttons & (1 << button as u32) != 0
+    }
+
+    pub fn set_pressed(&mut self, button: GamepadButton, pressed: bool) {
+        if pressed {
+            self.buttons |= 1 << button as u32;
+        } else {
+            self.buttons &= !(1 << button as u32);
+        }
+    }
+
+    /// The state sent in `Misc`.
+    pub fn to_message(&self) -> hbb_common::message_proto::GamepadState {
+        hbb_common::message_proto::GamepadState {
+            index: self.index as _,
+            connected: self.connected,
+            buttons: self.buttons,
+            axes: self.axes.iter().map(|x| *x as _).collect(),
+            triggers: self.triggers.iter().map(|x| *x as _).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The state received, `None` for a gamepad beyond `GAMEPAD_MAX`. The axes and triggers
+    /// missing are released.
+    pub fn from_message(m: &hbb_common::message_proto::GamepadState) -> Option<Self> {
+        if m.index >= GAMEPAD_MAX as u32 {
+            return None;
+        }
+        let axis = |i: usize| {
+            m.axes
+                .get(i)
+                .map_or(0, |x| (*x).clamp(i16::MIN as _, i16::MAX as _) as i16)
+        };
+        let trigger = |i: usize| {
+            m.triggers
+                .get(i)
+                .map_or(0, |x| (*x).min(u8::MAX as _) as u8)
+        };
+        Some(Self {
+            index: m.index as _,
+            connected: m.connected,
+            buttons: m.buttons,
+            axes: [axis(0), axis(1), axis(2), axis(3)],
+            triggers: [trigger(0), trigger(1)],
+        })
+    }
+}
+
+/// The barrel button of the pen closest to its tip.
+pub const PEN_BUTTON_PRIMARY: u8 = 1;
+pub const PEN_BUTTON_SECONDARY: u8 = 2;
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub struct PenEvent {
+    /// The position on the displays, like the one of `MouseEvent`.
+    pub x: i32,
+    pub y: i32,
+    /// The pen leaves the tablet once out of range.
+    pub in_range: bool,
+    /// The tip touches the tablet.
+    pub down: bool,
+    /// The pen is upside down.
+    pub eraser: bool,
+    /// `PEN_BUTTON_PRIMARY` and `PEN_BUTTON_SECONDARY`.
+    pub buttons: u8,
+    /// The pressure of the tip, full at `u16::MAX`.
+    pub pressure: u16,
+    /// The tilt toward the right and the bottom, in degrees from the perpendicular.
+    pub tilt_x: i8,
+    pub tilt_y: i8,
+}
+
+impl PenEvent {
+    /// Set the tilt from the angle of the pen from the perpendicular and the direction it points
+    /// to, clockwise from the top, both in radians, like the ones of Flutter.
+    pub fn set_tilt(&mut self, tilt: f64, orientation: f64) {
+        // Not flat, where the tilt along the direction pointed to is undefined
+        let tilt = tilt.clamp(0., 89_f64.to_radians());
+        let degrees = |v: f64| v.to_degrees().round().clamp(-90., 90.) as i8;
+        self.tilt_x = degrees((tilt.sin() * orientation.sin()).atan2(tilt.cos()));
+        self.tilt_y = degrees((-tilt.sin() * orientation.cos()).atan2(tilt.cos()));
+    }
+
+    /// The pen sent in `PointerDeviceEvent`.
+    pub fn to_message(&self) -> hbb_common::message_proto::PenEvent {
+        hbb_common::message_proto::PenEvent {
+            x: self.x,
+            y: self.y,
+            in_range: self.in_range,
+            down: self.down,
+            eraser: self.eraser,
+            buttons: self.buttons as _,
+            pressure: self.pressure as _,
+            tilt_x: self.tilt_x as _,
+            tilt_y: self.tilt_y as _,
+            ..Default::default()
+        }
+    }
+
+    /// The pen received, the values out of range clamped.
+    pub fn from_message(m: &hbb_common::message_proto::PenEvent) -> Self {
+        Self {
+            x: m.x,
+            y: m.y,
+            in_range: m.in_range,
+            down: m.down,
+            eraser: m.eraser,
+            buttons: (m.buttons & (PEN_BUTTON_PRIMARY | PEN_BUTTON_SECONDARY) as u32) as _,
+            pressure: m.pressure.min(u16::MAX as _) as _,
+            tilt_x: m.tilt_x.clamp(-90, 90) as _,
+            tilt_y: m.tilt_y.clamp(-90, 90) as _,
+        }
+    }
+}
+
+/// Generate a key pair for recording encryption, returned as base64 `(public, secret)`.
+pub fn gen_record_key_pair() -> (String, String) {
+    let (pk, sk) = box_::gen_keypair();
+    (encode64(pk.0), encode64(sk.0))
+}
+
+/// Decrypt an encrypted recording with the base64 secret key printed by `--gen-record-key`,
+/// read from `key_file`, or from stdin if it's `-`, to keep it out of the command line.
+///
+/// Returns false if the recording was cut off, see `scrap::record::decrypt_file`.
+pub fn decrypt_record(input: &str, key_file: &str, output: &str) -> ResultType<bool> {
+    if input == output {
+        bail!("Output must differ from the input");
+    }
+    let secret_key = if key_file == "-" {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line
+    } else {
+        std::fs::read_to_string(key_file)
+            .with_context(|| format!("Failed to read the secret key from {key_file}"))?
+    };
+    let Some(sk) = decode64(secret_key.trim())
+        .ok()
+        .and_then(|x| get_pk(&x))
+        .map(box_::SecretKey)
+    else {
+        bail!("Invalid secret key");
+    };
+    let complete = scrap::record::decrypt_file(input, output, &sk.public_key(), &sk)?;
+    // encrypted recordings are left unfinished by a crash, and WebM and Matroska ones get no cues
+    if scrap::record::is_plain_matroska(output) {
+        scrap::record::repair(output)?;
+    }
+    Ok(complete)
+}
+
 pub fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String, [u8; 32])> {
     let res = IdPk::parse_from_bytes(
         &sign::verify(signed, key).map_err(|_| anyhow!("Signature mismatch"))?,
@@ -3004,4 +3540,128 @@ mod tests {
         assert_eq!(combined_mask & MOUSE_TYPE_MASK, MOUSE_TYPE_DOWN);
         assert_eq!(combined_mask >> 3, MOUSE_BUTTON_LEFT | MOUSE_BUTTON_RIGHT);
     }
+
+    #[test]
+    fn test_echo_canceller() {
+        let mut aec = EchoCanceller::new();
+        let delay = 240; // 5ms
+        let mut far_all: Vec<f32> = vec![];
+        let mut seed = 1u32;
+        let (mut echo, mut residual) = (0., 0.);
+        for n in 0..200 {
+            let far: Vec<f32> = (0..VOICE_FRAME_SIZE)
+                .map(|_| {
+                    let t = far_all.len() as f32 / VOICE_SAMPLE_RATE as f32;
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    let noise = (seed >> 16) as f32 / 32768. - 1.;
+                    let x = 0.3 * (std::f32::consts::TAU * 300. * t).sin()
+                        + 0.2 * (std::f32::consts::TAU * 700. * t).sin()
+                        + 0.05 * noise;
+                    far_all.push(x);
+                    x
+                })
+                .collect();
+            let start = far_all.len() - VOICE_FRAME_SIZE;
+            let mut near: Vec<f32> = (start..far_all.len())
+                .map(|i| {
+                    if i >= delay {
+                        0.3 * far_all[i - delay]
+                    } else {
+                        0.
+                    }
+                })
+                .collect();
+            let energy: f32 = near.iter().map(|x| x * x).sum();
+            aec.process(&mut near, &far);
+            if n >= 150 {
+                echo += energy;
+                residual += near.iter().map(|x| x * x).sum::<f32>();
+            }
+        }
+        // At least 10dB of the echo removed once converged
+        assert!(residual < echo * 0.1, "{residual} / {echo}");
+    }
+
+    #[test]
+    fn test_echo_reference_delay() {
+        push_echo_reference(&[0.5; 960], 48000, 2, Duration::ZERO);
+        assert!(ECHO_REFERENCE.lock().unwrap().is_empty(), "no voice call");
+        set_echo_reference(true);
+        // 10ms of stereo at 44.1kHz, played in 20ms
+        push_echo_reference(&[0.5; 882], 44100, 2, Duration::from_millis(20));
+        let lock = ECHO_REFERENCE.lock().unwrap();
+        assert_eq!(lock.len(), VOICE_FRAME_SIZE * 3);
+        assert!(lock.iter().take(VOICE_FRAME_SIZE * 2).all(|x| *x == 0.));
+        drop(lock);
+        // Queued for longer than the delay already
+        push_echo_reference(&[0.5; 960], 48000, 2, Duration::ZERO);
+        assert_eq!(ECHO_REFERENCE.lock().unwrap().len(), VOICE_FRAME_SIZE * 4);
+        set_echo_reference(false);
+        assert!(ECHO_REFERENCE.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_gamepad_state() {
+        let mut state = GamepadState {
+            index: 1,
+            connected: true,
+            axes: [i16::MIN, -1, 0, i16::MAX],
+            triggers: [0, 255],
+            ..Default::default()
+        };
+        state.set_pressed(GamepadButton::South, true);
+        state.set_pressed(GamepadButton::DPadRight, true);
+        state.set_pressed(GamepadButton::South, false);
+        assert!(state.is_pressed(GamepadButton::DPadRight));
+        assert!(!state.is_pressed(GamepadButton::South));
+        let mut misc = Misc::new();
+        misc.set_gamepad_state(state.to_message());
+        let bytes = misc.write_to_bytes().unwrap();
+        let misc = Misc::parse_from_bytes(&bytes).unwrap();
+        let Some(misc::Union::GamepadState(m)) = misc.union else {
+            panic!("no gamepad state");
+        };
+        assert_eq!(GamepadState::from_message(&m), Some(state));
+        let mut m = state.to_message();
+        m.index = GAMEPAD_MAX as _;
+        assert_eq!(GamepadState::from_message(&m), None);
+        // Released if missing
+        m.index = 0;
+        m.axes.truncate(1);
+        m.triggers.clear();
+        let state = GamepadState::from_message(&m).unwrap();
+        assert_eq!((state.axes, state.triggers), ([i16::MIN, 0, 0, 0], [0, 0]));
+    }
+
+    #[test]
+    fn test_pen_event() {
+        let mut pen = PenEvent {
+            x: -1920,
+            y: 1080,
+            in_range: true,
+            down: true,
+            buttons: PEN_BUTTON_SECONDARY,
+            pressure: u16::MAX,
+            ..Default::default()
+        };
+        // Pointing to the top right
+        pen.set_tilt(std::f64::consts::FRAC_PI_4, std::f64::consts::FRAC_PI_4);
+        assert!(pen.tilt_x > 0 && pen.tilt_y < 0 && pen.tilt_x == -pen.tilt_y);
+        pen.set_tilt(std::f64::consts::FRAC_PI_3, -std::f64::consts::FRAC_PI_2);
+        assert_eq!((pen.tilt_x, pen.tilt_y), (-60, 0));
+        let mut evt = PointerDeviceEvent::new();
+        evt.set_pen_event(pen.to_message());
+        let bytes = evt.write_to_bytes().unwrap();
+        let evt = PointerDeviceEvent::parse_from_bytes(&bytes).unwrap();
+        let Some(pointer_device_event::Union::PenEvent(m)) = evt.union else {
+            panic!("no pen");
+        };
+        assert_eq!(PenEvent::from_message(&m), pen);
+        let mut m = pen.to_message();
+        (m.pressure, m.tilt_x, m.buttons) = (u32::MAX, -180, 0xff);
+        let pen = PenEvent::from_message(&m);
+        assert_eq!(pen.pressure, u16::MAX);
+        assert_eq!(pen.tilt_x, -90);
+        assert_eq!(pen.buttons, PEN_BUTTON_PRIMARY | PEN_BUTTON_SECONDARY);
+    }
 }
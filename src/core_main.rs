@@ -689,6 +689,96 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--gen-record-key" {
+            let (pk, sk) = crate::gen_record_key_pair();
+            println!("public key: {pk}");
+            println!("secret key: {sk}");
+            return None;
+        } else if args[0] == "--decrypt-record" {
+            if args.len() == 3 || args.len() == 4 {
+                let output = args.get(3).cloned().unwrap_or(
+                    args[1]
+                        .trim_end_matches(scrap::record::ENCRYPTED_EXT)
+                        .to_owned(),
+                );
+                match crate::decrypt_record(&args[1], &args[2], &output) {
+                    Ok(true) => println!("Decrypted to {output}"),
+                    Ok(false) => println!(
+                        "Decrypted to {output}, the recording was cut off by a crash or truncated"
+                    ),
+                    Err(err) => println!("{err}"),
+                }
+            } else {
+                println!("Usage: --decrypt-record <file> <secret key file or -> [output]");
+            }
+            return None;
+        } else if args[0] == "--recording-info" {
+            if args.len() == 2 {
+                match scrap::record::recording_info(&args[1]) {
+                    Ok(info) => {
+                        println!("Codec: {}", info.codec);
+                        println!("Duration: {:.3}s", info.duration as f64 / 1000.0);
+                        for (pts, w, h) in info.resolutions.iter() {
+                            println!("Resolution: {w}x{h} from {pts}ms");
+                        }
+                        match &info.audio_file {
+                            Some(file) => println!("Audio: {} in {file}", info.audio),
+                            None => println!("Audio: {}", info.audio),
+                        }
+                        if let Some(cues) = info.cues {
+                            println!("Cues: {cues}");
+                        }
+                        println!(
+                            "Finalized: {}{}",
+                            info.finalized,
+                            if info.recovered {
+                                " (recovered after a crash)"
+                            } else {
+                                ""
+                            }
+                        );
+                        println!(
+                            "Keyframes ({}): {}",
+                            info.keyframes.len(),
+                            info.keyframes
+                                .iter()
+                                .map(|pts| format!("{pts}ms"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    }
+                    Err(err) => println!("{err}"),
+                }
+            } else {
+                println!("Usage: --recording-info <file>");
+            }
+            return None;
+        } else if args[0] == "--session-stats" {
+            match crate::ipc::get_session_stats() {
+                Ok(stats) => {
+                    for s in stats.iter() {
+                        println!("{}", serde_json::to_string(s).unwrap_or_default());
+                    }
+                }
+                Err(err) => println!("{err}"),
+            }
+            return None;
+        } else if args[0] == "--list-windows" {
+            // The windows to share by the option `share-window`, or to pick by a controller.
+            #[cfg(target_os = "linux")]
+            match scrap::x11::Server::default() {
+                Ok(server) => {
+                    for w in server.windows() {
+                        let r = w.rect;
+                        println!(
+                            "0x{:08x}\t{}x{}+{}+{}\t{}",
+                            w.id, r.w, r.h, r.x, r.y, w.title
+                        );
+                    }
+                }
+                Err(err) => println!("Failed to connect to X server: {err:?}"),
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             crate::ipc::hwcodec_process();
@@ -727,6 +727,10 @@ impl InvokeUiSession for FlutterHandler {
                     &status.codec_format.map_or(NULL, |it| it.to_string()),
                 ),
                 ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                (
+                    "bandwidth_limit",
+                    &status.bandwidth_limit.map_or(NULL, |it| it.to_string()),
+                ),
             ],
             &[],
         );
@@ -1101,6 +1105,33 @@ impl InvokeUiSession for FlutterHandler {
         }
     }
 
+    fn update_shared_windows(&self, windows: &SharedWindows) {
+        let list: Vec<_> = windows
+            .windows
+            .iter()
+            .map(|w| json!({ "id": w.id, "title": w.title }))
+            .collect();
+        self.push_event(
+            "shared_windows",
+            &[
+                ("windows", json!(list)),
+                ("selected", json!(windows.selected.unwrap_or_default())),
+            ],
+            &[],
+        );
+    }
+
+    fn update_audio_apps(&self, apps: &AudioApps) {
+        self.push_event(
+            "audio_apps",
+            &[
+                ("apps", json!(apps.apps)),
+                ("selected", json!(apps.selected)),
+            ],
+            &[],
+        );
+    }
+
     fn update_record_status(&self, start: bool) {
         self.push_event("record_status", &[("start", &start.to_string())], &[]);
     }
@@ -1929,6 +1960,42 @@ fn session_send_touch_event(
     }
 }
 
+// The pen leaves the tablet on "leave", the pressure is from 0 to 1 and the angles in radians.
+fn session_send_pen(
+    session_id: SessionID,
+    v: &serde_json::Value,
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+    command: bool,
+) {
+    let (Some(t), Some(v)) = (v.get("t").and_then(|t| t.as_str()), v.get("v")) else {
+        return;
+    };
+    let (Some(x), Some(y)) = (
+        v.get("x").and_then(|x| x.as_i64()),
+        v.get("y").and_then(|y| y.as_i64()),
+    ) else {
+        return;
+    };
+    let flag = |k: &str| v.get(k).and_then(|f| f.as_bool()).unwrap_or_default();
+    let number = |k: &str| v.get(k).and_then(|n| n.as_f64()).unwrap_or_default();
+    let mut pen = crate::PenEvent {
+        x: x as _,
+        y: y as _,
+        in_range: t != "leave",
+        down: flag("down"),
+        eraser: flag("eraser"),
+        buttons: number("buttons") as _,
+        pressure: (number("pressure").clamp(0., 1.) * u16::MAX as f64) as _,
+        ..Default::default()
+    };
+    pen.set_tilt(number("tilt"), number("orientation"));
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_pen(pen, alt, ctrl, shift, command);
+    }
+}
+
 pub fn session_send_pointer(session_id: SessionID, msg: String) {
     if let Ok(m) = serde_json::from_str::<HashMap<String, serde_json::Value>>(&msg) {
         let alt = m.get("alt").is_some();
@@ -1938,6 +2005,7 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
         match (m.get("k"), m.get("v")) {
             (Some(k), Some(v)) => match k.as_str() {
                 Some("touch") => session_send_touch_event(session_id, v, alt, ctrl, shift, command),
+                Some("pen") => session_send_pen(session_id, v, alt, ctrl, shift, command),
                 _ => {}
             },
             _ => {}
@@ -537,6 +537,87 @@ pub fn session_set_custom_fps(session_id: SessionID, fps: i32) {
     }
 }
 
+pub fn session_set_bandwidth_limit(session_id: SessionID, kbps: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_bandwidth_limit(kbps.max(0) as _);
+    }
+}
+
+pub fn session_query_shared_windows(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.share_window(None);
+    }
+}
+
+pub fn session_share_window(session_id: SessionID, id: u32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.share_window(Some(id));
+    }
+}
+
+pub fn session_set_audio_preset(session_id: SessionID, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_audio_preset(value);
+    }
+}
+
+pub fn session_query_audio_apps(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_audio_apps(None);
+    }
+}
+
+pub fn session_set_audio_apps(session_id: SessionID, selected: Vec<String>) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_audio_apps(Some(selected));
+    }
+}
+
+/// The keyboard macros of the peer and of all peers, as JSON.
+pub fn session_get_macros(_session_id: SessionID) -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return SyncReturn(serde_json::to_string(&session.get_macros()).unwrap_or_default());
+    }
+    SyncReturn("[]".to_owned())
+}
+
+/// Save the keyboard macros, returns the error if any.
+pub fn session_set_macros(_session_id: SessionID, _macros: String) -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        let res = serde_json::from_str(&_macros)
+            .map_err(hbb_common::anyhow::Error::from)
+            .and_then(|macros| session.set_macros(macros));
+        if let Err(err) = res {
+            return SyncReturn(err.to_string());
+        }
+    }
+    SyncReturn("".to_owned())
+}
+
+pub fn session_play_macro(_session_id: SessionID, _name: String) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.play_macro(&_name);
+    }
+}
+
+/// Play the macro of the hotkey, like `Ctrl+Alt+1`, returns false if there is none.
+pub fn session_play_macro_hotkey(_session_id: SessionID, _hotkey: String) -> SyncReturn<bool> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return SyncReturn(session.play_macro_hotkey(&_hotkey));
+    }
+    SyncReturn(false)
+}
+
+pub fn session_set_viewport(session_id: SessionID, display: i32, width: i32, height: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_viewport(display, width, height);
+    }
+}
+
 pub fn session_get_trackpad_speed(session_id: SessionID) -> Option<i32> {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         Some(session.get_trackpad_speed())
@@ -2075,6 +2156,14 @@ pub fn session_alternative_codecs(session_id: SessionID) -> String {
     }
 }
 
+pub fn session_support_crisp_text(session_id: SessionID) -> SyncReturn<bool> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        SyncReturn(session.support_crisp_text())
+    } else {
+        SyncReturn(false)
+    }
+}
+
 pub fn session_change_prefer_codec(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.update_supported_decodings();
@@ -1,95 +1,625 @@
 use crate::hbbs_http::create_http_client_with_url;
 use bytes::Bytes;
 use hbb_common::{bail, config::Config, lazy_static, log, ResultType};
-use reqwest::blocking::{Body, Client};
+use reqwest::{
+    blocking::{Body, Client},
+    Method,
+};
 use scrap::record::RecordState;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Map;
 use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
     fs::File,
-    io::{prelude::*, SeekFrom},
-    sync::{mpsc::Receiver, Arc, Mutex},
+    hash::Hasher,
+    io::{prelude::*, ErrorKind, SeekFrom},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
+mod s3;
+
 const MAX_HEADER_LEN: usize = 1024;
 const SHOULD_SEND_TIME: Duration = Duration::from_secs(1);
 const SHOULD_SEND_SIZE: u64 = 1024 * 1024;
+// keep the memory bounded when resuming a large file
+const MAX_PART_LEN: u64 = 16 * 1024 * 1024;
+// what was uploaded is kept track of in blocks of this size, see `rewritten`
+const DIGEST_BLOCK_LEN: u64 = 64 * 1024;
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+const MAX_FAILURES: u32 = 20;
+const STATE_FILE: &str = "record_upload.json";
+
+pub const OPTION_RECORD_UPLOAD_TARGET: &str = "record-upload-target";
+pub const OPTION_RECORD_UPLOAD_URL: &str = "record-upload-url";
+pub const OPTION_RECORD_UPLOAD_AUTH: &str = "record-upload-auth";
+pub const OPTION_RECORD_UPLOAD_S3_BUCKET: &str = "record-upload-s3-bucket";
+pub const OPTION_RECORD_UPLOAD_S3_REGION: &str = "record-upload-s3-region";
+pub const OPTION_RECORD_UPLOAD_S3_ACCESS_KEY: &str = "record-upload-s3-access-key";
+pub const OPTION_RECORD_UPLOAD_S3_SECRET_KEY: &str = "record-upload-s3-secret-key";
+
+const TARGET_PRO: &str = "pro";
+const TARGET_HTTP: &str = "http";
+const TARGET_S3: &str = "s3";
 
 lazy_static::lazy_static! {
     static ref ENABLE: Arc<Mutex<bool>> = Default::default();
+    static ref WORKER: Mutex<Option<Sender<Job>>> = Default::default();
 }
 
 pub fn is_enable() -> bool {
-    ENABLE.lock().unwrap().clone()
+    ENABLE.lock().unwrap().clone() || !Config::get_option(OPTION_RECORD_UPLOAD_TARGET).is_empty()
+}
+
+fn configured_target() -> String {
+    let target = Config::get_option(OPTION_RECORD_UPLOAD_TARGET);
+    if target.is_empty() {
+        TARGET_PRO.to_owned()
+    } else {
+        target
+    }
 }
 
+fn create_target(kind: &str) -> ResultType<Box<dyn Target>> {
+    Ok(match kind {
+        TARGET_PRO => Box::new(ProTarget::new()),
+        TARGET_HTTP => {
+            let url = Config::get_option(OPTION_RECORD_UPLOAD_URL);
+            if url.is_empty() {
+                bail!("{} is not set", OPTION_RECORD_UPLOAD_URL);
+            }
+            Box::new(HttpTarget::new(
+                create_http_client_with_url(&url),
+                url,
+                Config::get_option(OPTION_RECORD_UPLOAD_AUTH),
+            ))
+        }
+        TARGET_S3 => Box::new(s3::S3Target::from_config()?),
+        _ => bail!("unknown record upload target: {kind}"),
+    })
+}
+
+// What the recorders report, resolved to the file it applies to.
+enum Job {
+    New {
+        filepath: String,
+        previous: Option<String>,
+    },
+    Grow(String),
+    Tail(String),
+    Remove(String),
+}
+
+/// Forward the states of one recorder to the upload worker.
 pub fn run(rx: Receiver<RecordState>) {
+    let tx = worker();
     std::thread::spawn(move || {
-        let api_server = crate::get_api_server(
-            Config::get_option("api-server"),
-            Config::get_option("custom-rendezvous-server"),
-        );
-        // This URL is used for TLS connectivity testing and fallback detection.
-        let login_option_url = format!("{}/api/login-options", &api_server);
-        let client = create_http_client_with_url(&login_option_url);
-        let mut uploader = RecordUploader {
-            client,
-            api_server,
-            filepath: Default::default(),
-            filename: Default::default(),
-            upload_size: Default::default(),
-            running: Default::default(),
-            last_send: Instant::now(),
-        };
+        let mut current: Option<String> = None;
         loop {
-            if let Err(e) = match rx.recv() {
-                Ok(state) => match state {
-                    RecordState::NewFile(filepath) => uploader.handle_new_file(filepath),
-                    RecordState::NewFrame => {
-                        if uploader.running {
-                            uploader.handle_frame(false)
-                        } else {
-                            Ok(())
-                        }
+            let state = match rx.recv() {
+                Ok(state) => state,
+                Err(e) => {
+                    log::trace!("upload thread stop: {}", e);
+                    break;
+                }
+            };
+            let job = match state {
+                RecordState::NewFile(filepath) => {
+                    current = Some(filepath.clone());
+                    Job::New {
+                        filepath,
+                        previous: None,
                     }
-                    RecordState::WriteTail => {
-                        if uploader.running {
-                            uploader.handle_tail()
-                        } else {
-                            Ok(())
-                        }
+                }
+                RecordState::NewSegment(filepath) => Job::New {
+                    previous: current
+                        .replace(filepath.clone())
+                        .and_then(|f| filename_of(&f)),
+                    filepath,
+                },
+                // uploaded on its own, it's complete already
+                RecordState::Sidecar(filepath) => {
+                    let new = Job::New {
+                        filepath: filepath.clone(),
+                        previous: None,
+                    };
+                    if tx.send(new).is_err() {
+                        break;
                     }
-                    RecordState::RemoveFile => {
-                        if uploader.running {
-                            uploader.handle_remove()
-                        } else {
-                            Ok(())
-                        }
+                    Job::Tail(filepath)
+                }
+                RecordState::NewFrame => match &current {
+                    Some(f) => Job::Grow(f.clone()),
+                    None => continue,
+                },
+                RecordState::WriteTail(filepath) => Job::Tail(filepath),
+                RecordState::RemoveFile(filepath) => Job::Remove(filepath),
+            };
+            if tx.send(job).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Resume the uploads interrupted by a restart.
+pub fn resume() {
+    if Config::path(STATE_FILE).exists() {
+        worker();
+    }
+}
+
+fn worker() -> Sender<Job> {
+    let mut worker = WORKER.lock().unwrap();
+    if let Some(tx) = worker.as_ref() {
+        return tx.clone();
+    }
+    let (tx, rx) = channel();
+    std::thread::spawn(move || Uploader::load().run(rx));
+    *worker = Some(tx.clone());
+    tx
+}
+
+fn filename_of(filepath: &str) -> Option<String> {
+    std::path::PathBuf::from(filepath)
+        .file_name()
+        .and_then(|f| f.to_owned().into_string().ok())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Phase {
+    Recording,
+    Tail,
+    Remove,
+}
+
+/// The progress of one file, persisted so that uploads survive a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UploadFile {
+    filepath: String,
+    filename: String,
+    target: String,
+    // the file this segment continues
+    previous: Option<String>,
+    phase: Phase,
+    created: bool,
+    // bytes uploaded
+    offset: u64,
+    upload_id: Option<String>,
+    etags: Vec<String>,
+    #[serde(skip)]
+    failures: u32,
+    #[serde(skip)]
+    retry_at: Option<Instant>,
+    #[serde(skip)]
+    last_send: Option<Instant>,
+    // offset -> (length, digest) of the uploaded ranges, split at the blocks. Kept in memory
+    // only, what was uploaded before a restart is sent again with the tail.
+    #[serde(skip)]
+    sent: BTreeMap<u64, (u64, u64)>,
+}
+
+impl UploadFile {
+    fn new(filepath: String, previous: Option<String>, target: String) -> ResultType<Self> {
+        let Some(filename) = filename_of(&filepath) else {
+            bail!("can't parse filepath:{}", filepath);
+        };
+        Ok(Self {
+            filepath,
+            filename,
+            target,
+            previous,
+            phase: Phase::Recording,
+            created: false,
+            offset: 0,
+            upload_id: None,
+            etags: vec![],
+            failures: 0,
+            retry_at: None,
+            last_send: None,
+            sent: Default::default(),
+        })
+    }
+
+    fn retry_delay(&self) -> Duration {
+        (MIN_RETRY_DELAY * 2u32.saturating_pow(self.failures.saturating_sub(1)))
+            .min(MAX_RETRY_DELAY)
+    }
+
+    fn set_sent(&mut self, digests: Vec<(u64, u64, u64)>) {
+        for (offset, size, digest) in digests {
+            let block = offset - offset % DIGEST_BLOCK_LEN;
+            let stale: Vec<u64> = self
+                .sent
+                .range(block..offset + size)
+                .filter(|(o, (len, _))| *o + len > offset)
+                .map(|(o, _)| *o)
+                .collect();
+            for o in stale {
+                self.sent.remove(&o);
+            }
+            self.sent.insert(offset, (size, digest));
+        }
+    }
+}
+
+trait Target: Send {
+    /// Parts must be of this size while the file grows, the remainder is left to `tail`.
+    /// Parts are sent again whole if any of it was rewritten.
+    fn part_size(&self) -> Option<u64> {
+        None
+    }
+    fn create(&self, up: &mut UploadFile) -> ResultType<()>;
+    fn part(&self, up: &mut UploadFile, offset: u64, data: Vec<u8>) -> ResultType<()>;
+    /// Called once the file is complete and every part is uploaded, including the ranges
+    /// rewritten after they were uploaded, see `rewritten`. The file may have been cut
+    /// to `len` below what was uploaded.
+    fn tail(&self, up: &mut UploadFile, file: &mut File, len: u64) -> ResultType<()>;
+    fn remove(&self, up: &mut UploadFile) -> ResultType<()>;
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> ResultType<Vec<u8>> {
+    let mut buf = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn digest(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+// `(offset, length, digest)` of `data` at `offset`, split at the blocks.
+fn digests(offset: u64, data: &[u8]) -> Vec<(u64, u64, u64)> {
+    let mut digests = vec![];
+    let mut start = offset;
+    let end = offset + data.len() as u64;
+    while start < end {
+        let next = (start - start % DIGEST_BLOCK_LEN + DIGEST_BLOCK_LEN).min(end);
+        let piece = &data[(start - offset) as usize..(next - offset) as usize];
+        digests.push((start, next - start, digest(piece)));
+        start = next;
+    }
+    digests
+}
+
+// The ranges below `up.offset` which differ from what was uploaded. The muxers patch sizes
+// and the header as they go and on finalization, and the repair cuts off a broken end.
+fn rewritten(up: &UploadFile, file: &mut File, len: u64) -> ResultType<Vec<(u64, u64)>> {
+    let end = up.offset.min(len);
+    let mut ranges: Vec<(u64, u64)> = vec![];
+    let mut push = |offset: u64, size: u64| match ranges.last_mut() {
+        Some((o, s)) if *o + *s == offset => *s += size,
+        _ => ranges.push((offset, size)),
+    };
+    let mut covered = 0;
+    for (offset, (size, digest_sent)) in up.sent.range(..end) {
+        if *offset > covered {
+            push(covered, offset - covered);
+        }
+        let size = (*size).min(end - offset);
+        if read_at(file, *offset, size).map_or(true, |data| digest(&data) != *digest_sent) {
+            push(*offset, size);
+        }
+        covered = offset + size;
+    }
+    if end > covered {
+        push(covered, end - covered);
+    }
+    Ok(ranges)
+}
+
+// Send the rewritten ranges again, in whole parts if the target has a part size.
+fn upload_rewritten(
+    target: &dyn Target,
+    up: &mut UploadFile,
+    file: &mut File,
+    len: u64,
+) -> ResultType<()> {
+    let mut parts: Vec<(u64, u64)> = vec![];
+    for (offset, size) in rewritten(up, file, len)? {
+        match target.part_size() {
+            Some(part_size) => {
+                let mut start = offset - offset % part_size;
+                while start < offset + size {
+                    if parts.last().map_or(true, |(o, _)| *o != start) {
+                        parts.push((start, part_size.min(len - start)));
                     }
+                    start += part_size;
+                }
+            }
+            None => {
+                let mut start = offset;
+                while start < offset + size {
+                    let size = (offset + size - start).min(MAX_PART_LEN);
+                    parts.push((start, size));
+                    start += size;
+                }
+            }
+        }
+    }
+    for (offset, size) in parts {
+        let data = read_at(file, offset, size)?;
+        let digests = digests(offset, &data);
+        target.part(up, offset, data)?;
+        up.set_sent(digests);
+    }
+    // uploaded past the end of a cut off file
+    up.offset = up.offset.min(len);
+    up.sent.split_off(&len);
+    Ok(())
+}
+
+fn upload_new(target: &dyn Target, up: &mut UploadFile, flush: bool) -> ResultType<()> {
+    if !flush
+        && up
+            .last_send
+            .map_or(false, |t| t.elapsed() < SHOULD_SEND_TIME)
+    {
+        return Ok(());
+    }
+    let mut file = match File::open(&up.filepath) {
+        Ok(file) => file,
+        // Encrypted hardware recordings only appear once the tail is written.
+        Err(e) if !flush && e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => bail!(e.to_string()),
+    };
+    let len = file.metadata()?.len();
+    let part_size = target.part_size();
+    while len > up.offset {
+        let mut size = len - up.offset;
+        match part_size {
+            Some(part_size) if size < part_size => break,
+            Some(part_size) => size = part_size,
+            None if !flush && size < SHOULD_SEND_SIZE => break,
+            None => size = size.min(MAX_PART_LEN),
+        }
+        let offset = up.offset;
+        let data = read_at(&mut file, offset, size)?;
+        let digests = digests(offset, &data);
+        target.part(up, offset, data)?;
+        up.set_sent(digests);
+        up.offset += size;
+        up.last_send = Some(Instant::now());
+    }
+    Ok(())
+}
+
+// Returns true once the file needs nothing more.
+fn step(target: &dyn Target, up: &mut UploadFile) -> ResultType<bool> {
+    if up.phase == Phase::Remove {
+        if up.created {
+            target.remove(up)?;
+        }
+        return Ok(true);
+    }
+    if !up.created {
+        target.create(up)?;
+        up.created = true;
+    }
+    match up.phase {
+        Phase::Recording => {
+            upload_new(target, up, false)?;
+            Ok(false)
+        }
+        _ => {
+            let mut file = match File::open(&up.filepath) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    log::error!("upload stop, {} is gone", up.filepath);
+                    return Ok(true);
+                }
+                Err(e) => bail!(e.to_string()),
+            };
+            let len = file.metadata()?.len();
+            upload_rewritten(target, up, &mut file, len)?;
+            upload_new(target, up, true)?;
+            target.tail(up, &mut file, len)?;
+            log::info!("upload success, file: {}", up.filename);
+            Ok(true)
+        }
+    }
+}
+
+struct Uploader {
+    files: Vec<UploadFile>,
+    targets: HashMap<String, Box<dyn Target>>,
+}
+
+impl Uploader {
+    fn load() -> Self {
+        let mut files: Vec<UploadFile> = std::fs::read(Config::path(STATE_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        for up in files.iter_mut() {
+            // the recorder of an interrupted file is gone, it will not grow anymore
+            if up.phase == Phase::Recording {
+                up.phase = Phase::Tail;
+                // but was left unfinished, fix it up before the tail is uploaded
+                if scrap::record::is_plain_matroska(&up.filepath) {
+                    if let Err(e) = scrap::record::repair(&up.filepath) {
+                        log::error!("Failed to repair {}: {e}", up.filepath);
+                    }
+                }
+            }
+            // the repair may have cut off the broken end
+            if let Ok(meta) = std::fs::metadata(&up.filepath) {
+                up.offset = up.offset.min(meta.len());
+            }
+            log::info!("resume upload of {}", up.filename);
+        }
+        Self {
+            files,
+            targets: Default::default(),
+        }
+    }
+
+    fn save(&self) {
+        let path = Config::path(STATE_FILE);
+        let res = if self.files.is_empty() {
+            std::fs::remove_file(&path).or_else(|e| {
+                if e.kind() == ErrorKind::NotFound {
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            })
+        } else {
+            serde_json::to_vec(&self.files)
+                .map_err(std::io::Error::from)
+                .and_then(|data| std::fs::write(&path, data))
+        };
+        if let Err(e) = res {
+            log::error!("Failed to save upload state: {e}");
+        }
+    }
+
+    // How long to wait for a job before the next retry is due.
+    fn next_retry(&self) -> Option<Duration> {
+        self.files
+            .iter()
+            .filter(|up| up.phase != Phase::Recording || up.retry_at.is_some())
+            .map(|up| {
+                up.retry_at.map_or(Duration::ZERO, |t| {
+                    t.saturating_duration_since(Instant::now())
+                })
+            })
+            .min()
+    }
+
+    fn run(mut self, rx: Receiver<Job>) {
+        loop {
+            let job = match self.next_retry() {
+                Some(timeout) => match rx.recv_timeout(timeout) {
+                    Ok(job) => Some(job),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(job) => Some(job),
+                    Err(_) => break,
                 },
+            };
+            if let Some(job) = job {
+                self.handle(job);
+            }
+            self.process();
+        }
+    }
+
+    fn handle(&mut self, job: Job) {
+        let (filepath, phase) = match job {
+            Job::New { filepath, previous } => {
+                if !self.files.iter().any(|up| up.filepath == filepath) {
+                    match UploadFile::new(filepath, previous, configured_target()) {
+                        Ok(up) => self.files.push(up),
+                        Err(e) => log::error!("upload stop: {}", e),
+                    }
+                    self.save();
+                }
+                return;
+            }
+            Job::Grow(_) => return,
+            Job::Tail(filepath) => (filepath, Phase::Tail),
+            Job::Remove(filepath) => (filepath, Phase::Remove),
+        };
+        if let Some(up) = self.files.iter_mut().find(|up| up.filepath == filepath) {
+            up.phase = phase;
+            // don't wait for the backoff of a failed part, the tail retries it anyway
+            up.retry_at = None;
+            self.save();
+        }
+    }
+
+    fn process(&mut self) {
+        let now = Instant::now();
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.files.len() {
+            let up = &mut self.files[i];
+            if up.retry_at.map_or(false, |t| t > now) {
+                i += 1;
+                continue;
+            }
+            if !self.targets.contains_key(&up.target) {
+                match create_target(&up.target) {
+                    Ok(target) => {
+                        self.targets.insert(up.target.clone(), target);
+                    }
+                    Err(e) => {
+                        log::error!("upload stop, {}: {}", up.filename, e);
+                        self.files.remove(i);
+                        changed = true;
+                        continue;
+                    }
+                }
+            }
+            let target = &self.targets[&up.target];
+            let offset = up.offset;
+            let res = step(target.as_ref(), up);
+            changed |= up.offset != offset || res.is_err();
+            match res {
+                Ok(done) => {
+                    up.failures = 0;
+                    up.retry_at = None;
+                    if done {
+                        self.files.remove(i);
+                        changed = true;
+                        continue;
+                    }
+                }
                 Err(e) => {
-                    log::trace!("upload thread stop: {}", e);
-                    break;
+                    up.failures += 1;
+                    if up.failures > MAX_FAILURES {
+                        log::error!("upload stop, {}: {}", up.filename, e);
+                        self.files.remove(i);
+                        changed = true;
+                        continue;
+                    }
+                    let delay = up.retry_delay();
+                    log::warn!(
+                        "upload of {} failed, retry in {:?}: {}",
+                        up.filename,
+                        delay,
+                        e
+                    );
+                    up.retry_at = Some(now + delay);
                 }
-            } {
-                uploader.running = false;
-                log::error!("upload stop: {}", e);
             }
+            i += 1;
         }
-    });
+        if changed {
+            self.save();
+        }
+    }
 }
 
-struct RecordUploader {
+/// The record API of the pro server.
+struct ProTarget {
     client: Client,
     api_server: String,
-    filepath: String,
-    filename: String,
-    upload_size: u64,
-    running: bool,
-    last_send: Instant,
 }
-impl RecordUploader {
+
+impl ProTarget {
+    fn new() -> Self {
+        let api_server = crate::get_api_server(
+            Config::get_option("api-server"),
+            Config::get_option("custom-rendezvous-server"),
+        );
+        // This URL is used for TLS connectivity testing and fallback detection.
+        let login_option_url = format!("{}/api/login-options", &api_server);
+        let client = create_http_client_with_url(&login_option_url);
+        Self { client, api_server }
+    }
+
     fn send<Q, B>(&self, query: &Q, body: B) -> ResultType<()>
     where
         Q: Serialize + ?Sized,
@@ -113,99 +643,311 @@ impl RecordUploader {
             Err(e) => bail!(e.to_string()),
         }
     }
+}
 
-    fn handle_new_file(&mut self, filepath: String) -> ResultType<()> {
-        match std::path::PathBuf::from(&filepath).file_name() {
-            Some(filename) => match filename.to_owned().into_string() {
-                Ok(filename) => {
-                    self.filename = filename.clone();
-                    self.filepath = filepath.clone();
-                    self.upload_size = 0;
-                    self.running = true;
-                    self.last_send = Instant::now();
-                    self.send(&[("type", "new"), ("file", &filename)], Bytes::new())?;
-                    Ok(())
-                }
-                Err(_) => bail!("can't parse filename:{:?}", filename),
-            },
-            None => bail!("can't parse filepath:{}", filepath),
+impl Target for ProTarget {
+    // A segment continues the previous file, which is passed along so the server can chain them.
+    fn create(&self, up: &mut UploadFile) -> ResultType<()> {
+        match &up.previous {
+            Some(previous) => self.send(
+                &[
+                    ("type", "new"),
+                    ("file", &up.filename),
+                    ("previous", previous),
+                ],
+                Bytes::new(),
+            ),
+            None => self.send(&[("type", "new"), ("file", &up.filename)], Bytes::new()),
         }
     }
 
-    fn handle_frame(&mut self, flush: bool) -> ResultType<()> {
-        if !flush && self.last_send.elapsed() < SHOULD_SEND_TIME {
-            return Ok(());
+    fn part(&self, up: &mut UploadFile, offset: u64, data: Vec<u8>) -> ResultType<()> {
+        self.send(
+            &[
+                ("type", "part"),
+                ("file", &up.filename),
+                ("offset", &offset.to_string()),
+                ("length", &data.len().to_string()),
+            ],
+            data,
+        )
+    }
+
+    // The rewritten ranges went as parts already, the header along with the tail is what
+    // tells the server the file is complete.
+    fn tail(&self, up: &mut UploadFile, file: &mut File, len: u64) -> ResultType<()> {
+        let header = read_at(file, 0, len.min(MAX_HEADER_LEN as _))?;
+        self.send(
+            &[
+                ("type", "tail"),
+                ("file", &up.filename),
+                ("offset", "0"),
+                ("length", &header.len().to_string()),
+            ],
+            header,
+        )
+    }
+
+    fn remove(&self, up: &mut UploadFile) -> ResultType<()> {
+        self.send(&[("type", "remove"), ("file", &up.filename)], Bytes::new())
+    }
+}
+
+/// A plain HTTP endpoint, for self-hosted storage.
+///
+/// Each range is written with `PUT <url>/<file>` and `Content-Range: bytes <first>-<last>/*`,
+/// the ranges rewritten by the muxers are written again. The tail is an empty
+/// `Content-Range: bytes */<total>`, which marks the file complete and cuts off what is
+/// beyond. The first range of a segment carries `X-Record-Previous: <file>`.
+/// A discarded recording is removed with `DELETE <url>/<file>`.
+struct HttpTarget {
+    client: Client,
+    url: String,
+    // value of the Authorization header
+    auth: String,
+}
+
+impl HttpTarget {
+    fn new(client: Client, url: String, auth: String) -> Self {
+        Self {
+            client,
+            url: url.trim_end_matches('/').to_owned(),
+            auth,
         }
-        match File::open(&self.filepath) {
-            Ok(mut file) => match file.metadata() {
-                Ok(m) => {
-                    let len = m.len();
-                    if len <= self.upload_size {
-                        return Ok(());
-                    }
-                    if !flush && len - self.upload_size < SHOULD_SEND_SIZE {
-                        return Ok(());
-                    }
-                    let mut buf = Vec::new();
-                    match file.seek(SeekFrom::Start(self.upload_size)) {
-                        Ok(_) => match file.read_to_end(&mut buf) {
-                            Ok(length) => {
-                                self.send(
-                                    &[
-                                        ("type", "part"),
-                                        ("file", &self.filename),
-                                        ("offset", &self.upload_size.to_string()),
-                                        ("length", &length.to_string()),
-                                    ],
-                                    buf,
-                                )?;
-                                self.upload_size = len;
-                                self.last_send = Instant::now();
-                                Ok(())
-                            }
-                            Err(e) => bail!(e.to_string()),
-                        },
-                        Err(e) => bail!(e.to_string()),
-                    }
-                }
-                Err(e) => bail!(e.to_string()),
-            },
-            Err(e) => bail!(e.to_string()),
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        up: &UploadFile,
+        headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> ResultType<()> {
+        let mut req = self
+            .client
+            .request(method, format!("{}/{}", self.url, up.filename))
+            .body(body);
+        if !self.auth.is_empty() {
+            req = req.header("Authorization", &self.auth);
         }
+        for (k, v) in headers {
+            req = req.header(*k, v);
+        }
+        let resp = req.send()?;
+        if !resp.status().is_success() {
+            bail!("HTTP {}", resp.status());
+        }
+        Ok(())
+    }
+}
+
+impl Target for HttpTarget {
+    fn create(&self, _up: &mut UploadFile) -> ResultType<()> {
+        Ok(())
     }
 
-    fn handle_tail(&mut self) -> ResultType<()> {
-        self.handle_frame(true)?;
-        match File::open(&self.filepath) {
-            Ok(mut file) => {
-                let mut buf = vec![0u8; MAX_HEADER_LEN];
-                match file.read(&mut buf) {
-                    Ok(length) => {
-                        buf.truncate(length);
-                        self.send(
-                            &[
-                                ("type", "tail"),
-                                ("file", &self.filename),
-                                ("offset", "0"),
-                                ("length", &length.to_string()),
-                            ],
-                            buf,
-                        )?;
-                        log::info!("upload success, file: {}", self.filename);
-                        Ok(())
+    fn part(&self, up: &mut UploadFile, offset: u64, data: Vec<u8>) -> ResultType<()> {
+        let mut headers = vec![(
+            "Content-Range",
+            format!("bytes {}-{}/*", offset, offset + data.len() as u64 - 1),
+        )];
+        if let Some(previous) = up.previous.as_ref().filter(|_| offset == 0) {
+            headers.push(("X-Record-Previous", previous.clone()));
+        }
+        self.request(Method::PUT, up, &headers, data)
+    }
+
+    fn tail(&self, up: &mut UploadFile, _file: &mut File, len: u64) -> ResultType<()> {
+        let range = format!("bytes */{len}");
+        self.request(Method::PUT, up, &[("Content-Range", range)], vec![])
+    }
+
+    fn remove(&self, up: &mut UploadFile) -> ResultType<()> {
+        self.request(Method::DELETE, up, &[], vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::BufReader,
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    pub struct Request {
+        pub method: String,
+        pub target: String,
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    pub type Response = (u16, Vec<(String, String)>, Vec<u8>);
+
+    /// A local stand-in for the upload endpoints, returns its base URL.
+    pub fn serve<F>(handler: F) -> String
+    where
+        F: Fn(&Request) -> Response + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    break;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_owned();
+                let target = parts.next().unwrap_or_default().to_owned();
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((k, v)) = line.split_once(':') {
+                        headers.insert(k.trim().to_lowercase(), v.trim().to_owned());
                     }
-                    Err(e) => bail!(e.to_string()),
                 }
+                let len = headers
+                    .get("content-length")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0u8; len];
+                reader.read_exact(&mut body).unwrap();
+                let (status, resp_headers, resp_body) = handler(&Request {
+                    method,
+                    target,
+                    headers,
+                    body,
+                });
+                let mut resp = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    resp_body.len()
+                );
+                for (k, v) in resp_headers {
+                    resp += &format!("{k}: {v}\r\n");
+                }
+                resp += "\r\n";
+                stream.write_all(resp.as_bytes()).unwrap();
+                stream.write_all(&resp_body).unwrap();
             }
-            Err(e) => bail!(e.to_string()),
+        });
+        format!("http://{addr}")
+    }
+
+    // Apply a `Content-Range: bytes <first>-<last>/<total>` write.
+    pub fn apply_range(stored: &mut Vec<u8>, range: &str, body: &[u8]) {
+        let range = range.trim_start_matches("bytes ");
+        let (span, total) = range.split_once('/').unwrap();
+        if let Some((first, _)) = span.split_once('-') {
+            let first: usize = first.parse().unwrap();
+            if stored.len() < first + body.len() {
+                stored.resize(first + body.len(), 0);
+            }
+            stored[first..first + body.len()].copy_from_slice(body);
+        }
+        if let Ok(total) = total.parse::<usize>() {
+            stored.truncate(total);
         }
     }
 
-    fn handle_remove(&mut self) -> ResultType<()> {
-        self.send(
-            &[("type", "remove"), ("file", &self.filename)],
-            Bytes::new(),
-        )?;
-        Ok(())
+    #[test]
+    fn test_http_resume_after_failure_and_restart() {
+        let stored = Arc::new(Mutex::new(vec![]));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let (stored2, requests2) = (stored.clone(), requests.clone());
+        let url = serve(move |req| {
+            // the first attempt fails, as if the storage was unreachable
+            if requests2.fetch_add(1, Ordering::SeqCst) == 0 {
+                return (503, vec![], vec![]);
+            }
+            assert_eq!(req.method, "PUT");
+            assert_eq!(req.target, "/records/incoming_test.webm");
+            assert_eq!(req.headers.get("authorization").unwrap(), "Bearer token");
+            let range = req.headers.get("content-range").unwrap();
+            apply_range(&mut stored2.lock().unwrap(), range, &req.body);
+            (200, vec![], vec![])
+        });
+        let target = HttpTarget::new(
+            Client::new(),
+            format!("{url}/records/"),
+            "Bearer token".to_owned(),
+        );
+
+        let path = std::env::temp_dir().join(format!("incoming_test_{}", std::process::id()));
+        let mut content: Vec<u8> = (0..3 * SHOULD_SEND_SIZE).map(|x| (x % 253) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+        let mut up = UploadFile::new(
+            path.to_string_lossy().to_string(),
+            None,
+            TARGET_HTTP.to_owned(),
+        )
+        .unwrap();
+        up.filename = "incoming_test.webm".to_owned();
+
+        assert!(step(&target, &mut up).is_err());
+        assert_eq!(up.offset, 0);
+        assert!(!step(&target, &mut up).unwrap());
+        assert_eq!(up.offset, content.len() as u64);
+
+        // the process restarts while the recording is finalized
+        let mut up: UploadFile = serde_json::from_slice(&serde_json::to_vec(&up).unwrap()).unwrap();
+        content.extend_from_slice(&[7u8; 100]);
+        content[..4].copy_from_slice(&[1, 2, 3, 4]);
+        std::fs::write(&path, &content).unwrap();
+        up.phase = Phase::Tail;
+        assert!(step(&target, &mut up).unwrap());
+        assert_eq!(*stored.lock().unwrap(), content);
+        // what was uploaded before the restart is sent again, it may have been rewritten
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_http_resend_rewritten() {
+        let stored = Arc::new(Mutex::new(vec![]));
+        let sent = Arc::new(AtomicUsize::new(0));
+        let (stored2, sent2) = (stored.clone(), sent.clone());
+        let url = serve(move |req| {
+            let range = req.headers.get("content-range").unwrap();
+            apply_range(&mut stored2.lock().unwrap(), range, &req.body);
+            sent2.fetch_add(req.body.len(), Ordering::SeqCst);
+            (200, vec![], vec![])
+        });
+        let target = HttpTarget::new(Client::new(), url, String::new());
+
+        let path = std::env::temp_dir().join(format!("incoming_rewritten_{}", std::process::id()));
+        let mut content: Vec<u8> = (0..3 * SHOULD_SEND_SIZE).map(|x| (x % 241) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+        let mut up = UploadFile::new(
+            path.to_string_lossy().to_string(),
+            None,
+            TARGET_HTTP.to_owned(),
+        )
+        .unwrap();
+        assert!(!step(&target, &mut up).unwrap());
+        assert_eq!(sent.swap(0, Ordering::SeqCst), content.len());
+
+        // sizes patched in the header and a cluster, and a broken end cut off
+        content[..4].copy_from_slice(&[1, 2, 3, 4]);
+        content[SHOULD_SEND_SIZE as usize + 10] = 0xff;
+        content.truncate(2 * SHOULD_SEND_SIZE as usize + 5);
+        std::fs::write(&path, &content).unwrap();
+        up.phase = Phase::Tail;
+        assert!(step(&target, &mut up).unwrap());
+        assert_eq!(*stored.lock().unwrap(), content);
+        assert_eq!(
+            sent.load(Ordering::SeqCst),
+            2 * DIGEST_BLOCK_LEN as usize + 5,
+            "only the rewritten blocks"
+        );
+        std::fs::remove_file(&path).ok();
     }
 }
@@ -285,6 +285,8 @@ fn heartbeat_url() -> String {
 }
 
 fn handle_config_options(config_options: HashMap<String, String>) {
+    #[cfg(not(target_os = "ios"))]
+    let bandwidth_limit = crate::get_bandwidth_limit_option();
     let mut options = Config::get_options();
     let default_settings = config::DEFAULT_SETTINGS.read().unwrap().clone();
     config_options
@@ -301,6 +303,10 @@ fn handle_config_options(config_options: HashMap<String, String>) {
         })
         .count();
     Config::set_options(options);
+    #[cfg(not(target_os = "ios"))]
+    if bandwidth_limit != crate::get_bandwidth_limit_option() {
+        crate::server::bandwidth::update();
+    }
 }
 
 #[allow(unused)]
@@ -314,6 +314,10 @@ pub enum Data {
         recording: bool,
         block_input: bool,
         privacy_mode: bool,
+        #[serde(default)]
+        mic_redirect: bool,
+        #[serde(default)]
+        gamepad: bool,
         from_switch: bool,
     },
     ChatMessage {
@@ -356,6 +360,10 @@ pub enum Data {
     KeyboardResponse(DataKeyboardResponse),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Mouse(DataMouse),
+    #[cfg(target_os = "linux")]
+    Gamepad(crate::GamepadState),
+    #[cfg(target_os = "linux")]
+    Pen(crate::PenEvent),
     Control(DataControl),
     Theme(String),
     Language(String),
@@ -477,6 +485,7 @@ pub enum Data {
     ControlPermissionsRemoteModify(Option<bool>),
     #[cfg(target_os = "windows")]
     FileTransferEnabledState(Option<bool>),
+    SessionStats(Option<Vec<crate::server::stats::SessionStats>>),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -664,6 +673,7 @@ pub struct CheckIfRestart {
     disable_udp: String,
     allow_insecure_tls_fallback: String,
     api_server: String,
+    bandwidth_limit: Option<u32>,
 }
 
 impl CheckIfRestart {
@@ -679,6 +689,7 @@ impl CheckIfRestart {
                 config::keys::OPTION_ALLOW_INSECURE_TLS_FALLBACK,
             ),
             api_server: Config::get_option("api-server"),
+            bandwidth_limit: crate::get_bandwidth_limit_option(),
         }
     }
 }
@@ -710,6 +721,9 @@ impl Drop for CheckIfRestart {
                 true,
             )
         }
+        if self.bandwidth_limit != crate::get_bandwidth_limit_option() {
+            crate::server::bandwidth::update();
+        }
     }
 }
 
@@ -812,6 +826,10 @@ async fn handle(data: Data, stream: &mut Connection) {
             }
             _ => {}
         },
+        Data::SessionStats(None) => {
+            let stats = crate::server::stats::last();
+            allow_err!(stream.send(&Data::SessionStats(Some(stats))).await);
+        }
         #[cfg(feature = "flutter")]
         Data::VideoConnCount(None) => {
             let n = crate::server::AUTHED_CONNS
@@ -1729,6 +1747,17 @@ async fn get_options_(ms_timeout: u64) -> ResultType<HashMap<String, String>> {
     }
 }
 
+/// The last QoS snapshot of the sessions served by the server process.
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_session_stats() -> ResultType<Vec<crate::server::stats::SessionStats>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::SessionStats(None)).await?;
+    if let Some(Data::SessionStats(Some(stats))) = c.next_timeout(1000).await? {
+        return Ok(stats);
+    }
+    bail!("No session stats received");
+}
+
 pub async fn get_options_async() -> HashMap<String, String> {
     get_options_(1000).await.unwrap_or(Config::get_options())
 }
@@ -758,5 +758,27 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("wayland-keyboard-input-reset-choice-tip", "Нулиране на избора за въвеждане от клавиатура"),
         ("remember-wayland-keyboard-choice-tip", "Не питай отново за този отдалечен компютър"),
         ("Why this happens", "Защо се случва това"),
+        ("Crisp text", ""),
+        ("Bandwidth limit", ""),
+        ("bandwidth-limit-tip", ""),
+        ("Audio preset", ""),
+        ("Voice", ""),
+        ("Music", ""),
+        ("Low bandwidth", ""),
+        ("Audio of applications", ""),
+        ("audio-apps-tip", ""),
+        ("Enable microphone redirection", ""),
+        ("Redirect microphone", ""),
+        ("Noise suppression and echo cancellation", ""),
+        ("Keyboard macros", ""),
+        ("Hotkey", ""),
+        ("For all peers", ""),
+        ("Edit", ""),
+        ("keyboard-macros-tip", ""),
+        ("Forward gamepads", ""),
+        ("Enable gamepad", ""),
+        ("Share a window", ""),
+        ("No window", ""),
+        ("share-window-tip", ""),
     ].iter().cloned().collect();
 }
@@ -758,5 +758,27 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("wayland-keyboard-input-reset-choice-tip", "重置键盘输入选择"),
         ("remember-wayland-keyboard-choice-tip", "以后对这台远程电脑不再询问"),
         ("Why this happens", "了解原因"),
+        ("Crisp text", "清晰文字"),
+        ("Bandwidth limit", "带宽限制"),
+        ("bandwidth-limit-tip", "本会话的带宽上限，单位 kbps，留空表示不限制"),
+        ("Audio preset", "音频预设"),
+        ("Voice", "语音"),
+        ("Music", "音乐"),
+        ("Low bandwidth", "低带宽"),
+        ("Audio of applications", "应用程序音频"),
+        ("audio-apps-tip", "仅传输勾选的应用程序的声音，未勾选任何应用时传输远程桌面的全部声音"),
+        ("Enable microphone redirection", "允许麦克风重定向"),
+        ("Redirect microphone", "重定向麦克风"),
+        ("Noise suppression and echo cancellation", "降噪和回声消除"),
+        ("Keyboard macros", "键盘宏"),
+        ("Hotkey", "快捷键"),
+        ("For all peers", "用于所有设备"),
+        ("Edit", "编辑"),
+        ("keyboard-macros-tip", "输入文本和标签，例如 {ENTER}、{+CTRL}c{-CTRL}、{DELAY 100}、{CLICK LEFT}、{MOVE 100 200}、{+REPEAT 3}...{-REPEAT} 或 {+UNICODE}...{-UNICODE}。带 Ctrl、Alt 或 Meta 的快捷键可在会话中播放宏。"),
+        ("Forward gamepads", "转发游戏手柄"),
+        ("Enable gamepad", "启用游戏手柄"),
+        ("Share a window", "共享窗口"),
+        ("No window", "不共享窗口"),
+        ("share-window-tip", "窗口作为额外的显示器共享，可像其他显示器一样切换过去"),
     ].iter().cloned().collect();
 }
@@ -758,5 +758,27 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("wayland-keyboard-input-reset-choice-tip", "Resetovat volbu vstupu z klávesnice"),
         ("remember-wayland-keyboard-choice-tip", "Pro tento vzdálený počítač se již neptat"),
         ("Why this happens", "Proč k tomu dochází"),
+        ("Crisp text", ""),
+        ("Bandwidth limit", ""),
+        ("bandwidth-limit-tip", ""),
+        ("Audio preset", ""),
+        ("Voice", ""),
+        ("Music", ""),
+        ("Low bandwidth", ""),
+        ("Audio of applications", ""),
+        ("audio-apps-tip", ""),
+        ("Enable microphone redirection", ""),
+        ("Redirect microphone", ""),
+        ("Noise suppression and echo cancellation", ""),
+        ("Keyboard macros", ""),
+        ("Hotkey", ""),
+        ("For all peers", ""),
+        ("Edit", ""),
+        ("keyboard-macros-tip", ""),
+        ("Forward gamepads", ""),
+        ("Enable gamepad", ""),
+        ("Share a window", ""),
+        ("No window", ""),
+        ("share-window-tip", ""),
     ].iter().cloned().collect();
 }
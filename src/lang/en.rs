@@ -279,5 +279,9 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("wayland-soft-keyboard-input-label", "Soft keyboard input"),
         ("wayland-keyboard-input-reset-choice-tip", "Reset keyboard input choice"),
         ("remember-wayland-keyboard-choice-tip", "Don't ask again for this remote computer"),
+        ("bandwidth-limit-tip", "The bandwidth ceiling of this session in kbps, leave empty for no limit"),
+        ("audio-apps-tip", "Stream the audio of the checked applications only, all the audio of the remote desktop if none is checked"),
+        ("keyboard-macros-tip", "Type text and tags like {ENTER}, {+CTRL}c{-CTRL}, {DELAY 100}, {CLICK LEFT}, {MOVE 100 200}, {+REPEAT 3}...{-REPEAT} or {+UNICODE}...{-UNICODE}. A hotkey with Ctrl, Alt or Meta plays the macro in the session."),
+        ("share-window-tip", "The window is shared as an extra display, switch to it like to any other display"),
     ].iter().cloned().collect();
 }
@@ -442,6 +442,12 @@ fn start_uinput_service() {
     std::thread::spawn(|| {
         service::start_service_mouse();
     });
+    std::thread::spawn(|| {
+        service::start_service_gamepad();
+    });
+    std::thread::spawn(|| {
+        service::start_service_pen();
+    });
 }
 
 /// Suggests the best terminal type based on the environment.
@@ -1152,10 +1158,11 @@ pub fn get_pa_monitor() -> String {
         .unwrap_or("".to_owned())
 }
 
+/// The name of the source with the description, or the name itself.
 pub fn get_pa_source_name(desc: &str) -> String {
     get_pa_sources()
         .drain(..)
-        .filter(|x| x.1 == desc)
+        .filter(|x| x.1 == desc || x.0 == desc)
         .map(|x| x.0)
         .next()
         .unwrap_or("".to_owned())
@@ -1200,6 +1207,159 @@ pub fn get_default_pa_source() -> Option<(String, String)> {
     None
 }
 
+/// The null sink the selected applications are moved to, for their audio to be captured alone.
+pub const PA_APPS_SINK: &str = "rustdesk_apps";
+
+/// The names of the applications playing audio.
+pub fn get_pa_apps() -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for (_, _, name) in get_pa_sink_inputs() {
+        if !out.contains(&name) {
+            out.push(name);
+        }
+    }
+    out
+}
+
+// (index, sink index, application name) of the sink inputs of the applications, the streams
+// of the modules like our loopback have no application name.
+fn get_pa_sink_inputs() -> Vec<(u32, u32, String)> {
+    use pulsectl::controllers::*;
+    let mut out = Vec::new();
+    match SinkController::create() {
+        Ok(mut handler) => {
+            if let Ok(apps) = handler.list_applications() {
+                for app in apps {
+                    if let Some(name) = app
+                        .proplist
+                        .get_str(pulse::proplist::properties::APPLICATION_NAME)
+                    {
+                        out.push((app.index, app.sink_index, name));
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to get_pa_sink_inputs: {:?}", err);
+        }
+    }
+    out
+}
+
+/// Move the applications in `apps` to the sink `sink`, and the others on it back to the default
+/// sink.
+pub fn move_pa_apps(apps: &[String], sink: &str) {
+    use pulsectl::controllers::*;
+    let mut handler = match SinkController::create() {
+        Ok(handler) => handler,
+        Err(err) => {
+            log::error!("Failed to move_pa_apps: {:?}", err);
+            return;
+        }
+    };
+    let (Ok(target), Ok(default)) = (
+        handler.get_device_by_name(sink),
+        handler.get_default_device(),
+    ) else {
+        return;
+    };
+    for (index, sink_index, name) in get_pa_sink_inputs() {
+        let selected = apps.contains(&name);
+        let to = if selected && sink_index != target.index {
+            target.index
+        } else if !selected && sink_index == target.index && default.index != target.index {
+            default.index
+        } else {
+            continue;
+        };
+        if let Err(err) = handler.move_app_by_index(index, to) {
+            log::error!("Failed to move {name} to sink {to}: {:?}", err);
+        }
+    }
+}
+
+/// Load the null sink of [`PA_APPS_SINK`], with a loopback to the default sink for the
+/// applications to be still heard locally. Returns the indexes of the modules loaded.
+pub fn load_pa_apps_sink() -> ResultType<Vec<u32>> {
+    let mut modules = vec![load_pa_null_sink(PA_APPS_SINK, "apps")?];
+    match pactl_load_module(&[
+        "module-loopback",
+        &format!("source={PA_APPS_SINK}.monitor"),
+        "latency_msec=20",
+    ]) {
+        Ok(module) => modules.push(module),
+        Err(err) => log::error!("Failed to load the loopback of {PA_APPS_SINK}: {err}"),
+    }
+    Ok(modules)
+}
+
+/// The null sink fed with the microphone of the controller.
+pub const PA_MIC_SINK: &str = "rustdesk_mic";
+/// The virtual microphone remapped from the monitor of [`PA_MIC_SINK`], for the applications to
+/// record from.
+pub const PA_MIC_SOURCE: &str = "rustdesk_mic_source";
+
+/// Load the null sink of [`PA_MIC_SINK`] and the source of [`PA_MIC_SOURCE`] on its monitor.
+/// Returns the indexes of the modules loaded.
+pub fn load_pa_virtual_mic() -> ResultType<Vec<u32>> {
+    let sink = load_pa_null_sink(PA_MIC_SINK, "mic-sink")?;
+    let description = format!("{}-Microphone", crate::get_app_name().replace(' ', "-"));
+    match pactl_load_module(&[
+        "module-remap-source",
+        &format!("master={PA_MIC_SINK}.monitor"),
+        &format!("source_name={PA_MIC_SOURCE}"),
+        &format!("source_properties=device.description={description}"),
+    ]) {
+        Ok(source) => Ok(vec![source, sink]),
+        Err(err) => {
+            unload_pa_modules(&[sink]);
+            Err(err)
+        }
+    }
+}
+
+// Load a null sink, after unloading the one left by a crash, the modules on its monitor go with it.
+fn load_pa_null_sink(name: &str, description: &str) -> ResultType<u32> {
+    use pulsectl::controllers::*;
+    let stale = SinkController::create()
+        .and_then(|mut handler| handler.get_device_by_name(name))
+        .ok()
+        .and_then(|dev| dev.owner_module);
+    if let Some(module) = stale {
+        unload_pa_modules(&[module]);
+    }
+    let description = format!("{}-{description}", crate::get_app_name().replace(' ', "-"));
+    pactl_load_module(&[
+        "module-null-sink",
+        &format!("sink_name={name}"),
+        &format!("sink_properties=device.description={description}"),
+    ])
+}
+
+pub fn unload_pa_modules(modules: &[u32]) {
+    for module in modules {
+        allow_err!(Command::new("pactl")
+            .arg("unload-module")
+            .arg(module.to_string())
+            .status());
+    }
+}
+
+fn pactl_load_module(args: &[&str]) -> ResultType<u32> {
+    let output = Command::new("pactl")
+        .arg("load-module")
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "pactl load-module {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse()?)
+}
+
 pub fn lock_screen() {
     Command::new("xdg-screensaver").arg("lock").spawn().ok();
 }
@@ -66,12 +66,14 @@ pub mod input_service {
     pub const NAME_WINDOW_FOCUS: &'static str = "";
 }
 
+pub(crate) mod bandwidth;
 mod connection;
 mod login_failure_check;
 pub mod display_service;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
+pub mod stats;
 mod video_qos;
 pub mod video_service;
 
@@ -445,6 +447,17 @@ impl Server {
         }
     }
 
+    // whether no other connection is subscribed to the service
+    pub fn is_only_subscriber(&self, name: &str, conn_id: i32) -> bool {
+        match self.services.get(name) {
+            Some(s) => self
+                .connections
+                .keys()
+                .all(|id| *id == conn_id || !s.is_subed(*id)),
+            None => false,
+        }
+    }
+
     // get a new unique id
     pub fn get_new_id(&mut self) -> i32 {
         self.id_count += 1;
@@ -610,6 +623,8 @@ pub async fn start_server(is_server: bool, no_server: bool) {
         crate::platform::try_kill_broker();
         #[cfg(feature = "hwcodec")]
         scrap::hwcodec::start_check_process();
+        // so that crashed recordings get their cues without waiting for the next recording
+        scrap::record::repair_dir_once(&crate::ui_interface::video_save_directory(true));
         crate::RendezvousMediator::start_all().await;
     } else {
         match crate::ipc::connect(1000, "").await {
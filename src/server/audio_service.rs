@@ -13,10 +13,16 @@
 // https://github.com/krruzic/pulsectl
 
 use super::*;
+#[cfg(target_os = "linux")]
+use crate::platform::linux::PA_APPS_SINK;
+use crate::AudioPreset;
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
-use magnum_opus::{Application::*, Channels::*, Encoder};
-use std::sync::atomic::{AtomicBool, Ordering};
+use magnum_opus::{Application, Application::*, Bitrate, Channels::*, Encoder};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Instant,
+};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
@@ -24,8 +30,34 @@ static RESTARTING: AtomicBool = AtomicBool::new(false);
 
 lazy_static::lazy_static! {
     static ref VOICE_CALL_INPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
+    // The preset asked and the percentage of the frames lost of each connection
+    static ref PEERS: Mutex<HashMap<i32, (AudioPreset, u32)>> = Default::default();
+    // The settings of the encoder and when they were checked, `None` for a new encoder
+    static ref OPUS_SETTINGS: Mutex<(Option<OpusSettings>, Instant)> = Mutex::new((None, Instant::now()));
+    // The connections asking for the noise suppression and echo cancellation in voice calls
+    static ref VOICE_PROCESSING: Mutex<std::collections::HashSet<i32>> = Default::default();
+    static ref VOICE_PROCESSOR: Mutex<Option<crate::VoiceProcessor>> = Default::default();
 }
 
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    static ref APPS: Mutex<AppsRouting> = Default::default();
+    // The connections redirecting the microphone, and the modules of the virtual microphone
+    static ref VIRTUAL_MIC: Mutex<(std::collections::HashSet<i32>, Vec<u32>)> = Default::default();
+}
+
+// The applications streamed alone, routed to the null sink of PA_APPS_SINK
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct AppsRouting {
+    conn_id: i32, // The connection selecting them
+    selected: Vec<String>,
+    modules: Vec<u32>,
+}
+
+#[cfg(target_os = "linux")]
+const APPS_ROUTE_INTERVAL: Duration = Duration::from_secs(1);
+
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 pub fn new() -> GenericService {
     let svc = EmptyExtraFieldService::new(NAME.to_owned(), true);
@@ -55,11 +87,18 @@ pub fn set_voice_call_input_device(device: Option<String>, set_if_present: bool)
         return;
     }
     *VOICE_CALL_INPUT_DEVICE.lock().unwrap() = device;
+    update_echo_reference();
     restart();
 }
 
 #[inline]
 fn get_audio_input() -> String {
+    #[cfg(target_os = "linux")]
+    if VOICE_CALL_INPUT_DEVICE.lock().unwrap().is_none()
+        && !APPS.lock().unwrap().selected.is_empty()
+    {
+        return format!("{PA_APPS_SINK}.monitor");
+    }
     VOICE_CALL_INPUT_DEVICE
         .lock()
         .unwrap()
@@ -67,6 +106,203 @@ fn get_audio_input() -> String {
         .unwrap_or(Config::get_option("audio-input"))
 }
 
+/// The applications playing audio and the ones streamed alone.
+pub fn get_apps() -> AudioApps {
+    #[cfg(target_os = "linux")]
+    return AudioApps {
+        apps: crate::platform::linux::get_pa_apps(),
+        selected: APPS.lock().unwrap().selected.clone(),
+        ..Default::default()
+    };
+    #[cfg(not(target_os = "linux"))]
+    AudioApps::new()
+}
+
+/// Stream the audio of the `selected` applications only, all the audio of the desktop when
+/// empty. New streams of them are moved to the null sink every APPS_ROUTE_INTERVAL.
+///
+/// The capture is shared by all the connections, so only the connection which selected the
+/// current applications, or the `only` one receiving the audio, can change them.
+pub fn set_apps(conn_id: i32, selected: Vec<String>, only: bool) -> AudioApps {
+    #[cfg(target_os = "linux")]
+    {
+        use crate::platform::linux::*;
+        let mut lock = APPS.lock().unwrap();
+        let owner = lock.conn_id == conn_id && !lock.selected.is_empty();
+        if !owner && !only {
+            log::warn!("connection {conn_id} is not allowed to change the audio applications");
+            drop(lock);
+            return get_apps();
+        }
+        let was_on = !lock.selected.is_empty();
+        if selected.is_empty() {
+            unload_pa_modules(&lock.modules);
+            lock.modules.clear();
+        } else if lock.modules.is_empty() {
+            match load_pa_apps_sink() {
+                Ok(modules) => lock.modules = modules,
+                Err(err) => {
+                    log::error!("Failed to load the sink of the applications: {err}");
+                    drop(lock);
+                    return get_apps();
+                }
+            }
+        }
+        log::info!("audio of the applications: {selected:?}");
+        lock.conn_id = conn_id;
+        lock.selected = selected;
+        let is_on = !lock.selected.is_empty();
+        if is_on {
+            move_pa_apps(&lock.selected, PA_APPS_SINK);
+        }
+        drop(lock);
+        if is_on && !was_on {
+            std::thread::spawn(|| loop {
+                std::thread::sleep(APPS_ROUTE_INTERVAL);
+                let lock = APPS.lock().unwrap();
+                if lock.selected.is_empty() {
+                    break;
+                }
+                move_pa_apps(&lock.selected, PA_APPS_SINK);
+            });
+        }
+        if is_on != was_on {
+            restart();
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (conn_id, selected, only);
+    get_apps()
+}
+
+/// The sink to play the voice call of the connection to, feeding the virtual microphone, `None`
+/// for the speakers. The virtual microphone is removed with the last connection redirecting.
+pub fn redirect_mic(conn_id: i32, on: bool) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        use crate::platform::linux::*;
+        let mut lock = VIRTUAL_MIC.lock().unwrap();
+        if on {
+            if lock.1.is_empty() {
+                match load_pa_virtual_mic() {
+                    Ok(modules) => lock.1 = modules,
+                    Err(err) => {
+                        log::error!("Failed to load the virtual microphone: {err}");
+                        return None;
+                    }
+                }
+                log::info!("virtual microphone {PA_MIC_SOURCE} loaded");
+            }
+            lock.0.insert(conn_id);
+            return Some(PA_MIC_SINK.to_owned());
+        }
+        if lock.0.remove(&conn_id) && lock.0.is_empty() {
+            unload_pa_modules(&lock.1);
+            lock.1.clear();
+            log::info!("virtual microphone {PA_MIC_SOURCE} unloaded");
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (conn_id, on);
+    None
+}
+
+pub fn set_preset(conn_id: i32, preset: AudioPreset) {
+    let old = get_preset();
+    PEERS.lock().unwrap().entry(conn_id).or_default().0 = preset;
+    on_preset_changed(old);
+}
+
+/// The percentage of the audio frames the controller lost recently.
+pub fn set_loss(conn_id: i32, percent: u32) {
+    PEERS.lock().unwrap().entry(conn_id).or_default().1 = percent;
+}
+
+pub fn on_connection_close(conn_id: i32) {
+    let old = get_preset();
+    PEERS.lock().unwrap().remove(&conn_id);
+    on_preset_changed(old);
+    #[cfg(target_os = "linux")]
+    {
+        let lock = APPS.lock().unwrap();
+        let selecting = lock.conn_id == conn_id && !lock.selected.is_empty();
+        drop(lock);
+        if selecting {
+            set_apps(conn_id, vec![], true);
+        }
+    }
+    redirect_mic(conn_id, false);
+    VOICE_PROCESSING.lock().unwrap().remove(&conn_id);
+    update_echo_reference();
+}
+
+/// Process the microphone with noise suppression and echo cancellation in voice calls, if any
+/// connection asks for it.
+pub fn set_voice_processing(conn_id: i32, on: bool) {
+    let mut lock = VOICE_PROCESSING.lock().unwrap();
+    let changed = if on {
+        lock.insert(conn_id)
+    } else {
+        lock.remove(&conn_id)
+    };
+    drop(lock);
+    if changed {
+        log::info!("voice processing of connection {conn_id}: {on}");
+        update_echo_reference();
+    }
+}
+
+fn is_voice_processed() -> bool {
+    get_voice_call_input_device().is_some() && !VOICE_PROCESSING.lock().unwrap().is_empty()
+}
+
+// The audio played is the reference of the echo canceller, only recorded while it's used.
+fn update_echo_reference() {
+    crate::set_echo_reference(is_voice_processed());
+}
+
+// The microphone of a voice call processed, `None` if not asked
+fn process_voice(data: &[f32]) -> Option<Vec<f32>> {
+    if !is_voice_processed() {
+        return None;
+    }
+    let mut data = data.to_owned();
+    VOICE_PROCESSOR.lock().unwrap().as_mut()?.process(&mut data);
+    Some(data)
+}
+
+// The audio is shared by all the connections, the preset saving the most bandwidth wins, then
+// music over voice.
+fn get_preset() -> AudioPreset {
+    PEERS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|p| p.0)
+        .max()
+        .unwrap_or_default()
+}
+
+// The application of the encoder can't be changed, a new one is created.
+fn on_preset_changed(old: AudioPreset) {
+    let preset = get_preset();
+    if preset != old {
+        log::info!("audio preset: {old:?} -> {preset:?}");
+        restart();
+    }
+}
+
+fn new_encoder(sample_rate: u32, channels: magnum_opus::Channels) -> ResultType<Encoder> {
+    *OPUS_SETTINGS.lock().unwrap() = (None, Instant::now());
+    *VOICE_PROCESSOR.lock().unwrap() = Some(crate::VoiceProcessor::new(sample_rate, channels as _));
+    let application = match get_preset() {
+        AudioPreset::Auto => LowDelay,
+        AudioPreset::Voice | AudioPreset::LowBandwidth => Voip,
+        AudioPreset::Music => Application::Audio,
+    };
+    Ok(Encoder::new(sample_rate, channels, application)?)
+}
+
 pub fn restart() {
     log::info!("restart the audio service, freezing now...");
     if RESTARTING.load(Ordering::SeqCst) {
@@ -100,7 +336,7 @@ mod pa_impl {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        let mut encoder = new_encoder(crate::platform::PA_SAMPLE_RATE, Stereo)?;
         #[cfg(target_os = "linux")]
         allow_err!(
             stream
@@ -404,7 +640,7 @@ mod cpal_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let device_channel = config.channels();
-        let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        let mut encoder = new_encoder(sample_rate, encode_channel)?;
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
         // Do not set `frame_size = sample_rate as usize / 100;`
@@ -450,6 +686,7 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
     let format = AudioFormat {
         sample_rate,
         channels: channels as _,
+        audio_preset: get_preset() as _,
         ..Default::default()
     };
     let mut misc = Misc::new();
@@ -464,8 +701,95 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
 // MAX_AUDIO_ZERO_COUNT=800 is similar as Gate Attack Time 3~5s(Linux) || 6~8s(Windows)
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
+// With DTX, Opus codes the frames not worth sending in at most this many bytes.
+// https://opus-codec.org/docs/opus_api-1.3.1/group__opus__encoder.html#ga88621a963b809ebfc27887f13518c966
+const DTX_FRAME_MAX_LEN: usize = 2;
+
+static AUDIO_SEQ: AtomicU64 = AtomicU64::new(0);
+
+const ADAPT_INTERVAL: Duration = Duration::from_secs(1);
+const AUTO_KBPS: u32 = 96; // About the automatic bitrate of Opus for 48000 stereo
+const MIN_KBPS: u32 = 8;
+const MIN_FEC_LOSS: u32 = 1; // In-band FEC is turned on from 1% loss
+const FEC_EXPECTED_LOSS: u32 = 5; // The loss the FEC presets prepare for
+const MAX_FEC_LOSS: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OpusSettings {
+    kbps: Option<u32>, // `None` for auto
+    fec: bool,
+    loss: u32, // The expected packet loss percentage, how much of the bitrate FEC takes
+    dtx: bool,
+}
+
+impl OpusSettings {
+    fn new(preset: AudioPreset, delay: Option<u32>, loss: u32, budget: Option<u32>) -> Self {
+        let (kbps, fec, dtx) = match preset {
+            AudioPreset::Auto => (None, false, false),
+            AudioPreset::Voice => (Some(32), true, true),
+            AudioPreset::Music => (Some(128), false, false),
+            AudioPreset::LowBandwidth => (Some(16), true, true),
+        };
+        // Leave room for the video under network delay
+        let kbps = match delay.unwrap_or(0) {
+            0..=149 => kbps,
+            150..=299 => Some(kbps.unwrap_or(AUTO_KBPS) * 3 / 4),
+            _ => Some(kbps.unwrap_or(AUTO_KBPS) / 2),
+        };
+        let kbps = match (kbps, budget) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let loss = if fec {
+            loss.max(FEC_EXPECTED_LOSS)
+        } else {
+            loss
+        };
+        let fec = fec || loss >= MIN_FEC_LOSS;
+        OpusSettings {
+            kbps: kbps.map(|v| v.max(MIN_KBPS)),
+            fec,
+            loss: if fec { loss.min(MAX_FEC_LOSS) } else { 0 },
+            dtx,
+        }
+    }
+}
+
+// Tune the encoder to the preset, the network of the connections and the audio budget of the
+// bandwidth limit, checked every ADAPT_INTERVAL.
+fn adapt_encoder(encoder: &mut Encoder) {
+    let mut lock = OPUS_SETTINGS.lock().unwrap();
+    if lock.0.is_some() && lock.1.elapsed() < ADAPT_INTERVAL {
+        return;
+    }
+    lock.1 = Instant::now();
+    let delay = super::video_service::VIDEO_QOS.lock().unwrap().max_delay();
+    let loss = PEERS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|p| p.1)
+        .max()
+        .unwrap_or(0);
+    let settings = OpusSettings::new(get_preset(), delay, loss, super::bandwidth::audio_kbps());
+    if lock.0 != Some(settings) {
+        log::debug!("audio encoder settings: {settings:?}");
+        let bitrate = match settings.kbps {
+            Some(kbps) => Bitrate::Bits(kbps as i32 * 1000),
+            None => Bitrate::Auto,
+        };
+        allow_err!(encoder.set_bitrate(bitrate));
+        allow_err!(encoder.set_inband_fec(settings.fec));
+        allow_err!(encoder.set_packet_loss_perc(settings.loss as _));
+        allow_err!(encoder.set_dtx(settings.dtx));
+        lock.0 = Some(settings);
+    }
+}
 
 fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+    let processed = process_voice(data);
+    let data = processed.as_deref().unwrap_or(data);
+    adapt_encoder(encoder);
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
@@ -495,14 +819,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                 match encoder
                     .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
                 {
-                    Ok(data) => {
-                        let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
-                            data: data.into(),
-                            ..Default::default()
-                        });
-                        sp.send(msg_out);
-                    }
+                    Ok(data) => send_frame(data, sp),
                     Err(_) => {}
                 }
             }
@@ -514,14 +831,51 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
 
     #[cfg(not(target_os = "android"))]
     match encoder.encode_vec_float(data, data.len() * 6) {
-        Ok(data) => {
-            let mut msg_out = Message::new();
-            msg_out.set_audio_frame(AudioFrame {
-                data: data.into(),
-                ..Default::default()
-            });
-            sp.send(msg_out);
-        }
+        Ok(data) => send_frame(data, sp),
         Err(_) => {}
     }
 }
+
+fn send_frame(data: Vec<u8>, sp: &GenericService) {
+    if data.len() <= DTX_FRAME_MAX_LEN {
+        return;
+    }
+    let frame = AudioFrame {
+        data: data.into(),
+        seq: Some(AUDIO_SEQ.fetch_add(1, Ordering::Relaxed)),
+        ..Default::default()
+    };
+    super::video_service::record_audio(&frame);
+    let mut msg_out = Message::new();
+    msg_out.set_audio_frame(frame);
+    sp.send(msg_out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opus_settings() {
+        let auto = OpusSettings::new(AudioPreset::Auto, Some(50), 0, None);
+        assert_eq!((auto.kbps, auto.fec, auto.dtx), (None, false, false));
+        let auto = OpusSettings::new(AudioPreset::Auto, Some(400), 3, None);
+        assert_eq!(
+            (auto.kbps, auto.fec, auto.loss),
+            (Some(AUTO_KBPS / 2), true, 3)
+        );
+
+        let voice = OpusSettings::new(AudioPreset::Voice, None, 0, None);
+        assert_eq!(voice.kbps, Some(32));
+        assert!(voice.fec && voice.dtx);
+        assert_eq!(voice.loss, FEC_EXPECTED_LOSS);
+        let voice = OpusSettings::new(AudioPreset::Voice, Some(200), 50, Some(20));
+        assert_eq!((voice.kbps, voice.loss), (Some(20), MAX_FEC_LOSS));
+
+        let music = OpusSettings::new(AudioPreset::Music, Some(200), 0, None);
+        assert_eq!((music.kbps, music.fec, music.dtx), (Some(96), false, false));
+
+        let low = OpusSettings::new(AudioPreset::LowBandwidth, Some(1000), 0, Some(4));
+        assert_eq!(low.kbps, Some(MIN_KBPS));
+    }
+}
@@ -0,0 +1,206 @@
+//! Bandwidth ceilings of the sessions, in kbps.
+//!
+//! The controlled side caps all the connections together with the option `bandwidth-limit`,
+//! and a controller may cap its own connection lower. Video and audio are broadcast to every
+//! connection, so they are held to the lowest ceiling of all. Audio takes its part first, a
+//! running file transfer halves what is left for the video, and gets the rest of the ceiling of
+//! its own connection.
+//!
+//! Only what this side sends is paced. The files the controller sends are written as they
+//! arrive and are not held to the ceilings, holding them back here would only fill the buffers
+//! in between. The budgets are recalculated when a connection or a ceiling changes, see
+//! `update`.
+
+use super::*;
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Instant,
+};
+
+const AUDIO_SHARE: u32 = 10; // Audio takes 1/10 of the ceiling
+const AUDIO_MIN_KBPS: u32 = 12;
+const AUDIO_MAX_KBPS: u32 = 64;
+const FILE_MIN_KBPS: u32 = 32; // File transfer never stalls completely
+const FILE_BURST_SECS: f64 = 0.25;
+
+#[derive(Default, Debug, Clone)]
+struct ConnLimit {
+    limit: u32, // Asked by the controller, 0 for no limit
+    file_transfer: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref CONNS: Mutex<HashMap<i32, ConnLimit>> = Default::default();
+}
+
+// 0 for no limit
+static AUDIO_KBPS: AtomicU32 = AtomicU32::new(0);
+
+pub fn on_connection_open(id: i32) {
+    CONNS.lock().unwrap().insert(id, ConnLimit::default());
+    update();
+}
+
+pub fn on_connection_close(id: i32) {
+    CONNS.lock().unwrap().remove(&id);
+    update();
+}
+
+/// Set the ceiling asked by the controller, 0 for no limit.
+pub fn set_connection_limit(id: i32, kbps: u32) {
+    if let Some(conn) = CONNS.lock().unwrap().get_mut(&id) {
+        conn.limit = kbps;
+    }
+    update();
+}
+
+pub fn set_file_transfer(id: i32, active: bool) {
+    let changed = match CONNS.lock().unwrap().get_mut(&id) {
+        Some(conn) if conn.file_transfer != active => {
+            conn.file_transfer = active;
+            true
+        }
+        _ => false,
+    };
+    if changed {
+        update();
+    }
+}
+
+/// The ceiling in effect for the connection.
+pub fn connection_limit(id: i32) -> Option<u32> {
+    let conns = CONNS.lock().unwrap();
+    let conn = conns.get(&id)?;
+    limit_of(conn, crate::get_bandwidth_limit_option(), conns.len())
+}
+
+/// The bitrate of the audio encoder, `None` for no limit.
+pub fn audio_kbps() -> Option<u32> {
+    Some(AUDIO_KBPS.load(Ordering::Relaxed)).filter(|v| *v > 0)
+}
+
+/// The throughput the file transfer of the connection may use.
+pub fn file_transfer_limit(id: i32) -> Option<u32> {
+    let global = crate::get_bandwidth_limit_option();
+    let conns = CONNS.lock().unwrap();
+    let limit = limit_of(conns.get(&id)?, global, conns.len())?;
+    let (audio, video) = split(shared_limit(&conns, global)?, true);
+    Some(limit.saturating_sub(audio + video).max(FILE_MIN_KBPS))
+}
+
+/// Hand the budgets over to the video and audio services, called when the connections or the
+/// ceilings change, including the option `bandwidth-limit`.
+pub fn update() {
+    let conns = CONNS.lock().unwrap();
+    let budgets = shared_limit(&conns, crate::get_bandwidth_limit_option())
+        .map(|limit| split(limit, conns.values().any(|c| c.file_transfer)));
+    drop(conns);
+    AUDIO_KBPS.store(budgets.map_or(0, |b| b.0), Ordering::Relaxed);
+    video_service::VIDEO_QOS
+        .lock()
+        .unwrap()
+        .set_bandwidth_limit(budgets.map(|b| b.1));
+}
+
+// The global ceiling is divided evenly among the connections.
+fn limit_of(conn: &ConnLimit, global: Option<u32>, conn_count: usize) -> Option<u32> {
+    let global = global.map(|g| g / conn_count.max(1) as u32);
+    let own = Some(conn.limit).filter(|v| *v > 0);
+    match (own, global) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn shared_limit(conns: &HashMap<i32, ConnLimit>, global: Option<u32>) -> Option<u32> {
+    conns
+        .values()
+        .filter_map(|c| limit_of(c, global, conns.len()))
+        .min()
+}
+
+// (audio, video)
+fn split(limit: u32, file_transfer: bool) -> (u32, u32) {
+    let audio = (limit / AUDIO_SHARE).clamp(AUDIO_MIN_KBPS, AUDIO_MAX_KBPS);
+    let rest = limit.saturating_sub(audio);
+    (audio, if file_transfer { rest / 2 } else { rest })
+}
+
+/// Paces the file transfer of a connection to its share of the ceiling.
+#[derive(Default, Debug)]
+pub struct FilePacer {
+    allowance: f64, // bytes
+    last: Option<Instant>,
+}
+
+impl FilePacer {
+    /// Whether to read the next blocks now.
+    pub fn ready(&mut self, kbps: Option<u32>) -> bool {
+        self.ready_at(kbps, Instant::now())
+    }
+
+    fn ready_at(&mut self, kbps: Option<u32>, now: Instant) -> bool {
+        let Some(kbps) = kbps else {
+            self.last = None;
+            self.allowance = 0.;
+            return true;
+        };
+        let bytes_per_sec = kbps as f64 * 1000. / 8.;
+        let elapsed = self.last.map_or(0., |last| (now - last).as_secs_f64());
+        self.last = Some(now);
+        self.allowance =
+            (self.allowance + elapsed * bytes_per_sec).min(bytes_per_sec * FILE_BURST_SECS);
+        self.allowance > 0.
+    }
+
+    pub fn sent(&mut self, bytes: u64) {
+        if self.last.is_some() {
+            self.allowance -= bytes as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_budgets() {
+        let conn = |limit| ConnLimit {
+            limit,
+            file_transfer: false,
+        };
+        assert_eq!(limit_of(&conn(0), None, 1), None);
+        assert_eq!(limit_of(&conn(2000), None, 1), Some(2000));
+        assert_eq!(limit_of(&conn(0), Some(3000), 2), Some(1500));
+        assert_eq!(limit_of(&conn(1000), Some(3000), 2), Some(1000));
+
+        let conns: HashMap<i32, ConnLimit> = [(1, conn(0)), (2, conn(500))].into();
+        assert_eq!(shared_limit(&conns, None), Some(500));
+        assert_eq!(shared_limit(&conns, Some(800)), Some(400));
+
+        assert_eq!(split(2000, false), (64, 1936));
+        assert_eq!(split(2000, true), (64, 968));
+        assert_eq!(split(100, false), (12, 88));
+    }
+
+    #[test]
+    fn test_file_pacer() {
+        let mut pacer = FilePacer::default();
+        let start = Instant::now();
+        let kbps = Some(800); // 100KB/s
+        assert!(!pacer.ready_at(kbps, start));
+        let t = start + Duration::from_millis(100);
+        assert!(pacer.ready_at(kbps, t));
+        pacer.sent(64 * 1024);
+        // 10KB allowed in 100ms, the block is paid back over the next ~560ms
+        assert!(!pacer.ready_at(kbps, t + Duration::from_millis(500)));
+        assert!(pacer.ready_at(kbps, t + Duration::from_millis(600)));
+        // idle time does not build up more than a short burst
+        let t = t + Duration::from_secs(10);
+        assert!(pacer.ready_at(kbps, t));
+        pacer.sent(64 * 1024);
+        assert!(!pacer.ready_at(kbps, t));
+        assert!(pacer.ready_at(None, t));
+    }
+}
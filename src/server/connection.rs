@@ -51,7 +51,7 @@ use hbb_common::{
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use scrap::android::{call_main_service_key_event, call_main_service_pointer_input};
-use scrap::camera;
+use scrap::{camera, record::RecordEvent};
 use serde_derive::Serialize;
 use serde_json::{json, value::Value};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -267,6 +267,7 @@ pub struct Connection {
     read_jobs: Vec<fs::TransferJob>,
     timer: crate::RustDeskInterval,
     file_timer: crate::RustDeskInterval,
+    file_pacer: bandwidth::FilePacer,
     file_transfer: Option<(String, bool)>,
     view_camera: bool,
     terminal: bool,
@@ -283,6 +284,8 @@ pub struct Connection {
     recording: bool,
     block_input: bool,
     privacy_mode: bool,
+    mic_redirect: bool,
+    gamepad: bool,
     control_permissions: Option<ControlPermissions>,
     last_test_delay: Option<Instant>,
     network_delay: u32,
@@ -320,6 +323,19 @@ pub struct Connection {
     from_switch: bool,
     voice_call_request_timestamp: Option<NonZeroI64>,
     voice_calling: bool,
+    // The format of the voice call audio of the controller
+    voice_call_format: Option<AudioFormat>,
+    // The controller asks to feed its microphone into the virtual one
+    mic_redirect_asked: bool,
+    // The virtual gamepads of the controller, removed when it's closed
+    #[cfg(target_os = "linux")]
+    gamepad_ipc: Option<ipc::Connection>,
+    // Not to wait for the uinput service missing at every gamepad message
+    #[cfg(target_os = "linux")]
+    gamepad_ipc_failed: Option<Instant>,
+    // The pen of the controller last sent to the uinput tablet
+    #[cfg(target_os = "linux")]
+    pen: crate::PenEvent,
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "ios")))]
     pressed_modifiers: HashSet<rdev::Key>,
@@ -373,6 +389,15 @@ impl Subscriber for ConnInner {
 
     #[inline]
     fn send(&mut self, msg: Arc<Message>) {
+        // The clipboard service sends the local clipboard to each of its subscribers
+        match &msg.union {
+            Some(message::Union::Clipboard(cb)) => Connection::record_clipboard(self.id, false, cb),
+            Some(message::Union::MultiClipboards(mcb)) => mcb
+                .clipboards
+                .iter()
+                .for_each(|cb| Connection::record_clipboard(self.id, false, cb)),
+            _ => {}
+        }
         // Send SwitchDisplay on the same channel as VideoFrame to avoid send order problems.
         let tx_by_video = match &msg.union {
             Some(message::Union::VideoFrame(_)) => true,
@@ -400,6 +425,8 @@ const MILLI1: Duration = Duration::from_millis(1);
 const SEND_TIMEOUT_VIDEO: u64 = 12_000;
 const SEND_TIMEOUT_OTHER: u64 = SEND_TIMEOUT_VIDEO * 10;
 const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+// The name of the macro played by the peer is cut to this many chars in the logs and the audit.
+const MAX_MACRO_NAME_CHARS: usize = 64;
 
 impl Connection {
     pub async fn start(
@@ -459,6 +486,7 @@ impl Connection {
             read_jobs: Vec::new(),
             timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_timer: crate::rustdesk_interval(time::interval(SEC30)),
+            file_pacer: Default::default(),
             file_transfer: None,
             view_camera: false,
             terminal: false,
@@ -475,6 +503,8 @@ impl Connection {
             recording: Self::permission(keys::OPTION_ENABLE_RECORD_SESSION, &control_permissions),
             block_input: Self::permission(keys::OPTION_ENABLE_BLOCK_INPUT, &control_permissions),
             privacy_mode: Self::permission(keys::OPTION_ENABLE_PRIVACY_MODE, &control_permissions),
+            mic_redirect: Self::permission(crate::OPTION_ALLOW_MIC_REDIRECT, &control_permissions),
+            gamepad: Self::permission(crate::OPTION_ALLOW_GAMEPAD, &control_permissions),
             control_permissions,
             last_test_delay: None,
             network_delay: 0,
@@ -506,6 +536,14 @@ impl Connection {
             audio_sender: None,
             voice_call_request_timestamp: None,
             voice_calling: false,
+            voice_call_format: None,
+            mic_redirect_asked: false,
+            #[cfg(target_os = "linux")]
+            gamepad_ipc: None,
+            #[cfg(target_os = "linux")]
+            gamepad_ipc_failed: None,
+            #[cfg(target_os = "linux")]
+            pen: Default::default(),
             options_in_login: None,
             #[cfg(not(any(target_os = "ios")))]
             pressed_modifiers: Default::default(),
@@ -587,7 +625,7 @@ impl Connection {
         );
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-        std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
+        std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned, id));
         let mut second_timer = crate::rustdesk_interval(time::interval(Duration::from_secs(1)));
 
         #[cfg(feature = "unix-file-copy-paste")]
@@ -642,6 +680,10 @@ impl Connection {
                             }
                         }
                         ipc::Data::ChatMessage{text} => {
+                            video_service::record_event(conn.inner.id(), RecordEvent::Chat {
+                                incoming: false,
+                                text: text.clone(),
+                            });
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
@@ -761,6 +803,16 @@ impl Connection {
                                 }
                                 conn.privacy_mode = enabled;
                                 conn.send_permission(Permission::PrivacyMode, enabled).await;
+                            } else if &name == "mic_redirect" {
+                                conn.mic_redirect = enabled;
+                                conn.start_voice_call_audio();
+                            } else if &name == "gamepad" {
+                                conn.gamepad = enabled;
+                                // Unplug the virtual gamepads
+                                #[cfg(target_os = "linux")]
+                                if !enabled {
+                                    conn.gamepad_ipc = None;
+                                }
                             }
                         }
                         ipc::Data::RawMessage(bytes) => {
@@ -900,6 +952,12 @@ impl Connection {
                 },
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
+                        bandwidth::set_file_transfer(id, true);
+                        if !conn.file_pacer.ready(bandwidth::file_transfer_limit(id)) {
+                            continue;
+                        }
+                        let transferred = |jobs: &Vec<fs::TransferJob>| jobs.iter().map(|j| j.transferred()).sum::<u64>();
+                        let before = transferred(&conn.read_jobs);
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
                         match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
                             Ok(log) => {
@@ -912,7 +970,10 @@ impl Connection {
                                 break;
                             }
                         }
+                        // the finished jobs are removed, their last blocks go uncounted
+                        conn.file_pacer.sent(transferred(&conn.read_jobs).saturating_sub(before));
                     } else {
+                        bandwidth::set_file_transfer(id, false);
                         conn.file_timer = crate::rustdesk_interval(time::interval_at(Instant::now() + SEC30, SEC30));
                     }
                 }
@@ -1035,6 +1096,7 @@ impl Connection {
                         msg_out.set_test_delay(TestDelay{
                             last_delay: conn.network_delay,
                             target_bitrate: video_service::VIDEO_QOS.lock().unwrap().bitrate(),
+                            bandwidth_limit: Some(bandwidth::connection_limit(id).unwrap_or(0)),
                             ..Default::default()
                         });
                         conn.send(msg_out.into()).await;
@@ -1099,7 +1161,7 @@ impl Connection {
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    fn handle_input(receiver: std_mpsc::Receiver<MessageInput>, tx: Sender) {
+    fn handle_input(receiver: std_mpsc::Receiver<MessageInput>, tx: Sender, _id: i32) {
         let mut block_input_mode = false;
         #[cfg(any(target_os = "windows", target_os = "macos"))]
         {
@@ -1199,7 +1261,11 @@ impl Connection {
             }
         }
         #[cfg(target_os = "linux")]
-        clear_remapped_keycode();
+        {
+            clear_remapped_keycode();
+            // After the pen released on close
+            remove_pen(_id);
+        }
         log::debug!("Input thread exited");
     }
 
@@ -1267,6 +1333,13 @@ impl Connection {
     }
 
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
+        video_service::record_event(
+            self.inner.id(),
+            RecordEvent::Permission {
+                name: format!("{:?}", permission),
+                enabled,
+            },
+        );
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
             permission: permission.into(),
@@ -1390,6 +1463,18 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
+        video_service::record_event(
+            self.inner.id(),
+            RecordEvent::FileTransfer {
+                action: match r#type {
+                    FileAuditType::RemoteSend => "remote_send",
+                    FileAuditType::RemoteReceive => "remote_receive",
+                }
+                .to_owned(),
+                path: path.to_owned(),
+                files: files.clone(),
+            },
+        );
         if self.server_audit_file.is_empty() {
             return;
         }
@@ -1993,6 +2078,8 @@ impl Connection {
             recording: self.recording,
             block_input: self.block_input,
             privacy_mode: self.privacy_mode,
+            mic_redirect: self.mic_redirect,
+            gamepad: self.gamepad,
             from_switch: self.from_switch,
         });
     }
@@ -2075,6 +2162,65 @@ impl Connection {
         self.tx_input.send(MessageInput::Key((msg, press))).ok();
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn record_mouse_event(conn_id: i32, me: &MouseEvent) {
+        let evt_type = me.mask & crate::input::MOUSE_TYPE_MASK;
+        // Moves are too frequent to be useful in a timeline.
+        if evt_type == crate::input::MOUSE_TYPE_MOVE
+            || evt_type == crate::input::MOUSE_TYPE_MOVE_RELATIVE
+        {
+            return;
+        }
+        video_service::record_event(
+            conn_id,
+            RecordEvent::Mouse {
+                x: me.x,
+                y: me.y,
+                mask: me.mask,
+            },
+        );
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn record_key_event(conn_id: i32, me: &KeyEvent) {
+        let (key, printable) = match &me.union {
+            Some(key_event::Union::ControlKey(ck)) => (format!("{:?}", ck.enum_value()), false),
+            Some(key_event::Union::Chr(chr)) => match me.mode.enum_value() {
+                Ok(KeyboardMode::Legacy) => (
+                    char::from_u32(*chr).map_or(chr.to_string(), |c| c.to_string()),
+                    true,
+                ),
+                _ => (format!("keycode {}", chr), true),
+            },
+            Some(key_event::Union::Unicode(u)) => (
+                char::from_u32(*u).map_or(u.to_string(), |c| c.to_string()),
+                true,
+            ),
+            Some(key_event::Union::Seq(seq)) => (seq.clone(), true),
+            _ => return,
+        };
+        let down = me.down || me.press;
+        video_service::record_event(
+            conn_id,
+            RecordEvent::Key {
+                down,
+                key,
+                printable,
+            },
+        );
+    }
+
+    fn record_clipboard(conn_id: i32, incoming: bool, cb: &Clipboard) {
+        video_service::record_event(
+            conn_id,
+            RecordEvent::Clipboard {
+                incoming,
+                format: format!("{:?}", cb.format.enum_value()),
+                len: cb.content.len(),
+            },
+        );
+    }
+
     fn verify_h1(&self, h1: &[u8]) -> bool {
         let mut hasher2 = Sha256::new();
         hasher2.update(h1);
@@ -2278,6 +2424,8 @@ impl Connection {
                 keys::OPTION_ENABLE_RECORD_SESSION => Some(Permission::recording),
                 keys::OPTION_ENABLE_BLOCK_INPUT => Some(Permission::block_input),
                 keys::OPTION_ENABLE_PRIVACY_MODE => Some(Permission::privacy_mode),
+                // The control permissions have no bits of the microphone redirection and the
+                // gamepads, `allow-mic-redirect` and `allow-gamepad` are only local options.
                 _ => None,
             };
             if let Some(permission) = permission {
@@ -2659,6 +2807,9 @@ impl Connection {
                         .user_network_delay(self.inner.id(), new_delay);
                     self.network_delay = new_delay;
                 }
+                if let Some(loss) = t.audio_loss {
+                    super::audio_service::set_loss(self.inner.id(), loss.min(100));
+                }
             }
         } else if let Some(message::Union::SwitchSidesResponse(_s)) = msg.union {
             #[cfg(feature = "flutter")]
@@ -2698,12 +2849,17 @@ impl Connection {
                     if self.is_authed_view_camera_conn() {
                         return true;
                     }
+                    #[cfg(target_os = "linux")]
+                    if !display_service::clip_to_shared_area(self.display_idx, &mut me) {
+                        return true;
+                    }
                     #[cfg(any(target_os = "android", target_os = "ios"))]
                     if let Err(e) = call_main_service_pointer_input("mouse", me.mask, me.x, me.y) {
                         log::debug!("call_main_service_pointer_input fail:{}", e);
                     }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.peer_keyboard_enabled() {
+                        Self::record_mouse_event(self.inner.id(), &me);
                         if is_left_up(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         } else {
@@ -2737,6 +2893,37 @@ impl Connection {
                     if self.is_authed_view_camera_conn() {
                         return true;
                     }
+                    #[cfg(target_os = "linux")]
+                    let mut pde = pde;
+                    #[cfg(target_os = "linux")]
+                    match &mut pde.union {
+                        Some(pointer_device_event::Union::TouchEvent(touch)) => {
+                            if !display_service::clip_touch_to_shared_area(self.display_idx, touch)
+                            {
+                                return true;
+                            }
+                        }
+                        Some(pointer_device_event::Union::PenEvent(m)) => {
+                            let mut pen = crate::PenEvent::from_message(m);
+                            // Only the hovering outside the shared area is dropped, not to break
+                            // the strokes or leave the pen on the tablet
+                            let keep = pen.down || self.pen.down || !pen.in_range;
+                            match display_service::clip_point_to_shared_area(
+                                self.display_idx,
+                                pen.x,
+                                pen.y,
+                                keep,
+                            ) {
+                                Some((x, y)) => (pen.x, pen.y) = (x, y),
+                                None => return true,
+                            }
+                            *m = pen.to_message();
+                            if self.peer_keyboard_enabled() {
+                                self.pen = pen;
+                            }
+                        }
+                        _ => {}
+                    }
                     #[cfg(any(target_os = "android", target_os = "ios"))]
                     if let Err(e) = match pde.union {
                         Some(pointer_device_event::Union::TouchEvent(touch)) => match touch.union {
@@ -2835,6 +3022,7 @@ impl Connection {
                         return true;
                     }
                     if self.peer_keyboard_enabled() {
+                        Self::record_key_event(self.inner.id(), &me);
                         if is_enter(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         }
@@ -2892,6 +3080,7 @@ impl Connection {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if self.clipboard_enabled() {
+                        Self::record_clipboard(self.inner.id(), true, &cb);
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Host);
                         // ios as the controlled side is actually not supported for now.
@@ -2920,6 +3109,9 @@ impl Connection {
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
                     if self.clipboard_enabled() {
+                        for cb in _mcb.clipboards.iter() {
+                            Self::record_clipboard(self.inner.id(), true, cb);
+                        }
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(_mcb.clipboards, ClipboardSide::Host);
                         #[cfg(target_os = "android")]
@@ -3312,6 +3504,13 @@ impl Connection {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        video_service::record_event(
+                            self.inner.id(),
+                            RecordEvent::Chat {
+                                incoming: true,
+                                text: c.text.clone(),
+                            },
+                        );
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -3368,14 +3567,8 @@ impl Connection {
                         _ => {}
                     },
                     Some(misc::Union::AudioFormat(format)) => {
-                        if !self.disable_audio {
-                            // Drop the audio sender previously.
-                            drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
-                            self.audio_sender
-                                .as_ref()
-                                .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
-                        }
+                        self.voice_call_format = Some(format);
+                        self.start_voice_call_audio();
                     }
                     #[cfg(feature = "flutter")]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -3455,6 +3648,85 @@ impl Connection {
                             self.send(msg_out).await;
                         }
                     }
+                    Some(misc::Union::KeyframeRequest(display)) => {
+                        self.request_keyframe(display as _);
+                    }
+                    Some(misc::Union::AudioApps(apps)) => {
+                        if self.audio_enabled() {
+                            let apps = if apps.select {
+                                let id = self.inner.id();
+                                let only = self
+                                    .server
+                                    .upgrade()
+                                    .map(|s| {
+                                        s.read()
+                                            .unwrap()
+                                            .is_only_subscriber(super::audio_service::NAME, id)
+                                    })
+                                    .unwrap_or(false);
+                                super::audio_service::set_apps(id, apps.selected, only)
+                            } else {
+                                super::audio_service::get_apps()
+                            };
+                            let mut misc = Misc::new();
+                            misc.set_audio_apps(apps);
+                            let mut msg_out = Message::new();
+                            msg_out.set_misc(misc);
+                            self.send(msg_out).await;
+                        }
+                    }
+                    #[cfg(target_os = "linux")]
+                    Some(misc::Union::SharedWindows(windows)) => {
+                        if self.is_remote() {
+                            // the window shared is the same for everyone, like the screens
+                            if let Some(id) = windows.selected {
+                                if self.peer_keyboard_enabled() {
+                                    display_service::pick_shared_window(self.inner.id(), id);
+                                }
+                            }
+                            let mut misc = Misc::new();
+                            misc.set_shared_windows(display_service::get_shared_windows());
+                            let mut msg_out = Message::new();
+                            msg_out.set_misc(misc);
+                            self.send(msg_out).await;
+                        }
+                    }
+                    Some(misc::Union::Viewport(v)) => {
+                        video_service::VIDEO_QOS.lock().unwrap().user_viewport(
+                            self.inner.id(),
+                            &video_service::get_service_name(self.video_source(), v.display as _),
+                            v.width.max(0) as _,
+                            v.height.max(0) as _,
+                        );
+                    }
+                    // Advisory, the events of the macro are ordinary input
+                    Some(misc::Union::MacroPlayback(name)) => {
+                        if self.peer_keyboard_enabled() {
+                            // from the peer, kept short and on one line in the logs
+                            let name: String = name
+                                .chars()
+                                .filter(|c| !c.is_control())
+                                .take(MAX_MACRO_NAME_CHARS)
+                                .collect();
+                            log::info!("#{} plays the keyboard macro {}", self.inner.id(), name);
+                            video_service::record_event(
+                                self.inner.id(),
+                                RecordEvent::Macro { name: name.clone() },
+                            );
+                            self.post_conn_audit(json!({
+                                "action": "macro",
+                                "name": name,
+                            }));
+                        }
+                    }
+                    #[cfg(target_os = "linux")]
+                    Some(misc::Union::GamepadState(state)) => {
+                        if let Some(state) = crate::GamepadState::from_message(&state) {
+                            if self.gamepad && self.is_remote() {
+                                self.handle_gamepad(state).await;
+                            }
+                        }
+                    }
                     _ => {}
                 },
                 Some(message::Union::AudioFrame(frame)) => {
@@ -3975,6 +4247,16 @@ impl Connection {
         });
     }
 
+    fn request_keyframe(&self, display: usize) {
+        self.server.upgrade().map(|s| {
+            s.read().unwrap().set_video_service_opt(
+                Some((self.video_source(), display)),
+                video_service::OPTION_KEYFRAME,
+                super::service::SERVICE_OPTION_VALUE_TRUE,
+            );
+        });
+    }
+
     async fn handle_switch_display(&mut self, s: SwitchDisplay) {
         let display_idx = s.display as usize;
         if self.display_idx != display_idx {
@@ -4225,11 +4507,63 @@ impl Connection {
         }
     }
 
+    // Play the voice call of the controller, into the virtual microphone if it redirects its
+    // microphone and it's permitted.
+    fn start_voice_call_audio(&mut self) {
+        let Some(format) = self.voice_call_format.clone() else {
+            return;
+        };
+        if self.disable_audio {
+            return;
+        }
+        // Drop the audio sender previously.
+        drop(std::mem::replace(&mut self.audio_sender, None));
+        let device = crate::audio_service::redirect_mic(
+            self.inner.id(),
+            self.mic_redirect_asked && self.mic_redirect,
+        );
+        self.audio_sender = Some(start_audio_thread(None, device));
+        self.audio_sender
+            .as_ref()
+            .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
+    }
+
+    // The uinput service creates a virtual gamepad for each gamepad of the controller, without
+    // the need of a display.
+    #[cfg(target_os = "linux")]
+    async fn handle_gamepad(&mut self, state: crate::GamepadState) {
+        if self.gamepad_ipc.is_none() {
+            if self
+                .gamepad_ipc_failed
+                .map_or(false, |t| t.elapsed() < Duration::from_secs(10))
+            {
+                return;
+            }
+            match super::uinput::client::connect_gamepad().await {
+                Ok(conn) => self.gamepad_ipc = Some(conn),
+                Err(err) => {
+                    log::error!("Failed to connect to the uinput gamepad service: {}", err);
+                    self.gamepad_ipc_failed = Some(Instant::now());
+                    return;
+                }
+            }
+        }
+        if let Some(conn) = self.gamepad_ipc.as_mut() {
+            if let Err(err) = conn.send(&ipc::Data::Gamepad(state)).await {
+                log::error!("Failed to send the gamepad state: {}", err);
+                self.gamepad_ipc = None;
+            }
+        }
+    }
+
     pub async fn close_voice_call(&mut self) {
         crate::audio_service::set_voice_call_input_device(None, true);
         // Notify the connection manager that the voice call has been closed.
         self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
         self.voice_calling = false;
+        self.voice_call_format = None;
+        drop(std::mem::replace(&mut self.audio_sender, None));
+        crate::audio_service::redirect_mic(self.inner.id(), false);
         if self.is_authed_view_camera_conn() {
             if let Some(s) = self.server.upgrade() {
                 s.write()
@@ -4259,6 +4593,23 @@ impl Connection {
                     .user_image_quality(self.inner.id(), image_quality);
             }
         }
+        if let Some(kbps) = o.bandwidth_limit {
+            bandwidth::set_connection_limit(self.inner.id(), kbps);
+        }
+        if let Some(preset) = o.audio_preset {
+            super::audio_service::set_preset(self.inner.id(), crate::AudioPreset::from_u32(preset));
+        }
+        if let Ok(q) = o.mic_redirect.enum_value() {
+            if q != BoolOption::NotSet {
+                self.mic_redirect_asked = q == BoolOption::Yes;
+                self.start_voice_call_audio();
+            }
+        }
+        if let Ok(q) = o.voice_processing.enum_value() {
+            if q != BoolOption::NotSet {
+                super::audio_service::set_voice_processing(self.inner.id(), q == BoolOption::Yes);
+            }
+        }
         if o.custom_fps > 0 {
             video_service::VIDEO_QOS
                 .lock()
@@ -5008,6 +5359,16 @@ impl Connection {
         self.pressed_modifiers.clear();
     }
 
+    // Not to leave the pen drawing on the tablet once closed.
+    #[cfg(target_os = "linux")]
+    fn release_pen(&mut self) {
+        if std::mem::take(&mut self.pen).in_range {
+            let mut pde = PointerDeviceEvent::new();
+            pde.set_pen_event(crate::PenEvent::default().to_message());
+            self.input_pointer(pde, self.inner.id());
+        }
+    }
+
     fn get_auto_disconenct_timer() -> Option<(Instant, u64)> {
         if Config::get_option("allow-auto-disconnect") == "Y" {
             let mut minute: u64 = Config::get_option("auto-disconnect-timeout")
@@ -5712,6 +6073,10 @@ impl Drop for Connection {
     fn drop(&mut self) {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         self.release_pressed_modifiers();
+        #[cfg(target_os = "linux")]
+        self.release_pen();
+        #[cfg(target_os = "linux")]
+        display_service::unpick_shared_window(self.inner.id());
 
         if let Some(s) = self.terminal_generic_service.as_ref() {
             s.join();
@@ -5935,9 +6300,23 @@ mod raii {
                     .unwrap()
                     .on_connection_open(conn_id);
             }
+            if Self::limit_bandwidth(conn_type) {
+                bandwidth::on_connection_open(conn_id);
+            }
+            if conn_type == AuthConnType::Remote || conn_type == AuthConnType::ViewCamera {
+                stats::on_connection_open(conn_id, lr.my_id.clone());
+            }
             Self(conn_id, conn_type)
         }
 
+        // Port forward and terminal are left out, they do not share the budget
+        fn limit_bandwidth(conn_type: AuthConnType) -> bool {
+            matches!(
+                conn_type,
+                AuthConnType::Remote | AuthConnType::ViewCamera | AuthConnType::FileTransfer
+            )
+        }
+
         fn check_wake_lock() {
             let conn_count = AUTHED_CONNS.lock().unwrap().len();
             let remote_count = AUTHED_CONNS
@@ -6057,6 +6436,11 @@ mod raii {
                     .unwrap()
                     .on_connection_close(self.0);
             }
+            if Self::limit_bandwidth(self.1) {
+                bandwidth::on_connection_close(self.0);
+            }
+            stats::on_connection_close(self.0);
+            audio_service::on_connection_close(self.0);
             // Clear per-connection state to avoid stale behavior if conn ids are reused.
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             clear_relative_mouse_active(self.0);
@@ -17,6 +17,13 @@ pub const NAME: &'static str = "display";
 #[cfg(windows)]
 const DUMMY_DISPLAY_SIDE_MAX_SIZE: usize = 1024;
 
+// The top-level window to share in place of the screens, by its id or a part of its title.
+#[cfg(target_os = "linux")]
+pub const OPTION_SHARE_WINDOW: &str = "share-window";
+// The region of the screens to share in place of them, `<x>,<y>,<width>x<height>`.
+#[cfg(target_os = "linux")]
+pub const OPTION_SHARE_REGION: &str = "share-region";
+
 struct ChangedResolution {
     original: (i32, i32),
     changed: (i32, i32),
@@ -34,6 +41,19 @@ lazy_static::lazy_static! {
 // https://github.com/rustdesk/rustdesk/pull/8537
 static TEMP_IGNORE_DISPLAYS_CHANGED: AtomicBool = AtomicBool::new(false);
 
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // The display index of the shared window or region, and (x, y, width, height) of it now on
+    // the root window.
+    static ref SHARED_AREA: Mutex<Option<(usize, (i32, i32, i32, i32))>> = Default::default();
+    // The window found by the option, kept while it exists, the title may change.
+    static ref SHARED_WINDOW: Mutex<Option<(String, u32)>> = Default::default();
+    // The connection id and the window picked by its controller, if the options share none.
+    static ref PICKED_WINDOW: Mutex<Option<(i32, u32)>> = Default::default();
+    // The last failure to share, not to log it on every check of the displays.
+    static ref SHARED_AREA_ERROR: Mutex<String> = Default::default();
+}
+
 #[derive(Default)]
 struct SyncDisplaysInfo {
     displays: Vec<DisplayInfo>,
@@ -420,9 +440,268 @@ fn no_displays(displays: &Vec<Display>) -> bool {
 #[inline]
 #[cfg(not(windows))]
 pub fn try_get_displays() -> ResultType<Vec<Display>> {
+    // The synthetic source replaces the screens, for testing without any, e.g. in CI.
+    #[cfg(all(target_os = "linux", feature = "synthetic"))]
+    if let Some(config) = scrap::synthetic::config() {
+        return Ok(vec![Display::SYNTHETIC(scrap::synthetic::Display::new(
+            config,
+        ))]);
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // Only the window or region the options share, none if it's gone, not the screens.
+        if is_shared_by_options() {
+            return Ok(get_shared_area(0).into_iter().collect());
+        }
+        let mut displays = Display::all()?;
+        if let Some(display) = get_shared_area(displays.len()) {
+            displays.push(display);
+        }
+        return Ok(displays);
+    }
+    #[cfg(not(target_os = "linux"))]
     Ok(Display::all()?)
 }
 
+// The options share a window or region in place of the screens. Without them, the window picked
+// by a controller is an extra display after the screens, to switch to like to any other display.
+#[cfg(target_os = "linux")]
+fn is_shared_by_options() -> bool {
+    is_x11()
+        && !(Config::get_option(OPTION_SHARE_WINDOW).trim().is_empty()
+            && Config::get_option(OPTION_SHARE_REGION).trim().is_empty())
+}
+
+#[cfg(target_os = "linux")]
+fn get_shared_area(idx: usize) -> Option<Display> {
+    if !is_x11() {
+        return None;
+    }
+    let display = match try_get_shared_area() {
+        Ok(display) => {
+            SHARED_AREA_ERROR.lock().unwrap().clear();
+            display
+        }
+        Err(e) => {
+            let e = format!("{e:?}");
+            let mut last = SHARED_AREA_ERROR.lock().unwrap();
+            if *last != e {
+                log::error!("{e}");
+                *last = e;
+            }
+            None
+        }
+    };
+    *SHARED_AREA.lock().unwrap() = display.as_ref().map(|d| {
+        let (x, y) = d.origin();
+        (idx, (x, y, d.width() as i32, d.height() as i32))
+    });
+    display
+}
+
+#[cfg(target_os = "linux")]
+fn try_get_shared_area() -> ResultType<Option<Display>> {
+    let window = Config::get_option(OPTION_SHARE_WINDOW).trim().to_owned();
+    let region = Config::get_option(OPTION_SHARE_REGION).trim().to_owned();
+    if !window.is_empty() {
+        let id = find_shared_window(&window)?;
+        return Ok(Some(
+            Display::window(id).with_context(|| format!("Failed to share window {window}"))?,
+        ));
+    }
+    if !region.is_empty() {
+        let Some((x, y, w, h)) = parse_region(&region) else {
+            bail!("Invalid region to share: {region}");
+        };
+        return Ok(Some(
+            Display::region(x, y, w, h).with_context(|| format!("Failed to share {region}"))?,
+        ));
+    }
+    let picked = *PICKED_WINDOW.lock().unwrap();
+    let Some((conn_id, id)) = picked else {
+        return Ok(None);
+    };
+    match Display::window(id) {
+        Ok(display) => Ok(Some(display)),
+        Err(e) => {
+            log::info!("The picked window 0x{id:08x} is not shared any more: {e}");
+            let mut picked = PICKED_WINDOW.lock().unwrap();
+            if *picked == Some((conn_id, id)) {
+                *picked = None;
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// The windows to share for the controller, with the one picked now. None if the options
+/// share a window or region, the titles of the others are not told then.
+#[cfg(target_os = "linux")]
+pub fn get_shared_windows() -> SharedWindows {
+    if is_shared_by_options() {
+        return SharedWindows::new();
+    }
+    let windows = match scrap::x11::Server::default() {
+        Ok(server) => server.windows(),
+        Err(e) => {
+            log::error!("Failed to connect to X server: {e:?}");
+            vec![]
+        }
+    };
+    SharedWindows {
+        windows: windows
+            .into_iter()
+            .map(|w| SharedWindow {
+                id: w.id,
+                title: w.title,
+                ..Default::default()
+            })
+            .collect(),
+        selected: PICKED_WINDOW.lock().unwrap().map(|(_, id)| id),
+        ..Default::default()
+    }
+}
+
+/// Share the window picked by the controller of the connection as the extra display, 0 for
+/// none. It's kept until the connection closes, or another controller picks one.
+/// The display service tells the controllers of the changed displays.
+#[cfg(target_os = "linux")]
+pub fn pick_shared_window(conn_id: i32, id: u32) {
+    if is_shared_by_options() {
+        return;
+    }
+    log::info!("#{conn_id} picks the window 0x{id:08x} to share");
+    *PICKED_WINDOW.lock().unwrap() = (id != 0).then_some((conn_id, id));
+}
+
+/// Stop sharing the window picked by the controller of the closed connection.
+#[cfg(target_os = "linux")]
+pub fn unpick_shared_window(conn_id: i32) {
+    let mut picked = PICKED_WINDOW.lock().unwrap();
+    if picked.map_or(false, |(id, _)| id == conn_id) {
+        *picked = None;
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn find_shared_window(option: &str) -> ResultType<u32> {
+    let windows = scrap::x11::Server::default()
+        .map_err(|e| hbb_common::anyhow::anyhow!("Failed to connect to X server: {e:?}"))?
+        .windows();
+    let mut cached = SHARED_WINDOW.lock().unwrap();
+    if let Some((value, id)) = cached.as_ref() {
+        if value == option && windows.iter().any(|w| w.id == *id) {
+            return Ok(*id);
+        }
+    }
+    let id = match parse_window_id(option) {
+        Some(id) => windows.iter().find(|w| w.id == id),
+        None => {
+            let title = option.to_lowercase();
+            windows
+                .iter()
+                .find(|w| w.title.to_lowercase().contains(&title))
+        }
+    }
+    .map(|w| w.id);
+    let Some(id) = id else {
+        bail!("No window to share: {option}");
+    };
+    *cached = Some((option.to_owned(), id));
+    Ok(id)
+}
+
+// Decimal or hex with `0x`, as printed by `xwininfo` or `wmctrl -l`.
+#[cfg(target_os = "linux")]
+fn parse_window_id(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn parse_region(s: &str) -> Option<(i32, i32, i32, i32)> {
+    let mut parts = s.split(',').map(|p| p.trim());
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    let (w, h) = parts.next()?.split_once('x')?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((x, y, w.trim().parse().ok()?, h.trim().parse().ok()?))
+}
+
+/// Translate the mouse event on the display `idx` from the shared window or region the peer
+/// knows of to where it is now. Returns false if the event is outside and should be dropped,
+/// button-ups are kept inside not to leave a button pressed.
+#[cfg(target_os = "linux")]
+pub fn clip_to_shared_area(idx: usize, e: &mut MouseEvent) -> bool {
+    let evt_type = e.mask & crate::input::MOUSE_TYPE_MASK;
+    // Delta-based events do not contain absolute coordinates.
+    if evt_type == crate::input::MOUSE_TYPE_WHEEL
+        || evt_type == crate::input::MOUSE_TYPE_TRACKPAD
+        || evt_type == crate::input::MOUSE_TYPE_MOVE_RELATIVE
+    {
+        return true;
+    }
+    match clip_point_to_shared_area(idx, e.x, e.y, evt_type == crate::input::MOUSE_TYPE_UP) {
+        Some((x, y)) => {
+            (e.x, e.y) = (x, y);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Translate the touch event like `clip_to_shared_area`, the ends of pans are kept.
+#[cfg(target_os = "linux")]
+pub fn clip_touch_to_shared_area(idx: usize, e: &mut TouchEvent) -> bool {
+    let (x, y, keep) = match &mut e.union {
+        Some(touch_event::Union::PanStart(e)) => (&mut e.x, &mut e.y, false),
+        Some(touch_event::Union::PanUpdate(e)) => (&mut e.x, &mut e.y, false),
+        Some(touch_event::Union::PanEnd(e)) => (&mut e.x, &mut e.y, true),
+        _ => return true,
+    };
+    match clip_point_to_shared_area(idx, *x, *y, keep) {
+        Some(p) => {
+            (*x, *y) = p;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Translate the point like `clip_to_shared_area`, `None` if it's outside and `keep` is false.
+#[cfg(target_os = "linux")]
+pub fn clip_point_to_shared_area(idx: usize, x: i32, y: i32, keep: bool) -> Option<(i32, i32)> {
+    let area = *SHARED_AREA.lock().unwrap();
+    match area {
+        Some((area_idx, area)) if area_idx == idx => {
+            translate_to_shared_area(idx, area, x, y, keep)
+        }
+        _ => Some((x, y)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn translate_to_shared_area(
+    idx: usize,
+    (x, y, w, h): (i32, i32, i32, i32),
+    px: i32,
+    py: i32,
+    keep: bool,
+) -> Option<(i32, i32)> {
+    // the window may have moved since the peer got the display
+    let (known_x, known_y) = get_display_info(idx).map_or((x, y), |d| (d.x, d.y));
+    let (ex, ey) = (px - known_x + x, py - known_y + y);
+    let inside = ex >= x && ey >= y && ex < x + w && ey < y + h;
+    if !inside && !keep {
+        return None;
+    }
+    Some((ex.clamp(x, x + w - 1), ey.clamp(y, y + h - 1)))
+}
+
 #[inline]
 #[cfg(windows)]
 pub fn try_get_displays() -> ResultType<Vec<Display>> {
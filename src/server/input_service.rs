@@ -1019,6 +1019,12 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
         return;
     }
 
+    #[cfg(target_os = "linux")]
+    if let Some(pointer_device_event::Union::PenEvent(pen)) = &evt.union {
+        handle_pen(crate::PenEvent::from_message(pen), conn);
+        return;
+    }
+
     match &evt.union {
         Some(TouchEvent(evt)) => match &evt.union {
             Some(ScaleUpdate(_scale_evt)) => {
@@ -1031,6 +1037,101 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
     }
 }
 
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    static ref UINPUT_PENS: Mutex<HashMap<i32, PenTablet>> = Default::default();
+}
+
+// The tablet of the uinput pen service of a connection.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct PenTablet {
+    tablet: Option<super::uinput::client::UInputPen>,
+    // When the connection to the uinput service failed last
+    failed_at: Option<Instant>,
+    // The tip is down as the left button of the mouse, without the uinput service
+    mouse_down: bool,
+}
+
+/// Remove the tablet of the connection.
+#[cfg(target_os = "linux")]
+pub fn remove_pen(conn: i32) {
+    if UINPUT_PENS.lock().unwrap().remove(&conn).is_some() {
+        log::info!("UInput pen of connection {} removed", conn);
+    }
+}
+
+// The pen is on a tablet of the uinput service, mapped to the bounding box of the displays.
+// It is the mouse without the service, like not running as root.
+#[cfg(target_os = "linux")]
+fn handle_pen(pen: crate::PenEvent, conn: i32) {
+    use super::uinput::{client::UInputPen, PEN_RANGE};
+    let mut lock = UINPUT_PENS.lock().unwrap();
+    let state = lock.entry(conn).or_default();
+    if state.tablet.is_none() && pen.in_range {
+        // Retried a while after failing
+        if state
+            .failed_at
+            .map_or(true, |t| t.elapsed() >= Duration::from_secs(10))
+        {
+            match UInputPen::new() {
+                Ok(t) => {
+                    log::info!("UInput pen of connection {} created", conn);
+                    state.tablet = Some(t);
+                }
+                Err(err) => {
+                    log::error!("Failed to connect to the uinput pen service: {}", err);
+                    state.failed_at = Some(Instant::now());
+                }
+            }
+        }
+    }
+    if let Some(t) = state.tablet.as_mut() {
+        let displays = super::display_service::get_sync_displays();
+        let left = displays.iter().map(|d| d.x).min();
+        let top = displays.iter().map(|d| d.y).min();
+        let right = displays.iter().map(|d| d.x + d.width).max();
+        let bottom = displays.iter().map(|d| d.y + d.height).max();
+        let (Some(left), Some(top), Some(right), Some(bottom)) = (left, top, right, bottom) else {
+            return;
+        };
+        let scale = |v: i32, min: i32, max: i32| {
+            ((v - min) as i64 * PEN_RANGE as i64 / (max - min - 1).max(1) as i64) as i32
+        };
+        let mut scaled = pen;
+        scaled.x = scale(pen.x, left, right);
+        scaled.y = scale(pen.y, top, bottom);
+        match t.send(scaled) {
+            Ok(_) => return,
+            Err(err) => {
+                log::error!("Failed to send the pen to the uinput service: {}", err);
+                state.tablet = None;
+                state.failed_at = Some(Instant::now());
+            }
+        }
+    }
+    let mut masks = vec![];
+    if pen.in_range {
+        masks.push(MOUSE_TYPE_MOVE);
+    }
+    let down = pen.in_range && pen.down;
+    if down != state.mouse_down {
+        state.mouse_down = down;
+        let button = MOUSE_BUTTON_LEFT << 3;
+        masks.push(button | if down { MOUSE_TYPE_DOWN } else { MOUSE_TYPE_UP });
+    }
+    drop(lock);
+    for mask in masks {
+        let evt = MouseEvent {
+            mask,
+            x: pen.x,
+            y: pen.y,
+            ..Default::default()
+        };
+        handle_mouse_simulation_(&evt, conn);
+    }
+}
+
 pub fn handle_mouse_(
     evt: &MouseEvent,
     conn: i32,
@@ -0,0 +1,296 @@
+//! Periodic QoS snapshots of the sessions, one row for each connection and display.
+//!
+//! The video services count the frames they encode and send, the snapshot combines them with
+//! the targets and the network delay of `VideoQoS`. The last snapshot is served over IPC with
+//! `ipc::Data::SessionStats`, and appended to the file of the option `session-stats-file` if
+//! set, as CSV for a `.csv` file and JSON lines otherwise.
+
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    sync::Once,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_SESSION_STATS_FILE: &str = "session-stats-file";
+pub const OPTION_SESSION_STATS_INTERVAL: &str = "session-stats-interval";
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+
+const CSV_HEADER: &str = "time,conn_id,peer_id,display,codec,hardware,fps,target_fps,encode_ms,\
+bitrate,target_bitrate,ratio,rtt,delay,dropped,late";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub time: i64, // ms since the epoch
+    pub conn_id: i32,
+    pub peer_id: String,
+    pub display: usize,
+    pub codec: String,
+    pub hardware: bool,
+    pub fps: f32, // Frames sent per second
+    pub target_fps: u32,
+    pub encode_ms: f32, // Average encode time of a frame
+    pub bitrate: u32,   // kbps sent
+    pub target_bitrate: u32,
+    pub ratio: f32,
+    pub rtt: Option<u32>,   // ms
+    pub delay: Option<u32>, // ms, network delay minus RTT
+    pub dropped: u32,       // Frames failed to encode
+    pub late: u32,          // Frames not fetched by the connection in time
+}
+
+impl SessionStats {
+    fn to_csv(&self) -> String {
+        let opt = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+        format!(
+            "{},{},{},{},{},{},{:.1},{},{:.1},{},{},{:.2},{},{},{},{}",
+            self.time,
+            self.conn_id,
+            self.peer_id,
+            self.display,
+            self.codec,
+            self.hardware,
+            self.fps,
+            self.target_fps,
+            self.encode_ms,
+            self.bitrate,
+            self.target_bitrate,
+            self.ratio,
+            opt(self.rtt),
+            opt(self.delay),
+            self.dropped,
+            self.late
+        )
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+struct ConnCounters {
+    frames: u32,
+    bytes: u64,
+    dropped: u32,
+    late: u32,
+}
+
+#[derive(Default, Debug, Clone)]
+struct DisplayCounters {
+    display: usize,
+    codec: String,
+    hardware: bool,
+    encoded: u32,
+    encode_time: Duration,
+    conns: HashMap<i32, ConnCounters>,
+}
+
+lazy_static::lazy_static! {
+    // Keyed by the name of the video service
+    static ref DISPLAYS: Mutex<HashMap<String, DisplayCounters>> = Default::default();
+    static ref PEERS: Mutex<HashMap<i32, String>> = Default::default();
+    static ref LAST: Mutex<Vec<SessionStats>> = Default::default();
+}
+
+pub fn on_connection_open(id: i32, peer_id: String) {
+    PEERS.lock().unwrap().insert(id, peer_id);
+}
+
+pub fn on_connection_close(id: i32) {
+    PEERS.lock().unwrap().remove(&id);
+    for d in DISPLAYS.lock().unwrap().values_mut() {
+        d.conns.remove(&id);
+    }
+}
+
+/// Called by the video service when its encoder is set up.
+pub fn set_encoder(name: &str, display: usize, codec: String, hardware: bool) {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        std::thread::spawn(run);
+    });
+    let mut displays = DISPLAYS.lock().unwrap();
+    let d = displays.entry(name.to_owned()).or_default();
+    d.display = display;
+    d.codec = codec;
+    d.hardware = hardware;
+}
+
+pub fn remove_display(name: &str) {
+    DISPLAYS.lock().unwrap().remove(name);
+}
+
+pub fn on_frame_encoded(name: &str, conn_ids: &HashSet<i32>, encode_time: Duration, bytes: usize) {
+    if let Some(d) = DISPLAYS.lock().unwrap().get_mut(name) {
+        d.encoded += 1;
+        d.encode_time += encode_time;
+        for id in conn_ids {
+            let c = d.conns.entry(*id).or_default();
+            c.frames += 1;
+            c.bytes += bytes as u64;
+        }
+    }
+}
+
+pub fn on_frame_dropped(name: &str) {
+    if let Some(d) = DISPLAYS.lock().unwrap().get_mut(name) {
+        d.conns.values_mut().for_each(|c| c.dropped += 1);
+    }
+}
+
+pub fn on_frame_late<'a>(name: &str, conn_ids: impl Iterator<Item = &'a i32>) {
+    if let Some(d) = DISPLAYS.lock().unwrap().get_mut(name) {
+        for id in conn_ids {
+            d.conns.entry(*id).or_default().late += 1;
+        }
+    }
+}
+
+/// The last snapshot.
+pub fn last() -> Vec<SessionStats> {
+    LAST.lock().unwrap().clone()
+}
+
+fn interval() -> Duration {
+    let secs = Config::get_option(OPTION_SESSION_STATS_INTERVAL)
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+fn run() {
+    let mut last = Instant::now();
+    loop {
+        std::thread::sleep(interval());
+        let elapsed = last.elapsed();
+        last = Instant::now();
+        let stats = take_snapshot(elapsed);
+        let file = Config::get_option(OPTION_SESSION_STATS_FILE);
+        if !file.is_empty() && !stats.is_empty() {
+            if let Err(e) = append(Path::new(&file), &stats) {
+                log::error!("Failed to write session stats to {file}: {e}");
+            }
+        }
+        *LAST.lock().unwrap() = stats;
+    }
+}
+
+// Rates over the period, the counters start over afterwards.
+fn take_snapshot(period: Duration) -> Vec<SessionStats> {
+    let displays: Vec<(String, DisplayCounters)> = DISPLAYS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .map(|(name, d)| {
+            let taken = d.clone();
+            d.encoded = 0;
+            d.encode_time = Duration::ZERO;
+            d.conns
+                .values_mut()
+                .for_each(|c| *c = ConnCounters::default());
+            (name.clone(), taken)
+        })
+        .collect();
+    let peers = PEERS.lock().unwrap().clone();
+    let time = hbb_common::get_time();
+    let secs = period.as_secs_f32().max(0.001);
+    let mut qos = video_service::VIDEO_QOS.lock().unwrap();
+    let mut stats = vec![];
+    for (name, d) in displays {
+        let encode_ms = if d.encoded > 0 {
+            d.encode_time.as_secs_f32() * 1000. / d.encoded as f32
+        } else {
+            0.
+        };
+        for (conn_id, c) in d.conns {
+            let Some(peer_id) = peers.get(&conn_id) else {
+                continue;
+            };
+            let delay = qos.user_delay(conn_id);
+            stats.push(SessionStats {
+                time,
+                conn_id,
+                peer_id: peer_id.clone(),
+                display: d.display,
+                codec: d.codec.clone(),
+                hardware: d.hardware,
+                fps: c.frames as f32 / secs,
+                target_fps: qos.fps(&name),
+                encode_ms,
+                bitrate: (c.bytes as f32 * 8. / 1000. / secs) as u32,
+                target_bitrate: qos.display_bitrate(&name),
+                ratio: qos.ratio(&name),
+                rtt: delay.and_then(|d| d.0),
+                delay: delay.map(|d| d.1),
+                dropped: c.dropped,
+                late: c.late,
+            });
+        }
+    }
+    stats.sort_by_key(|s| (s.conn_id, s.display));
+    stats
+}
+
+fn append(path: &Path, stats: &[SessionStats]) -> ResultType<()> {
+    let csv = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut out = String::new();
+    if csv && file.metadata()?.len() == 0 {
+        out.push_str(CSV_HEADER);
+        out.push('\n');
+    }
+    for s in stats {
+        if csv {
+            out.push_str(&s.to_csv());
+        } else {
+            out.push_str(&serde_json::to_string(s)?);
+        }
+        out.push('\n');
+    }
+    file.write_all(out.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_stats() {
+        let dir = std::env::temp_dir().join(format!("session_stats_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stats = vec![SessionStats {
+            time: 1,
+            conn_id: 2,
+            peer_id: "123456789".to_owned(),
+            codec: "VP9".to_owned(),
+            fps: 29.5,
+            target_fps: 30,
+            rtt: Some(20),
+            ..Default::default()
+        }];
+
+        let csv = dir.join("stats.csv");
+        append(&csv, &stats).unwrap();
+        append(&csv, &stats).unwrap();
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3, "the header is written once");
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1].split(',').count(), CSV_HEADER.split(',').count());
+        assert!(lines[1].starts_with("1,2,123456789,0,VP9,false,29.5,30,"));
+
+        let jsonl = dir.join("stats.jsonl");
+        append(&jsonl, &stats).unwrap();
+        let text = std::fs::read_to_string(&jsonl).unwrap();
+        let parsed: SessionStats = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(parsed, stats[0]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -2,7 +2,8 @@ use crate::ipc::{self, new_listener, Connection, Data, DataKeyboard, DataMouse};
 use enigo::{Key, KeyboardControllable, MouseButton, MouseControllable};
 use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
-    AttributeSet, EventType, InputEvent,
+    AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, InputEvent, InputId,
+    UinputAbsSetup,
 };
 use hbb_common::{
     allow_err, bail, log,
@@ -15,6 +16,10 @@ static IPC_REQUEST_TIMEOUT: u64 = 1000;
 static IPC_POSTFIX_KEYBOARD: &str = "_uinput_keyboard";
 static IPC_POSTFIX_MOUSE: &str = "_uinput_mouse";
 static IPC_POSTFIX_CONTROL: &str = "_uinput_control";
+static IPC_POSTFIX_GAMEPAD: &str = "_uinput_gamepad";
+static IPC_POSTFIX_PEN: &str = "_uinput_pen";
+/// The positions of the uinput pen are from 0 to this, on the bounding box of the displays.
+pub const PEN_RANGE: i32 = 32767;
 
 pub mod client {
     use super::*;
@@ -180,6 +185,31 @@ pub mod client {
         let _ = conn.next().await?;
         Ok(())
     }
+
+    /// Connect to the uinput gamepad service with `Data::Gamepad`. The gamepads created through the
+    /// connection are removed when it's closed.
+    pub async fn connect_gamepad() -> ResultType<Connection> {
+        ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_GAMEPAD).await
+    }
+
+    /// The tablet of the uinput pen service, removed when dropped.
+    pub struct UInputPen {
+        conn: Connection,
+        rt: Runtime,
+    }
+
+    impl UInputPen {
+        pub fn new() -> ResultType<Self> {
+            let rt = Runtime::new()?;
+            let conn = rt.block_on(ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_PEN))?;
+            Ok(Self { conn, rt })
+        }
+
+        /// Send the pen with its position from 0 to `PEN_RANGE`.
+        pub fn send(&mut self, pen: crate::PenEvent) -> ResultType<()> {
+            self.rt.block_on(self.conn.send(&Data::Pen(pen)))
+        }
+    }
 }
 
 pub mod service {
@@ -192,7 +222,10 @@ pub mod service {
     };
     #[cfg(target_os = "linux")]
     use std::os::unix::io::AsRawFd;
-    use std::{collections::HashMap, sync::Mutex};
+    use std::{
+        collections::{hash_map::Entry, HashMap},
+        sync::Mutex,
+    };
 
     lazy_static::lazy_static! {
     static ref KEY_MAP: HashMap<enigo::Key, evdev::Key> = HashMap::from(
@@ -530,6 +563,85 @@ pub mod service {
         }
     }
 
+    // The buttons of an Xbox 360 controller as reported by xpad, which reports X and Y as
+    // `BTN_X` and `BTN_Y`, the same codes as `BTN_NORTH` and `BTN_WEST`.
+    const GAMEPAD_KEY_MAP: [(crate::GamepadButton, evdev::Key); 11] = [
+        (crate::GamepadButton::South, evdev::Key::BTN_SOUTH),
+        (crate::GamepadButton::East, evdev::Key::BTN_EAST),
+        (crate::GamepadButton::West, evdev::Key::BTN_NORTH),
+        (crate::GamepadButton::North, evdev::Key::BTN_WEST),
+        (crate::GamepadButton::LeftShoulder, evdev::Key::BTN_TL),
+        (crate::GamepadButton::RightShoulder, evdev::Key::BTN_TR),
+        (crate::GamepadButton::Select, evdev::Key::BTN_SELECT),
+        (crate::GamepadButton::Start, evdev::Key::BTN_START),
+        (crate::GamepadButton::Mode, evdev::Key::BTN_MODE),
+        (crate::GamepadButton::LeftThumb, evdev::Key::BTN_THUMBL),
+        (crate::GamepadButton::RightThumb, evdev::Key::BTN_THUMBR),
+    ];
+
+    // Created with the ids of an Xbox 360 controller, so that games and SDL map it without
+    // configuration.
+    fn create_uinput_gamepad(index: u8) -> ResultType<VirtualDevice> {
+        let mut keys = AttributeSet::<evdev::Key>::new();
+        for (_, key) in GAMEPAD_KEY_MAP.iter() {
+            keys.insert(*key);
+        }
+        let stick = AbsInfo::new(0, i16::MIN as _, i16::MAX as _, 16, 128, 0);
+        let trigger = AbsInfo::new(0, 0, u8::MAX as _, 0, 0, 0);
+        let hat = AbsInfo::new(0, -1, 1, 0, 0, 0);
+        let name = format!("RustDesk UInput Gamepad {}", index + 1);
+        let mut builder = VirtualDeviceBuilder::new()?
+            .name(&name)
+            .input_id(InputId::new(BusType::BUS_USB, 0x045e, 0x028e, 0x0110))
+            .with_keys(&keys)?;
+        for (axis, info) in [
+            (AbsoluteAxisType::ABS_X, stick),
+            (AbsoluteAxisType::ABS_Y, stick),
+            (AbsoluteAxisType::ABS_RX, stick),
+            (AbsoluteAxisType::ABS_RY, stick),
+            (AbsoluteAxisType::ABS_Z, trigger),
+            (AbsoluteAxisType::ABS_RZ, trigger),
+            (AbsoluteAxisType::ABS_HAT0X, hat),
+            (AbsoluteAxisType::ABS_HAT0Y, hat),
+        ] {
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, info))?;
+        }
+        Ok(builder.build()?)
+    }
+
+    // A tablet mapped to the whole screen, with a pen and its eraser.
+    fn create_uinput_pen() -> ResultType<VirtualDevice> {
+        let mut keys = AttributeSet::<evdev::Key>::new();
+        for key in [
+            evdev::Key::BTN_TOOL_PEN,
+            evdev::Key::BTN_TOOL_RUBBER,
+            evdev::Key::BTN_TOUCH,
+            evdev::Key::BTN_STYLUS,
+            evdev::Key::BTN_STYLUS2,
+        ] {
+            keys.insert(key);
+        }
+        // libinput ignores the tablets without the resolution, in units per mm
+        let position = AbsInfo::new(0, 0, PEN_RANGE, 0, 0, 100);
+        let pressure = AbsInfo::new(0, 0, u16::MAX as _, 0, 0, 0);
+        // In degrees, the resolution in units per radian
+        let tilt = AbsInfo::new(0, -90, 90, 0, 0, 57);
+        let mut builder = VirtualDeviceBuilder::new()?
+            .name("RustDesk UInput Pen")
+            .input_id(InputId::new(BusType::BUS_VIRTUAL, 0, 0, 0))
+            .with_keys(&keys)?;
+        for (axis, info) in [
+            (AbsoluteAxisType::ABS_X, position),
+            (AbsoluteAxisType::ABS_Y, position),
+            (AbsoluteAxisType::ABS_PRESSURE, pressure),
+            (AbsoluteAxisType::ABS_TILT_X, tilt),
+            (AbsoluteAxisType::ABS_TILT_Y, tilt),
+        ] {
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, info))?;
+        }
+        Ok(builder.build()?)
+    }
+
     fn create_uinput_keyboard() -> ResultType<VirtualDevice> {
         // TODO: ensure keys here
         let mut keys = AttributeSet::<evdev::Key>::new();
@@ -778,6 +890,108 @@ pub mod service {
         }
     }
 
+    fn handle_gamepad(gamepads: &mut HashMap<u8, VirtualDevice>, state: &crate::GamepadState) {
+        use crate::GamepadButton;
+        if state.index >= crate::GAMEPAD_MAX {
+            return;
+        }
+        if !state.connected {
+            if gamepads.remove(&state.index).is_some() {
+                log::info!("UInput gamepad {} removed", state.index);
+            }
+            return;
+        }
+        let gamepad = match gamepads.entry(state.index) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => match create_uinput_gamepad(state.index) {
+                Ok(gamepad) => {
+                    log::info!("UInput gamepad {} created", state.index);
+                    e.insert(gamepad)
+                }
+                Err(err) => {
+                    log::error!("Failed to create gamepad {}", err);
+                    return;
+                }
+            },
+        };
+        // The events of the values not changed are dropped by the kernel
+        let mut events: Vec<_> = GAMEPAD_KEY_MAP
+            .iter()
+            .map(|(button, key)| {
+                InputEvent::new(EventType::KEY, key.code(), state.is_pressed(*button) as _)
+            })
+            .collect();
+        let hat = |neg: GamepadButton, pos: GamepadButton| {
+            state.is_pressed(pos) as i32 - state.is_pressed(neg) as i32
+        };
+        let hat_x = hat(GamepadButton::DPadLeft, GamepadButton::DPadRight);
+        let hat_y = hat(GamepadButton::DPadUp, GamepadButton::DPadDown);
+        for (axis, value) in [
+            (AbsoluteAxisType::ABS_X, state.axes[0] as i32),
+            (AbsoluteAxisType::ABS_Y, state.axes[1] as _),
+            (AbsoluteAxisType::ABS_RX, state.axes[2] as _),
+            (AbsoluteAxisType::ABS_RY, state.axes[3] as _),
+            (AbsoluteAxisType::ABS_Z, state.triggers[0] as _),
+            (AbsoluteAxisType::ABS_RZ, state.triggers[1] as _),
+            (AbsoluteAxisType::ABS_HAT0X, hat_x),
+            (AbsoluteAxisType::ABS_HAT0Y, hat_y),
+        ] {
+            events.push(InputEvent::new(EventType::ABSOLUTE, axis.0, value));
+        }
+        allow_err!(gamepad.emit(&events));
+    }
+
+    struct PenDevice {
+        device: VirtualDevice,
+        // The tool in range, the pen or the eraser
+        tool: Option<evdev::Key>,
+    }
+
+    fn handle_pen(pen: &mut PenDevice, evt: &crate::PenEvent) {
+        let key =
+            |k: evdev::Key, value: bool| InputEvent::new(EventType::KEY, k.code(), value as _);
+        let abs = |axis: AbsoluteAxisType, value: i32| {
+            InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+        };
+        let tool = match (evt.in_range, evt.eraser) {
+            (false, _) => None,
+            (true, false) => Some(evdev::Key::BTN_TOOL_PEN),
+            (true, true) => Some(evdev::Key::BTN_TOOL_RUBBER),
+        };
+        // The tool leaves in its own frame, before another one comes in range
+        if let Some(old) = pen.tool.filter(|old| Some(*old) != tool) {
+            allow_err!(pen.device.emit(&[
+                key(evdev::Key::BTN_TOUCH, false),
+                key(evdev::Key::BTN_STYLUS, false),
+                key(evdev::Key::BTN_STYLUS2, false),
+                abs(AbsoluteAxisType::ABS_PRESSURE, 0),
+                key(old, false),
+            ]));
+        }
+        pen.tool = tool;
+        let Some(tool) = tool else {
+            return;
+        };
+        let pressure = if evt.down { evt.pressure as i32 } else { 0 };
+        allow_err!(pen.device.emit(&[
+            abs(AbsoluteAxisType::ABS_X, evt.x.clamp(0, PEN_RANGE)),
+            abs(AbsoluteAxisType::ABS_Y, evt.y.clamp(0, PEN_RANGE)),
+            abs(AbsoluteAxisType::ABS_PRESSURE, pressure),
+            abs(AbsoluteAxisType::ABS_TILT_X, evt.tilt_x as _),
+            abs(AbsoluteAxisType::ABS_TILT_Y, evt.tilt_y as _),
+            key(tool, true),
+            key(evdev::Key::BTN_TOUCH, evt.down),
+            key(
+                evdev::Key::BTN_STYLUS,
+                evt.buttons & crate::PEN_BUTTON_PRIMARY != 0
+            ),
+            key(
+                evdev::Key::BTN_STYLUS2,
+                evt.buttons & crate::PEN_BUTTON_SECONDARY != 0
+            ),
+        ]));
+    }
+
     fn spawn_keyboard_handler(mut stream: Connection) {
         log::debug!("spawn_keyboard_handler: new keyboard handler connection");
         tokio::spawn(async move {
@@ -884,6 +1098,57 @@ pub mod service {
         });
     }
 
+    // Needs no display, the gamepads are read by the applications from /dev/input.
+    fn spawn_gamepad_handler(mut stream: ipc::Connection) {
+        tokio::spawn(async move {
+            // Removed once the connection is closed
+            let mut gamepads = HashMap::new();
+            loop {
+                match stream.next().await {
+                    Err(err) => {
+                        log::info!("UInput gamepad ipc connection closed: {}", err);
+                        break;
+                    }
+                    Ok(Some(Data::Gamepad(state))) => {
+                        handle_gamepad(&mut gamepads, &state);
+                    }
+                    Ok(Some(_)) => {
+                        log::warn!("Unexpected data type in gamepad handler");
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    fn spawn_pen_handler(mut stream: ipc::Connection) {
+        tokio::spawn(async move {
+            // Removed once the connection is closed
+            let mut pen = match create_uinput_pen() {
+                Ok(device) => PenDevice { device, tool: None },
+                Err(err) => {
+                    log::error!("Failed to create pen {}", err);
+                    return;
+                }
+            };
+            loop {
+                match stream.next().await {
+                    Err(err) => {
+                        log::info!("UInput pen ipc connection closed: {}", err);
+                        break;
+                    }
+                    Ok(Some(Data::Pen(evt))) => {
+                        handle_pen(&mut pen, &evt);
+                    }
+                    Ok(Some(_)) => {
+                        log::warn!("Unexpected data type in pen handler");
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+
     fn spawn_controller_handler(mut stream: ipc::Connection) {
         tokio::spawn(async move {
             loop {
@@ -988,6 +1253,20 @@ pub mod service {
         start_service(IPC_POSTFIX_MOUSE, spawn_mouse_handler).await;
     }
 
+    /// Start uinput gamepad service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_gamepad() {
+        log::info!("start uinput gamepad service");
+        start_service(IPC_POSTFIX_GAMEPAD, spawn_gamepad_handler).await;
+    }
+
+    /// Start uinput pen service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_pen() {
+        log::info!("start uinput pen service");
+        start_service(IPC_POSTFIX_PEN, spawn_pen_handler).await;
+    }
+
     /// Start uinput mouse service.
     #[tokio::main(flavor = "current_thread")]
     pub async fn start_service_control() {
@@ -1004,6 +1283,12 @@ pub mod service {
     pub fn stop_service_control() {
         log::info!("stop uinput control service");
     }
+    pub fn stop_service_gamepad() {
+        log::info!("stop uinput gamepad service");
+    }
+    pub fn stop_service_pen() {
+        log::info!("stop uinput pen service");
+    }
 }
 
 // https://github.com/emrebicer/mouce
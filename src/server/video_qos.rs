@@ -6,18 +6,21 @@ use std::{
 };
 
 /*
+Every display has its own fps and ratio, the encoder of each display is tuned separately.
+
 FPS adjust:
 a. new user connected =>set to INIT_FPS
 b. TestDelay receive => update user's fps according to network delay
     When network delay < DELAY_THRESHOLD_150MS, set minimum fps according to image quality, and increase fps;
     When network delay >= DELAY_THRESHOLD_150MS, set minimum fps according to image quality, and decrease fps;
 c. second timeout / TestDelay receive => update real fps to the minimum fps from all users
+d. an idle display next to a dynamic one keeps at least INIT_FPS, it sends little and does not cause the delay
 
 ratio adjust:
 a. user set image quality => update to the maximum ratio of the latest quality
-b. 3 seconds timeout => update ratio according to network delay
-    When network delay < DELAY_THRESHOLD_150MS, increase ratio, max 150kbps;
-    When network delay >= DELAY_THRESHOLD_150MS, decrease ratio;
+b. 3 seconds timeout of each display => update the display's ratio according to network delay
+    When network delay < DELAY_THRESHOLD_150MS, increase ratio of dynamic display, max 150kbps;
+    When network delay >= DELAY_THRESHOLD_150MS, decrease ratio of dynamic display, or of all displays if none is dynamic;
 
 adjust between FPS and ratio:
     When network delay < DELAY_THRESHOLD_150MS, fps is always higher than the minimum fps, and ratio is increasing;
@@ -25,6 +28,14 @@ adjust between FPS and ratio:
 
 delay:
     use delay minus RTT as the actual network delay
+
+bandwidth limit:
+    the ratio of each display is capped to keep its bitrate within an even share of the video budget, see `bandwidth`
+
+scale:
+    the display is downscaled before encoding to the largest viewport of the users, in steps of SCALE_STEPS;
+    a display kept at the minimum ratio under network delay is downscaled one more step every
+    SCALE_PRESSURE_INTERVAL, each step is restored after SCALE_PRESSURE_HOLD when the network is good again
 */
 
 // Constants
@@ -42,8 +53,14 @@ const MAX_BR_MULTIPLE: f32 = 1.0;
 const HISTORY_DELAY_LEN: usize = 2;
 const ADJUST_RATIO_INTERVAL: usize = 3; // Adjust quality ratio every 3 seconds
 const DYNAMIC_SCREEN_THRESHOLD: usize = 2; // Allow increase quality ratio if encode more than 2 times in one second
+const DYNAMIC_DAMAGE_THRESHOLD: f32 = 0.01; // Changes below 1% of the screen on average, e.g. a blinking cursor, are not dynamic
 const DELAY_THRESHOLD_150MS: u32 = 150; // 150ms is the threshold for good network condition
 
+const SCALE_STEPS: [f32; 4] = [1.0, 0.75, 0.5, 0.25];
+const MAX_SCALE_PRESSURE: usize = 2; // Network pressure downscales to 0.5 at most
+const SCALE_PRESSURE_INTERVAL: Duration = Duration::from_secs(10); // Wait for the delay to settle before the next step down
+const SCALE_PRESSURE_HOLD: Duration = Duration::from_secs(30); // Each step is kept at least 30 seconds
+
 #[derive(Default, Debug, Clone)]
 struct UserDelay {
     response_delayed: bool,
@@ -93,22 +110,51 @@ struct UserData {
     quality: Option<(i64, Quality)>, // (time, quality)
     delay: UserDelay,
     record: bool,
+    viewports: HashMap<String, (usize, usize)>, // The sizes the displays are shown at
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 struct DisplayData {
     send_counter: usize, // Number of times encode during period
+    damage: f32, // Sum of the changed ratio of the screen of the frames encoded during period
     support_changing_quality: bool,
+    fps: u32,
+    ratio: f32,
+    bitrate: u32,
+    bitrate_per_ratio: f32, // Bitrate of the encoder for ratio 1.0
+    dynamic: bool,          // Whether the display was dynamic in the last period
+    adjust_ratio_instant: Instant,
+}
+
+impl DisplayData {
+    fn new(ratio: f32) -> Self {
+        DisplayData {
+            send_counter: 0,
+            damage: 0.0,
+            support_changing_quality: false,
+            fps: FPS,
+            ratio,
+            bitrate: 0,
+            bitrate_per_ratio: 0.0,
+            dynamic: false,
+            adjust_ratio_instant: Instant::now(),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        self.send_counter >= ADJUST_RATIO_INTERVAL * DYNAMIC_SCREEN_THRESHOLD
+            && self.damage >= self.send_counter as f32 * DYNAMIC_DAMAGE_THRESHOLD
+    }
 }
 
 // Main QoS controller structure
 pub struct VideoQoS {
-    fps: u32,
-    ratio: f32,
+    fps: u32, // The fps allowed by the users, the upper limit of the fps of all displays
     users: HashMap<i32, UserData>,
     displays: HashMap<String, DisplayData>,
-    bitrate_store: u32,
-    adjust_ratio_instant: Instant,
+    last_ratios: HashMap<String, f32>, // Ratios of the displays whose video service is restarting
+    scale_pressures: HashMap<String, (usize, Instant)>, // Extra downscale steps of the displays
+    bandwidth_limit: Option<u32>,      // kbps of all displays
     abr_config: bool,
     new_user_instant: Instant,
 }
@@ -117,11 +163,11 @@ impl Default for VideoQoS {
     fn default() -> Self {
         VideoQoS {
             fps: FPS,
-            ratio: BR_BALANCED,
             users: Default::default(),
             displays: Default::default(),
-            bitrate_store: 0,
-            adjust_ratio_instant: Instant::now(),
+            last_ratios: Default::default(),
+            scale_pressures: Default::default(),
+            bandwidth_limit: None,
             abr_config: true,
             new_user_instant: Instant::now(),
         }
@@ -130,14 +176,18 @@ impl Default for VideoQoS {
 
 // Basic functionality
 impl VideoQoS {
-    // Calculate seconds per frame based on current FPS
-    pub fn spf(&self) -> Duration {
-        Duration::from_secs_f32(1. / (self.fps() as f32))
+    // Calculate seconds per frame based on current FPS of the display
+    pub fn spf(&self, video_service_name: &str) -> Duration {
+        Duration::from_secs_f32(1. / (self.fps(video_service_name) as f32))
     }
 
-    // Get current FPS within valid range
-    pub fn fps(&self) -> u32 {
-        let fps = self.fps;
+    // Get current FPS of the display within valid range
+    pub fn fps(&self, video_service_name: &str) -> u32 {
+        let fps = self
+            .displays
+            .get(video_service_name)
+            .map(|d| d.fps)
+            .unwrap_or(self.fps);
         if fps >= MIN_FPS && fps <= MAX_FPS {
             fps
         } else {
@@ -145,22 +195,85 @@ impl VideoQoS {
         }
     }
 
-    // Store bitrate for later use
-    pub fn store_bitrate(&mut self, bitrate: u32) {
-        self.bitrate_store = bitrate;
+    // Store bitrate of the display for later use, the encoder has just been set to the display's ratio
+    pub fn store_bitrate(&mut self, video_service_name: &str, bitrate: u32) {
+        let Some(ratio) = self
+            .displays
+            .get(video_service_name)
+            .map(|d| self.capped_ratio(d))
+        else {
+            return;
+        };
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.bitrate = bitrate;
+            if ratio > 0.0 {
+                display.bitrate_per_ratio = bitrate as f32 / ratio;
+            }
+        }
+    }
+
+    pub fn set_bandwidth_limit(&mut self, kbps: Option<u32>) {
+        if self.bandwidth_limit != kbps {
+            log::info!("video bandwidth limit: {kbps:?} kbps");
+            self.bandwidth_limit = kbps;
+        }
     }
 
-    // Get stored bitrate
+    pub fn bandwidth_limit(&self) -> Option<u32> {
+        self.bandwidth_limit
+    }
+
+    // Get stored bitrate of all displays
     pub fn bitrate(&self) -> u32 {
-        self.bitrate_store
+        self.displays.values().map(|d| d.bitrate).sum()
+    }
+
+    pub fn display_bitrate(&self, video_service_name: &str) -> u32 {
+        self.displays
+            .get(video_service_name)
+            .map_or(0, |d| d.bitrate)
     }
 
-    // Get current bitrate ratio with bounds checking
-    pub fn ratio(&mut self) -> f32 {
-        if self.ratio < BR_MIN_HIGH_RESOLUTION || self.ratio > BR_MAX {
-            self.ratio = BR_BALANCED;
+    // Get current bitrate ratio of the display with bounds checking
+    pub fn ratio(&mut self, video_service_name: &str) -> f32 {
+        let Some(display) = self.displays.get_mut(video_service_name) else {
+            return BR_BALANCED;
+        };
+        if display.ratio < BR_MIN_HIGH_RESOLUTION || display.ratio > BR_MAX {
+            display.ratio = BR_BALANCED;
+        }
+        self.capped_ratio(&self.displays[video_service_name])
+    }
+
+    // The ratio of the display within its share of the bandwidth limit, which wins over the
+    // lower bounds. The ratio itself is kept for when the limit is raised.
+    fn capped_ratio(&self, display: &DisplayData) -> f32 {
+        let mut ratio = display.ratio;
+        if let Some(limit) = self.bandwidth_limit {
+            if display.bitrate_per_ratio > 0.0 {
+                let share = limit as f32 / self.displays.len().max(1) as f32;
+                ratio = ratio.min(share / display.bitrate_per_ratio);
+            }
         }
-        self.ratio
+        ratio
+    }
+
+    // (RTT, network delay minus RTT) of the user
+    pub fn user_delay(&self, id: i32) -> Option<(Option<u32>, u32)> {
+        let user = self.users.get(&id)?;
+        if user.delay.delay_history.is_empty() {
+            return None;
+        }
+        Some((user.delay.rtt_calculator.get_rtt(), user.delay.avg_delay()))
+    }
+
+    // The highest network delay of the users, for the audio to adapt to
+    pub fn max_delay(&self) -> Option<u32> {
+        self.users
+            .values()
+            .filter(|u| !u.delay.delay_history.is_empty())
+            .map(|u| u.delay.avg_delay())
+            .max()
     }
 
     // Check if any user is in recording mode
@@ -168,15 +281,44 @@ impl VideoQoS {
         self.users.iter().any(|u| u.1.record)
     }
 
+    // The factor to downscale the display of `width` x `height` by before encoding, the smallest
+    // step still covering the viewports of all users, lowered under network pressure.
+    // The full size is kept while recording, or for the users not telling a viewport, they
+    // can't show a smaller video.
+    pub fn scale(&self, video_service_name: &str, width: usize, height: usize) -> f32 {
+        if width == 0 || height == 0 || self.users.is_empty() || self.record() {
+            return 1.0;
+        }
+        let mut wanted: f32 = 0.0;
+        for user in self.users.values() {
+            let Some((w, h)) = user.viewports.get(video_service_name) else {
+                return 1.0;
+            };
+            wanted = wanted
+                .max(*w as f32 / width as f32)
+                .max(*h as f32 / height as f32);
+        }
+        let step = SCALE_STEPS.iter().rposition(|s| *s >= wanted).unwrap_or(0);
+        let pressure = self
+            .scale_pressures
+            .get(video_service_name)
+            .map_or(0, |p| p.0);
+        SCALE_STEPS[(step + pressure).min(SCALE_STEPS.len() - 1)]
+    }
+
     pub fn set_support_changing_quality(&mut self, video_service_name: &str, support: bool) {
         if let Some(display) = self.displays.get_mut(video_service_name) {
             display.support_changing_quality = support;
         }
     }
 
-    // Check if variable bitrate encoding is supported and enabled
-    pub fn in_vbr_state(&self) -> bool {
-        self.abr_config && self.displays.iter().all(|e| e.1.support_changing_quality)
+    // Check if variable bitrate encoding is supported by the display and enabled
+    pub fn in_vbr_state(&self, video_service_name: &str) -> bool {
+        self.abr_config
+            && self
+                .displays
+                .get(video_service_name)
+                .is_some_and(|d| d.support_changing_quality)
     }
 }
 
@@ -193,7 +335,16 @@ impl VideoQoS {
     pub fn on_connection_close(&mut self, id: i32) {
         self.users.remove(&id);
         if self.users.is_empty() {
-            *self = Default::default();
+            // The video services and the bandwidth limit may outlive the connections, only drop
+            // what was adapted to the users
+            self.fps = FPS;
+            self.last_ratios.clear();
+            self.scale_pressures.clear();
+            let ratio = self.latest_quality().ratio();
+            for display in self.displays.values_mut() {
+                display.fps = FPS;
+                display.ratio = ratio;
+            }
         }
     }
 
@@ -233,7 +384,27 @@ impl VideoQoS {
         if let Some(user) = self.users.get_mut(&id) {
             user.quality = quality;
             // update ratio directly
-            self.ratio = self.latest_quality().ratio();
+            let ratio = self.latest_quality().ratio();
+            self.displays.values_mut().for_each(|d| d.ratio = ratio);
+            self.last_ratios.clear();
+        }
+    }
+
+    // A zero size for the full size
+    pub fn user_viewport(
+        &mut self,
+        id: i32,
+        video_service_name: &str,
+        width: usize,
+        height: usize,
+    ) {
+        if let Some(user) = self.users.get_mut(&id) {
+            if width == 0 || height == 0 {
+                user.viewports.remove(video_service_name);
+            } else {
+                user.viewports
+                    .insert(video_service_name.to_owned(), (width, height));
+            }
         }
     }
 
@@ -329,7 +500,10 @@ impl VideoQoS {
         self.adjust_fps();
         if adjust_ratio && !cfg!(target_os = "linux") {
             //Reduce the possibility of vaapi being created twice
-            self.adjust_ratio(false);
+            let names: Vec<String> = self.displays.keys().cloned().collect();
+            for name in names {
+                self.adjust_ratio(&name, false);
+            }
         }
     }
 
@@ -347,33 +521,51 @@ impl VideoQoS {
 // Common adjust functions
 impl VideoQoS {
     pub fn new_display(&mut self, video_service_name: String) {
-        self.displays
-            .insert(video_service_name, DisplayData::default());
+        // Continue with the ratio before the restart, e.g. switching codec
+        let ratio = self
+            .last_ratios
+            .remove(&video_service_name)
+            .unwrap_or_else(|| self.latest_quality().ratio());
+        let mut display = DisplayData::new(ratio);
+        display.fps = self.fps;
+        self.displays.insert(video_service_name, display);
     }
 
     pub fn remove_display(&mut self, video_service_name: &str) {
-        self.displays.remove(video_service_name);
+        if let Some(display) = self.displays.remove(video_service_name) {
+            self.last_ratios
+                .insert(video_service_name.to_owned(), display.ratio);
+        }
     }
 
-    pub fn update_display_data(&mut self, video_service_name: &str, send_counter: usize) {
-        if let Some(display) = self.displays.get_mut(video_service_name) {
-            display.send_counter += send_counter;
-        }
-        self.adjust_fps();
-        let abr_enabled = self.in_vbr_state();
+    // damage: sum of the changed ratio of the screen of the frames encoded, see `scrap::damage`
+    pub fn update_display_data(
+        &mut self,
+        video_service_name: &str,
+        send_counter: usize,
+        damage: f32,
+    ) {
+        let abr_enabled = self.in_vbr_state(video_service_name);
+        let latest_ratio = self.latest_quality().ratio();
+        let Some(display) = self.displays.get_mut(video_service_name) else {
+            return;
+        };
+        display.send_counter += send_counter;
+        display.damage += damage;
+        let mut adjust_ratio = None;
         if abr_enabled {
-            if self.adjust_ratio_instant.elapsed().as_secs() >= ADJUST_RATIO_INTERVAL as u64 {
-                let dynamic_screen = self
-                    .displays
-                    .iter()
-                    .any(|d| d.1.send_counter >= ADJUST_RATIO_INTERVAL * DYNAMIC_SCREEN_THRESHOLD);
-                self.displays.iter_mut().for_each(|d| {
-                    d.1.send_counter = 0;
-                });
-                self.adjust_ratio(dynamic_screen);
+            if display.adjust_ratio_instant.elapsed().as_secs() >= ADJUST_RATIO_INTERVAL as u64 {
+                display.dynamic = display.is_dynamic();
+                display.send_counter = 0;
+                display.damage = 0.0;
+                adjust_ratio = Some(display.dynamic);
             }
         } else {
-            self.ratio = self.latest_quality().ratio();
+            display.ratio = latest_ratio;
+        }
+        self.adjust_fps();
+        if let Some(dynamic_screen) = adjust_ratio {
+            self.adjust_ratio(video_service_name, dynamic_screen);
         }
     }
 
@@ -412,9 +604,9 @@ impl VideoQoS {
             .1
     }
 
-    // Adjust quality ratio based on network delay and screen changes
-    fn adjust_ratio(&mut self, dynamic_screen: bool) {
-        if !self.in_vbr_state() {
+    // Adjust quality ratio of the display based on network delay and its screen changes
+    fn adjust_ratio(&mut self, video_service_name: &str, dynamic_screen: bool) {
+        if !self.in_vbr_state(video_service_name) {
             return;
         }
         // Get maximum delay from all users
@@ -422,11 +614,18 @@ impl VideoQoS {
         let Some(max_delay) = max_delay else {
             return;
         };
-
+        // The delay is caused by the dynamic displays if there are any, leave the others
+        let other_dynamic = self
+            .displays
+            .iter()
+            .any(|(name, d)| name != video_service_name && d.dynamic);
         let target_quality = self.latest_quality();
         let target_ratio = self.latest_quality().ratio();
-        let current_ratio = self.ratio;
-        let current_bitrate = self.bitrate();
+        let Some(display) = self.displays.get_mut(video_service_name) else {
+            return;
+        };
+        let current_ratio = display.ratio;
+        let current_bitrate = display.bitrate;
 
         // Calculate minimum ratio for high resolution (1Mbps baseline)
         let ratio_1mbps = if current_bitrate > 0 {
@@ -483,6 +682,8 @@ impl VideoQoS {
             if dynamic_screen {
                 v = current_ratio * 1.05;
             }
+        } else if !dynamic_screen && other_dynamic {
+            // keep
         } else if max_delay < 200 {
             v = current_ratio * 0.95;
         } else if max_delay < 300 {
@@ -503,8 +704,21 @@ impl VideoQoS {
             }
         }
 
-        self.ratio = v.clamp(min, max);
-        self.adjust_ratio_instant = Instant::now();
+        display.ratio = v.clamp(min, max);
+        display.adjust_ratio_instant = Instant::now();
+
+        // Trade the resolution for quality when the ratio can't go lower, see `scale`
+        let pressure = self
+            .scale_pressures
+            .entry(video_service_name.to_owned())
+            .or_insert((0, Instant::now()));
+        if display.ratio <= min && max_delay >= DELAY_THRESHOLD_150MS {
+            if pressure.0 < MAX_SCALE_PRESSURE && pressure.1.elapsed() >= SCALE_PRESSURE_INTERVAL {
+                *pressure = (pressure.0 + 1, Instant::now());
+            }
+        } else if max_delay < 50 && pressure.0 > 0 && pressure.1.elapsed() >= SCALE_PRESSURE_HOLD {
+            *pressure = (pressure.0 - 1, Instant::now());
+        }
     }
 
     // Adjust fps based on network delay and user response time
@@ -533,6 +747,16 @@ impl VideoQoS {
 
         // Ensure fps stays within valid range
         self.fps = fps.clamp(MIN_FPS, highest_fps);
+
+        let any_dynamic = self.displays.values().any(|d| d.dynamic);
+        let idle_fps = self.fps.max(INIT_FPS.min(highest_fps));
+        for display in self.displays.values_mut() {
+            display.fps = if any_dynamic && !display.dynamic {
+                idle_fps
+            } else {
+                self.fps
+            };
+        }
     }
 }
 
@@ -593,3 +817,128 @@ impl RttCalculator {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_display_qos() {
+        let (busy, idle) = ("display0", "display1");
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.abr_config = true;
+        for name in [busy, idle] {
+            qos.new_display(name.to_owned());
+            qos.set_support_changing_quality(name, true);
+            qos.store_bitrate(name, 2000);
+        }
+        for _ in 0..5 {
+            qos.user_network_delay(1, 400);
+        }
+        let ratio = qos.ratio(busy);
+        assert_eq!(ratio, qos.ratio(idle));
+
+        for name in [busy, idle] {
+            qos.displays.get_mut(name).unwrap().adjust_ratio_instant =
+                Instant::now() - Duration::from_secs(ADJUST_RATIO_INTERVAL as _);
+        }
+        qos.update_display_data(busy, 90, 30.0);
+        qos.update_display_data(idle, 3, 0.03);
+        assert!(qos.ratio(busy) < ratio, "the busy display is throttled");
+        assert_eq!(qos.ratio(idle), ratio, "the idle display is left alone");
+        assert!(qos.fps(busy) < INIT_FPS);
+        assert_eq!(qos.fps(idle), INIT_FPS);
+        assert_eq!(qos.bitrate(), 4000);
+
+        // 500kbps for each display
+        qos.set_bandwidth_limit(Some(1000));
+        assert!((qos.ratio(idle) - ratio / 4.0).abs() < 0.001);
+        qos.store_bitrate(idle, 500);
+        // below the lower bound of the ratio
+        qos.set_bandwidth_limit(Some(20));
+        assert!(qos.ratio(idle) < BR_MIN_HIGH_RESOLUTION);
+        qos.set_bandwidth_limit(None);
+        assert_eq!(qos.ratio(idle), ratio, "recovered once the limit is lifted");
+
+        // the ratio survives the restart of the video service
+        let ratio = qos.ratio(busy);
+        qos.remove_display(busy);
+        qos.new_display(busy.to_owned());
+        assert_eq!(qos.ratio(busy), ratio);
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let name = "display0";
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.new_display(name.to_owned());
+        qos.set_support_changing_quality(name, true);
+        qos.set_bandwidth_limit(Some(1000));
+        qos.user_image_quality(1, ImageQuality::Low.value());
+        qos.on_connection_close(1);
+        assert!(qos.in_vbr_state(name));
+        assert_eq!(qos.bandwidth_limit(), Some(1000));
+        assert_eq!(qos.ratio(name), qos.latest_quality().ratio());
+        assert_eq!(qos.fps(name), FPS);
+    }
+
+    #[test]
+    fn test_scale() {
+        let name = "display0";
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.on_connection_open(2);
+        qos.abr_config = true;
+        qos.new_display(name.to_owned());
+        qos.set_support_changing_quality(name, true);
+        qos.store_bitrate(name, 2000);
+        qos.user_viewport(1, name, 960, 540);
+        assert_eq!(qos.scale(name, 1920, 1080), 1.0, "user 2 has no viewport");
+        qos.user_viewport(2, name, 1000, 500);
+        assert_eq!(qos.scale(name, 1920, 1080), 0.75, "covers the largest one");
+        qos.user_viewport(2, name, 0, 0);
+        assert_eq!(qos.scale(name, 1920, 1080), 1.0);
+        qos.user_viewport(2, name, 400, 200);
+        assert_eq!(qos.scale(name, 1920, 1080), 0.5);
+        qos.user_record(2, true);
+        assert_eq!(
+            qos.scale(name, 1920, 1080),
+            1.0,
+            "recordings are kept at full size"
+        );
+        qos.user_record(2, false);
+
+        // one more step down for each adjustment at the minimum ratio under delay
+        for _ in 0..5 {
+            qos.user_network_delay(1, 600);
+        }
+        let force_adjust = |qos: &mut VideoQoS| {
+            let display = qos.displays.get_mut(name).unwrap();
+            display.ratio = BR_MIN_HIGH_RESOLUTION;
+            display.adjust_ratio_instant =
+                Instant::now() - Duration::from_secs(ADJUST_RATIO_INTERVAL as _);
+            qos.scale_pressures
+                .entry(name.to_owned())
+                .or_insert((0, Instant::now()))
+                .1 = Instant::now() - SCALE_PRESSURE_HOLD;
+            qos.update_display_data(name, 90, 30.0);
+        };
+        force_adjust(&mut qos);
+        assert_eq!(qos.scale(name, 1920, 1080), 0.25);
+        force_adjust(&mut qos);
+        force_adjust(&mut qos);
+        qos.user_viewport(1, name, 1920, 1080);
+        qos.user_viewport(2, name, 1920, 1080);
+        assert_eq!(qos.scale(name, 1920, 1080), 0.5, "at most 2 steps");
+
+        // restored step by step with a good network
+        for _ in 0..5 {
+            qos.user_network_delay(1, 10);
+            qos.user_network_delay(2, 10);
+        }
+        force_adjust(&mut qos);
+        assert_eq!(qos.scale(name, 1920, 1080), 0.75);
+    }
+}
@@ -40,6 +40,8 @@ use hbb_common::{
 };
 #[cfg(feature = "hwcodec")]
 use scrap::hwcodec::{HwRamEncoder, HwRamEncoderConfig};
+#[cfg(feature = "openh264")]
+use scrap::openh264::SwH264EncoderConfig;
 #[cfg(feature = "vram")]
 use scrap::vram::{VRamEncoder, VRamEncoderConfig};
 #[cfg(not(windows))]
@@ -47,7 +49,8 @@ use scrap::Capturer;
 use scrap::{
     aom::AomEncoderConfig,
     codec::{Encoder, EncoderCfg},
-    record::{Recorder, RecorderContext},
+    damage::DamageTracker,
+    record::{RecordEvent, Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, Display, EncodeInput, TraitCapturer, TraitPixelBuffer,
 };
@@ -61,6 +64,15 @@ use std::{
 };
 
 pub const OPTION_REFRESH: &'static str = "refresh";
+pub const OPTION_KEYFRAME: &'static str = "keyframe";
+// Keyframes asked by the controllers are made at most once in this interval, the requests in
+// between are served when it ends.
+const MIN_KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(1000);
+// Restarting the video for a keyframe costs the viewers a lot more, the hardware encoders without
+// keyframes on demand do it at most once in this interval.
+const MIN_KEYFRAME_SWITCH_INTERVAL: Duration = Duration::from_secs(10);
+// The encoder is recreated at a new scale only after it has been asked for this long.
+const SCALE_SETTLE_TIME: Duration = Duration::from_millis(1000);
 
 type FrameFetchedNotifierSender = UnboundedSender<(i32, Option<Instant>)>;
 type FrameFetchedNotifierReceiver = Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>;
@@ -78,6 +90,11 @@ lazy_static::lazy_static! {
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     static ref SCREENSHOTS: Mutex<HashMap<usize, Screenshot>> = Default::default();
+    // Incoming recorders of all running video services and their services, for audio and the
+    // event timeline.
+    static ref RECORDERS: Mutex<Vec<(GenericService, Weak<Mutex<Option<Recorder>>>)>> = Default::default();
+    // The last restart of the video service of each display for a keyframe, outliving the service.
+    static ref KEYFRAME_SWITCH_INSTANTS: Mutex<HashMap<String, Instant>> = Default::default();
 }
 
 struct Screenshot {
@@ -116,6 +133,31 @@ pub fn notify_video_frame_fetched_by_conn_id(conn_id: i32, frame_tm: Option<Inst
     }
 }
 
+fn for_each_recorder(f: impl Fn(&GenericService, &mut Recorder)) {
+    RECORDERS.lock().unwrap().retain(|(sp, r)| {
+        let Some(r) = r.upgrade() else {
+            return false;
+        };
+        r.lock().unwrap().as_mut().map(|r| f(sp, r));
+        true
+    });
+}
+
+/// Write an event of the connection `conn_id` to the timeline of the incoming recordings of
+/// the displays it watches.
+pub fn record_event(conn_id: i32, event: RecordEvent) {
+    for_each_recorder(|sp, r| {
+        if sp.is_subed(conn_id) {
+            r.write_event(conn_id, &event);
+        }
+    });
+}
+
+/// Write an audio frame to every incoming recording.
+pub fn record_audio(frame: &AudioFrame) {
+    for_each_recorder(|_, r| r.write_audio(frame));
+}
+
 struct VideoFrameController {
     display_idx: usize,
     cur: Instant,
@@ -395,6 +437,9 @@ fn get_capturer_monitor(
         }
     }
 
+    #[cfg(not(windows))]
+    let mut displays = display_service::try_get_displays()?;
+    #[cfg(windows)]
     let mut displays = Display::all()?;
     let ndisplay = displays.len();
     if ndisplay <= current {
@@ -569,17 +614,23 @@ fn run(vs: VideoService) -> ResultType<()> {
         c.set_gdi();
     }
     let mut video_qos = VIDEO_QOS.lock().unwrap();
-    let mut spf = video_qos.spf();
-    let mut quality = video_qos.ratio();
+    let mut spf = video_qos.spf(&sp.name());
+    let mut quality = video_qos.ratio(&sp.name());
     let record_incoming = config::option2bool(
         "allow-auto-record-incoming",
         &Config::get_option("allow-auto-record-incoming"),
     );
     let client_record = video_qos.record();
+    let scale = if vs.source.is_monitor() && !record_incoming {
+        video_qos.scale(&sp.name(), c.width, c.height)
+    } else {
+        1.0
+    };
     drop(video_qos);
     let (mut encoder, encoder_cfg, codec_format, use_i444, recorder) = match setup_encoder(
         &c,
-        sp.name(),
+        &sp,
+        scale,
         quality,
         client_record,
         record_incoming,
@@ -590,16 +641,18 @@ fn run(vs: VideoService) -> ResultType<()> {
         Ok(result) => result,
         Err(err) => {
             log::error!("Failed to create encoder: {err:?}, fallback to VP9");
+            let (width, height) = scaled_size(c.width, c.height, scale);
             Encoder::set_fallback(&EncoderCfg::VPX(VpxEncoderConfig {
-                width: c.width as _,
-                height: c.height as _,
+                width: width as _,
+                height: height as _,
                 quality,
                 codec: VpxVideoCodecId::VP9,
                 keyframe_interval: None,
             }));
             setup_encoder(
                 &c,
-                sp.name(),
+                &sp,
+                scale,
                 quality,
                 client_record,
                 record_incoming,
@@ -611,6 +664,12 @@ fn run(vs: VideoService) -> ResultType<()> {
     };
     #[cfg(feature = "vram")]
     c.set_output_texture(encoder.input_texture());
+    // The textures and the recordings of the incoming sessions are kept at the capture size
+    #[cfg(feature = "vram")]
+    let scalable = vs.source.is_monitor() && !record_incoming && !encoder.input_texture();
+    #[cfg(not(feature = "vram"))]
+    let scalable = vs.source.is_monitor() && !record_incoming;
+    let scale = if scalable { scale } else { 1.0 };
     #[cfg(target_os = "android")]
     if vs.source.is_monitor() {
         if let Err(e) = check_change_scale(encoder.is_hardware()) {
@@ -618,12 +677,21 @@ fn run(vs: VideoService) -> ResultType<()> {
             bail!(e);
         }
     }
-    VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+    VIDEO_QOS
+        .lock()
+        .unwrap()
+        .store_bitrate(&sp.name(), encoder.bitrate());
     VIDEO_QOS
         .lock()
         .unwrap()
         .set_support_changing_quality(&sp.name(), encoder.support_changing_quality());
-    log::info!("initial quality: {quality:?}");
+    super::stats::set_encoder(
+        &sp.name(),
+        display_idx,
+        codec_format.to_string(),
+        encoder.is_hardware(),
+    );
+    log::info!("initial quality: {quality:?}, scale: {scale}");
 
     if sp.is_option_true(OPTION_REFRESH) {
         sp.set_option_bool(OPTION_REFRESH, false);
@@ -648,9 +716,14 @@ fn run(vs: VideoService) -> ResultType<()> {
     let repeat_encode_max = 10;
     let mut encode_fail_counter = 0;
     let mut first_frame = true;
+    let mut frame_seq = 0;
     let capture_width = c.width;
     let capture_height = c.height;
     let (mut second_instant, mut send_counter) = (Instant::now(), 0);
+    let mut damage_tracker = DamageTracker::new();
+    let mut damage = 0.0;
+    let mut keyframe_request = KeyframeRequest::default();
+    let mut scale_changed_instant = None;
 
     while sp.ok() {
         #[cfg(windows)]
@@ -661,6 +734,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             &mut spf,
             client_record,
             &mut send_counter,
+            &mut damage,
             &mut second_instant,
             &sp.name(),
         )?;
@@ -671,6 +745,10 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch to refresh");
             bail!("SWITCH");
         }
+        if sp.is_option_true(OPTION_KEYFRAME) {
+            sp.set_option_bool(OPTION_KEYFRAME, false);
+            keyframe_request.request();
+        }
         if codec_format != Encoder::negotiated_codec() {
             log::info!(
                 "switch due to codec changed, {:?} -> {:?}",
@@ -688,6 +766,12 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to i444 changed");
             bail!("SWITCH");
         }
+        if let EncoderCfg::AOM(aom) = &encoder_cfg {
+            if aom.crisp_text != Encoder::use_crisp_text() {
+                log::info!("switch due to crisp text changed");
+                bail!("SWITCH");
+            }
+        }
         #[cfg(all(windows, feature = "vram"))]
         if c.is_gdi() && encoder.input_texture() {
             log::info!("changed to gdi when using vram");
@@ -712,6 +796,20 @@ fn run(vs: VideoService) -> ResultType<()> {
             // The previous check in `sp.is_option_true(OPTION_REFRESH)` block may be enough.
             try_broadcast_display_changed(&sp, display_idx, &c, false)?;
         }
+        if scalable {
+            // Restart at the new size once the scale has settled, not on every step of resizing
+            // the window of the viewer
+            let new_scale = VIDEO_QOS
+                .lock()
+                .unwrap()
+                .scale(&sp.name(), c.width, c.height);
+            if new_scale == scale {
+                scale_changed_instant = None;
+            } else if scale_changed_instant.get_or_insert(now).elapsed() >= SCALE_SETTLE_TIME {
+                log::info!("switch due to scale changed, {scale} -> {new_scale}");
+                bail!("SWITCH");
+            }
+        }
 
         frame_controller.reset();
 
@@ -764,6 +862,24 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
 
+                    if keyframe_request.take_due(now)
+                        && keyframe_request.make_keyframe(&mut encoder, &sp.name(), now)?
+                    {
+                        damage_tracker.reset();
+                    }
+                    let frame_damage = match &frame {
+                        scrap::Frame::PixelBuffer(f) if encoder.support_damage() => {
+                            damage_tracker.update(f)
+                        }
+                        _ => None,
+                    };
+                    match frame_damage {
+                        Some(frame_damage) => {
+                            encoder.set_damage(frame_damage);
+                            damage += frame_damage.changed_ratio();
+                        }
+                        None => damage += 1.0,
+                    }
                     let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
                     let send_conn_ids = handle_one_frame(
                         display_idx,
@@ -774,6 +890,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                         recorder.clone(),
                         &mut encode_fail_counter,
                         &mut first_frame,
+                        &mut frame_seq,
                         capture_width,
                         capture_height,
                     )?;
@@ -820,7 +937,28 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
                 }
-                if !encoder.latency_free() && yuv.len() > 0 {
+                // The screen is still, code the last frame again as the keyframe asked for
+                if yuv.len() > 0
+                    && keyframe_request.take_due(now)
+                    && keyframe_request.make_keyframe(&mut encoder, &sp.name(), now)?
+                {
+                    encoder.set_damage(&Default::default());
+                    let send_conn_ids = handle_one_frame(
+                        display_idx,
+                        &sp,
+                        EncodeInput::YUV(&yuv),
+                        ms,
+                        &mut encoder,
+                        recorder.clone(),
+                        &mut encode_fail_counter,
+                        &mut first_frame,
+                        &mut frame_seq,
+                        capture_width,
+                        capture_height,
+                    )?;
+                    frame_controller.set_send(now, send_conn_ids);
+                    send_counter += 1;
+                } else if !encoder.latency_free() && yuv.len() > 0 {
                     // yun.len() > 0 means the frame is not texture.
                     if repeat_encode_counter < repeat_encode_max {
                         repeat_encode_counter += 1;
@@ -833,6 +971,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                             recorder.clone(),
                             &mut encode_fail_counter,
                             &mut first_frame,
+                            &mut frame_seq,
                             capture_width,
                             capture_height,
                         )?;
@@ -877,6 +1016,12 @@ fn run(vs: VideoService) -> ResultType<()> {
                 break;
             }
         }
+        if fetched_conn_ids.len() < frame_controller.send_conn_ids.len() {
+            super::stats::on_frame_late(
+                &sp.name(),
+                frame_controller.send_conn_ids.difference(&fetched_conn_ids),
+            );
+        }
         DISPLAY_CONN_IDS.lock().unwrap().remove(&display_idx);
 
         let elapsed = now.elapsed();
@@ -918,13 +1063,15 @@ impl Drop for Raii {
         #[cfg(feature = "vram")]
         Encoder::update(scrap::codec::EncodingUpdate::Check);
         VIDEO_QOS.lock().unwrap().remove_display(&self.name);
+        super::stats::remove_display(&self.name);
         DISPLAY_CONN_IDS.lock().unwrap().remove(&self.display_idx);
     }
 }
 
 fn setup_encoder(
     c: &CapturerInfo,
-    name: String,
+    sp: &GenericService,
+    scale: f32,
     quality: f32,
     client_record: bool,
     record_incoming: bool,
@@ -940,7 +1087,8 @@ fn setup_encoder(
 )> {
     let encoder_cfg = get_encoder_config(
         &c,
-        name.to_string(),
+        sp.name(),
+        scale,
         quality,
         client_record || record_incoming,
         last_portable_service_running,
@@ -948,7 +1096,12 @@ fn setup_encoder(
     );
     Encoder::set_fallback(&encoder_cfg);
     let codec_format = Encoder::negotiated_codec();
-    let recorder = get_recorder(record_incoming, display_idx, source == VideoSource::Camera);
+    let recorder = get_recorder(
+        record_incoming,
+        sp,
+        display_idx,
+        source == VideoSource::Camera,
+    );
     let use_i444 = Encoder::use_i444(&encoder_cfg);
     let encoder = Encoder::new(encoder_cfg.clone(), use_i444)?;
     Ok((encoder, encoder_cfg, codec_format, use_i444, recorder))
@@ -957,6 +1110,7 @@ fn setup_encoder(
 fn get_encoder_config(
     c: &CapturerInfo,
     _name: String,
+    scale: f32,
     quality: f32,
     record: bool,
     _portable_service: bool,
@@ -969,9 +1123,16 @@ fn get_encoder_config(
     }
     #[cfg(feature = "vram")]
     Encoder::update(scrap::codec::EncodingUpdate::Check);
-    // https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
-    let keyframe_interval = if record { Some(240) } else { None };
     let negotiated_codec = Encoder::negotiated_codec();
+    let keyframe_interval = scrap::codec::keyframe_interval(negotiated_codec);
+    // https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
+    let keyframe_interval = if record {
+        Some(keyframe_interval.map_or(240, |v| v.min(240)))
+    } else {
+        keyframe_interval
+    };
+    // The frames in memory are downscaled before encoding, the textures are not
+    let (width, height) = scaled_size(c.width, c.height, scale);
     match negotiated_codec {
         CodecFormat::H264 | CodecFormat::H265 => {
             #[cfg(feature = "vram")]
@@ -990,23 +1151,33 @@ fn get_encoder_config(
                 return EncoderCfg::HWRAM(HwRamEncoderConfig {
                     name: hw.name,
                     mc_name: hw.mc_name,
-                    width: c.width,
-                    height: c.height,
+                    width,
+                    height,
                     quality,
                     keyframe_interval,
                 });
             }
+            #[cfg(feature = "openh264")]
+            if negotiated_codec == CodecFormat::H264 {
+                return EncoderCfg::SWH264(SwH264EncoderConfig {
+                    width,
+                    height,
+                    quality,
+                    keyframe_interval,
+                    fps: VIDEO_QOS.lock().unwrap().fps(&_name),
+                });
+            }
             EncoderCfg::VPX(VpxEncoderConfig {
-                width: c.width as _,
-                height: c.height as _,
+                width: width as _,
+                height: height as _,
                 quality,
                 codec: VpxVideoCodecId::VP9,
                 keyframe_interval,
             })
         }
         format @ (CodecFormat::VP8 | CodecFormat::VP9) => EncoderCfg::VPX(VpxEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             codec: if format == CodecFormat::VP8 {
                 VpxVideoCodecId::VP8
@@ -1016,14 +1187,15 @@ fn get_encoder_config(
             keyframe_interval,
         }),
         CodecFormat::AV1 => EncoderCfg::AOM(AomEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             keyframe_interval,
+            crisp_text: Encoder::use_crisp_text(),
         }),
         _ => EncoderCfg::VPX(VpxEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             codec: VpxVideoCodecId::VP9,
             keyframe_interval,
@@ -1031,8 +1203,18 @@ fn get_encoder_config(
     }
 }
 
+// Even sizes for the 2x2 subsampled chroma
+fn scaled_size(width: usize, height: usize, scale: f32) -> (usize, usize) {
+    if scale >= 1.0 {
+        return (width, height);
+    }
+    let scaled = |v: usize| ((v as f32 * scale) as usize & !1).max(2);
+    (scaled(width), scaled(height))
+}
+
 fn get_recorder(
     record_incoming: bool,
+    sp: &GenericService,
     display_idx: usize,
     camera: bool,
 ) -> Arc<Mutex<Option<Recorder>>> {
@@ -1050,15 +1232,33 @@ fn get_recorder(
         } else {
             None
         };
-        Recorder::new(RecorderContext {
-            server: true,
-            id: Config::get_id(),
-            dir: crate::ui_interface::video_save_directory(root),
-            display_idx,
-            camera,
-            tx,
-        })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
+        match crate::get_record_public_key() {
+            Ok(public_key) => {
+                let recorder = Recorder::new(RecorderContext {
+                    server: true,
+                    id: Config::get_id(),
+                    dir: crate::ui_interface::video_save_directory(root),
+                    display_idx,
+                    camera,
+                    tx,
+                    public_key,
+                    timeline: crate::get_record_timeline_config(),
+                    audio: !camera,
+                    segment: crate::get_record_segment_config(),
+                    retention: crate::get_record_retention_policy(),
+                })
+                .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
+                RECORDERS
+                    .lock()
+                    .unwrap()
+                    .push((sp.clone(), Arc::downgrade(&recorder)));
+                recorder
+            }
+            Err(e) => {
+                log::error!("Recording disabled: {e}");
+                Default::default()
+            }
+        }
     } else {
         Default::default()
     };
@@ -1135,6 +1335,7 @@ fn handle_one_frame(
     recorder: Arc<Mutex<Option<Recorder>>>,
     encode_fail_counter: &mut usize,
     first_frame: &mut bool,
+    frame_seq: &mut u64,
     width: usize,
     height: usize,
 ) -> ResultType<HashSet<i32>> {
@@ -1150,10 +1351,15 @@ fn handle_one_frame(
     let mut send_conn_ids: HashSet<i32> = Default::default();
     let first = *first_frame;
     *first_frame = false;
+    let encode_begin = Instant::now();
     match encoder.encode_to_message(frame, ms) {
         Ok(mut vf) => {
+            let encode_time = encode_begin.elapsed();
             *encode_fail_counter = 0;
             vf.display = display as _;
+            // for the controllers to notice the frames lost on the way
+            *frame_seq += 1;
+            vf.seq = *frame_seq;
             let mut msg = Message::new();
             msg.set_video_frame(vf);
             recorder
@@ -1161,10 +1367,13 @@ fn handle_one_frame(
                 .unwrap()
                 .as_mut()
                 .map(|r| r.write_message(&msg, width, height));
+            let size = msg.compute_size() as usize;
             send_conn_ids = sp.send_video_frame(msg);
+            super::stats::on_frame_encoded(&sp.name(), &send_conn_ids, encode_time, size);
         }
         Err(e) => {
             *encode_fail_counter += 1;
+            super::stats::on_frame_dropped(&sp.name());
             // Encoding errors are not frequent except on Android
             if !cfg!(target_os = "android") {
                 log::error!("encode fail: {e:?}, times: {}", *encode_fail_counter,);
@@ -1197,6 +1406,58 @@ fn handle_one_frame(
     Ok(send_conn_ids)
 }
 
+/// Rate limits the keyframes asked by the controllers to recover from lost frames.
+#[derive(Debug, Default)]
+struct KeyframeRequest {
+    pending: bool,
+    last: Option<Instant>,
+}
+
+impl KeyframeRequest {
+    fn request(&mut self) {
+        self.pending = true;
+    }
+
+    // Whether to make the keyframe now.
+    fn take_due(&mut self, now: Instant) -> bool {
+        if !self.pending
+            || self.last.is_some_and(|last| {
+                now.saturating_duration_since(last) < MIN_KEYFRAME_REQUEST_INTERVAL
+            })
+        {
+            return false;
+        }
+        self.pending = false;
+        self.last = Some(now);
+        true
+    }
+
+    // Ask the encoder for the keyframe, `false` if it can't make one now. The encoders without
+    // keyframes on demand are recreated for it, at most once in `MIN_KEYFRAME_SWITCH_INTERVAL`.
+    fn make_keyframe(
+        &mut self,
+        encoder: &mut Encoder,
+        name: &str,
+        now: Instant,
+    ) -> ResultType<bool> {
+        if encoder.request_keyframe() {
+            return Ok(true);
+        }
+        let mut instants = KEYFRAME_SWITCH_INSTANTS.lock().unwrap();
+        if instants
+            .get(name)
+            .is_some_and(|last| now.saturating_duration_since(*last) < MIN_KEYFRAME_SWITCH_INTERVAL)
+        {
+            // served when the interval ends
+            self.pending = true;
+            return Ok(false);
+        }
+        instants.insert(name.to_owned(), now);
+        log::info!("switch to make a keyframe");
+        bail!("SWITCH");
+    }
+}
+
 #[inline]
 pub fn refresh() {
     #[cfg(target_os = "android")]
@@ -1313,19 +1574,24 @@ fn check_qos(
     spf: &mut Duration,
     client_record: bool,
     send_counter: &mut usize,
+    damage: &mut f32,
     second_instant: &mut Instant,
     name: &str,
 ) -> ResultType<()> {
     let mut video_qos = VIDEO_QOS.lock().unwrap();
-    *spf = video_qos.spf();
-    if *ratio != video_qos.ratio() {
-        *ratio = video_qos.ratio();
+    let new_spf = video_qos.spf(name);
+    if *spf != new_spf {
+        *spf = new_spf;
+        encoder.set_fps(video_qos.fps(name));
+    }
+    if *ratio != video_qos.ratio(name) {
+        *ratio = video_qos.ratio(name);
         if encoder.support_changing_quality() {
             allow_err!(encoder.set_quality(*ratio));
-            video_qos.store_bitrate(encoder.bitrate());
+            video_qos.store_bitrate(name, encoder.bitrate());
         } else {
             // Now only vaapi doesn't support changing quality
-            if !video_qos.in_vbr_state() && !video_qos.latest_quality().is_custom() {
+            if !video_qos.in_vbr_state(name) && !video_qos.latest_quality().is_custom() {
                 log::info!("switch to change quality");
                 bail!("SWITCH");
             }
@@ -1337,8 +1603,9 @@ fn check_qos(
     }
     if second_instant.elapsed() > Duration::from_secs(1) {
         *second_instant = Instant::now();
-        video_qos.update_display_data(&name, *send_counter);
+        video_qos.update_display_data(&name, *send_counter, *damage);
         *send_counter = 0;
+        *damage = 0.0;
     }
     drop(video_qos);
     Ok(())
@@ -1355,30 +1622,11 @@ pub fn set_take_screenshot(display_idx: usize, sid: String, tx: Sender) {
     );
 }
 
-// We need to this function, because the `stride` may be larger than `width * 4`.
 fn get_rgba_from_pixelbuf<'a>(pixbuf: &scrap::PixelBuffer<'a>) -> ResultType<Vec<u8>> {
-    let w = pixbuf.width();
-    let h = pixbuf.height();
-    let stride = pixbuf.stride();
-    let Some(s) = stride.get(0) else {
-        bail!("Invalid pixel buf stride.")
-    };
-
-    if *s == w * 4 {
-        let mut rgba = vec![];
-        scrap::convert(pixbuf, scrap::Pixfmt::RGBA, &mut rgba)?;
-        Ok(rgba)
-    } else {
-        let bgra = pixbuf.data();
-        let mut bit_flipped = Vec::with_capacity(w * h * 4);
-        for y in 0..h {
-            for x in 0..w {
-                let i = s * y + 4 * x;
-                bit_flipped.extend_from_slice(&[bgra[i + 2], bgra[i + 1], bgra[i], bgra[i + 3]]);
-            }
-        }
-        Ok(bit_flipped)
-    }
+    // The rows are packed, the `stride` may be larger than `width * 4`.
+    let mut rgba = vec![];
+    scrap::convert(pixbuf, scrap::Pixfmt::RGBA, &mut rgba)?;
+    Ok(rgba)
 }
 
 fn handle_screenshot(screenshot: Screenshot, msg: String, w: usize, h: usize, data: Vec<u8>) {
@@ -144,6 +144,8 @@ pub struct Client {
     pub recording: bool,
     pub block_input: bool,
     pub privacy_mode: bool,
+    pub mic_redirect: bool,
+    pub gamepad: bool,
     pub from_switch: bool,
     pub in_voice_call: bool,
     pub incoming_voice_call: bool,
@@ -232,6 +234,8 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         recording: bool,
         block_input: bool,
         privacy_mode: bool,
+        mic_redirect: bool,
+        gamepad: bool,
         from_switch: bool,
         #[cfg(not(any(target_os = "ios")))] tx: mpsc::UnboundedSender<Data>,
     ) {
@@ -254,6 +258,8 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             recording,
             block_input,
             privacy_mode,
+            mic_redirect,
+            gamepad,
             from_switch,
             #[cfg(not(any(target_os = "ios")))]
             tx,
@@ -543,9 +549,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                         }
                         Ok(Some(data)) => {
                             match data {
-                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, avatar, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, privacy_mode, from_switch} => {
+                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, avatar, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, privacy_mode, mic_redirect, gamepad, from_switch} => {
                                     log::debug!("conn_id: {}", id);
-                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, avatar, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, privacy_mode, from_switch, self.tx.clone());
+                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, avatar, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, privacy_mode, mic_redirect, gamepad, from_switch, self.tx.clone());
                                     self.conn_id = id;
                                     #[cfg(target_os = "windows")]
                                     {
@@ -896,6 +902,8 @@ pub async fn start_listen<T: InvokeUiCM>(
                 recording,
                 block_input,
                 privacy_mode,
+                mic_redirect,
+                gamepad,
                 from_switch,
                 ..
             }) => {
@@ -918,6 +926,8 @@ pub async fn start_listen<T: InvokeUiCM>(
                     recording,
                     block_input,
                     privacy_mode,
+                    mic_redirect,
+                    gamepad,
                     from_switch,
                     tx.clone(),
                 );
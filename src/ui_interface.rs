@@ -460,7 +460,13 @@ pub fn set_option(key: String, value: String) {
     #[cfg(any(target_os = "android", target_os = "ios"))]
     {
         let _nat = crate::CheckTestNatType::new();
+        #[cfg(target_os = "android")]
+        let bandwidth_limit = key == crate::OPTION_BANDWIDTH_LIMIT;
         Config::set_option(key, value);
+        #[cfg(target_os = "android")]
+        if bandwidth_limit {
+            crate::server::bandwidth::update();
+        }
     }
 }
 
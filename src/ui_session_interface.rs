@@ -41,6 +41,8 @@ use std::{
 use uuid::Uuid;
 
 use crate::client::io_loop::Remote;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::client::macros::{self, KeyMacro, MacroStep};
 use crate::client::{
     check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
     input_os_password, send_mouse, send_pointer_device_event, FileManager, Key, LoginConfigHandler,
@@ -383,6 +385,10 @@ impl<T: InvokeUiSession> Session<T> {
         if name == keys::OPTION_ENABLE_FILE_COPY_PASTE {
             self.send(Data::ToggleClipboardFile);
         }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if name == crate::OPTION_FORWARD_GAMEPADS || name == "view-only" {
+            self.send(Data::ToggleGamepad);
+        }
         if let Some(msg) = msg {
             self.send(Data::Message(msg));
         }
@@ -437,6 +443,41 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    /// Ask for a keyframe of the display to recover from lost or undecodable frames, the peers
+    /// not supporting it restart the video instead.
+    pub fn request_keyframe(&self, display: i32) {
+        if self.lc.read().unwrap().supported_encoding.keyframe_request {
+            self.send(Data::Message(LoginConfigHandler::request_keyframe(
+                display as _,
+            )));
+        } else {
+            self.refresh_video(display);
+        }
+    }
+
+    /// Ask for the windows of the peer with `selected` unset, or share the `selected` one as an
+    /// extra display, 0 for none.
+    pub fn share_window(&self, selected: Option<u32>) {
+        let mut misc = Misc::new();
+        misc.set_shared_windows(SharedWindows {
+            selected,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Tell the size in physical pixels the display is shown at, the peer may downscale the video
+    /// to it. A zero size asks for the full size.
+    pub fn set_viewport(&self, display: i32, width: i32, height: i32) {
+        self.send(Data::Message(LoginConfigHandler::viewport(
+            display.max(0) as _,
+            width.max(0) as _,
+            height.max(0) as _,
+        )));
+    }
+
     pub fn toggle_virtual_display(&self, index: i32, on: bool) {
         let mut misc = Misc::new();
         misc.set_toggle_virtual_display(ToggleVirtualDisplay {
@@ -503,6 +544,115 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    pub fn set_bandwidth_limit(&self, kbps: u32) {
+        let msg = self.lc.write().unwrap().set_bandwidth_limit(kbps);
+        self.send(Data::Message(msg));
+    }
+
+    pub fn set_audio_preset(&self, value: String) {
+        let msg = self.lc.write().unwrap().set_audio_preset(&value);
+        self.send(Data::Message(msg));
+    }
+
+    /// Ask for the applications playing audio on the peer with `selected` unset, or stream the
+    /// `selected` ones only, all the audio when empty.
+    pub fn set_audio_apps(&self, selected: Option<Vec<String>>) {
+        let mut misc = Misc::new();
+        misc.set_audio_apps(AudioApps {
+            select: selected.is_some(),
+            selected: selected.unwrap_or_default(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// The keyboard macros of this peer and of all peers.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn get_macros(&self) -> Vec<KeyMacro> {
+        macros::get_macros(
+            &self
+                .lc
+                .read()
+                .unwrap()
+                .get_option(macros::OPTION_KEYBOARD_MACROS),
+        )
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn set_macros(&self, macros: Vec<KeyMacro>) -> hbb_common::ResultType<()> {
+        let option = macros::set_macros(macros)?;
+        self.lc
+            .write()
+            .unwrap()
+            .set_option(macros::OPTION_KEYBOARD_MACROS.to_owned(), option);
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn play_macro(&self, name: &str) {
+        if let Some(m) = self.get_macros().into_iter().find(|m| m.name == name) {
+            self.play_key_macro(&m);
+        }
+    }
+
+    /// Play the macro of `hotkey`, like `Ctrl+Alt+1`, if any.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn play_macro_hotkey(&self, hotkey: &str) -> bool {
+        let Some(m) = self
+            .get_macros()
+            .into_iter()
+            .find(|m| macros::is_same_hotkey(&m.hotkey, hotkey))
+        else {
+            return false;
+        };
+        self.play_key_macro(&m);
+        true
+    }
+
+    // The peer is told the name of the macro to log it, then the events are sent in a thread
+    // to wait for the delays.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn play_key_macro(&self, m: &KeyMacro) {
+        if self.lc.read().unwrap().view_only.v {
+            return;
+        }
+        let steps = match macros::compile(&m.text) {
+            Ok(steps) => steps,
+            Err(err) => {
+                log::error!("Failed to compile the macro {}: {}", m.name, err);
+                return;
+            }
+        };
+        log::info!("Play the macro {}", m.name);
+        let mut misc = Misc::new();
+        misc.set_macro_playback(m.name.clone());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+        let session = self.clone();
+        std::thread::spawn(move || {
+            for step in steps {
+                match step {
+                    MacroStep::Key(evt) => session.send_key_event(&evt),
+                    MacroStep::Mouse {
+                        mask,
+                        x,
+                        y,
+                        alt,
+                        ctrl,
+                        shift,
+                        command,
+                    } => send_mouse(mask, x, y, alt, ctrl, shift, command, &session),
+                    MacroStep::Delay(ms) => {
+                        std::thread::sleep(std::time::Duration::from_millis(ms))
+                    }
+                }
+            }
+        });
+    }
+
     pub fn get_remember(&self) -> bool {
         self.lc.read().unwrap().remember
     }
@@ -547,6 +697,10 @@ impl<T: InvokeUiSession> Session<T> {
         (vp8, av1, h264, h265)
     }
 
+    pub fn support_crisp_text(&self) -> bool {
+        self.lc.read().unwrap().supported_encoding.crisp_text
+    }
+
     pub fn update_supported_decodings(&self) {
         let msg = self.lc.write().unwrap().update_supported_decodings();
         self.send(Data::Message(msg));
@@ -1199,6 +1353,21 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    /// Send the pen with its pressure and tilt, drawn on a virtual tablet by the peer on Linux, or
+    /// with its mouse if the tablet is unavailable.
+    pub fn send_pen(
+        &self,
+        pen: crate::PenEvent,
+        alt: bool,
+        ctrl: bool,
+        shift: bool,
+        command: bool,
+    ) {
+        let mut evt = PointerDeviceEvent::new();
+        evt.set_pen_event(pen.to_message());
+        send_pointer_device_event(evt, alt, ctrl, shift, command, self);
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn is_scroll_reverse_mode(&self) -> bool {
@@ -1730,6 +1899,8 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn is_multi_ui_session(&self) -> bool;
     fn update_record_status(&self, start: bool);
     fn update_empty_dirs(&self, _res: ReadEmptyDirsResponse) {}
+    fn update_shared_windows(&self, _windows: &SharedWindows) {}
+    fn update_audio_apps(&self, _apps: &AudioApps) {}
     fn printer_request(&self, id: i32, path: String);
     fn handle_screenshot_resp(&self, sid: String, msg: String);
     fn handle_terminal_response(&self, response: TerminalResponse);
@@ -1891,6 +2062,7 @@ impl<T: InvokeUiSession> Interface for Session<T> {
             self.update_quality_status(QualityStatus {
                 delay: Some(t.last_delay as _),
                 target_bitrate: Some(t.target_bitrate as _),
+                bandwidth_limit: t.bandwidth_limit,
                 ..Default::default()
             });
             handle_test_delay(t, peer).await;